@@ -94,26 +94,43 @@ def decrypt_pdf(content: bytes, k_doc: bytes) -> bytes:
         raise SPDFFormatError(f"Decryption failed: {e}")
 
 
-def sign_spdf(data: bytes, private_key: Ed25519PrivateKey) -> bytes:
+def signature_digest(data: bytes, hash_alg: str = "sha256") -> bytes:
+    """
+    Digest covered by the Ed25519 signature, per the header's hash_alg.
+
+    "blake3" requires the optional `blake3` package.
+    """
+    if hash_alg == "sha256":
+        return hashlib.sha256(data).digest()
+    if hash_alg == "blake3":
+        import blake3
+        return blake3.blake3(data).digest()
+    raise SPDFSignatureError(f"Unsupported hash_alg: {hash_alg}")
+
+
+def sign_spdf(data: bytes, private_key: Ed25519PrivateKey, hash_alg: str = "sha256") -> bytes:
     """
     Sign SPDF data using Ed25519.
     
     Args:
         data: Data to sign (everything except signature)
         private_key: Ed25519 private key
+        hash_alg: Header hash_alg ("sha256" or "blake3")
     
     Returns:
         64-byte signature
     """
     # Hash the data first
-    data_hash = hashlib.sha256(data).digest()
+    data_hash = signature_digest(data, hash_alg)
     
     # Sign the hash
     signature = private_key.sign(data_hash)
     return signature
 
 
-def verify_spdf_signature(data: bytes, signature: bytes, public_key: Ed25519PublicKey) -> bool:
+def verify_spdf_signature(
+    data: bytes, signature: bytes, public_key: Ed25519PublicKey, hash_alg: str = "sha256"
+) -> bool:
     """
     Verify SPDF signature using Ed25519.
     
@@ -121,6 +138,7 @@ def verify_spdf_signature(data: bytes, signature: bytes, public_key: Ed25519Publ
         data: Data that was signed
         signature: 64-byte signature
         public_key: Ed25519 public key
+        hash_alg: Header hash_alg ("sha256" or "blake3")
     
     Returns:
         True if signature is valid
@@ -129,7 +147,7 @@ def verify_spdf_signature(data: bytes, signature: bytes, public_key: Ed25519Publ
         raise SPDFSignatureError(f"Signature must be {SIGNATURE_LENGTH} bytes")
     
     # Hash the data
-    data_hash = hashlib.sha256(data).digest()
+    data_hash = signature_digest(data, hash_alg)
     
     # Verify signature
     try:
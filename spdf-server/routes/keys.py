@@ -8,12 +8,14 @@ from pydantic import BaseModel
 from datetime import datetime
 from cryptography.hazmat.primitives.ciphers.aead import AESGCM
 import os
+import re
 import base64
 
 from database import get_db
 from models import User, Device, License, DocumentKey, Document
 from routes.auth import get_current_user
 from config import K_MASTER
+from spdf_converter import get_org_public_key
 
 router = APIRouter(prefix="/keys", tags=["keys"])
 
@@ -28,6 +30,35 @@ class KeyResponse(BaseModel):
     k_doc: str  # base64 encoded
     permissions: dict
     watermark_data: dict
+    # The device this key was registered to; viewers only cache keys for offline
+    # use when it is their own
+    bound_device_id: str | None = None
+
+
+class BatchKeyRequest(BaseModel):
+    doc_ids: list[str]
+    device_id: str
+    device_name: str
+
+
+class BatchKeyResult(BaseModel):
+    doc_id: str
+    status: int
+    key: KeyResponse | None = None
+    detail: str | None = None
+
+
+class BatchKeyResponse(BaseModel):
+    results: list[BatchKeyResult]
+
+
+# Upper bound on doc_ids per /keys/get-batch request
+MAX_BATCH_SIZE = 50
+
+
+class OrgKeysResponse(BaseModel):
+    org_id: str
+    keys: list[str]  # PEM-encoded Ed25519 public keys
 
 
 def encrypt_k_doc(k_doc: bytes) -> bytes:
@@ -147,5 +178,69 @@ def get_key(
     return KeyResponse(
         k_doc=base64.b64encode(k_doc).decode('utf-8'),
         permissions=permissions,
-        watermark_data=watermark_data
+        watermark_data=watermark_data,
+        bound_device_id=device.device_id
     )
+
+
+@router.post("/get-batch", response_model=BatchKeyResponse)
+def get_keys_batch(
+    request: BatchKeyRequest,
+    current_user: User = Depends(get_current_user),
+    db: Session = Depends(get_db)
+):
+    """
+    Get decryption keys for several documents in one request.
+    Each doc_id goes through the same checks as /keys/get; results are
+    returned in request order with a per-document status.
+    """
+    if len(request.doc_ids) > MAX_BATCH_SIZE:
+        raise HTTPException(
+            status_code=status.HTTP_400_BAD_REQUEST,
+            detail=f"At most {MAX_BATCH_SIZE} documents per batch",
+        )
+
+    results = []
+    for doc_id in request.doc_ids:
+        key_request = KeyRequest(
+            doc_id=doc_id,
+            device_id=request.device_id,
+            device_name=request.device_name,
+        )
+        try:
+            key = get_key(key_request, current_user=current_user, db=db)
+            results.append(BatchKeyResult(doc_id=doc_id, status=status.HTTP_200_OK, key=key))
+        except HTTPException as e:
+            results.append(BatchKeyResult(doc_id=doc_id, status=e.status_code, detail=e.detail))
+
+    return BatchKeyResponse(results=results)
+
+
+@router.get("/org/{org_id}", response_model=OrgKeysResponse)
+def get_org_keys(
+    org_id: str,
+    current_user: User = Depends(get_current_user),
+):
+    """
+    Get the currently trusted signing keys for an organization.
+    Viewers use this to accept a legitimate key rotation when a
+    document's key no longer matches their pinned key.
+    """
+    if not re.fullmatch(r"[A-Za-z0-9_-]+", org_id):
+        raise HTTPException(
+            status_code=status.HTTP_400_BAD_REQUEST,
+            detail="Invalid organization id",
+        )
+
+    try:
+        public_pem = get_org_public_key(org_id)
+    except FileNotFoundError:
+        raise HTTPException(
+            status_code=status.HTTP_404_NOT_FOUND,
+            detail="Organization not found",
+        )
+
+    if isinstance(public_pem, bytes):
+        public_pem = public_pem.decode("utf-8")
+
+    return OrgKeysResponse(org_id=org_id, keys=[public_pem])
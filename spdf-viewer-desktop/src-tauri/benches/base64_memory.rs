@@ -0,0 +1,109 @@
+// Peak memory of encoding a decrypted PDF for the webview
+//
+// Compares `encode_base64_consuming` with encoding the whole plaintext in one
+// call and then dropping it, on a 50 MB buffer. A counting allocator tracks
+// the high-water mark of live heap bytes; pages the allocator maps lazily
+// still count in full, so this is an upper bound on resident memory. Fails
+// unless the chunked encode peaks lower.
+//
+//     cargo bench --bench base64_memory
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use base64::{engine::general_purpose, Engine as _};
+use spdf_viewer_desktop_lib::base64_stream::encode_base64_consuming;
+
+const PDF_LEN: usize = 50 * 1024 * 1024;
+
+struct CountingAlloc;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        LIVE.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            LIVE.fetch_sub(layout.size(), Ordering::SeqCst);
+            record_alloc(new_size);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+fn record_alloc(size: usize) {
+    let live = LIVE.fetch_add(size, Ordering::SeqCst) + size;
+    PEAK.fetch_max(live, Ordering::SeqCst);
+}
+
+/// Peak live heap bytes while `encode` turns a fresh plaintext into base64,
+/// counted from just before the plaintext is handed over, and the time taken
+fn measure(encode: fn(Vec<u8>) -> String) -> (usize, f64) {
+    let plaintext: Vec<u8> = (0..PDF_LEN).map(|i| (i * 31 % 251) as u8).collect();
+    let baseline = LIVE.load(Ordering::SeqCst);
+    PEAK.store(baseline, Ordering::SeqCst);
+
+    let started = Instant::now();
+    let encoded = encode(plaintext);
+    let elapsed = started.elapsed().as_secs_f64();
+    let peak = PEAK.load(Ordering::SeqCst);
+    assert_eq!(encoded.len(), PDF_LEN.div_ceil(3) * 4);
+    (peak, elapsed)
+}
+
+fn encode_one_shot(plaintext: Vec<u8>) -> String {
+    general_purpose::STANDARD.encode(&plaintext)
+}
+
+fn main() {
+    let mib = |bytes: usize| bytes as f64 / (1024.0 * 1024.0);
+    println!("base64 of a {:.0} MiB plaintext", mib(PDF_LEN));
+    let [one_shot, chunked] = [
+        ("one-shot", encode_one_shot as fn(Vec<u8>) -> String),
+        ("chunked", encode_base64_consuming),
+    ]
+    .map(|(name, encode)| {
+        let (peak, elapsed) = measure(encode);
+        println!(
+            "{:>9}: peak {:7.1} MiB ({:.2}x plaintext), {:6.1} ms",
+            name,
+            mib(peak),
+            peak as f64 / PDF_LEN as f64,
+            elapsed * 1000.0
+        );
+        peak
+    });
+
+    assert!(
+        chunked < one_shot,
+        "chunked encode peaked at {} bytes, one-shot at {}",
+        chunked,
+        one_shot
+    );
+}
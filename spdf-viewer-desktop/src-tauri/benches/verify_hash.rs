@@ -0,0 +1,60 @@
+// Signature verification time by `hash_alg`
+//
+// Signs the same 64 MB document with each digest this build supports and
+// times `verify_signature` on it. BLAKE3 needs the `blake3` feature:
+//
+//     cargo bench --bench verify_hash --features blake3
+
+use std::time::Instant;
+
+use ed25519_dalek::SigningKey;
+use spdf_viewer_desktop_lib::spdf_parser::{
+    SpdfFile, SpdfHeader, WriteOptions, SUPPORTED_HASH_ALGS, WRAPPED_KEY_LENGTH,
+};
+use spdf_viewer_desktop_lib::verify::{ed25519_public_key_pem, verify_signature};
+
+const PDF_LEN: usize = 64 * 1024 * 1024;
+const RUNS: u32 = 5;
+
+fn signed_file(hash_alg: &str, plaintext: &[u8], signing_key: &SigningKey) -> SpdfFile {
+    let public_key = ed25519_public_key_pem(signing_key.verifying_key().as_bytes());
+    let header: SpdfHeader = serde_json::from_value(serde_json::json!({
+        "spdf_version": "1.0",
+        "doc_id": "DOC-BENCH",
+        "org_id": "bench_org",
+        "server_url": "https://spdf.example.com",
+        "created_at": "2025-01-01T00:00:00Z",
+        "public_key": public_key,
+        "permissions": { "allow_print": false, "allow_copy": false, "max_devices": 1 },
+        "hash_alg": hash_alg,
+    }))
+    .expect("bench header");
+    SpdfFile::create(&WriteOptions {
+        header: &header,
+        flags: 0,
+        plaintext,
+        doc_key: &[0x42; 32],
+        wrapped_key: &[0u8; WRAPPED_KEY_LENGTH],
+        signing_key,
+    })
+    .expect("bench file")
+}
+
+fn main() {
+    let plaintext: Vec<u8> = (0..PDF_LEN).map(|i| (i * 31 % 251) as u8).collect();
+    let signing_key = SigningKey::from_bytes(&[0x07; 32]);
+
+    println!("verify_signature on a {} MiB document, best of {}", PDF_LEN >> 20, RUNS);
+    for &hash_alg in SUPPORTED_HASH_ALGS {
+        let spdf = signed_file(hash_alg, &plaintext, &signing_key);
+        let best = (0..RUNS)
+            .map(|_| {
+                let started = Instant::now();
+                verify_signature(&spdf).expect("bench file verifies");
+                started.elapsed().as_secs_f64()
+            })
+            .fold(f64::INFINITY, f64::min);
+        let throughput = PDF_LEN as f64 / (1024.0 * 1024.0) / best;
+        println!("{:>8}: {:7.1} ms ({:.0} MiB/s)", hash_alg, best * 1000.0, throughput);
+    }
+}
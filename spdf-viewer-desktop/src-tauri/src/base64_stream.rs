@@ -0,0 +1,69 @@
+// Base64 Stream Module - Encoding large plaintexts for the webview
+//
+// The decrypted PDF crosses the IPC boundary as base64.
+// `encode_base64_consuming` turns the plaintext buffer into its own encoding:
+// the buffer is grown once to the encoded length and filled from the end in
+// fixed-size chunks, each landing on plaintext that has already been
+// encoded. The peak is the base64 plus one chunk, where a one-shot encode
+// holds the plaintext and the base64 together; `benches/base64_memory.rs`
+// measures the two.
+
+use base64::{engine::general_purpose, Engine as _};
+use zeroize::Zeroizing;
+
+/// Plaintext bytes encoded per step; a multiple of 3 so chunks need no padding
+pub const BASE64_CHUNK_LEN: usize = 3 * 256 * 1024;
+
+/// Length of the padded standard base64 encoding of `len` bytes
+pub fn base64_encoded_len(len: usize) -> usize {
+    len.div_ceil(3) * 4
+}
+
+/// Standard base64 of `data`, encoded in place chunk by chunk
+///
+/// The plaintext is consumed: its buffer is grown once and every plaintext
+/// byte is overwritten by the encoding. Each chunk is first copied to a
+/// scratch buffer, wiped on drop. Growing may move the buffer like any `Vec`
+/// reallocation; callers that cannot allow that reserve
+/// `base64_encoded_len` bytes up front.
+pub fn encode_base64_consuming(mut data: Vec<u8>) -> String {
+    let len = data.len();
+    let encoded_len = base64_encoded_len(len);
+    data.reserve_exact(encoded_len - len);
+    data.resize(encoded_len, 0);
+
+    let mut scratch = Zeroizing::new(vec![0u8; len.min(BASE64_CHUNK_LEN)]);
+    let mut end = len;
+    while end > 0 {
+        // Chunk boundaries are aligned from the start of the data so that
+        // only the final chunk can carry padding. A chunk's output starts no
+        // earlier than its input, so it never reaches plaintext still to encode.
+        let start = (end - 1) / BASE64_CHUNK_LEN * BASE64_CHUNK_LEN;
+        let input = &mut scratch[..end - start];
+        input.copy_from_slice(&data[start..end]);
+        general_purpose::STANDARD
+            .encode_slice(&*input, &mut data[start / 3 * 4..])
+            .expect("buffer sized for the full encoding");
+        end = start;
+    }
+
+    String::from_utf8(data).expect("base64 output is ASCII")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_one_shot_encoding() {
+        let lengths = [0, 1, 2, 3, BASE64_CHUNK_LEN - 1, BASE64_CHUNK_LEN, 2 * BASE64_CHUNK_LEN + 2];
+        for len in lengths {
+            let data: Vec<u8> = (0..len).map(|i| (i * 31 % 251) as u8).collect();
+            let expected = general_purpose::STANDARD.encode(&data);
+
+            let encoded = encode_base64_consuming(data);
+            assert_eq!(encoded.len(), base64_encoded_len(len));
+            assert_eq!(encoded, expected, "length {}", len);
+        }
+    }
+}
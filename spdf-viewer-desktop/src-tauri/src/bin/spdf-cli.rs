@@ -0,0 +1,263 @@
+// spdf-cli - Inspect and verify .spdf files without the viewer
+//
+// A thin front end over the library for scripts and CI:
+//   spdf-cli info <file>      header summary
+//   spdf-cli verify <file>    signature check against the org's trusted key
+//   spdf-cli fingerprint      this device's hardware hash
+// `--json` prints one JSON object instead of text.
+//
+// `verify` trusts the same keys the viewer does (the OS key store in
+// `platform-keystore` builds, else `~/.spdf/keys` or `--keys-dir`), never
+// the key a file carries in its own header.
+//
+// Exit codes: 0 success, 1 signature did not verify, 2 the file or device
+// could not be read (clap also uses 2 for usage errors).
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+use spdf_viewer_desktop_lib::device_id::generate_device_hash;
+use spdf_viewer_desktop_lib::key_source::{verify_signature_against_trust_store, KeySource};
+use spdf_viewer_desktop_lib::spdf_parser::SpdfFile;
+use spdf_viewer_desktop_lib::verify::VerifyFailure;
+use spdf_viewer_desktop_lib::SpdfInfo;
+
+const EXIT_VERIFY_FAILED: u8 = 1;
+const EXIT_ERROR: u8 = 2;
+
+#[derive(Parser)]
+#[command(name = "spdf-cli", version, about = "Inspect and verify SPDF files")]
+struct Cli {
+    /// Print results as JSON
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the header summary of a file
+    Info { file: String },
+    /// Check a file's signature against its org's trusted key; exits 1 if
+    /// it does not verify
+    Verify {
+        file: String,
+        /// Directory of `{org_id}_public.pem` keys [default: ~/.spdf/keys]
+        #[arg(long)]
+        keys_dir: Option<PathBuf>,
+    },
+    /// Print this device's hardware hash (`v1:…`), the one offline grants
+    /// and device manifests are bound to. The key server sees a per-install
+    /// device ID instead, which only the viewer can derive.
+    Fingerprint,
+}
+
+#[derive(Serialize)]
+struct VerifyOutput {
+    file: String,
+    verified: bool,
+    failure: Option<VerifyFailure>,
+}
+
+#[derive(Serialize)]
+struct FingerprintOutput {
+    device_hash: String,
+}
+
+#[derive(Serialize)]
+struct ErrorOutput {
+    error: String,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(&cli.command, cli.json) {
+        Ok(code) => code,
+        Err(error) => {
+            if cli.json {
+                print_json(&ErrorOutput { error });
+            } else {
+                eprintln!("spdf-cli: {}", error);
+            }
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
+
+fn run(command: &Command, json: bool) -> Result<ExitCode, String> {
+    match command {
+        Command::Info { file } => {
+            let spdf = read(file)?;
+            let info = SpdfInfo::of(&spdf);
+            if json {
+                print_json(&info);
+            } else {
+                print_info(&info);
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Verify { file, keys_dir } => {
+            let spdf = read(file)?;
+            let keys_dir = match keys_dir {
+                Some(dir) => dir.clone(),
+                None => default_keys_dir()?,
+            };
+            let trust_store = KeySource::for_keys_dir(&keys_dir);
+            let failure = verify_signature_against_trust_store(&spdf, &trust_store).err();
+            let output = VerifyOutput {
+                file: file.clone(),
+                verified: failure.is_none(),
+                failure,
+            };
+            if json {
+                print_json(&output);
+            } else {
+                match &output.failure {
+                    None => println!("{}: signature OK", file),
+                    Some(failure) => println!("{}: {}", file, failure),
+                }
+            }
+            if output.verified {
+                Ok(ExitCode::SUCCESS)
+            } else {
+                Ok(ExitCode::from(EXIT_VERIFY_FAILED))
+            }
+        }
+        Command::Fingerprint => {
+            let device_hash = generate_device_hash().map_err(|e| e.to_string())?;
+            if json {
+                print_json(&FingerprintOutput { device_hash });
+            } else {
+                println!("{}", device_hash);
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
+
+/// `~/.spdf/keys`, where the viewer installs org public keys
+fn default_keys_dir() -> Result<PathBuf, String> {
+    dirs::home_dir()
+        .map(|home| home.join(".spdf").join("keys"))
+        .ok_or_else(|| "Could not determine home directory".to_string())
+}
+
+fn read(file: &str) -> Result<SpdfFile, String> {
+    SpdfFile::read(file).map_err(|e| format!("{}: {}", file, e))
+}
+
+fn print_info(info: &SpdfInfo) {
+    println!("doc_id:      {}", info.doc_id);
+    println!("title:       {}", info.title);
+    println!("org_id:      {}", info.org_id);
+    println!("server_url:  {}", info.server_url);
+    println!("created_at:  {}", info.created_at);
+    println!("print:       {}", yes_no(info.allow_print));
+    println!("copy:        {}", yes_no(info.allow_copy));
+    println!("max_devices: {}", info.max_devices);
+    println!("device_bind: {}", yes_no(info.requires_device_binding));
+    if let Some(author) = &info.metadata.author {
+        println!("author:      {}", author);
+    }
+    if let Some(page_count) = info.metadata.page_count {
+        println!("pages:       {}", page_count);
+    }
+    if let Some(language) = &info.metadata.language {
+        println!("language:    {}", language);
+    }
+    for warning in &info.display_warnings {
+        println!("warning:     {}", warning);
+    }
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+fn print_json<T: Serialize>(value: &T) {
+    match serde_json::to_string_pretty(value) {
+        Ok(json) => println!("{}", json),
+        Err(e) => eprintln!("spdf-cli: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_arguments() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::try_parse_from(["spdf-cli", "verify", "a.spdf", "--json"]).unwrap();
+        assert!(cli.json);
+        let is_verify_a = |command: &Command| {
+            matches!(command, Command::Verify { file, keys_dir: None } if file == "a.spdf")
+        };
+        assert!(is_verify_a(&cli.command));
+        assert!(Cli::try_parse_from(["spdf-cli", "info"]).is_err());
+    }
+
+    #[test]
+    fn test_unreadable_file_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = Command::Verify {
+            file: dir.path().join("missing.spdf").display().to_string(),
+            keys_dir: None,
+        };
+        assert!(run(&command, true).is_err());
+    }
+
+    #[cfg(not(feature = "platform-keystore"))]
+    #[test]
+    fn test_verify_uses_trusted_key_not_header_key() {
+        use ed25519_dalek::SigningKey;
+        use spdf_viewer_desktop_lib::spdf_parser::{SpdfHeader, WriteOptions, WRAPPED_KEY_LENGTH};
+        use spdf_viewer_desktop_lib::verify::ed25519_public_key_pem;
+
+        let signing_key = SigningKey::from_bytes(&[0x07; 32]);
+        let public_key = ed25519_public_key_pem(signing_key.verifying_key().as_bytes());
+        let header: SpdfHeader = serde_json::from_value(serde_json::json!({
+            "spdf_version": "1.0",
+            "doc_id": "DOC-CLI",
+            "org_id": "cli_org",
+            "server_url": "https://spdf.example.com",
+            "created_at": "2025-01-01T00:00:00Z",
+            "public_key": public_key,
+            "permissions": { "allow_print": false, "allow_copy": false, "max_devices": 1 },
+        }))
+        .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let keys_dir = dir.path().join("keys");
+        std::fs::create_dir_all(&keys_dir).unwrap();
+        let file = dir.path().join("doc.spdf");
+        let options = WriteOptions {
+            header: &header,
+            flags: 0,
+            plaintext: b"%PDF-1.7 cli",
+            doc_key: &[0x42; 32],
+            wrapped_key: &[0u8; WRAPPED_KEY_LENGTH],
+            signing_key: &signing_key,
+        };
+        SpdfFile::write(file.to_str().unwrap(), &options).unwrap();
+        let verify = |keys_dir: &PathBuf| Command::Verify {
+            file: file.display().to_string(),
+            keys_dir: Some(keys_dir.clone()),
+        };
+
+        // Signed with the header's key, but no key is trusted for the org
+        assert_eq!(run(&verify(&keys_dir), true), Ok(ExitCode::from(EXIT_VERIFY_FAILED)));
+
+        std::fs::write(keys_dir.join("cli_org_public.pem"), &public_key).unwrap();
+        assert_eq!(run(&verify(&keys_dir), true), Ok(ExitCode::SUCCESS));
+    }
+}
@@ -0,0 +1,423 @@
+// Capability Module - "Can this build open the file?" pre-flight
+//
+// Collects every reason a file cannot be opened (format version, header
+// version, cipher, signature algorithm and hash, key wrapping, minimum viewer
+// version) instead of failing on the first one, so the UI can tell the
+// user what to upgrade.
+// Only the prefix and header are read.
+//
+// Once a file is opened, `document_capabilities` tells the UI what the
+// document permits and which watermark to render, `action_requirement`
+// explains which flag or permission decides a single user action, and
+// `crypto_profile` summarizes the primitives it uses for audits.
+
+use serde::{Deserialize, Serialize};
+
+use crate::policy::{effective_watermark, EffectiveWatermark, SecurityPolicy, WatermarkContext};
+use crate::spdf_parser::{
+    read_raw_header, wrapped_key_length, SpdfFile, SpdfHeader, NONCE_LENGTH, SUPPORTED_ENC_ALGS,
+    SUPPORTED_HASH_ALGS, SUPPORTED_SIG_ALGS, SUPPORTED_VERSIONS, TAG_LENGTH, VERSION,
+    VERSION_SEGMENTED,
+};
+
+/// Release of this viewer, compared against `min_viewer_version`
+pub const VIEWER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Whether the file can be opened, and if not, why
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpenCapability {
+    pub supported: bool,
+    pub reasons: Vec<String>,
+}
+
+/// What an opened document allows, as the UI should enforce it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DocumentCapabilities {
+    pub allow_print: bool,
+    pub allow_copy: bool,
+    pub allow_offline: bool,
+    /// `None` when the document does not require a watermark
+    pub watermark: Option<EffectiveWatermark>,
+}
+
+/// A user action gated by the document's permissions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UserAction {
+    Print,
+    Copy,
+    Offline,
+}
+
+impl UserAction {
+    /// Parse an action name as the UI sends it
+    pub fn parse(action: &str) -> Result<Self, String> {
+        match action {
+            "print" => Ok(UserAction::Print),
+            "copy" => Ok(UserAction::Copy),
+            "offline" => Ok(UserAction::Offline),
+            other => Err(format!(
+                "Unknown action '{}'; expected print, copy or offline",
+                other
+            )),
+        }
+    }
+}
+
+/// Whether a document permits an action, and what decides it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActionRequirement {
+    pub action: UserAction,
+    pub allowed: bool,
+    /// Header flag or permission that decides the action, e.g.
+    /// `FLAG_PRINT_ALLOWED` or `permissions.offline_days`
+    pub controlled_by: String,
+    /// One sentence for the UI, e.g. "Printing is disabled by this document"
+    pub explanation: String,
+}
+
+/// Cryptographic primitives a file uses, as named in audit reports
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CryptoProfile {
+    pub format_version: u8,
+    pub aead: String,
+    pub nonce_bits: u32,
+    pub tag_bits: u32,
+    pub key_wrap: String,
+    pub signature_alg: String,
+    /// Digest the signature is computed over
+    pub hash_alg: String,
+    /// Hash under the HKDF that derives the key protecting cached doc keys
+    pub kdf: String,
+    /// Content is split into independently authenticated segments (v2)
+    pub segmented: bool,
+}
+
+/// Capabilities of a parsed document under the local policy
+pub fn document_capabilities(
+    spdf: &SpdfFile,
+    context: &WatermarkContext,
+    policy: &SecurityPolicy,
+) -> DocumentCapabilities {
+    DocumentCapabilities {
+        allow_print: spdf.allows_print(),
+        allow_copy: spdf.allows_copy(),
+        allow_offline: spdf.allows_offline(),
+        watermark: effective_watermark(spdf, context, policy),
+    }
+}
+
+/// What decides whether `spdf` permits `action`
+///
+/// Offline viewing needs `FLAG_OFFLINE_ALLOWED`, no
+/// `FLAG_NO_OFFLINE_CACHE` and a non-zero `offline_days`; the first one
+/// missing is reported.
+pub fn action_requirement(spdf: &SpdfFile, action: UserAction) -> ActionRequirement {
+    let by_flag = |allowed: bool, flag, what| {
+        let verdict = if allowed { "allowed" } else { "disabled" };
+        (allowed, flag, format!("{} is {} by this document", what, verdict))
+    };
+    let (allowed, controlled_by, explanation) = match action {
+        UserAction::Print => by_flag(spdf.allows_print(), "FLAG_PRINT_ALLOWED", "Printing"),
+        UserAction::Copy => by_flag(spdf.allows_copy(), "FLAG_COPY_ALLOWED", "Copying text"),
+        UserAction::Offline => offline_requirement(spdf),
+    };
+    ActionRequirement {
+        action,
+        allowed,
+        controlled_by: controlled_by.to_string(),
+        explanation,
+    }
+}
+
+fn offline_requirement(spdf: &SpdfFile) -> (bool, &'static str, String) {
+    let offline_days = spdf.header.permissions.offline_days;
+    if spdf.requires_fresh_key() {
+        (
+            false,
+            "FLAG_NO_OFFLINE_CACHE",
+            "This document must be unlocked online every time it is opened".to_string(),
+        )
+    } else if !spdf.allows_offline() {
+        (
+            false,
+            "FLAG_OFFLINE_ALLOWED",
+            "Offline viewing is disabled by this document".to_string(),
+        )
+    } else if offline_days == 0 {
+        (
+            false,
+            "permissions.offline_days",
+            "This document grants no offline viewing days".to_string(),
+        )
+    } else {
+        let explanation = format!(
+            "Offline viewing is allowed for {} day{} after opening online",
+            offline_days,
+            if offline_days == 1 { "" } else { "s" }
+        );
+        (true, "permissions.offline_days", explanation)
+    }
+}
+
+/// The primitives a parsed file was produced with
+pub fn crypto_profile(spdf: &SpdfFile) -> CryptoProfile {
+    CryptoProfile {
+        format_version: spdf.version,
+        aead: spdf.header.enc_alg().to_string(),
+        nonce_bits: (NONCE_LENGTH * 8) as u32,
+        tag_bits: (TAG_LENGTH * 8) as u32,
+        key_wrap: spdf.header.wrap_scheme().to_string(),
+        signature_alg: spdf.header.sig_alg().to_string(),
+        hash_alg: hash_display_name(spdf.header.hash_alg()),
+        kdf: "SHA-256".to_string(),
+        segmented: spdf.version == VERSION_SEGMENTED,
+    }
+}
+
+/// Audit-report name of a header `hash_alg`
+fn hash_display_name(hash_alg: &str) -> String {
+    match hash_alg {
+        "sha256" => "SHA-256".to_string(),
+        "blake3" => "BLAKE3".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Pre-flight check of a file on disk
+pub fn can_open(file_path: &str) -> Result<OpenCapability, String> {
+    let (version, header_json) = read_raw_header(file_path).map_err(|e| e.to_string())?;
+    Ok(open_capability(version, &header_json))
+}
+
+/// Check a format version byte and raw header JSON against this build
+pub fn open_capability(version: u8, header_json: &[u8]) -> OpenCapability {
+    let mut reasons = Vec::new();
+
+    if version != VERSION && version != VERSION_SEGMENTED {
+        reasons.push(format!("unsupported format version {}", version));
+    }
+
+    match serde_json::from_slice::<SpdfHeader>(header_json) {
+        Ok(header) => {
+            if !SUPPORTED_VERSIONS.contains(&header.spdf_version.as_str()) {
+                reasons.push(format!("unsupported spdf_version '{}'", header.spdf_version));
+            }
+            if !SUPPORTED_ENC_ALGS.contains(&header.enc_alg()) {
+                reasons.push(format!("unsupported cipher '{}'", header.enc_alg()));
+            }
+            if !SUPPORTED_SIG_ALGS.contains(&header.sig_alg()) {
+                reasons.push(format!("unsupported signature algorithm '{}'", header.sig_alg()));
+            }
+            if !SUPPORTED_HASH_ALGS.contains(&header.hash_alg()) {
+                reasons.push(format!("unsupported signature hash '{}'", header.hash_alg()));
+            }
+            if wrapped_key_length(header.wrap_scheme()).is_err() {
+                reasons.push(format!("unsupported key wrapping '{}'", header.wrap_scheme()));
+            }
+            if let Some(required) = &header.min_viewer_version {
+                match version_at_least(VIEWER_VERSION, required) {
+                    Some(true) => {}
+                    Some(false) => reasons.push(format!(
+                        "requires viewer {} or newer (this is {})",
+                        required, VIEWER_VERSION
+                    )),
+                    None => reasons.push(format!("invalid min_viewer_version '{}'", required)),
+                }
+            }
+        }
+        Err(e) => reasons.push(format!("unreadable header: {}", e)),
+    }
+
+    OpenCapability {
+        supported: reasons.is_empty(),
+        reasons,
+    }
+}
+
+/// Compare dotted numeric versions; missing components count as 0
+///
+/// Returns `None` if either version is not dotted numbers.
+fn version_at_least(current: &str, required: &str) -> Option<bool> {
+    let parse = |v: &str| {
+        v.split('.')
+            .map(|part| part.parse::<u64>().ok())
+            .collect::<Option<Vec<u64>>>()
+    };
+    let mut current = parse(current)?;
+    let mut required = parse(required)?;
+
+    let len = current.len().max(required.len());
+    current.resize(len, 0);
+    required.resize(len, 0);
+    Some(current >= required)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sample_header;
+
+    fn capability_with(field: &str, value: &str) -> OpenCapability {
+        let mut header = sample_header();
+        header[field] = serde_json::json!(value);
+        open_capability(VERSION, &serde_json::to_vec(&header).unwrap())
+    }
+
+    #[test]
+    fn test_default_file_supported() {
+        let header = serde_json::to_vec(&sample_header()).unwrap();
+        let capability = open_capability(VERSION, &header);
+        assert!(capability.supported);
+        assert!(capability.reasons.is_empty());
+    }
+
+    #[test]
+    fn test_unsupported_cipher() {
+        assert!(capability_with("enc_alg", "ChaCha20-Poly1305").supported);
+        let capability = capability_with("enc_alg", "AES-128-CBC");
+        assert!(!capability.supported);
+        assert_eq!(capability.reasons, ["unsupported cipher 'AES-128-CBC'"]);
+    }
+
+    #[test]
+    fn test_reasons_aggregated() {
+        let mut header = sample_header();
+        header["spdf_version"] = serde_json::json!("3.0");
+        header["enc_alg"] = serde_json::json!("AES-128-CBC");
+        header["sig_alg"] = serde_json::json!("ML-DSA-65");
+        header["min_viewer_version"] = serde_json::json!("99.0.0");
+
+        let capability = open_capability(0x07, &serde_json::to_vec(&header).unwrap());
+        assert!(!capability.supported);
+        assert_eq!(capability.reasons.len(), 5);
+    }
+
+    #[test]
+    fn test_min_viewer_version() {
+        assert!(capability_with("min_viewer_version", "0.9").supported);
+        assert!(capability_with("min_viewer_version", VIEWER_VERSION).supported);
+        assert!(!capability_with("min_viewer_version", "99.0.0").supported);
+        assert!(!capability_with("min_viewer_version", "next").supported);
+    }
+
+    #[test]
+    fn test_document_capabilities_empty_watermark() {
+        use crate::test_support::{build_spdf_with, DEFAULT_FLAGS};
+
+        let mut header = sample_header();
+        header["watermark"]["text"] = serde_json::json!("");
+        let spdf = SpdfFile::parse(&build_spdf_with(&header, DEFAULT_FLAGS, b"%PDF")).unwrap();
+        let context = WatermarkContext {
+            user_email: "alice@example.com".to_string(),
+            device_id: "DEVICE-1".to_string(),
+            ..WatermarkContext::default()
+        };
+
+        let capabilities = document_capabilities(&spdf, &context, &SecurityPolicy::default());
+        assert!(!capabilities.allow_print);
+        let watermark = capabilities.watermark.unwrap();
+        assert_eq!(watermark.text, "alice@example.com");
+        assert!(watermark.warning.is_some());
+    }
+
+    #[test]
+    fn test_crypto_profile() {
+        use crate::test_support::{build_segmented_spdf, build_spdf};
+
+        let spdf = SpdfFile::parse(&build_spdf(b"%PDF")).unwrap();
+        let expected = CryptoProfile {
+            format_version: VERSION,
+            aead: "AES-256-GCM".to_string(),
+            nonce_bits: 96,
+            tag_bits: 128,
+            key_wrap: "AES-KW".to_string(),
+            signature_alg: "Ed25519".to_string(),
+            hash_alg: "SHA-256".to_string(),
+            kdf: "SHA-256".to_string(),
+            segmented: false,
+        };
+        assert_eq!(crypto_profile(&spdf), expected);
+
+        let spdf = SpdfFile::parse(&build_segmented_spdf(&[b"%PDF", b"-1.7"])).unwrap();
+        let profile = crypto_profile(&spdf);
+        assert_eq!(profile.format_version, VERSION_SEGMENTED);
+        assert!(profile.segmented);
+        assert_eq!(profile.aead, expected.aead);
+        assert_eq!(profile.nonce_bits, 96);
+    }
+
+    fn file_with(flags: u16, offline_days: u32) -> SpdfFile {
+        use crate::test_support::build_spdf_with;
+        let mut header = sample_header();
+        header["permissions"]["offline_days"] = serde_json::json!(offline_days);
+        SpdfFile::parse(&build_spdf_with(&header, flags, b"%PDF")).unwrap()
+    }
+
+    #[test]
+    fn test_print_and_copy_requirements() {
+        use crate::spdf_parser::{FLAG_COPY_ALLOWED, FLAG_PRINT_ALLOWED};
+        use crate::test_support::DEFAULT_FLAGS;
+
+        let locked = file_with(DEFAULT_FLAGS, 0);
+        let print = action_requirement(&locked, UserAction::Print);
+        assert!(!print.allowed);
+        assert_eq!(print.controlled_by, "FLAG_PRINT_ALLOWED");
+        assert_eq!(print.explanation, "Printing is disabled by this document");
+        let copy = action_requirement(&locked, UserAction::Copy);
+        assert!(!copy.allowed);
+        assert_eq!(copy.controlled_by, "FLAG_COPY_ALLOWED");
+
+        let open = file_with(DEFAULT_FLAGS | FLAG_PRINT_ALLOWED | FLAG_COPY_ALLOWED, 0);
+        assert!(action_requirement(&open, UserAction::Print).allowed);
+        let copy = action_requirement(&open, UserAction::Copy);
+        assert!(copy.allowed);
+        assert_eq!(copy.explanation, "Copying text is allowed by this document");
+    }
+
+    #[test]
+    fn test_offline_requirement() {
+        use crate::spdf_parser::{FLAG_NO_OFFLINE_CACHE, FLAG_OFFLINE_ALLOWED};
+        use crate::test_support::DEFAULT_FLAGS;
+        let offline = |flags, offline_days| {
+            action_requirement(&file_with(flags, offline_days), UserAction::Offline)
+        };
+
+        let allowed = offline(DEFAULT_FLAGS | FLAG_OFFLINE_ALLOWED, 7);
+        assert!(allowed.allowed);
+        assert_eq!(allowed.controlled_by, "permissions.offline_days");
+        assert_eq!(
+            allowed.explanation,
+            "Offline viewing is allowed for 7 days after opening online"
+        );
+
+        let denied = offline(DEFAULT_FLAGS, 7);
+        assert!(!denied.allowed);
+        assert_eq!(denied.controlled_by, "FLAG_OFFLINE_ALLOWED");
+        let no_days = offline(DEFAULT_FLAGS | FLAG_OFFLINE_ALLOWED, 0);
+        assert!(!no_days.allowed);
+        assert_eq!(no_days.controlled_by, "permissions.offline_days");
+        // Forbidding the key cache overrides the offline flag
+        let fresh = offline(DEFAULT_FLAGS | FLAG_OFFLINE_ALLOWED | FLAG_NO_OFFLINE_CACHE, 7);
+        assert!(!fresh.allowed);
+        assert_eq!(fresh.controlled_by, "FLAG_NO_OFFLINE_CACHE");
+    }
+
+    #[test]
+    fn test_action_names() {
+        assert_eq!(UserAction::parse("print"), Ok(UserAction::Print));
+        assert_eq!(UserAction::parse("offline"), Ok(UserAction::Offline));
+        assert_eq!(
+            UserAction::parse("share"),
+            Err("Unknown action 'share'; expected print, copy or offline".to_string())
+        );
+    }
+
+    #[test]
+    fn test_version_at_least() {
+        assert_eq!(version_at_least("1.2.0", "1.2"), Some(true));
+        assert_eq!(version_at_least("1.10.0", "1.9.3"), Some(true));
+        assert_eq!(version_at_least("1.2.0", "1.2.1"), Some(false));
+        assert_eq!(version_at_least("1.2.0", "1.x"), None);
+    }
+}
@@ -0,0 +1,111 @@
+// Clock Skew Module - Client vs. server clock comparison
+//
+// Expiry and token validity are judged against the local clock, so a
+// client clock that is off by minutes makes documents expire early (or
+// late) and fresh tokens look expired. The server's HTTP `Date` header is
+// an independent reading; comparing the two tells the user when their
+// clock, not the document, is the problem.
+
+use serde::{Deserialize, Serialize};
+use time::format_description::well_known::Rfc2822;
+use time::{Duration, OffsetDateTime};
+
+/// Skew beyond which expiry decisions are no longer trustworthy
+pub const SKEW_WARNING_THRESHOLD: Duration = Duration::minutes(5);
+
+/// Outcome of comparing the local clock with a server's
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkewReport {
+    #[serde(with = "time::serde::rfc3339")]
+    pub server_time: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub local_time: OffsetDateTime,
+    /// Local minus server; positive means the local clock is ahead
+    pub skew_seconds: i64,
+    pub threshold_seconds: i64,
+    /// Set once the skew exceeds the threshold
+    pub warning: Option<String>,
+}
+
+/// Parse an HTTP `Date` header (IMF-fixdate, e.g.
+/// "Sun, 06 Nov 1994 08:49:37 GMT")
+pub fn parse_http_date(value: &str) -> Result<OffsetDateTime, String> {
+    OffsetDateTime::parse(value.trim(), &Rfc2822)
+        .map_err(|e| format!("Invalid Date header '{}': {}", value, e))
+}
+
+/// Compare `local_time` with `server_time`
+///
+/// The `Date` header has one-second resolution, so skews under a second
+/// read as zero.
+pub fn skew_report(server_time: OffsetDateTime, local_time: OffsetDateTime) -> SkewReport {
+    let skew = local_time - server_time;
+    let warning = (skew.abs() > SKEW_WARNING_THRESHOLD).then(|| {
+        format!(
+            "Your clock is {} minutes {} the server's; expiry checks may be wrong",
+            skew.whole_minutes().abs(),
+            if skew.is_positive() { "ahead of" } else { "behind" }
+        )
+    });
+    SkewReport {
+        server_time,
+        local_time,
+        skew_seconds: skew.whole_seconds(),
+        threshold_seconds: SKEW_WARNING_THRESHOLD.whole_seconds(),
+        warning,
+    }
+}
+
+/// Read the `Date` header of the server's `/health` endpoint and compare
+/// it with the local clock
+pub async fn check_clock_skew(
+    client: &reqwest::Client,
+    server_url: &str,
+) -> Result<SkewReport, String> {
+    let health_url = format!("{}/health", server_url.trim_end_matches('/'));
+    let res = client
+        .get(&health_url)
+        .send()
+        .await
+        .map_err(|e| format!("Network error: {}", e))?;
+    let local_time = OffsetDateTime::now_utc();
+
+    let date = res
+        .headers()
+        .get(reqwest::header::DATE)
+        .ok_or("Server response has no Date header")?
+        .to_str()
+        .map_err(|e| format!("Invalid Date header: {}", e))?;
+    let server_time = parse_http_date(date)?;
+    Ok(skew_report(server_time, local_time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::format_description::well_known::Rfc3339;
+
+    #[test]
+    fn test_parse_date_header_and_skew() {
+        let server_time = parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+        assert_eq!(
+            server_time,
+            OffsetDateTime::parse("1994-11-06T08:49:37Z", &Rfc3339).unwrap()
+        );
+
+        // Within the threshold: no warning
+        let report = skew_report(server_time, server_time + Duration::seconds(90));
+        assert_eq!(report.skew_seconds, 90);
+        assert_eq!(report.warning, None);
+
+        // Local clock 10 minutes slow
+        let report = skew_report(server_time, server_time - Duration::minutes(10));
+        assert_eq!(report.skew_seconds, -600);
+        assert_eq!(
+            report.warning.as_deref(),
+            Some("Your clock is 10 minutes behind the server's; expiry checks may be wrong")
+        );
+
+        assert!(parse_http_date("yesterday").is_err());
+    }
+}
@@ -1,13 +1,161 @@
 // Decrypt Module - SPDF content decryption
 //
-// This module provides AES-256-GCM decryption for SPDF file content.
+// This module provides decryption of SPDF file content with the cipher the
+// header's `enc_alg` names (AES-256-GCM unless it says ChaCha20-Poly1305),
+// and AES-KW unwrapping of the document key.
 
+use aes::cipher::{BlockEncrypt, KeyIvInit, StreamCipher};
+use aes::Aes256;
 use aes_gcm::{
-    aead::{Aead, KeyInit},
-    Aes256Gcm, Nonce,
+    aead::{Aead, AeadInPlace, KeyInit},
+    Aes256Gcm, Nonce, Tag,
 };
+use aes_kw::KekAes256;
+use chacha20::cipher::StreamCipherSeek;
+use chacha20::ChaCha20;
+use chacha20poly1305::ChaCha20Poly1305;
+use flate2::read::ZlibDecoder;
+use ghash::universal_hash::UniversalHash;
+use ghash::GHash;
+use poly1305::Poly1305;
 
-use crate::spdf_parser::{SpdfFile, SpdfError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+use subtle::ConstantTimeEq;
+use zeroize::Zeroizing;
+
+use crate::pdf::pdf_page_count;
+use crate::spdf_parser::{
+    SpdfFile, SpdfError, CHACHA20_POLY1305_ENC_ALG, DEFAULT_ENC_ALG, DEFAULT_WRAP_SCHEME,
+    NONCE_LENGTH, TAG_LENGTH, WRAPPED_KEY_LENGTH,
+};
+
+/// Largest plaintext one AES-GCM (key, nonce) pair may protect:
+/// 2^39 - 256 bits (NIST SP 800-38D), i.e. 2^36 - 32 bytes (~64 GiB)
+pub const GCM_MAX_PLAINTEXT: u64 = (1 << 36) - 32;
+
+/// Error message for a file with nothing to decrypt
+pub const NO_CONTENT_MESSAGE: &str = "document has no content";
+
+/// Reject a file whose ciphertext is empty
+///
+/// Such a file can carry a perfectly valid signature, and GCM happily
+/// "decrypts" it to nothing. It is a format error, not a decryption
+/// failure, so the key is never blamed for it.
+pub fn check_has_content(spdf: &SpdfFile) -> Result<(), SpdfError> {
+    if !spdf.has_content() {
+        return Err(SpdfError::FormatError(NO_CONTENT_MESSAGE.to_string()));
+    }
+    Ok(())
+}
+
+/// Reject a ciphertext (tag excluded) whose plaintext would exceed
+/// `GCM_MAX_PLAINTEXT`; beyond it the GCM counter wraps and confidentiality
+/// and integrity no longer hold
+pub fn check_gcm_length(ciphertext_len: u64) -> Result<(), SpdfError> {
+    if ciphertext_len > GCM_MAX_PLAINTEXT {
+        return Err(SpdfError::DecryptionError(
+            "ciphertext exceeds AES-GCM safe limit".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// The content AEAD a header's `enc_alg` names, keyed with the document key
+///
+/// Both ciphers take a 12-byte nonce and produce a 16-byte tag, so the file
+/// layout is the same whichever one encrypted it.
+pub enum ContentCipher {
+    /// Boxed: the expanded AES key schedule dwarfs a ChaCha20 key
+    Aes256Gcm(Box<Aes256Gcm>),
+    ChaCha20Poly1305(ChaCha20Poly1305),
+}
+
+impl ContentCipher {
+    /// The cipher for `enc_alg`; an identifier this build does not
+    /// implement is a `FormatError`
+    pub fn new(enc_alg: &str, doc_key: &[u8; 32]) -> Result<Self, SpdfError> {
+        match enc_alg {
+            DEFAULT_ENC_ALG => Ok(ContentCipher::Aes256Gcm(Box::new(Aes256Gcm::new(
+                doc_key.into(),
+            )))),
+            CHACHA20_POLY1305_ENC_ALG => Ok(ContentCipher::ChaCha20Poly1305(
+                ChaCha20Poly1305::new(doc_key.into()),
+            )),
+            other => Err(SpdfError::FormatError(format!(
+                "unsupported enc_alg '{}'",
+                other
+            ))),
+        }
+    }
+
+    /// Reject a ciphertext (tag excluded) too long for one (key, nonce)
+    /// pair; only AES-GCM's limit is within reach of a document
+    pub fn check_length(&self, ciphertext_len: u64) -> Result<(), SpdfError> {
+        match self {
+            ContentCipher::Aes256Gcm(_) => check_gcm_length(ciphertext_len),
+            ContentCipher::ChaCha20Poly1305(_) => Ok(()),
+        }
+    }
+
+    /// Encrypt `plaintext`, returning the ciphertext with the tag appended
+    pub fn encrypt(
+        &self,
+        nonce: &[u8; NONCE_LENGTH],
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>, aes_gcm::Error> {
+        let nonce = Nonce::from_slice(nonce);
+        match self {
+            ContentCipher::Aes256Gcm(cipher) => cipher.encrypt(nonce, plaintext),
+            ContentCipher::ChaCha20Poly1305(cipher) => cipher.encrypt(nonce, plaintext),
+        }
+    }
+
+    /// Decrypt a ciphertext with the tag appended
+    pub fn decrypt(
+        &self,
+        nonce: &[u8; NONCE_LENGTH],
+        ciphertext_with_tag: &[u8],
+    ) -> Result<Vec<u8>, aes_gcm::Error> {
+        let nonce = Nonce::from_slice(nonce);
+        match self {
+            ContentCipher::Aes256Gcm(cipher) => cipher.decrypt(nonce, ciphertext_with_tag),
+            ContentCipher::ChaCha20Poly1305(cipher) => cipher.decrypt(nonce, ciphertext_with_tag),
+        }
+    }
+
+    /// Decrypt `buffer` in place against a separate tag
+    fn decrypt_in_place_detached(
+        &self,
+        nonce: &[u8; NONCE_LENGTH],
+        buffer: &mut [u8],
+        tag: &[u8],
+    ) -> Result<(), aes_gcm::Error> {
+        let (nonce, tag) = (Nonce::from_slice(nonce), Tag::from_slice(tag));
+        match self {
+            ContentCipher::Aes256Gcm(cipher) => {
+                cipher.decrypt_in_place_detached(nonce, b"", buffer, tag)
+            }
+            ContentCipher::ChaCha20Poly1305(cipher) => {
+                cipher.decrypt_in_place_detached(nonce, b"", buffer, tag)
+            }
+        }
+    }
+}
+
+/// Best guess at why an authenticated decryption failed
+///
+/// AES-GCM reports a wrong key and a modified ciphertext identically, so
+/// this is a heuristic based on the key material alone, not a proof.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DecryptFailureKind {
+    LikelyWrongKey,
+    LikelyCorruptOrTampered,
+}
+
+/// A document key, wiped from memory when dropped
+pub type DocKey = Zeroizing<[u8; 32]>;
 
 /// Decrypt SPDF content using the document key
 ///
@@ -16,8 +164,95 @@ use crate::spdf_parser::{SpdfFile, SpdfError};
 /// * `doc_key` - 32-byte AES-256 key
 ///
 /// # Returns
-/// Decrypted PDF bytes
+/// Decrypted PDF bytes, after checking them against the signed
+/// `expected_pages` if the header has one. The plaintext is not wiped on
+/// drop; callers keep it only as long as it takes to hand it on, inside
+/// `Zeroizing` or through `encode_base64_consuming`.
 pub fn decrypt_content(spdf: &SpdfFile, doc_key: &[u8; 32]) -> Result<Vec<u8>, SpdfError> {
+    let plaintext = inflate_content(spdf, decrypt_authenticated(spdf, doc_key)?)?;
+    check_expected_pages(spdf, &plaintext)?;
+    Ok(plaintext)
+}
+
+/// Inflate authenticated plaintext if the file is `FLAG_COMPRESSED`;
+/// otherwise hand it back unchanged
+///
+/// Inflation stops one byte past the signed `content_length`, so a
+/// decompression bomb costs no more memory than the header admits to.
+/// Content that does not inflate to exactly that length, or not to a PDF,
+/// is a `FormatError`: the tag already verified, so the key is not to blame.
+pub fn inflate_content(spdf: &SpdfFile, plaintext: Vec<u8>) -> Result<Vec<u8>, SpdfError> {
+    if !spdf.is_compressed() {
+        return Ok(plaintext);
+    }
+    let compressed = Zeroizing::new(plaintext);
+    let Some(content_length) = spdf.header.content_length else {
+        return Err(SpdfError::FormatError(
+            "compressed content has no content_length in the header".to_string(),
+        ));
+    };
+    let mut inflated = Zeroizing::new(Vec::new());
+    ZlibDecoder::new(&compressed[..])
+        .take(content_length.saturating_add(1))
+        .read_to_end(&mut inflated)
+        .map_err(|e| {
+            SpdfError::FormatError(format!("compressed content does not inflate: {}", e))
+        })?;
+    if inflated.len() as u64 > content_length {
+        return Err(SpdfError::FormatError(format!(
+            "compressed content inflates past its content_length of {}",
+            content_length
+        )));
+    }
+    if inflated.len() as u64 != content_length {
+        return Err(SpdfError::FormatError(format!(
+            "compressed content inflates to {} bytes, header says {}",
+            inflated.len(),
+            content_length
+        )));
+    }
+    if !validate_pdf_content(&inflated) {
+        return Err(SpdfError::FormatError(
+            "inflated content is not a PDF".to_string(),
+        ));
+    }
+    Ok(std::mem::take(&mut *inflated))
+}
+
+/// Compare the decrypted PDF's page count with the signed `expected_pages`
+///
+/// GCM authenticates whatever was encrypted, so a document truncated
+/// before encryption still decrypts cleanly; the signed page count
+/// catches that. A PDF whose pages cannot be counted, such as one whose
+/// page objects all sit in compressed object streams, is not checked.
+pub fn check_expected_pages(spdf: &SpdfFile, plaintext: &[u8]) -> Result<(), SpdfError> {
+    let Some(expected) = spdf.header.expected_pages else {
+        return Ok(());
+    };
+    match pdf_page_count(plaintext) {
+        Some(actual) if actual == expected => Ok(()),
+        Some(actual) => Err(SpdfError::DecryptionError(format!(
+            "page count mismatch: expected {} got {}",
+            expected, actual
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Authenticated decryption of the whole document, without content checks
+fn decrypt_authenticated(spdf: &SpdfFile, doc_key: &[u8; 32]) -> Result<Vec<u8>, SpdfError> {
+    check_has_content(spdf)?;
+    let cipher = ContentCipher::new(spdf.header.enc_alg(), doc_key)?;
+
+    if spdf.is_segmented() {
+        // Sized up front so growing never leaves a stray copy behind
+        let mut plaintext = Vec::with_capacity(spdf.ciphertext.len());
+        for index in 0..spdf.segment_count() {
+            plaintext.extend_from_slice(&Zeroizing::new(spdf.decrypt_segment(index, doc_key)?));
+        }
+        return Ok(plaintext);
+    }
+
     // Validate nonce length
     if spdf.nonce.len() != 12 {
         return Err(SpdfError::DecryptionError(format!(
@@ -34,14 +269,12 @@ pub fn decrypt_content(spdf: &SpdfFile, doc_key: &[u8; 32]) -> Result<Vec<u8>, S
         )));
     }
 
-    // Create cipher
-    let cipher = Aes256Gcm::new(doc_key.into());
-    
+    cipher.check_length(spdf.ciphertext.len() as u64)?;
+
     // Create nonce
-    let nonce_bytes: [u8; 12] = spdf.nonce[..]
+    let nonce: [u8; 12] = spdf.nonce[..]
         .try_into()
         .map_err(|_| SpdfError::DecryptionError("Invalid nonce".to_string()))?;
-    let nonce = Nonce::from_slice(&nonce_bytes);
 
     // Combine ciphertext and auth tag
     let mut ciphertext_with_tag = spdf.ciphertext.clone();
@@ -49,10 +282,413 @@ pub fn decrypt_content(spdf: &SpdfFile, doc_key: &[u8; 32]) -> Result<Vec<u8>, S
 
     // Decrypt
     cipher
-        .decrypt(nonce, ciphertext_with_tag.as_ref())
+        .decrypt(&nonce, ciphertext_with_tag.as_ref())
         .map_err(|e| SpdfError::DecryptionError(format!("Decryption failed: {}", e)))
 }
 
+/// Ciphertext decrypted per step by `decrypt_content_to`; a multiple of
+/// the AES block size, as GHASH needs for all but the last chunk
+const STREAM_CHUNK: usize = 64 * 1024;
+
+/// When `decrypt_content_to` hands plaintext to its sink
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Write each chunk as soon as it is decrypted, holding one chunk at a
+    /// time. Output may reach the sink before the auth tag is checked.
+    Streamed,
+    /// Decrypt and check everything first, then write; the sink only ever
+    /// sees authenticated plaintext, at the cost of holding all of it
+    Buffered,
+}
+
+/// Decrypt the content into `out` without a second full-size copy
+///
+/// With `OutputMode::Streamed`, a version 1 file's plaintext is written
+/// chunk by chunk BEFORE its auth tag is verified: a tampered file or a
+/// wrong key fails only after everything has been written. Segmented files
+/// are written one authenticated segment at a time, but a later segment can
+/// still fail. Either way, on `Err` whatever reached `out` must be
+/// discarded. Documents with `expected_pages` are always buffered, since
+/// the page count needs the whole PDF, as are compressed ones.
+///
+/// Returns the number of plaintext bytes written.
+pub fn decrypt_content_to<W: Write>(
+    spdf: &SpdfFile,
+    doc_key: &[u8; 32],
+    mut out: W,
+    mode: OutputMode,
+) -> Result<u64, SpdfError> {
+    if mode == OutputMode::Buffered
+        || spdf.header.expected_pages.is_some()
+        || spdf.is_compressed()
+    {
+        let plaintext = Zeroizing::new(decrypt_content(spdf, doc_key)?);
+        out.write_all(&plaintext)?;
+        return Ok(plaintext.len() as u64);
+    }
+    decrypt_chunks(spdf, doc_key, &mut out, &mut |_, _| {})
+}
+
+/// Like `decrypt_content`, calling `progress(processed, total)` with the
+/// ciphertext bytes handled so far as it decrypts chunk by chunk
+///
+/// `progress` is called at least once, with `processed == total`, when
+/// decryption succeeds, however small the file.
+pub fn decrypt_content_with_progress(
+    spdf: &SpdfFile,
+    doc_key: &[u8; 32],
+    mut progress: impl FnMut(u64, u64),
+) -> Result<Vec<u8>, SpdfError> {
+    // Wiped if the tag or page count turns out to be wrong
+    let mut plaintext = Zeroizing::new(Vec::with_capacity(spdf.ciphertext.len()));
+    decrypt_chunks(spdf, doc_key, &mut *plaintext, &mut progress)?;
+    let mut plaintext = Zeroizing::new(inflate_content(spdf, std::mem::take(&mut *plaintext))?);
+    check_expected_pages(spdf, &plaintext)?;
+    Ok(std::mem::take(&mut *plaintext))
+}
+
+/// Check that `doc_key` opens `spdf` without keeping any plaintext
+///
+/// Every auth tag is verified, one chunk (or segment) in memory at a time,
+/// and the plaintext is dropped as it is produced. A wrong key or tampered
+/// content is a `DecryptionError`. Decompression and `expected_pages` are
+/// not checked, so `Ok` does not promise `decrypt_content` will succeed.
+pub fn verify_key(spdf: &SpdfFile, doc_key: &[u8; 32]) -> Result<(), SpdfError> {
+    decrypt_chunks(spdf, doc_key, &mut std::io::sink(), &mut |_, _| {}).map(|_| ())
+}
+
+/// Decrypt into `out` a chunk (or segment) at a time, reporting progress
+/// after each; the version 1 tag is checked only after the last chunk
+fn decrypt_chunks<W: Write>(
+    spdf: &SpdfFile,
+    doc_key: &[u8; 32],
+    out: &mut W,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<u64, SpdfError> {
+    check_has_content(spdf)?;
+    let total = spdf.ciphertext.len() as u64;
+    let mut written = 0;
+    if spdf.is_segmented() {
+        let mut processed = 0;
+        for index in 0..spdf.segment_count() {
+            let segment = Zeroizing::new(spdf.decrypt_segment(index, doc_key)?);
+            out.write_all(&segment)?;
+            written += segment.len() as u64;
+            processed += spdf.header.segments[index].length;
+            progress(processed.min(total), total);
+        }
+        // The segment table need not cover the whole area
+        if processed < total {
+            progress(total, total);
+        }
+        return Ok(written);
+    }
+
+    let nonce: [u8; NONCE_LENGTH] = spdf.nonce[..].try_into().map_err(|_| {
+        SpdfError::DecryptionError(format!(
+            "Invalid nonce length: expected {}, got {}",
+            NONCE_LENGTH,
+            spdf.nonce.len()
+        ))
+    })?;
+    if spdf.auth_tag.len() != TAG_LENGTH {
+        return Err(SpdfError::DecryptionError(format!(
+            "Invalid auth tag length: expected {}, got {}",
+            TAG_LENGTH,
+            spdf.auth_tag.len()
+        )));
+    }
+    let cipher = ContentCipher::new(spdf.header.enc_alg(), doc_key)?;
+    cipher.check_length(spdf.ciphertext.len() as u64)?;
+    match cipher {
+        ContentCipher::Aes256Gcm(_) => {
+            stream_chunks(GcmStream::new(doc_key, &nonce), spdf, out, progress)
+        }
+        ContentCipher::ChaCha20Poly1305(_) => {
+            stream_chunks(ChaChaStream::new(doc_key, &nonce), spdf, out, progress)
+        }
+    }
+}
+
+/// An AEAD decrypted a chunk at a time, for `decrypt_chunks`
+trait ChunkedAead {
+    /// Decrypt `chunk` in place; every chunk but the last must be a whole
+    /// number of 16-byte blocks
+    fn decrypt(&mut self, chunk: &mut [u8]);
+
+    /// Check the tag over everything passed to `decrypt`
+    fn verify(self, tag: &[u8]) -> Result<(), SpdfError>;
+}
+
+/// Decrypt version 1 content into `out` with `stream`, checking the tag
+/// once the last chunk is written
+fn stream_chunks<A: ChunkedAead, W: Write>(
+    mut stream: A,
+    spdf: &SpdfFile,
+    out: &mut W,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<u64, SpdfError> {
+    let total = spdf.ciphertext.len() as u64;
+    let mut written = 0;
+    let mut buffer = Zeroizing::new(vec![0u8; STREAM_CHUNK]);
+    for ciphertext in spdf.ciphertext.chunks(STREAM_CHUNK) {
+        let chunk = &mut buffer[..ciphertext.len()];
+        chunk.copy_from_slice(ciphertext);
+        stream.decrypt(chunk);
+        out.write_all(chunk)?;
+        written += chunk.len() as u64;
+        if written < total {
+            progress(written, total);
+        }
+    }
+    out.flush()?;
+    stream.verify(&spdf.auth_tag)?;
+    progress(total, total);
+    Ok(written)
+}
+
+/// Compare a computed tag with the file's in constant time
+fn check_tag(expected: &[u8], tag: &[u8]) -> Result<(), SpdfError> {
+    if bool::from(expected.ct_eq(tag)) {
+        Ok(())
+    } else {
+        Err(SpdfError::DecryptionError(format!(
+            "Decryption failed: {}",
+            aes_gcm::Error
+        )))
+    }
+}
+
+/// AES-256-GCM decryption a chunk at a time (NIST SP 800-38D): CTR from
+/// inc32(J0) for the content, GHASH over the associated data and the
+/// ciphertext for the tag
+struct GcmStream {
+    ctr: ctr::Ctr32BE<Aes256>,
+    ghash: GHash,
+    /// E(K, J0), masked into the GHASH output to form the tag
+    tag_mask: [u8; 16],
+    aad_length: u64,
+    length: u64,
+}
+
+impl GcmStream {
+    /// With no associated data, as SPDF content has none
+    fn new(key: &[u8; 32], nonce: &[u8; NONCE_LENGTH]) -> Self {
+        Self::with_aad(key, nonce, &[])
+    }
+
+    fn with_aad(key: &[u8; 32], nonce: &[u8; NONCE_LENGTH], aad: &[u8]) -> Self {
+        let cipher = <Aes256 as KeyInit>::new(key.into());
+        let mut hash_key = Default::default();
+        cipher.encrypt_block(&mut hash_key);
+
+        let mut j0 = [0u8; 16];
+        j0[..NONCE_LENGTH].copy_from_slice(nonce);
+        j0[15] = 1;
+        let mut tag_mask = j0.into();
+        cipher.encrypt_block(&mut tag_mask);
+        j0[15] = 2;
+
+        let mut ghash = <GHash as KeyInit>::new(&hash_key);
+        ghash.update_padded(aad);
+        GcmStream {
+            ctr: ctr::Ctr32BE::new(key.into(), &j0.into()),
+            ghash,
+            tag_mask: tag_mask.into(),
+            aad_length: aad.len() as u64,
+            length: 0,
+        }
+    }
+}
+
+impl ChunkedAead for GcmStream {
+    fn decrypt(&mut self, chunk: &mut [u8]) {
+        self.ghash.update_padded(chunk);
+        self.ctr.apply_keystream(chunk);
+        self.length += chunk.len() as u64;
+    }
+
+    fn verify(mut self, tag: &[u8]) -> Result<(), SpdfError> {
+        // len(A) || len(C), in bits
+        let mut lengths = [0u8; 16];
+        lengths[..8].copy_from_slice(&(self.aad_length * 8).to_be_bytes());
+        lengths[8..].copy_from_slice(&(self.length * 8).to_be_bytes());
+        self.ghash.update(&[lengths.into()]);
+        let mut expected: [u8; 16] = self.ghash.finalize().into();
+        for (byte, mask) in expected.iter_mut().zip(self.tag_mask) {
+            *byte ^= mask;
+        }
+        check_tag(&expected, tag)
+    }
+}
+
+/// ChaCha20-Poly1305 decryption a chunk at a time (RFC 8439): the first
+/// keystream block keys Poly1305, the content uses the keystream from
+/// block 1 on, and Poly1305 runs over the associated data and the
+/// ciphertext
+struct ChaChaStream {
+    chacha: ChaCha20,
+    poly: Poly1305,
+    aad_length: u64,
+    length: u64,
+}
+
+impl ChaChaStream {
+    /// With no associated data, as SPDF content has none
+    fn new(key: &[u8; 32], nonce: &[u8; NONCE_LENGTH]) -> Self {
+        Self::with_aad(key, nonce, &[])
+    }
+
+    fn with_aad(key: &[u8; 32], nonce: &[u8; NONCE_LENGTH], aad: &[u8]) -> Self {
+        let mut chacha = ChaCha20::new(key.into(), nonce.into());
+        let mut mac_key = poly1305::Key::default();
+        chacha.apply_keystream(&mut mac_key);
+        // The rest of block 0 is discarded
+        chacha.seek(64);
+
+        let mut poly = <Poly1305 as KeyInit>::new(&mac_key);
+        poly.update_padded(aad);
+        ChaChaStream {
+            chacha,
+            poly,
+            aad_length: aad.len() as u64,
+            length: 0,
+        }
+    }
+}
+
+impl ChunkedAead for ChaChaStream {
+    fn decrypt(&mut self, chunk: &mut [u8]) {
+        self.poly.update_padded(chunk);
+        self.chacha.apply_keystream(chunk);
+        self.length += chunk.len() as u64;
+    }
+
+    fn verify(mut self, tag: &[u8]) -> Result<(), SpdfError> {
+        // le64(len(A)) || le64(len(C)), in bytes
+        let mut lengths = [0u8; 16];
+        lengths[..8].copy_from_slice(&self.aad_length.to_le_bytes());
+        lengths[8..].copy_from_slice(&self.length.to_le_bytes());
+        self.poly.update(&[lengths.into()]);
+        let expected: [u8; 16] = self.poly.finalize().into();
+        check_tag(&expected, tag)
+    }
+}
+
+impl SpdfFile {
+    /// Decrypt a single segment of a version 2 (segmented) file
+    ///
+    /// Each segment carries its own nonce and auth tag, so a viewer can
+    /// decrypt only the pages it is displaying. A tampered segment fails
+    /// on its own without affecting the others.
+    pub fn decrypt_segment(&self, index: usize, doc_key: &[u8; 32]) -> Result<Vec<u8>, SpdfError> {
+        if !self.is_segmented() {
+            return Err(SpdfError::DecryptionError(
+                "File is not segmented; use decrypt_content".to_string(),
+            ));
+        }
+
+        let segment_bytes = self.segment_bytes(index)?;
+        let nonce_bytes = hex::decode(&self.header.segments[index].nonce)
+            .map_err(|e| SpdfError::DecryptionError(format!("Invalid segment nonce: {}", e)))?;
+        if nonce_bytes.len() != NONCE_LENGTH || segment_bytes.len() < TAG_LENGTH {
+            return Err(SpdfError::DecryptionError(format!(
+                "Malformed segment {}",
+                index
+            )));
+        }
+        let nonce: [u8; NONCE_LENGTH] = nonce_bytes.try_into().expect("length checked");
+        let cipher = ContentCipher::new(self.header.enc_alg(), doc_key)?;
+        // Each segment has its own nonce, so the limit applies per segment
+        cipher.check_length((segment_bytes.len() - TAG_LENGTH) as u64)?;
+
+        cipher
+            .decrypt(&nonce, segment_bytes)
+            .map_err(|e| {
+                SpdfError::DecryptionError(format!("Segment {} decryption failed: {}", index, e))
+            })
+    }
+
+    /// Decrypt the content in place, moving it out of the file
+    ///
+    /// Version 1 content is decrypted in the ciphertext's own buffer rather
+    /// than a copy of it. `ciphertext` is left empty either way, so verify
+    /// the signature first. The plaintext is checked against
+    /// `expected_pages`, as with `decrypt_content`.
+    pub fn take_decrypted(&mut self, doc_key: &[u8; 32]) -> Result<Vec<u8>, SpdfError> {
+        if self.is_segmented() {
+            let plaintext = decrypt_content(self, doc_key)?;
+            self.ciphertext = Vec::new();
+            return Ok(plaintext);
+        }
+
+        check_has_content(self)?;
+        let nonce: [u8; NONCE_LENGTH] = self.nonce[..].try_into().map_err(|_| {
+            SpdfError::DecryptionError(format!(
+                "Invalid nonce length: expected {}, got {}",
+                NONCE_LENGTH,
+                self.nonce.len()
+            ))
+        })?;
+        if self.auth_tag.len() != TAG_LENGTH {
+            return Err(SpdfError::DecryptionError(format!(
+                "Invalid auth tag length: expected {}, got {}",
+                TAG_LENGTH,
+                self.auth_tag.len()
+            )));
+        }
+        let cipher = ContentCipher::new(self.header.enc_alg(), doc_key)?;
+        cipher.check_length(self.ciphertext.len() as u64)?;
+
+        let mut buffer = std::mem::take(&mut self.ciphertext);
+        cipher
+            .decrypt_in_place_detached(&nonce, &mut buffer, &self.auth_tag)
+            .map_err(|e| SpdfError::DecryptionError(format!("Decryption failed: {}", e)))?;
+        let buffer = inflate_content(self, buffer)?;
+        check_expected_pages(self, &buffer)?;
+        Ok(buffer)
+    }
+}
+
+/// Recover k_doc from an AES-KW (RFC 3394) wrapped key
+///
+/// `kek` is the key the server wrapped k_doc with (K_master). Anything but
+/// a 40-byte wrapped key is rejected, and a failed integrity check (wrong
+/// KEK or altered wrapped key) is a `DecryptionError`.
+pub fn unwrap_doc_key(wrapped_key: &[u8], kek: &[u8; 32]) -> Result<DocKey, SpdfError> {
+    if wrapped_key.len() != WRAPPED_KEY_LENGTH {
+        return Err(SpdfError::DecryptionError(format!(
+            "Invalid wrapped key length: expected {}, got {}",
+            WRAPPED_KEY_LENGTH,
+            wrapped_key.len()
+        )));
+    }
+
+    let mut doc_key = Zeroizing::new([0u8; 32]);
+    KekAes256::from(*kek)
+        .unwrap(wrapped_key, doc_key.as_mut_slice())
+        .map_err(|_| {
+            SpdfError::DecryptionError(
+                "Key unwrap failed: integrity check failed (wrong KEK?)".to_string(),
+            )
+        })?;
+    Ok(doc_key)
+}
+
+/// Recover k_doc from a file's `wrapped_key` under its `wrap_scheme`
+///
+/// Only AES-KW keys unwrap with a KEK; a file wrapped any other way is
+/// refused rather than read as AES-KW.
+pub fn unwrap_file_key(spdf: &SpdfFile, kek: &[u8; 32]) -> Result<DocKey, SpdfError> {
+    match spdf.header.wrap_scheme() {
+        DEFAULT_WRAP_SCHEME => unwrap_doc_key(&spdf.wrapped_key, kek),
+        scheme => Err(SpdfError::DecryptionError(format!(
+            "unsupported wrap scheme '{}'",
+            scheme
+        ))),
+    }
+}
+
 /// Decrypt SPDF content with key provided as slice
 pub fn decrypt_content_slice(spdf: &SpdfFile, doc_key: &[u8]) -> Result<Vec<u8>, SpdfError> {
     if doc_key.len() != 32 {
@@ -62,9 +698,11 @@ pub fn decrypt_content_slice(spdf: &SpdfFile, doc_key: &[u8]) -> Result<Vec<u8>,
         )));
     }
 
-    let key_array: [u8; 32] = doc_key
-        .try_into()
-        .map_err(|_| SpdfError::DecryptionError("Invalid key".to_string()))?;
+    let key_array: DocKey = Zeroizing::new(
+        doc_key
+            .try_into()
+            .map_err(|_| SpdfError::DecryptionError("Invalid key".to_string()))?,
+    );
 
     decrypt_content(spdf, &key_array)
 }
@@ -73,22 +711,237 @@ pub fn decrypt_content_slice(spdf: &SpdfFile, doc_key: &[u8]) -> Result<Vec<u8>,
 pub fn decrypt_content_base64(spdf: &SpdfFile, doc_key_b64: &str) -> Result<Vec<u8>, SpdfError> {
     use base64::{engine::general_purpose, Engine as _};
     
-    let doc_key = general_purpose::STANDARD
-        .decode(doc_key_b64)
-        .map_err(|e| SpdfError::DecryptionError(format!("Invalid base64 key: {}", e)))?;
+    let doc_key = Zeroizing::new(
+        general_purpose::STANDARD
+            .decode(doc_key_b64)
+            .map_err(|e| SpdfError::DecryptionError(format!("Invalid base64 key: {}", e)))?,
+    );
 
     decrypt_content_slice(spdf, &doc_key)
 }
 
+/// Check that key material is plausibly a real AES-256 key
+///
+/// Rejects keys of the wrong length and degenerate keys made of a single
+/// repeated byte (e.g. all zeros from an uninitialised buffer).
+pub fn looks_like_valid_key(key: &[u8]) -> bool {
+    key.len() == 32 && key.iter().any(|&b| b != key[0])
+}
+
+/// Classify a decryption failure given the key that was used
+///
+/// A well-formed key that still fails the tag check points at the file;
+/// anything else points at the key.
+pub fn classify_decrypt_failure(doc_key: &[u8]) -> DecryptFailureKind {
+    if looks_like_valid_key(doc_key) {
+        DecryptFailureKind::LikelyCorruptOrTampered
+    } else {
+        DecryptFailureKind::LikelyWrongKey
+    }
+}
+
+/// Decrypt and check the plaintext's SHA-256 against an approved hash
+///
+/// The plaintext never leaves this function and is wiped before it
+/// returns. The digests are compared in constant time.
+pub fn decrypted_matches_sha256(
+    spdf: &SpdfFile,
+    doc_key: &[u8],
+    expected_sha256_hex: &str,
+) -> Result<bool, SpdfError> {
+    let expected = hex::decode(expected_sha256_hex.trim())
+        .map_err(|e| SpdfError::FormatError(format!("Invalid SHA-256 hex: {}", e)))?;
+    if expected.len() != 32 {
+        return Err(SpdfError::FormatError(format!(
+            "Invalid SHA-256 length: expected 32 bytes, got {}",
+            expected.len()
+        )));
+    }
+
+    let plaintext = Zeroizing::new(decrypt_content_slice(spdf, doc_key)?);
+    let actual = Sha256::digest(&*plaintext);
+    Ok(actual.ct_eq(&expected).into())
+}
+
+/// Whether two files are encrypted under the same document key
+///
+/// Each key must first decrypt its own file, so the answer is about the
+/// files' real keys rather than whatever was passed in. The keys are
+/// compared in constant time and the plaintexts wiped.
+pub fn same_doc_key(
+    spdf_a: &SpdfFile,
+    key_a: &[u8],
+    spdf_b: &SpdfFile,
+    key_b: &[u8],
+) -> Result<bool, SpdfError> {
+    for (name, spdf, key) in [("A", spdf_a, key_a), ("B", spdf_b, key_b)] {
+        decrypt_content_slice(spdf, key).map(Zeroizing::new).map_err(|e| {
+            let msg = format!("Key {} does not decrypt file {}: {}", name, name, e);
+            SpdfError::DecryptionError(msg)
+        })?;
+    }
+    Ok(key_a.ct_eq(key_b).into())
+}
+
+/// Problems with the encrypted content that can be seen without the key
+///
+/// Checks nonce and tag lengths and, for AES-GCM content, the data limit
+/// for each (key, nonce) pair. An empty list means decryption is worth
+/// attempting.
+pub fn integrity_precheck(spdf: &SpdfFile) -> Vec<String> {
+    let mut issues = Vec::new();
+    let check_length = |len| match spdf.header.enc_alg() {
+        DEFAULT_ENC_ALG => check_gcm_length(len),
+        _ => Ok(()),
+    };
+
+    if spdf.is_segmented() {
+        for (index, segment) in spdf.header.segments.iter().enumerate() {
+            let ciphertext_len = segment.length.saturating_sub(TAG_LENGTH as u64);
+            if let Err(e) = check_length(ciphertext_len) {
+                issues.push(format!("Segment {}: {}", index, e));
+            }
+        }
+        return issues;
+    }
+
+    if spdf.nonce.len() != NONCE_LENGTH {
+        issues.push(format!(
+            "Invalid nonce length: expected {}, got {}",
+            NONCE_LENGTH,
+            spdf.nonce.len()
+        ));
+    }
+    if spdf.auth_tag.len() != TAG_LENGTH {
+        issues.push(format!(
+            "Invalid auth tag length: expected {}, got {}",
+            TAG_LENGTH,
+            spdf.auth_tag.len()
+        ));
+    }
+    if let Err(e) = check_length(spdf.ciphertext.len() as u64) {
+        issues.push(e.to_string());
+    }
+    issues
+}
+
 /// Validate that content appears to be a valid PDF
 pub fn validate_pdf_content(content: &[u8]) -> bool {
     // PDF files start with %PDF-
     content.len() >= 4 && &content[0..4] == b"%PDF"
 }
 
+/// How far from the end `validate_pdf_structure` looks for the trailer
+pub const PDF_TRAILER_WINDOW: usize = 4 * 1024;
+
+/// Check that content is a PDF whose trailer survived: the `%PDF` magic,
+/// then `startxref` followed by `%%EOF` within the last
+/// `PDF_TRAILER_WINDOW` bytes
+///
+/// Catches truncated output and broken encoders that still start with
+/// `%PDF`. This scans the tail of the file, so use `validate_pdf_content`
+/// where the magic alone is enough.
+pub fn validate_pdf_structure(content: &[u8]) -> Result<(), SpdfError> {
+    if !validate_pdf_content(content) {
+        return Err(SpdfError::FormatError("content does not start with %PDF".to_string()));
+    }
+    let tail = &content[content.len().saturating_sub(PDF_TRAILER_WINDOW)..];
+    let rfind = |needle: &[u8]| tail.windows(needle.len()).rposition(|w| w == needle);
+    let Some(eof) = rfind(b"%%EOF") else {
+        return Err(SpdfError::FormatError(format!(
+            "no %%EOF in the last {} bytes; the PDF may be truncated",
+            PDF_TRAILER_WINDOW
+        )));
+    };
+    match rfind(b"startxref") {
+        Some(startxref) if startxref < eof => Ok(()),
+        _ => Err(SpdfError::FormatError("no startxref before the final %%EOF".to_string())),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn test_decrypt_content_to_matches_decrypt_content() {
+        let key = &test_support::DOC_KEY;
+        let large: Vec<u8> = (0..3 * STREAM_CHUNK + 5).map(|i| i as u8).collect();
+        let files = [
+            test_support::build_spdf(&large),
+            test_support::build_spdf(b"%PDF-1.7 short"),
+            test_support::build_segmented_spdf(&[b"%PDF", b"-1.7", b" segmented"]),
+        ];
+        for data in files {
+            let spdf = SpdfFile::parse(&data).unwrap();
+            let expected = decrypt_content(&spdf, key).unwrap();
+            for mode in [OutputMode::Streamed, OutputMode::Buffered] {
+                let mut out = Vec::new();
+                let written = decrypt_content_to(&spdf, key, &mut out, mode).unwrap();
+                assert_eq!(written, expected.len() as u64);
+                assert!(out == expected, "{:?} output differs", mode);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decrypt_progress() {
+        let key = &test_support::DOC_KEY;
+        let decrypt = |data: &[u8]| {
+            let spdf = SpdfFile::parse(data).unwrap();
+            let mut calls = Vec::new();
+            let plaintext =
+                decrypt_content_with_progress(&spdf, key, |done, total| calls.push((done, total)));
+            assert_eq!(plaintext.unwrap(), decrypt_content(&spdf, key).unwrap());
+            calls
+        };
+
+        let total = (2 * STREAM_CHUNK + 100) as u64;
+        let calls = decrypt(&test_support::build_spdf(&vec![0x25; total as usize]));
+        let chunk = STREAM_CHUNK as u64;
+        assert_eq!(calls, [(chunk, total), (2 * chunk, total), (total, total)]);
+
+        // Small files still get the completion call
+        assert_eq!(decrypt(&test_support::build_spdf(b"%PDF")), [(4, 4)]);
+        let calls = decrypt(&test_support::build_segmented_spdf(&[b"%PDF", b"-1.7"]));
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[1].0, calls[1].1);
+
+        // No completion when the tag does not verify
+        let mut spdf = SpdfFile::parse(&test_support::build_spdf(b"%PDF")).unwrap();
+        spdf.ciphertext[0] ^= 0x01;
+        let mut completed = false;
+        let result = decrypt_content_with_progress(&spdf, key, |done, total| {
+            completed = done == total;
+        });
+        assert!(result.is_err());
+        assert!(!completed);
+    }
+
+    #[test]
+    fn test_decrypt_content_to_tampered() {
+        let data = test_support::build_spdf(&[0x25; 2 * STREAM_CHUNK]);
+        let mut spdf = SpdfFile::parse(&data).unwrap();
+        spdf.ciphertext[STREAM_CHUNK + 7] ^= 0x01;
+        let key = &test_support::DOC_KEY;
+
+        // Streaming writes everything, then reports the bad tag
+        let mut out = Vec::new();
+        let result = decrypt_content_to(&spdf, key, &mut out, OutputMode::Streamed);
+        assert!(matches!(result, Err(SpdfError::DecryptionError(_))));
+        assert_eq!(out.len(), spdf.ciphertext.len());
+
+        // Buffering writes nothing unauthenticated
+        let mut out = Vec::new();
+        let result = decrypt_content_to(&spdf, key, &mut out, OutputMode::Buffered);
+        assert!(matches!(result, Err(SpdfError::DecryptionError(_))));
+        assert!(out.is_empty());
+
+        spdf.ciphertext[STREAM_CHUNK + 7] ^= 0x01;
+        let result = decrypt_content_to(&spdf, &[0x01; 32], Vec::new(), OutputMode::Streamed);
+        assert!(matches!(result, Err(SpdfError::DecryptionError(_))));
+    }
 
     #[test]
     fn test_validate_pdf_content() {
@@ -97,6 +950,25 @@ mod tests {
         assert!(!validate_pdf_content(b""));
     }
 
+    #[test]
+    fn test_validate_pdf_structure() {
+        let pdf = b"%PDF-1.4\n1 0 obj << >> endobj\nxref\ntrailer << >>\nstartxref\n9\n%%EOF\n";
+        assert!(validate_pdf_structure(pdf).is_ok());
+
+        // Truncated before the trailer, or the trailer out of reach
+        let truncated = &pdf[..pdf.len() - 20];
+        assert!(validate_pdf_structure(truncated).is_err());
+        let mut padded = pdf[..pdf.len() - 7].to_vec();
+        padded.resize(padded.len() + PDF_TRAILER_WINDOW, b' ');
+        padded.extend_from_slice(b"%%EOF\n");
+        assert!(validate_pdf_structure(&padded).is_err());
+
+        assert!(validate_pdf_structure(b"%PDF-1.4 %%EOF").is_err());
+        assert!(validate_pdf_structure(b"Not a PDF startxref 0 %%EOF").is_err());
+        // Passes the cheap check alone
+        assert!(validate_pdf_content(truncated));
+    }
+
     #[test]
     fn test_decrypt_invalid_key_length() {
         // This would need a valid SpdfFile structure which requires complex setup
@@ -106,4 +978,575 @@ mod tests {
         // Create minimal test case
         // In practice, this would be integration tested with real SPDF files
     }
+
+    #[test]
+    fn test_take_decrypted() {
+        let plaintext = b"%PDF-1.7 taken";
+        let mut spdf = SpdfFile::parse(&test_support::build_spdf(plaintext)).unwrap();
+        assert_eq!(spdf.take_decrypted(&test_support::DOC_KEY).unwrap(), plaintext);
+        assert!(spdf.ciphertext.is_empty());
+
+        let segments: [&[u8]; 2] = [b"%PDF-1.7 ", b"in segments"];
+        let data = test_support::build_segmented_spdf(&segments);
+        let mut spdf = SpdfFile::parse(&data).unwrap();
+        assert_eq!(
+            spdf.take_decrypted(&test_support::DOC_KEY).unwrap(),
+            b"%PDF-1.7 in segments"
+        );
+
+        let mut spdf = SpdfFile::parse(&test_support::build_spdf(plaintext)).unwrap();
+        assert!(matches!(
+            spdf.take_decrypted(&[0x01; 32]),
+            Err(SpdfError::DecryptionError(_))
+        ));
+    }
+
+    #[test]
+    fn test_decrypt_chacha20_poly1305() {
+        use crate::spdf_parser::{SpdfHeader, WriteOptions};
+        use crate::test_support::{sample_header, signing_key, DOC_KEY};
+
+        let mut header: SpdfHeader = serde_json::from_value(sample_header()).unwrap();
+        header.enc_alg = Some(CHACHA20_POLY1305_ENC_ALG.to_string());
+        let plaintext: Vec<u8> = (0..STREAM_CHUNK + 9).map(|i| i as u8).collect();
+        let mut spdf = SpdfFile::create(&WriteOptions {
+            header: &header,
+            flags: test_support::DEFAULT_FLAGS,
+            plaintext: &plaintext,
+            doc_key: &DOC_KEY,
+            wrapped_key: &[0xAA; WRAPPED_KEY_LENGTH],
+            signing_key: &signing_key(),
+        })
+        .unwrap();
+
+        assert_eq!(decrypt_content(&spdf, &DOC_KEY).unwrap(), plaintext);
+        let mut out = Vec::new();
+        decrypt_content_to(&spdf, &DOC_KEY, &mut out, OutputMode::Streamed).unwrap();
+        assert!(out == plaintext);
+        assert!(decrypt_content(&spdf, &[0x01; 32]).is_err());
+
+        // Streamed like AES-GCM: everything is written, then the tag fails
+        spdf.ciphertext[STREAM_CHUNK + 3] ^= 0x01;
+        let mut out = Vec::new();
+        let result = decrypt_content_to(&spdf, &DOC_KEY, &mut out, OutputMode::Streamed);
+        assert!(matches!(result, Err(SpdfError::DecryptionError(_))));
+        assert_eq!(out.len(), plaintext.len());
+        spdf.ciphertext[STREAM_CHUNK + 3] ^= 0x01;
+
+        // The cipher comes from the header, not a guess
+        spdf.header.enc_alg = None;
+        assert!(decrypt_content(&spdf, &DOC_KEY).is_err());
+        spdf.header.enc_alg = Some("AES-128-CBC".to_string());
+        match decrypt_content(&spdf, &DOC_KEY) {
+            Err(SpdfError::FormatError(msg)) => {
+                assert_eq!(msg, "unsupported enc_alg 'AES-128-CBC'")
+            }
+            other => panic!("expected format error, got {:?}", other),
+        }
+
+        spdf.header.enc_alg = Some(CHACHA20_POLY1305_ENC_ALG.to_string());
+        assert_eq!(spdf.take_decrypted(&DOC_KEY).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn test_compressed_round_trip() {
+        use crate::spdf_parser::{WriteOptions, FLAG_COMPRESSED};
+        use crate::test_support::{sample_header, signing_key, DOC_KEY};
+
+        let header = serde_json::from_value(sample_header()).unwrap();
+        let create = |plaintext: &[u8]| {
+            SpdfFile::create(&WriteOptions {
+                header: &header,
+                flags: test_support::DEFAULT_FLAGS | FLAG_COMPRESSED,
+                plaintext,
+                doc_key: &DOC_KEY,
+                wrapped_key: &[0xAA; WRAPPED_KEY_LENGTH],
+                signing_key: &signing_key(),
+            })
+            .unwrap()
+        };
+        let plaintext = b"%PDF-1.7 ".repeat(4096);
+        let mut spdf = create(&plaintext);
+        assert!(spdf.is_compressed());
+        assert!(spdf.ciphertext.len() < plaintext.len() / 10);
+
+        assert_eq!(decrypt_content(&spdf, &DOC_KEY).unwrap(), plaintext);
+        let with_progress = decrypt_content_with_progress(&spdf, &DOC_KEY, |_, _| {});
+        assert_eq!(with_progress.unwrap(), plaintext);
+        let mut out = Vec::new();
+        decrypt_content_to(&spdf, &DOC_KEY, &mut out, OutputMode::Streamed).unwrap();
+        assert!(out == plaintext);
+        assert_eq!(spdf.take_decrypted(&DOC_KEY).unwrap(), plaintext);
+
+        // Authenticated, but not a compressed PDF
+        let mut not_pdf = create(b"plain text");
+        assert!(matches!(
+            not_pdf.take_decrypted(&DOC_KEY),
+            Err(SpdfError::FormatError(msg)) if msg == "inflated content is not a PDF"
+        ));
+        let mut uncompressed = SpdfFile::parse(&test_support::build_spdf(b"%PDF")).unwrap();
+        uncompressed.flags |= FLAG_COMPRESSED;
+        assert!(matches!(
+            decrypt_content(&uncompressed, &DOC_KEY),
+            Err(SpdfError::FormatError(msg)) if msg.ends_with("no content_length in the header")
+        ));
+        uncompressed.header.content_length = Some(4);
+        assert!(matches!(
+            decrypt_content(&uncompressed, &DOC_KEY),
+            Err(SpdfError::FormatError(msg)) if msg.starts_with("compressed content does not")
+        ));
+
+        // Inflation stops just past the stated length
+        assert_eq!(spdf.header.content_length, Some(plaintext.len() as u64));
+        for (stated, message) in [
+            (1024, "compressed content inflates past its content_length of 1024"),
+            (1 << 20, "compressed content inflates to 36864 bytes, header says 1048576"),
+        ] {
+            let mut bomb = create(&plaintext);
+            bomb.header.content_length = Some(stated);
+            assert!(matches!(
+                decrypt_content(&bomb, &DOC_KEY),
+                Err(SpdfError::FormatError(msg)) if msg == message
+            ));
+        }
+    }
+
+    /// Run `stream` over `ciphertext` 64 bytes at a time
+    fn stream_open<A: ChunkedAead>(
+        mut stream: A,
+        ciphertext: &str,
+        tag: &str,
+    ) -> Result<Vec<u8>, SpdfError> {
+        let mut data = hex::decode(ciphertext).unwrap();
+        for chunk in data.chunks_mut(64) {
+            stream.decrypt(chunk);
+        }
+        stream.verify(&hex::decode(tag).unwrap())?;
+        Ok(data)
+    }
+
+    fn hex_array<const N: usize>(hex: &str) -> [u8; N] {
+        hex::decode(hex).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn test_gcm_stream_vectors() {
+        // GCM spec (McGrew & Viega) test cases 15 and 16, AES-256
+        let key = hex_array("feffe9928665731c6d6a8f9467308308feffe9928665731c6d6a8f9467308308");
+        let nonce = hex_array("cafebabefacedbaddecaf888");
+        let plaintext = "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72\
+                         1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b391aafd255";
+        let ciphertext = "522dc1f099567d07f47f37a32a84427d643a8cdcbfe5c0c97598a2bd2555d1aa\
+                          8cb08e48590dbb3da7b08b1056828838c5f61e6393ba7a0abcc9f662898015ad";
+
+        let opened = stream_open(
+            GcmStream::new(&key, &nonce),
+            ciphertext,
+            "b094dac5d93471bdec1a502270e3cc6c",
+        );
+        assert_eq!(hex::encode(opened.unwrap()), plaintext);
+
+        let aad = hex::decode("feedfacedeadbeeffeedfacedeadbeefabaddad2").unwrap();
+        let opened = stream_open(
+            GcmStream::with_aad(&key, &nonce, &aad),
+            &ciphertext[..120],
+            "76fc6ece0f4e1768cddf8853bb2d551b",
+        );
+        assert_eq!(hex::encode(opened.unwrap()), plaintext[..120]);
+
+        let forged = stream_open(
+            GcmStream::new(&key, &nonce),
+            ciphertext,
+            "b094dac5d93471bdec1a502270e3cc6d",
+        );
+        assert!(matches!(forged, Err(SpdfError::DecryptionError(_))));
+    }
+
+    #[test]
+    fn test_chacha_stream_vectors() {
+        // RFC 8439 section 2.8.2
+        let key = hex_array("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f");
+        let nonce = hex_array("070000004041424344454647");
+        let aad = hex::decode("50515253c0c1c2c3c4c5c6c7").unwrap();
+        let ciphertext = "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d6\
+                          3dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b36\
+                          92ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc\
+                          3ff4def08e4b7a9de576d26586cec64b6116";
+        let tag = "1ae10b594f09e26a7e902ecbd0600691";
+
+        let opened = stream_open(ChaChaStream::with_aad(&key, &nonce, &aad), ciphertext, tag);
+        assert_eq!(
+            String::from_utf8(opened.unwrap()).unwrap(),
+            "Ladies and Gentlemen of the class of '99: If I could offer you only one tip \
+             for the future, sunscreen would be it."
+        );
+
+        // The same AAD-free construction the chacha20poly1305 crate uses
+        let expected = ChaCha20Poly1305::new((&key).into())
+            .encrypt((&nonce).into(), &b"no associated data"[..])
+            .unwrap();
+        let (body, tag) = expected.split_at(expected.len() - TAG_LENGTH);
+        let (body, tag) = (hex::encode(body), hex::encode(tag));
+        let opened = stream_open(ChaChaStream::new(&key, &nonce), &body, &tag);
+        assert_eq!(opened.unwrap(), b"no associated data");
+
+        let forged = stream_open(ChaChaStream::new(&key, &nonce), &body, &"00".repeat(16));
+        assert!(matches!(forged, Err(SpdfError::DecryptionError(_))));
+    }
+
+    #[test]
+    fn test_unwrap_doc_key() {
+        // RFC 3394 section 4.6: 256-bit key data with a 256-bit KEK
+        let kek: [u8; 32] =
+            hex::decode("000102030405060708090A0B0C0D0E0F101112131415161718191A1B1C1D1E1F")
+                .unwrap()
+                .try_into()
+                .unwrap();
+        let mut wrapped = hex::decode(
+            "28C9F404C4B810F4CBCCB35CFB87F8263F5786E2D80ED326CBC7F0E71A99F43BFB988B9B7A02DD21",
+        )
+        .unwrap();
+        assert_eq!(
+            hex::encode_upper(*unwrap_doc_key(&wrapped, &kek).unwrap()),
+            "00112233445566778899AABBCCDDEEFF000102030405060708090A0B0C0D0E0F"
+        );
+
+        // Wrong KEK, or a flipped bit, fails the integrity check
+        assert!(matches!(
+            unwrap_doc_key(&wrapped, &[0x01; 32]),
+            Err(SpdfError::DecryptionError(_))
+        ));
+        wrapped[0] ^= 0x01;
+        assert!(matches!(
+            unwrap_doc_key(&wrapped, &kek),
+            Err(SpdfError::DecryptionError(_))
+        ));
+
+        // Only the 40-byte AES-KW form of a 32-byte key is accepted
+        match unwrap_doc_key(&[0u8; 48], &kek) {
+            Err(SpdfError::DecryptionError(msg)) => {
+                assert_eq!(msg, "Invalid wrapped key length: expected 40, got 48")
+            }
+            other => panic!("expected decryption error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unwrap_file_key_by_scheme() {
+        let kek = [0x07; 32];
+        let mut wrapped = [0u8; WRAPPED_KEY_LENGTH];
+        KekAes256::from(kek)
+            .wrap(&test_support::DOC_KEY, &mut wrapped)
+            .unwrap();
+        let mut header = test_support::sample_header();
+        let data = test_support::build_spdf_with_wrapped_key(
+            &header,
+            test_support::DEFAULT_FLAGS,
+            &wrapped,
+            b"%PDF",
+        );
+        let spdf = SpdfFile::parse(&data).unwrap();
+        assert_eq!(*unwrap_file_key(&spdf, &kek).unwrap(), test_support::DOC_KEY);
+
+        header["wrap_scheme"] = serde_json::json!("AES-GCM-SIV");
+        let data = test_support::build_spdf_with_wrapped_key(
+            &header,
+            test_support::DEFAULT_FLAGS,
+            &[0u8; 60],
+            b"%PDF",
+        );
+        let spdf = SpdfFile::parse(&data).unwrap();
+        match unwrap_file_key(&spdf, &kek) {
+            Err(SpdfError::DecryptionError(msg)) => {
+                assert_eq!(msg, "unsupported wrap scheme 'AES-GCM-SIV'")
+            }
+            other => panic!("expected decryption error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_doc_key_is_zeroizing() {
+        use zeroize::Zeroize;
+
+        let kek = [0x07; 32];
+        let mut wrapped = [0u8; WRAPPED_KEY_LENGTH];
+        KekAes256::from(kek)
+            .wrap(&test_support::DOC_KEY, &mut wrapped)
+            .unwrap();
+
+        // The annotation only compiles while unwrapping hands back a `Zeroizing` key
+        let mut doc_key: Zeroizing<[u8; 32]> = unwrap_doc_key(&wrapped, &kek).unwrap();
+        assert_eq!(*doc_key, test_support::DOC_KEY);
+        let spdf = SpdfFile::parse(&test_support::build_spdf(b"%PDF-1.7 wiped")).unwrap();
+        assert_eq!(decrypt_content(&spdf, &doc_key).unwrap(), b"%PDF-1.7 wiped");
+
+        // What the wrapper runs on drop
+        doc_key.zeroize();
+        assert_eq!(*doc_key, [0u8; 32]);
+    }
+
+    #[test]
+    fn test_decrypted_matches_sha256() {
+        let plaintext = b"%PDF-1.7 approved revision";
+        let spdf = SpdfFile::parse(&test_support::build_spdf(plaintext)).unwrap();
+        let approved = hex::encode(Sha256::digest(plaintext));
+
+        assert!(decrypted_matches_sha256(&spdf, &test_support::DOC_KEY, &approved).unwrap());
+        let upper = approved.to_uppercase();
+        assert!(decrypted_matches_sha256(&spdf, &test_support::DOC_KEY, &upper).unwrap());
+
+        let other = hex::encode(Sha256::digest(b"%PDF-1.7 draft"));
+        assert!(!decrypted_matches_sha256(&spdf, &test_support::DOC_KEY, &other).unwrap());
+    }
+
+    #[test]
+    fn test_decrypted_matches_sha256_rejects_bad_input() {
+        let spdf = SpdfFile::parse(&test_support::build_spdf(b"%PDF")).unwrap();
+        let approved = hex::encode(Sha256::digest(b"%PDF"));
+
+        assert!(decrypted_matches_sha256(&spdf, &test_support::DOC_KEY, "abcd").is_err());
+        assert!(decrypted_matches_sha256(&spdf, &test_support::DOC_KEY, "not hex").is_err());
+        assert!(matches!(
+            decrypted_matches_sha256(&spdf, &[0x01; 32], &approved),
+            Err(SpdfError::DecryptionError(_))
+        ));
+    }
+
+    #[test]
+    fn test_same_doc_key() {
+        use crate::spdf_parser::WriteOptions;
+        use crate::test_support::{sample_header, signing_key};
+
+        let file_under = |plaintext: &[u8], doc_key: &[u8; 32]| {
+            let header = serde_json::from_value(sample_header()).unwrap();
+            SpdfFile::create(&WriteOptions {
+                header: &header,
+                flags: test_support::DEFAULT_FLAGS,
+                plaintext,
+                doc_key,
+                wrapped_key: &[0xAA; WRAPPED_KEY_LENGTH],
+                signing_key: &signing_key(),
+            })
+            .unwrap()
+        };
+        let other_key = [0x24; 32];
+        let a = file_under(b"%PDF-1.7 a", &test_support::DOC_KEY);
+        let b = file_under(b"%PDF-1.7 b", &test_support::DOC_KEY);
+        let c = file_under(b"%PDF-1.7 c", &other_key);
+
+        assert!(same_doc_key(&a, &test_support::DOC_KEY, &b, &test_support::DOC_KEY).unwrap());
+        assert!(!same_doc_key(&a, &test_support::DOC_KEY, &c, &other_key).unwrap());
+
+        // A key that does not open its own file is refused, not compared
+        match same_doc_key(&a, &test_support::DOC_KEY, &c, &test_support::DOC_KEY) {
+            Err(SpdfError::DecryptionError(msg)) => {
+                assert!(msg.starts_with("Key B does not decrypt file B"))
+            }
+            other => panic!("expected decryption error, got {:?}", other),
+        }
+        assert!(same_doc_key(&a, &[0x01; 16], &b, &test_support::DOC_KEY).is_err());
+    }
+
+    #[test]
+    fn test_expected_pages() {
+        use crate::pdf::tests::pdf_with_pages;
+        use crate::test_support::{build_spdf_with, sample_header, DEFAULT_FLAGS};
+
+        let mut header = sample_header();
+        header["expected_pages"] = serde_json::json!(3);
+
+        let intact = build_spdf_with(&header, DEFAULT_FLAGS, &pdf_with_pages(3));
+        let spdf = SpdfFile::parse(&intact).unwrap();
+        assert!(decrypt_content(&spdf, &test_support::DOC_KEY).is_ok());
+
+        // Truncated before encryption: GCM authenticates it, the page count doesn't
+        let truncated = build_spdf_with(&header, DEFAULT_FLAGS, &pdf_with_pages(2));
+        let spdf = SpdfFile::parse(&truncated).unwrap();
+        match decrypt_content(&spdf, &test_support::DOC_KEY) {
+            Err(SpdfError::DecryptionError(msg)) => {
+                assert_eq!(msg, "page count mismatch: expected 3 got 2")
+            }
+            other => panic!("expected page count mismatch, got {:?}", other.map(|_| ())),
+        }
+
+        // Pages not countable, e.g. all in object streams: not checked
+        let uncountable = build_spdf_with(&header, DEFAULT_FLAGS, b"%PDF-1.7 /Type /ObjStm");
+        let spdf = SpdfFile::parse(&uncountable).unwrap();
+        assert!(decrypt_content(&spdf, &test_support::DOC_KEY).is_ok());
+
+        // No expected_pages: not checked
+        let unchecked = build_spdf_with(&sample_header(), DEFAULT_FLAGS, &pdf_with_pages(2));
+        let spdf = SpdfFile::parse(&unchecked).unwrap();
+        assert!(decrypt_content(&spdf, &test_support::DOC_KEY).is_ok());
+    }
+
+    #[test]
+    fn test_decrypt_segments_independently() {
+        let segments: [&[u8]; 3] = [b"%PDF-1.7 page one", b"page two", b"page three %%EOF"];
+        let data = test_support::build_segmented_spdf(&segments);
+        let spdf = SpdfFile::parse(&data).unwrap();
+
+        assert!(spdf.is_segmented());
+        assert_eq!(spdf.segment_count(), 3);
+        for (i, expected) in segments.iter().enumerate() {
+            let plaintext = spdf.decrypt_segment(i, &test_support::DOC_KEY).unwrap();
+            assert_eq!(&plaintext[..], *expected);
+        }
+
+        let whole = decrypt_content(&spdf, &test_support::DOC_KEY).unwrap();
+        assert_eq!(whole, segments.concat());
+    }
+
+    #[test]
+    fn test_decrypt_tampered_segment() {
+        let segments: [&[u8]; 3] = [b"%PDF-1.7 page one", b"page two", b"page three %%EOF"];
+        let data = test_support::build_segmented_spdf(&segments);
+        let mut spdf = SpdfFile::parse(&data).unwrap();
+
+        let start = spdf.header.segments[1].offset as usize;
+        spdf.ciphertext[start] ^= 0xFF;
+
+        assert!(spdf.decrypt_segment(0, &test_support::DOC_KEY).is_ok());
+        assert!(matches!(
+            spdf.decrypt_segment(1, &test_support::DOC_KEY),
+            Err(SpdfError::DecryptionError(_))
+        ));
+        assert!(spdf.decrypt_segment(2, &test_support::DOC_KEY).is_ok());
+        assert!(matches!(
+            spdf.decrypt_segment(3, &test_support::DOC_KEY),
+            Err(SpdfError::FormatError(_))
+        ));
+    }
+
+    #[test]
+    fn test_looks_like_valid_key() {
+        assert!(looks_like_valid_key(&test_support::DOC_KEY));
+        assert!(!looks_like_valid_key(&[0u8; 32]));
+        assert!(!looks_like_valid_key(&[0x42; 32]));
+        assert!(!looks_like_valid_key(&[0x13; 16]));
+    }
+
+    #[test]
+    fn test_classify_corrupted_ciphertext() {
+        let data = test_support::build_spdf(b"%PDF-1.4 classified");
+        let mut spdf = SpdfFile::parse(&data).unwrap();
+        spdf.ciphertext[0] ^= 0x80;
+
+        let key = test_support::DOC_KEY;
+        assert!(decrypt_content(&spdf, &key).is_err());
+        assert_eq!(
+            classify_decrypt_failure(&key),
+            DecryptFailureKind::LikelyCorruptOrTampered
+        );
+    }
+
+    #[test]
+    fn test_signed_empty_content_rejected() {
+        // Version 1 files need at least one ciphertext byte to parse, but
+        // every segment of a version 2 file may be empty
+        let data = test_support::build_segmented_spdf(&[b"", b""]);
+        let spdf = SpdfFile::parse(&data).unwrap();
+        let public_key = test_support::public_key_pem();
+        assert!(crate::verify::verify_signature_with_key(&spdf, &public_key).is_ok());
+        assert!(!spdf.has_content());
+
+        match decrypt_content(&spdf, &test_support::DOC_KEY) {
+            Err(SpdfError::FormatError(msg)) => assert_eq!(msg, NO_CONTENT_MESSAGE),
+            other => panic!("expected FormatError, got {:?}", other),
+        }
+
+        // A signed version 1 file with zero ciphertext bytes is under the
+        // minimum size: it fails to parse, and reads as a cut-off file
+        let data = test_support::build_spdf(b"");
+        let expected = [
+            "File too short",
+            crate::spdf_parser::INCOMPLETE_FILE_MESSAGE,
+        ];
+        for (parsed, expected) in [SpdfFile::parse(&data), SpdfFile::from_reader(&data[..])]
+            .into_iter()
+            .zip(expected)
+        {
+            match parsed {
+                Err(SpdfError::FormatError(msg)) => assert!(msg.starts_with(expected), "{}", msg),
+                other => panic!("expected FormatError, got {:?}", other.err()),
+            }
+        }
+        // and one emptied after parsing is refused before any decryption
+        let mut spdf = SpdfFile::parse(&test_support::build_spdf(b"%PDF")).unwrap();
+        spdf.ciphertext.clear();
+        assert!(!spdf.has_content());
+        for decrypted in [
+            decrypt_content(&spdf, &test_support::DOC_KEY),
+            decrypt_content_with_progress(&spdf, &test_support::DOC_KEY, |_, _| {}),
+        ] {
+            match decrypted {
+                Err(SpdfError::FormatError(msg)) => assert_eq!(msg, NO_CONTENT_MESSAGE),
+                other => panic!("expected FormatError, got {:?}", other),
+            }
+        }
+        assert!(verify_key(&spdf, &test_support::DOC_KEY).is_err());
+    }
+
+    #[test]
+    fn test_gcm_length_limit() {
+        assert!(check_gcm_length(0).is_ok());
+        assert!(check_gcm_length(GCM_MAX_PLAINTEXT).is_ok());
+        match check_gcm_length(GCM_MAX_PLAINTEXT + 1) {
+            Err(SpdfError::DecryptionError(msg)) => {
+                assert_eq!(msg, "ciphertext exceeds AES-GCM safe limit")
+            }
+            other => panic!("expected DecryptionError, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_integrity_precheck() {
+        let spdf = SpdfFile::parse(&test_support::build_spdf(b"%PDF-1.4")).unwrap();
+        assert!(integrity_precheck(&spdf).is_empty());
+
+        let mut spdf = spdf;
+        spdf.auth_tag.pop();
+        assert_eq!(integrity_precheck(&spdf), ["Invalid auth tag length: expected 16, got 15"]);
+
+        // A segment table claiming more than the limit, without the buffer
+        let data = test_support::build_segmented_spdf(&[b"%PDF-1.4", b"%%EOF"]);
+        let mut spdf = SpdfFile::parse(&data).unwrap();
+        assert!(integrity_precheck(&spdf).is_empty());
+        spdf.header.segments[1].length = GCM_MAX_PLAINTEXT + TAG_LENGTH as u64 + 1;
+        assert_eq!(
+            integrity_precheck(&spdf),
+            ["Segment 1: Decryption error: ciphertext exceeds AES-GCM safe limit"]
+        );
+    }
+
+    #[test]
+    fn test_classify_wrong_key() {
+        let data = test_support::build_spdf(b"%PDF-1.4 classified");
+        let spdf = SpdfFile::parse(&data).unwrap();
+
+        let wrong_key = [0u8; 32];
+        assert!(decrypt_content(&spdf, &wrong_key).is_err());
+        assert_eq!(
+            classify_decrypt_failure(&wrong_key),
+            DecryptFailureKind::LikelyWrongKey
+        );
+        assert_eq!(
+            classify_decrypt_failure(&[0x11; 16]),
+            DecryptFailureKind::LikelyWrongKey
+        );
+    }
+
+    #[test]
+    fn test_verify_key() {
+        let whole = test_support::build_spdf(b"%PDF-1.4 verify");
+        let segmented = test_support::build_segmented_spdf(&[b"%PDF-1.4 ", b"verify"]);
+        for data in [whole, segmented] {
+            let spdf = SpdfFile::parse(&data).unwrap();
+            assert!(verify_key(&spdf, &test_support::DOC_KEY).is_ok());
+            assert!(matches!(
+                verify_key(&spdf, &[0u8; 32]),
+                Err(SpdfError::DecryptionError(_))
+            ));
+        }
+    }
 }
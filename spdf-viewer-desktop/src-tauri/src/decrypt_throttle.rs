@@ -0,0 +1,77 @@
+// Decrypt Throttle Module - Rate limit on key attempts per file
+//
+// `decrypt_spdf` and every other command that tests a caller's key take
+// that key from the frontend, so a compromised frontend could try keys
+// against a file as fast as IPC allows. Each attempt is counted against
+// the file before its key is tried, which holds for attempts made in
+// parallel too, and a file with `MAX_FAILED_ATTEMPTS` in the last
+// `ATTEMPT_WINDOW` is refused until the oldest ages out. A successful decrypt clears the file's count.
+//
+// Counts live in memory only and reset when the app restarts.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Refusal message once a file's attempts are used up
+pub const TOO_MANY_ATTEMPTS: &str = "too many decryption attempts";
+
+/// Failed attempts allowed per file within `ATTEMPT_WINDOW`
+pub const MAX_FAILED_ATTEMPTS: usize = 5;
+
+pub const ATTEMPT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Recent unsuccessful attempts, by file
+#[derive(Debug, Default)]
+pub struct DecryptThrottle {
+    attempts: Mutex<HashMap<String, Vec<Instant>>>,
+}
+
+impl DecryptThrottle {
+    /// Count an attempt on `file`, or refuse it if the file's limit is
+    /// reached; the attempt stays counted unless `succeeded` is called
+    pub fn begin(&self, file: &str, now: Instant) -> Result<(), String> {
+        let mut attempts = self.attempts.lock().unwrap_or_else(|p| p.into_inner());
+        let recent = attempts.entry(file.to_string()).or_default();
+        recent.retain(|&at| now.saturating_duration_since(at) < ATTEMPT_WINDOW);
+        if recent.len() >= MAX_FAILED_ATTEMPTS {
+            return Err(TOO_MANY_ATTEMPTS.to_string());
+        }
+        recent.push(now);
+        Ok(())
+    }
+
+    /// Forget `file`'s attempts after it decrypted
+    pub fn succeeded(&self, file: &str) {
+        let mut attempts = self.attempts.lock().unwrap_or_else(|p| p.into_inner());
+        attempts.remove(file);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle_after_failed_attempts() {
+        let throttle = DecryptThrottle::default();
+        let start = Instant::now();
+
+        for _ in 0..MAX_FAILED_ATTEMPTS {
+            throttle.begin("a.spdf", start).unwrap();
+        }
+        assert_eq!(
+            throttle.begin("a.spdf", start),
+            Err(TOO_MANY_ATTEMPTS.to_string())
+        );
+        // Other files are unaffected, and the window slides
+        assert!(throttle.begin("b.spdf", start).is_ok());
+        assert!(throttle.begin("a.spdf", start + ATTEMPT_WINDOW).is_ok());
+
+        // Success resets the count
+        throttle.succeeded("a.spdf");
+        for _ in 0..MAX_FAILED_ATTEMPTS {
+            throttle.begin("a.spdf", start + ATTEMPT_WINDOW).unwrap();
+        }
+    }
+}
@@ -0,0 +1,50 @@
+// Dev Mode Module - Local stand-in for the key server
+//
+// Lets frontend work on the open flow go ahead without a live server. With
+// the `dev_mode` feature compiled in and SPDF_DEV_MODE=1 set,
+// `open_spdf_file` takes keys from `MockKeyProvider` instead of calling
+// `/keys/get`, and no login is needed. Every document gets DEV_DOC_KEY, so
+// local test files must be encrypted with it.
+//
+// The feature refuses to build without debug assertions, so a release build
+// can never carry it.
+
+#[cfg(not(debug_assertions))]
+compile_error!("the dev_mode feature must not be enabled in release builds");
+
+use base64::{engine::general_purpose, Engine as _};
+
+use crate::auth::DeviceInfo;
+use crate::spdf::SpdfHeader;
+use crate::{KeyOutcome, KeyResponse};
+
+/// Set to "1" to route key requests to `MockKeyProvider`
+pub const DEV_MODE_ENV: &str = "SPDF_DEV_MODE";
+/// Document key granted for every document in dev mode
+pub const DEV_DOC_KEY: [u8; 32] = *b"spdf-dev-mode-local-doc-key-32b!";
+
+/// Grants every document the same key and its own header permissions
+pub struct MockKeyProvider;
+
+impl MockKeyProvider {
+    /// The provider, if SPDF_DEV_MODE=1
+    pub fn from_env() -> Option<Self> {
+        (std::env::var(DEV_MODE_ENV).as_deref() == Ok("1")).then_some(MockKeyProvider)
+    }
+
+    /// What the key server would answer for `header` on this device
+    pub fn key_outcome(&self, header: &SpdfHeader, device_info: &DeviceInfo) -> KeyOutcome {
+        KeyOutcome::Granted(KeyResponse {
+            k_doc: general_purpose::STANDARD.encode(DEV_DOC_KEY),
+            permissions: header.permissions.clone(),
+            watermark_data: serde_json::json!({
+                "user_email": "dev@localhost",
+                "device_id": device_info.device_id,
+            }),
+            opens_remaining: None,
+            expiry_extension: None,
+            org_keys: Vec::new(),
+            bound_device_id: Some(device_info.device_id.clone()),
+        })
+    }
+}
@@ -3,15 +3,12 @@
 // This module generates a deterministic device hash from hardware information
 // that can be used to bind licenses to specific devices.
 
+use serde::{Deserialize, Serialize};
 use sha2::{Sha256, Digest};
+use subtle::ConstantTimeEq;
 use sysinfo::System;
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
 
-#[cfg(target_os = "windows")]
-use winreg::enums::*;
-#[cfg(target_os = "windows")]
-use winreg::RegKey;
+use crate::platform::stable_machine_id;
 
 /// Error type for device ID operations
 #[derive(Debug)]
@@ -32,7 +29,7 @@ impl std::fmt::Display for DeviceIdError {
 impl std::error::Error for DeviceIdError {}
 
 /// Hardware information collected for fingerprinting
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct HardwareInfo {
     pub cpu_id: String,
     pub os_info: String,
@@ -53,15 +50,10 @@ impl HardwareInfo {
             .unwrap_or_else(|| "unknown-cpu".to_string());
 
         // Get OS information
-        let os_info = format!(
-            "{}-{}-{}",
-            System::name().unwrap_or_default(),
-            System::os_version().unwrap_or_default(),
-            System::kernel_version().unwrap_or_default()
-        );
+        let os_info = os_parts().join("-");
 
         // Get machine ID (platform-specific)
-        let machine_id = get_machine_id().unwrap_or_else(|_| "unknown-machine".to_string());
+        let machine_id = stable_machine_id().unwrap_or_else(|_| "unknown-machine".to_string());
 
         // Get hostname
         let hostname = System::host_name().unwrap_or_else(|| "unknown-host".to_string());
@@ -75,95 +67,259 @@ impl HardwareInfo {
     }
 }
 
-/// Get machine-specific ID based on platform
-#[cfg(target_os = "windows")]
-fn get_machine_id() -> Result<String, DeviceIdError> {
-    // Try to get Windows Machine GUID
-    let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
-    
-    if let Ok(key) = hklm.open_subkey("SOFTWARE\\Microsoft\\Cryptography") {
-        if let Ok(guid) = key.get_value::<String, _>("MachineGuid") {
-            return Ok(guid);
+/// OS name, OS version and kernel version, as `os_info` joins them
+fn os_parts() -> [String; 3] {
+    [
+        System::name().unwrap_or_default(),
+        System::os_version().unwrap_or_default(),
+        System::kernel_version().unwrap_or_default(),
+    ]
+}
+
+/// Which volatile components a device hash covers
+///
+/// The CPU, machine-id and OS name are stable and always hashed. The OS
+/// and kernel versions change with system updates, and each one hashed
+/// makes the hash tell more machines apart (a cloned disk image on a
+/// patched and an unpatched box, say) at the cost of a new device ID, and
+/// a broken binding, after every update. `strict` hashes everything, as
+/// `generate_device_hash` always has; the default drops the kernel
+/// version, which changes far more often than the OS version. Each policy
+/// hashes under its own version (`hash_version`), so a hash says which
+/// components to recompute it from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FingerprintPolicy {
+    pub os_version: bool,
+    pub kernel_version: bool,
+}
+
+impl Default for FingerprintPolicy {
+    fn default() -> Self {
+        FingerprintPolicy {
+            os_version: true,
+            kernel_version: false,
         }
     }
-    
-    // Fallback to product ID
-    if let Ok(key) = hklm.open_subkey("SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion") {
-        if let Ok(product_id) = key.get_value::<String, _>("ProductId") {
-            return Ok(product_id);
+}
+
+impl FingerprintPolicy {
+    /// Every component; the hash `generate_device_hash` returns
+    pub fn strict() -> Self {
+        FingerprintPolicy {
+            os_version: true,
+            kernel_version: true,
         }
     }
-    
-    Err(DeviceIdError::SystemInfoError("Could not get Windows machine ID".to_string()))
-}
 
-#[cfg(target_os = "linux")]
-fn get_machine_id() -> Result<String, DeviceIdError> {
-    // Try /etc/machine-id first
-    if let Ok(id) = std::fs::read_to_string("/etc/machine-id") {
-        return Ok(id.trim().to_string());
+    /// Device hash version for this policy's components; strict is
+    /// version 1, the derivation `generate_device_hash` has always used
+    pub fn hash_version(&self) -> u32 {
+        match (self.os_version, self.kernel_version) {
+            (true, true) => 1,
+            (true, false) => 2,
+            (false, true) => 3,
+            (false, false) => 4,
+        }
     }
-    
-    // Try /var/lib/dbus/machine-id
-    if let Ok(id) = std::fs::read_to_string("/var/lib/dbus/machine-id") {
-        return Ok(id.trim().to_string());
+
+    /// The policy whose hashes carry `version`
+    pub fn for_hash_version(version: u32) -> Option<Self> {
+        let (os_version, kernel_version) = match version {
+            1 => (true, true),
+            2 => (true, false),
+            3 => (false, true),
+            4 => (false, false),
+            _ => return None,
+        };
+        Some(FingerprintPolicy {
+            os_version,
+            kernel_version,
+        })
     }
-    
-    Err(DeviceIdError::SystemInfoError("Could not get Linux machine ID".to_string()))
-}
 
-#[cfg(target_os = "macos")]
-fn get_machine_id() -> Result<String, DeviceIdError> {
-    use std::process::Command;
-    
-    // Get hardware UUID via ioreg
-    let output = Command::new("ioreg")
-        .args(["-rd1", "-c", "IOPlatformExpertDevice"])
-        .output()
-        .map_err(|e| DeviceIdError::SystemInfoError(e.to_string()))?;
-    
-    let result = String::from_utf8_lossy(&output.stdout);
-    
-    for line in result.lines() {
-        if line.contains("IOPlatformUUID") {
-            if let Some(uuid) = line.split('"').nth(3) {
-                return Ok(uuid.to_string());
-            }
+    /// `os_info` with the components this policy leaves out dropped
+    fn os_info(&self, [name, os_version, kernel_version]: [String; 3]) -> String {
+        let mut parts = vec![name];
+        if self.os_version {
+            parts.push(os_version);
+        }
+        if self.kernel_version {
+            parts.push(kernel_version);
         }
+        parts.join("-")
     }
-    
-    Err(DeviceIdError::SystemInfoError("Could not get macOS hardware UUID".to_string()))
-}
-
-#[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
-fn get_machine_id() -> Result<String, DeviceIdError> {
-    // For other platforms, use a hash of system info
-    let mut hasher = DefaultHasher::new();
-    System::host_name().hash(&mut hasher);
-    Ok(format!("{:016x}", hasher.finish()))
 }
 
 /// Salt for device fingerprinting (should match server)
 const DEVICE_SALT: &[u8] = b"spdf_device_salt_v1";
 
-/// Generate a deterministic device hash from hardware info
-pub fn generate_device_hash() -> Result<String, DeviceIdError> {
-    let info = HardwareInfo::collect()?;
-    
+/// Derivation `generate_device_hash` uses, named in its `v<N>:` prefix
+///
+/// A new salt or set of components gets a new version, and hashes of the
+/// old one still verify by their own derivation, so bindings can move over
+/// one at a time. Versions 2 to 4 are the narrower `FingerprintPolicy`
+/// component sets.
+pub const DEVICE_HASH_VERSION: u32 = 1;
+
+/// The hardware fields that feed the device hash, supplied explicitly
+///
+/// Lets the server ship a test vector (inputs, salt, expected hash) that
+/// the client checks without touching the live system.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HardwareInfoInput {
+    pub cpu_id: String,
+    pub machine_id: String,
+    pub os_info: String,
+}
+
+impl From<&HardwareInfo> for HardwareInfoInput {
+    fn from(info: &HardwareInfo) -> Self {
+        HardwareInfoInput {
+            cpu_id: info.cpu_id.clone(),
+            machine_id: info.machine_id.clone(),
+            os_info: info.os_info.clone(),
+        }
+    }
+}
+
+/// Names of the components that feed the device hash, in hash order
+pub const DEVICE_HASH_COMPONENTS: [&str; 3] = ["cpu_id", "machine_id", "os_info"];
+
+impl HardwareInfoInput {
+    /// Labeled (component_name, value) pairs, in `DEVICE_HASH_COMPONENTS`
+    /// order
+    pub fn components(&self) -> Vec<(String, String)> {
+        DEVICE_HASH_COMPONENTS
+            .iter()
+            .zip([&self.cpu_id, &self.machine_id, &self.os_info])
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect()
+    }
+
+    /// Rebuild the input from labeled components, in any order; each of
+    /// `DEVICE_HASH_COMPONENTS` must be present and others are ignored
+    pub fn from_components(components: &[(String, String)]) -> Result<Self, DeviceIdError> {
+        let value = |name: &str| {
+            components
+                .iter()
+                .find(|(component, _)| component == name)
+                .map(|(_, value)| value.clone())
+                .ok_or_else(|| DeviceIdError::HashError(format!("missing component '{}'", name)))
+        };
+        Ok(HardwareInfoInput {
+            cpu_id: value("cpu_id")?,
+            machine_id: value("machine_id")?,
+            os_info: value("os_info")?,
+        })
+    }
+}
+
+/// SHA256(salt || cpu_id ":" machine_id ":" os_info), hex-encoded
+fn compute_device_hash(hardware: &HardwareInfoInput, salt: &[u8]) -> String {
     let mut hasher = Sha256::new();
     
     // Add salt
-    hasher.update(DEVICE_SALT);
+    hasher.update(salt);
     
     // Add hardware info components
-    hasher.update(info.cpu_id.as_bytes());
+    hasher.update(hardware.cpu_id.as_bytes());
     hasher.update(b":");
-    hasher.update(info.machine_id.as_bytes());
+    hasher.update(hardware.machine_id.as_bytes());
     hasher.update(b":");
-    hasher.update(info.os_info.as_bytes());
+    hasher.update(hardware.os_info.as_bytes());
     
-    let result = hasher.finalize();
-    Ok(hex::encode(result))
+    hex::encode(hasher.finalize())
+}
+
+/// `v1:` followed by the version 1 hex digest of `hardware`
+fn device_hash_v1(hardware: &HardwareInfoInput) -> String {
+    format!("v1:{}", compute_device_hash(hardware, DEVICE_SALT))
+}
+
+/// Split a device hash into its version and hex digest
+///
+/// Accepts `v<N>:<hex>` and the legacy bare hex, which is version 1. The
+/// digest must be 64 hex characters, in either case.
+pub fn parse_device_hash(hash: &str) -> Option<(u32, &str)> {
+    let (version, digest) = match hash.strip_prefix('v').and_then(|rest| rest.split_once(':')) {
+        Some((version, digest)) => (version.parse().ok()?, digest),
+        None => (1, hash),
+    };
+    let is_digest = digest.len() == 64 && digest.bytes().all(|b| b.is_ascii_hexdigit());
+    is_digest.then_some((version, digest))
+}
+
+/// The hex digest of a device hash without its version prefix, or the
+/// input unchanged if it is not a device hash
+///
+/// Local keys and offline grants are tied to the digest, so they carry
+/// over from legacy bare-hex hashes to prefixed ones.
+pub fn device_hash_digest(hash: &str) -> &str {
+    parse_device_hash(hash).map_or(hash, |(_, digest)| digest)
+}
+
+/// Device hash of already collected hardware info
+pub fn device_hash_of(info: &HardwareInfo) -> String {
+    device_hash_v1(&HardwareInfoInput::from(info))
+}
+
+/// The labeled hardware components this device's hash is computed from
+///
+/// Comparing them with an earlier snapshot shows which one changed (an OS
+/// upgrade, a new machine-id) when a device binding stops matching.
+pub fn device_fingerprint_components() -> Result<Vec<(String, String)>, DeviceIdError> {
+    let info = HardwareInfo::collect()?;
+    Ok(HardwareInfoInput::from(&info).components())
+}
+
+/// Device hash of labeled components, as `device_fingerprint_components`
+/// returns them
+pub fn device_hash_from_components(
+    components: &[(String, String)],
+) -> Result<String, DeviceIdError> {
+    let hardware = HardwareInfoInput::from_components(components)?;
+    Ok(device_hash_v1(&hardware))
+}
+
+/// Generate a deterministic device hash from hardware info, as
+/// `v<DEVICE_HASH_VERSION>:<hex>`
+///
+/// Hashes every component (`FingerprintPolicy::strict`), so existing
+/// bindings keep matching; see `generate_device_hash_with_policy`.
+pub fn generate_device_hash() -> Result<String, DeviceIdError> {
+    generate_device_hash_version(DEVICE_HASH_VERSION)
+}
+
+/// This device's hash under the derivation of `version`
+pub fn generate_device_hash_version(version: u32) -> Result<String, DeviceIdError> {
+    let policy = FingerprintPolicy::for_hash_version(version).ok_or_else(|| {
+        DeviceIdError::HashError(format!("unsupported device hash version {}", version))
+    })?;
+    if version == DEVICE_HASH_VERSION {
+        return device_hash_from_components(&device_fingerprint_components()?);
+    }
+    let info = HardwareInfo::collect()?;
+    let hardware = HardwareInfoInput {
+        os_info: policy.os_info(os_parts()),
+        ..HardwareInfoInput::from(&info)
+    };
+    Ok(format!("v{}:{}", version, compute_device_hash(&hardware, DEVICE_SALT)))
+}
+
+/// Device hash over the components `policy` keeps, as
+/// `v<policy.hash_version()>:<hex>`
+pub fn generate_device_hash_with_policy(
+    policy: FingerprintPolicy,
+) -> Result<String, DeviceIdError> {
+    generate_device_hash_version(policy.hash_version())
+}
+
+/// Check a server-provided test vector against the client's derivation
+///
+/// Catches client/server drift in how the device hash is computed. The
+/// comparison ignores hex case.
+pub fn verify_device_hash_vector(hardware: HardwareInfoInput, salt: &str, expected: &str) -> bool {
+    compute_device_hash(&hardware, salt.as_bytes()).eq_ignore_ascii_case(expected)
 }
 
 /// Get a human-readable device name
@@ -174,9 +330,36 @@ pub fn get_device_name() -> String {
 }
 
 /// Verify that the current device matches a given hash
+///
+/// The hash may be prefixed or legacy bare hex; this device's hash is
+/// derived with whichever version it names. A version this build does not
+/// know is an error.
 pub fn verify_device_hash(expected_hash: &str) -> Result<bool, DeviceIdError> {
-    let current_hash = generate_device_hash()?;
-    Ok(current_hash == expected_hash)
+    let Some((version, _)) = parse_device_hash(expected_hash) else {
+        return Ok(false);
+    };
+    let current_hash = generate_device_hash_version(version)?;
+    Ok(device_hashes_match(&current_hash, expected_hash))
+}
+
+/// Compare two device hashes in constant time
+///
+/// Both must parse (`parse_device_hash`) to the same version; a legacy bare
+/// hash equals its `v1:` form. Anything else is rejected before the
+/// constant-time comparison of the digests.
+pub(crate) fn device_hashes_match(current: &str, expected: &str) -> bool {
+    let (Some((current_version, current)), Some((expected_version, expected))) =
+        (parse_device_hash(current), parse_device_hash(expected))
+    else {
+        return false;
+    };
+    if current_version != expected_version {
+        return false;
+    }
+    match (hex::decode(current), hex::decode(expected)) {
+        (Ok(current), Ok(expected)) => current.ct_eq(&expected).into(),
+        _ => false,
+    }
 }
 
 #[cfg(test)]
@@ -199,13 +382,159 @@ mod tests {
         assert!(hash1.is_ok());
         
         let hash1 = hash1.unwrap();
-        assert_eq!(hash1.len(), 64); // SHA-256 hex = 64 chars
+        assert_eq!(hash1.len(), 67); // "v1:" + SHA-256 hex = 64 chars
+        assert!(hash1.starts_with("v1:"));
         
         // Hash should be deterministic
         let hash2 = generate_device_hash().unwrap();
         assert_eq!(hash1, hash2);
     }
 
+    #[test]
+    fn test_device_hashes_match() {
+        let hash = generate_device_hash().unwrap();
+        let digest = device_hash_digest(&hash);
+        assert!(device_hashes_match(&hash, &hash));
+        assert!(device_hashes_match(&hash, &format!("v1:{}", digest.to_uppercase())));
+        assert!(verify_device_hash(&hash).unwrap());
+
+        let other = format!("{}0", &digest[..63]);
+        let other = if other == digest { format!("{}1", &digest[..63]) } else { other };
+        assert!(!device_hashes_match(&hash, &other));
+        assert!(!device_hashes_match(&hash, &digest[..62]));
+        assert!(!device_hashes_match(&hash, &format!("{}zz", &digest[..62])));
+        assert!(!device_hashes_match("", ""));
+    }
+
+    #[test]
+    fn test_device_hash_versions() {
+        let hash = generate_device_hash().unwrap();
+        let digest = device_hash_digest(&hash);
+        assert_eq!(parse_device_hash(&hash), Some((1, digest)));
+
+        // A legacy bare-hex binding verifies as version 1
+        assert_eq!(parse_device_hash(digest), Some((1, digest)));
+        assert!(device_hashes_match(&hash, digest));
+        assert!(verify_device_hash(digest).unwrap());
+
+        // Another version's hash of the same digest is a different hash
+        let v2 = format!("v2:{}", digest);
+        assert_eq!(parse_device_hash(&v2), Some((2, digest)));
+        assert!(!device_hashes_match(&hash, &v2));
+        assert!(!verify_device_hash(&v2).unwrap());
+        assert!(matches!(
+            verify_device_hash(&format!("v9:{}", digest)),
+            Err(DeviceIdError::HashError(msg)) if msg == "unsupported device hash version 9"
+        ));
+
+        assert_eq!(parse_device_hash("v1:abcd"), None);
+        assert_eq!(parse_device_hash(&format!("vx:{}", digest)), None);
+        assert!(!verify_device_hash("not-a-hash").unwrap());
+        assert_eq!(device_hash_digest("device-a"), "device-a");
+    }
+
+    fn vector_input() -> HardwareInfoInput {
+        HardwareInfoInput {
+            cpu_id: "Intel(R) Core(TM) i7-9750H-GenuineIntel".to_string(),
+            machine_id: "4c4c4544-0042-3610-8052-b2c04f4d3732".to_string(),
+            os_info: "Windows-10-19045".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_device_hash_vector() {
+        // hashlib.sha256(b"spdf_device_salt_v1" + b"<cpu>:<machine>:<os>").hexdigest()
+        let expected = "3bfde6946834c1af012d7da1397ce370433a9235787340a41cadd53df1945e54";
+        assert!(verify_device_hash_vector(vector_input(), "spdf_device_salt_v1", expected));
+        assert!(verify_device_hash_vector(
+            vector_input(),
+            "spdf_device_salt_v1",
+            &expected.to_uppercase()
+        ));
+    }
+
+    #[test]
+    fn test_device_hash_vector_mismatch() {
+        let expected = "3bfde6946834c1af012d7da1397ce370433a9235787340a41cadd53df1945e54";
+        assert!(!verify_device_hash_vector(vector_input(), "other_salt", expected));
+
+        let mut input = vector_input();
+        input.os_info = "Windows-11-22631".to_string();
+        assert!(!verify_device_hash_vector(input, "spdf_device_salt_v1", expected));
+    }
+
+    #[test]
+    fn test_device_hash_uses_vector_derivation() {
+        let info = HardwareInfo::collect().unwrap();
+        let hash = generate_device_hash().unwrap();
+        assert!(verify_device_hash_vector(
+            HardwareInfoInput::from(&info),
+            std::str::from_utf8(DEVICE_SALT).unwrap(),
+            device_hash_digest(&hash)
+        ));
+    }
+
+    #[test]
+    fn test_device_hash_from_components() {
+        let components = vector_input().components();
+        let names: Vec<&str> = components.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, DEVICE_HASH_COMPONENTS);
+
+        // Same derivation as the hash vector, whatever the order
+        let mut reordered = components.clone();
+        reordered.reverse();
+        reordered.push(("hostname".to_string(), "ignored".to_string()));
+        let expected = "v1:3bfde6946834c1af012d7da1397ce370433a9235787340a41cadd53df1945e54";
+        assert_eq!(device_hash_from_components(&components).unwrap(), expected);
+        assert_eq!(device_hash_from_components(&reordered).unwrap(), expected);
+
+        let missing = &components[..2];
+        assert!(matches!(
+            device_hash_from_components(missing),
+            Err(DeviceIdError::HashError(msg)) if msg == "missing component 'os_info'"
+        ));
+
+        let live = device_fingerprint_components().unwrap();
+        assert_eq!(device_hash_from_components(&live).unwrap(), generate_device_hash().unwrap());
+    }
+
+    #[test]
+    fn test_fingerprint_policy() {
+        let parts = || ["Windows".to_string(), "10".to_string(), "19045".to_string()];
+        assert_eq!(FingerprintPolicy::strict().os_info(parts()), "Windows-10-19045");
+        assert_eq!(FingerprintPolicy::default().os_info(parts()), "Windows-10");
+        let stable_only = FingerprintPolicy {
+            os_version: false,
+            kernel_version: false,
+        };
+        assert_eq!(stable_only.os_info(parts()), "Windows");
+
+        // Strict matches the existing hash
+        let strict = generate_device_hash_with_policy(FingerprintPolicy::strict()).unwrap();
+        assert_eq!(strict, generate_device_hash().unwrap());
+        let relaxed = generate_device_hash_with_policy(FingerprintPolicy::default()).unwrap();
+        assert_eq!(parse_device_hash(&relaxed).unwrap().0, 2);
+        assert!(relaxed.starts_with("v2:"));
+        assert_ne!(relaxed, strict);
+
+        // Each hash verifies under the policy it was made with, and only that one
+        assert!(verify_device_hash(&strict).unwrap());
+        assert!(verify_device_hash(&relaxed).unwrap());
+        assert!(!device_hashes_match(&relaxed, &strict));
+        let relaxed_digest = device_hash_digest(&relaxed);
+        assert!(!verify_device_hash(&format!("v1:{}", relaxed_digest)).unwrap());
+
+        for version in 1..=4 {
+            let policy = FingerprintPolicy::for_hash_version(version).unwrap();
+            assert_eq!(policy.hash_version(), version);
+            let hash = generate_device_hash_with_policy(policy).unwrap();
+            assert!(hash.starts_with(&format!("v{}:", version)));
+            assert!(verify_device_hash(&hash).unwrap());
+        }
+        assert!(FingerprintPolicy::for_hash_version(5).is_none());
+        assert!(verify_device_hash(&format!("v5:{}", relaxed_digest)).is_err());
+    }
+
     #[test]
     fn test_device_name() {
         let name = get_device_name();
@@ -0,0 +1,125 @@
+// Device Limit Module - Local check of `permissions.max_devices`
+//
+// The key server enforces `max_devices`; this is a second, local layer.
+// Each successful open records the device_id it was made from, per doc_id,
+// in ~/.spdf/devices.json, and an open from a device the installation has
+// not seen is refused once `max_devices` others already have.
+//
+// Like the open counter this is advisory: it only sees devices that share
+// this ~/.spdf (a synced or copied home directory, a replaced machine),
+// and deleting the file resets it. It never lets a document open that the
+// server refused; it only refuses earlier.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Outcome of checking a device against a document's limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceCheck {
+    /// Devices recorded for the document, not counting this one if new
+    pub devices: u32,
+    pub max_devices: u32,
+    /// This device is new and would take the count past `max_devices`
+    pub limit_reached: bool,
+}
+
+/// Persistent doc_id -> activating device_ids map
+#[derive(Debug)]
+pub struct DeviceRegistry {
+    path: PathBuf,
+    devices: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl DeviceRegistry {
+    /// Default registry file, `~/.spdf/devices.json`
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::home_dir().map(|home| home.join(".spdf").join("devices.json"))
+    }
+
+    /// Load devices from `path`; a missing file means none recorded yet
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let devices = if path.exists() {
+            let data = fs::read(path).map_err(|e| format!("Failed to read device list: {}", e))?;
+            serde_json::from_slice(&data).map_err(|e| format!("Invalid device list: {}", e))?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(DeviceRegistry {
+            path: path.to_path_buf(),
+            devices,
+        })
+    }
+
+    /// Distinct devices recorded as having opened `doc_id`
+    pub fn device_count(&self, doc_id: &str) -> u32 {
+        self.devices.get(doc_id).map_or(0, |devices| devices.len() as u32)
+    }
+
+    /// Whether `device_id` may open `doc_id` under `max_devices`
+    pub fn check(&self, doc_id: &str, device_id: &str, max_devices: u32) -> DeviceCheck {
+        let known = self
+            .devices
+            .get(doc_id)
+            .is_some_and(|devices| devices.contains(device_id));
+        let devices = self.device_count(doc_id);
+        DeviceCheck {
+            devices,
+            max_devices,
+            limit_reached: !known && devices >= max_devices,
+        }
+    }
+
+    /// Record that `device_id` opened `doc_id`, and save
+    pub fn record(&mut self, doc_id: &str, device_id: &str) -> Result<(), String> {
+        let devices = self.devices.entry(doc_id.to_string()).or_default();
+        if !devices.insert(device_id.to_string()) {
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create store dir: {}", e))?;
+        }
+        let json = serde_json::to_vec_pretty(&self.devices)
+            .map_err(|e| format!("Failed to serialize device list: {}", e))?;
+        fs::write(&self.path, json).map_err(|e| format!("Failed to write device list: {}", e))
+    }
+}
+
+/// Distinct devices this installation has seen open `doc_id`; 0 if the
+/// registry cannot be read
+pub fn local_device_count(doc_id: &str) -> u32 {
+    DeviceRegistry::default_path()
+        .and_then(|path| DeviceRegistry::load(&path).ok())
+        .map_or(0, |registry| registry.device_count(doc_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_counts_distinct_devices() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("devices.json");
+        let mut registry = DeviceRegistry::load(&path).unwrap();
+        assert_eq!(registry.device_count("DOC-1"), 0);
+
+        registry.record("DOC-1", "DEVICE-A").unwrap();
+        registry.record("DOC-1", "DEVICE-A").unwrap();
+        registry.record("DOC-1", "DEVICE-B").unwrap();
+        assert_eq!(registry.device_count("DOC-1"), 2);
+
+        // Known devices keep working at the limit; a third is refused
+        assert!(!registry.check("DOC-1", "DEVICE-B", 2).limit_reached);
+        let check = registry.check("DOC-1", "DEVICE-C", 2);
+        assert!(check.limit_reached);
+        assert_eq!(check.devices, 2);
+        assert!(!registry.check("DOC-1", "DEVICE-C", 3).limit_reached);
+        assert!(!registry.check("DOC-2", "DEVICE-C", 1).limit_reached);
+
+        // Persisted across restarts
+        let reloaded = DeviceRegistry::load(&path).unwrap();
+        assert!(reloaded.check("DOC-1", "DEVICE-C", 2).limit_reached);
+    }
+}
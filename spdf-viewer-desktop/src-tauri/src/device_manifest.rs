@@ -0,0 +1,171 @@
+// Device Manifest Module - Org-signed lists of devices allowed offline
+//
+// A device manifest names the device hashes (see `device_id`) an
+// organization allows to hold a document's key offline. It is signed with
+// the org's Ed25519 key over the doc_id and the hashes, in the order
+// listed, so entries cannot be added, removed or moved to another
+// document without the signature failing.
+//
+// Any hash can be checked against a manifest, not only this device's, so
+// admins can confirm a manifest covers the machines they expect.
+
+use base64::{engine::general_purpose, Engine as _};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+use crate::device_id::{device_hashes_match, parse_device_hash};
+use crate::spdf_parser::SpdfFile;
+
+/// Domain separator at the start of every signed manifest
+const MANIFEST_PREFIX: &[u8] = b"spdf-device-manifest-v1\n";
+
+/// Signed list of the devices allowed to open one document offline
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceManifest {
+    pub doc_id: String,
+    /// Device hashes, as `generate_device_hash` returns them or legacy bare
+    /// hex
+    pub device_hashes: Vec<String>,
+    /// Base64 Ed25519 signature over `manifest_message`
+    pub signature: String,
+}
+
+/// The bytes the org signs to allow `device_hashes` for `doc_id`
+pub fn manifest_message(doc_id: &str, device_hashes: &[String]) -> Vec<u8> {
+    let mut message = MANIFEST_PREFIX.to_vec();
+    message.extend_from_slice(doc_id.as_bytes());
+    for hash in device_hashes {
+        message.push(b'\n');
+        message.extend_from_slice(hash.as_bytes());
+    }
+    message
+}
+
+impl DeviceManifest {
+    /// Check the manifest's signature against the org key
+    pub fn verify(&self, org_key: &[u8; 32]) -> Result<(), String> {
+        let verifying_key =
+            VerifyingKey::from_bytes(org_key).map_err(|e| format!("Invalid org key: {}", e))?;
+        let signature: [u8; 64] = general_purpose::STANDARD
+            .decode(&self.signature)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or("device manifest signature is malformed")?;
+        verifying_key
+            .verify(
+                &manifest_message(&self.doc_id, &self.device_hashes),
+                &Signature::from_bytes(&signature),
+            )
+            .map_err(|_| "device manifest signature does not verify".to_string())
+    }
+
+    /// Whether `device_hash` is listed (ignoring hex case); does not check
+    /// the signature
+    pub fn lists(&self, device_hash: &str) -> bool {
+        self.device_hashes
+            .iter()
+            .any(|listed| device_hashes_match(listed, device_hash))
+    }
+}
+
+/// Whether `manifest`, verified against `org_key`, allows the device with
+/// `device_hash` to open `spdf` offline
+///
+/// The device need not be this one. Fails if the manifest is for another
+/// document or its signature does not verify, and if `device_hash` is not
+/// a device hash, prefixed or legacy bare hex.
+pub fn would_accept_device(
+    spdf: &SpdfFile,
+    device_hash: &str,
+    manifest: &DeviceManifest,
+    org_key: &[u8; 32],
+) -> Result<bool, String> {
+    if manifest.doc_id != spdf.header.doc_id {
+        return Err(format!(
+            "device manifest is for {}, not {}",
+            manifest.doc_id, spdf.header.doc_id
+        ));
+    }
+    manifest.verify(org_key)?;
+    if parse_device_hash(device_hash).is_none() {
+        return Err(format!("'{}' is not a device hash", device_hash));
+    }
+    Ok(manifest.lists(device_hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{build_spdf, signing_key};
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const IN: &str = "3bfde6946834c1af012d7da1397ce370433a9235787340a41cadd53df1945e54";
+    const OUT: &str = "0000000000000000000000000000000000000000000000000000000000000001";
+
+    fn manifest(key: &SigningKey, doc_id: &str, device_hashes: &[&str]) -> DeviceManifest {
+        let device_hashes: Vec<String> = device_hashes.iter().map(|h| h.to_string()).collect();
+        let signature = key.sign(&manifest_message(doc_id, &device_hashes));
+        DeviceManifest {
+            doc_id: doc_id.to_string(),
+            device_hashes,
+            signature: general_purpose::STANDARD.encode(signature.to_bytes()),
+        }
+    }
+
+    #[test]
+    fn test_hash_in_and_out_of_manifest() {
+        let spdf = SpdfFile::parse(&build_spdf(b"%PDF")).unwrap();
+        let org_key = signing_key().verifying_key().to_bytes();
+        let manifest = manifest(&signing_key(), &spdf.header.doc_id, &[IN]);
+
+        assert_eq!(
+            would_accept_device(&spdf, IN, &manifest, &org_key),
+            Ok(true)
+        );
+        let upper = IN.to_uppercase();
+        assert_eq!(
+            would_accept_device(&spdf, &upper, &manifest, &org_key),
+            Ok(true)
+        );
+        let versioned = format!("v1:{}", IN);
+        assert_eq!(
+            would_accept_device(&spdf, &versioned, &manifest, &org_key),
+            Ok(true)
+        );
+        assert_eq!(
+            would_accept_device(&spdf, OUT, &manifest, &org_key),
+            Ok(false)
+        );
+        assert!(would_accept_device(&spdf, "not-a-hash", &manifest, &org_key).is_err());
+    }
+
+    #[test]
+    fn test_tampered_manifest_rejected() {
+        let spdf = SpdfFile::parse(&build_spdf(b"%PDF")).unwrap();
+        let org_key = signing_key().verifying_key().to_bytes();
+        let doc_id = spdf.header.doc_id.clone();
+
+        // A device added after signing
+        let mut added = manifest(&signing_key(), &doc_id, &[IN]);
+        added.device_hashes.push(OUT.to_string());
+        assert_eq!(
+            would_accept_device(&spdf, OUT, &added, &org_key),
+            Err("device manifest signature does not verify".to_string())
+        );
+
+        // Signed by someone else, or for another document
+        let forged = manifest(&SigningKey::from_bytes(&[0x33; 32]), &doc_id, &[OUT]);
+        assert!(would_accept_device(&spdf, OUT, &forged, &org_key).is_err());
+        let mut moved = manifest(&signing_key(), "OTHER-DOC", &[OUT]);
+        assert!(would_accept_device(&spdf, OUT, &moved, &org_key).is_err());
+        moved.doc_id = doc_id;
+        assert!(would_accept_device(&spdf, OUT, &moved, &org_key).is_err());
+
+        let mut garbled = manifest(&signing_key(), &spdf.header.doc_id, &[IN]);
+        garbled.signature = "!!".to_string();
+        assert_eq!(
+            would_accept_device(&spdf, IN, &garbled, &org_key),
+            Err("device manifest signature is malformed".to_string())
+        );
+    }
+}
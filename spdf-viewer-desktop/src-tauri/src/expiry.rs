@@ -0,0 +1,210 @@
+// Expiry Module - Server-granted extensions of `expires_at`
+//
+// `expires_at` is fixed when a document is created, but the key server can
+// push it back by returning an `expiry_extension` with a key grant. The
+// extension is signed with the org's Ed25519 key over the doc_id and the
+// new date, so it cannot be forged or moved to another document, and it
+// can only lengthen the document's life.
+//
+// Verified extensions are kept in ~/.spdf/expiry.json so offline checks
+// use the extended date. Entries stay signed and are re-verified on use,
+// so editing the file gains nothing.
+
+use base64::{engine::general_purpose, Engine as _};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// Domain separator at the start of every signed extension
+const EXTENSION_PREFIX: &[u8] = b"spdf-expiry-extension-v1\n";
+
+/// A signed later `expires_at` for one document
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExpiryExtension {
+    /// RFC 3339
+    pub new_expires_at: String,
+    /// Base64 Ed25519 signature over `extension_message`
+    pub signature: String,
+}
+
+/// The bytes the org signs to extend `doc_id` to `new_expires_at`
+pub fn extension_message(doc_id: &str, new_expires_at: &str) -> Vec<u8> {
+    let mut message = EXTENSION_PREFIX.to_vec();
+    message.extend_from_slice(doc_id.as_bytes());
+    message.push(b'\n');
+    message.extend_from_slice(new_expires_at.as_bytes());
+    message
+}
+
+/// Parse an RFC 3339 `expires_at` value
+pub fn parse_expires_at(value: &str) -> Result<OffsetDateTime, String> {
+    OffsetDateTime::parse(value, &Rfc3339)
+        .map_err(|e| format!("Invalid expires_at '{}': {}", value, e))
+}
+
+impl ExpiryExtension {
+    /// Check the extension against the org key and return the new expiry
+    ///
+    /// Fails if the signature does not verify for `doc_id`, if the document
+    /// has no `original` expiry to extend, or if the new date is earlier
+    /// than the original.
+    pub fn verify(
+        &self,
+        doc_id: &str,
+        original: Option<OffsetDateTime>,
+        org_key: &[u8; 32],
+    ) -> Result<OffsetDateTime, String> {
+        let verifying_key =
+            VerifyingKey::from_bytes(org_key).map_err(|e| format!("Invalid org key: {}", e))?;
+        let signature: [u8; 64] = general_purpose::STANDARD
+            .decode(&self.signature)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or("expiry extension signature is malformed")?;
+        verifying_key
+            .verify(
+                &extension_message(doc_id, &self.new_expires_at),
+                &Signature::from_bytes(&signature),
+            )
+            .map_err(|_| "expiry extension signature does not verify".to_string())?;
+
+        let original = original.ok_or("document has no expiry to extend")?;
+        let extended = parse_expires_at(&self.new_expires_at)?;
+        if extended < original {
+            return Err("expiry extension ends before the original expiry".to_string());
+        }
+        Ok(extended)
+    }
+}
+
+/// Persistent doc_id -> latest verified extension map
+#[derive(Debug)]
+pub struct ExtensionCache {
+    path: PathBuf,
+    extensions: BTreeMap<String, ExpiryExtension>,
+}
+
+impl ExtensionCache {
+    /// Default cache file, `~/.spdf/expiry.json`
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::home_dir().map(|home| home.join(".spdf").join("expiry.json"))
+    }
+
+    /// Load extensions from `path`; a missing file means none granted yet
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let extensions = if path.exists() {
+            let data =
+                fs::read(path).map_err(|e| format!("Failed to read expiry extensions: {}", e))?;
+            serde_json::from_slice(&data)
+                .map_err(|e| format!("Invalid expiry extensions: {}", e))?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(ExtensionCache {
+            path: path.to_path_buf(),
+            extensions,
+        })
+    }
+
+    pub fn get(&self, doc_id: &str) -> Option<&ExpiryExtension> {
+        self.extensions.get(doc_id)
+    }
+
+    /// Remember a verified extension and save
+    pub fn insert(&mut self, doc_id: &str, extension: ExpiryExtension) -> Result<(), String> {
+        self.extensions.insert(doc_id.to_string(), extension);
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create store dir: {}", e))?;
+        }
+        let json = serde_json::to_vec_pretty(&self.extensions)
+            .map_err(|e| format!("Failed to serialize expiry extensions: {}", e))?;
+        fs::write(&self.path, json).map_err(|e| format!("Failed to write expiry extensions: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const ORIGINAL: &str = "2025-06-01T00:00:00Z";
+
+    fn org_key() -> SigningKey {
+        SigningKey::from_bytes(&[0x11; 32])
+    }
+
+    fn extension(key: &SigningKey, doc_id: &str, new_expires_at: &str) -> ExpiryExtension {
+        let signature = key.sign(&extension_message(doc_id, new_expires_at));
+        ExpiryExtension {
+            new_expires_at: new_expires_at.to_string(),
+            signature: general_purpose::STANDARD.encode(signature.to_bytes()),
+        }
+    }
+
+    #[test]
+    fn test_valid_extension() {
+        let original = parse_expires_at(ORIGINAL).unwrap();
+        let public = org_key().verifying_key().to_bytes();
+
+        let ext = extension(&org_key(), "DOC-1", "2025-12-01T00:00:00Z");
+        let extended = ext.verify("DOC-1", Some(original), &public).unwrap();
+        assert_eq!(extended, parse_expires_at("2025-12-01T00:00:00Z").unwrap());
+
+        // Re-granting the same date is not a shortening
+        let same = extension(&org_key(), "DOC-1", ORIGINAL);
+        assert_eq!(
+            same.verify("DOC-1", Some(original), &public).unwrap(),
+            original
+        );
+
+        // Nothing to extend on a document that never expires
+        assert!(ext.verify("DOC-1", None, &public).is_err());
+    }
+
+    #[test]
+    fn test_forged_extension_rejected() {
+        let original = Some(parse_expires_at(ORIGINAL).unwrap());
+        let public = org_key().verifying_key().to_bytes();
+        let later = "2026-01-01T00:00:00Z";
+
+        // Signed by someone else
+        let forged = extension(&SigningKey::from_bytes(&[0x12; 32]), "DOC-1", later);
+        assert_eq!(
+            forged.verify("DOC-1", original, &public).unwrap_err(),
+            "expiry extension signature does not verify"
+        );
+
+        // Date moved after signing, or replayed for another document
+        let mut moved = extension(&org_key(), "DOC-1", later);
+        moved.new_expires_at = "2030-01-01T00:00:00Z".to_string();
+        assert!(moved.verify("DOC-1", original, &public).is_err());
+        assert!(extension(&org_key(), "DOC-1", later)
+            .verify("DOC-2", original, &public)
+            .is_err());
+
+        // Validly signed but shorter than the document's own expiry
+        let shorter = extension(&org_key(), "DOC-1", "2025-05-01T00:00:00Z");
+        assert_eq!(
+            shorter.verify("DOC-1", original, &public).unwrap_err(),
+            "expiry extension ends before the original expiry"
+        );
+    }
+
+    #[test]
+    fn test_cache_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("expiry.json");
+        let ext = extension(&org_key(), "DOC-1", "2025-12-01T00:00:00Z");
+
+        let mut cache = ExtensionCache::load(&path).unwrap();
+        assert!(cache.get("DOC-1").is_none());
+        cache.insert("DOC-1", ext.clone()).unwrap();
+
+        let reloaded = ExtensionCache::load(&path).unwrap();
+        assert_eq!(reloaded.get("DOC-1"), Some(&ext));
+    }
+}
@@ -0,0 +1,163 @@
+// Fingerprint Log Module - Device hash stability over time
+//
+// Device binding fails when any hashed hardware component changes, and
+// such failures are often intermittent (a VM reporting a different CPU
+// string, an OS update changing the kernel version). Snapshots of the
+// collected hardware info and the resulting hash are appended to
+// ~/.spdf/fingerprints.json, each noting which components differ from the
+// previous snapshot.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use time::OffsetDateTime;
+
+use crate::device_id::{device_hash_of, device_hashes_match, HardwareInfo};
+
+/// Snapshots kept; older ones are dropped first
+pub const MAX_SNAPSHOTS: usize = 500;
+
+/// One observation of the hardware info and device hash
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FingerprintSnapshot {
+    #[serde(with = "time::serde::rfc3339")]
+    pub taken_at: OffsetDateTime,
+    pub hardware: HardwareInfo,
+    pub device_hash: String,
+    /// Components that differ from the previous snapshot
+    pub drifted: Vec<String>,
+    /// Whether the device hash differs from the previous snapshot
+    pub hash_changed: bool,
+}
+
+/// Components of `current` that differ from `previous`
+fn drifted_components(previous: &HardwareInfo, current: &HardwareInfo) -> Vec<String> {
+    [
+        ("cpu_id", &previous.cpu_id, &current.cpu_id),
+        ("machine_id", &previous.machine_id, &current.machine_id),
+        ("os_info", &previous.os_info, &current.os_info),
+        ("hostname", &previous.hostname, &current.hostname),
+    ]
+    .into_iter()
+    .filter(|(_, before, after)| before != after)
+    .map(|(name, _, _)| name.to_string())
+    .collect()
+}
+
+/// Persistent, bounded list of snapshots, oldest first
+#[derive(Debug)]
+pub struct FingerprintLog {
+    path: PathBuf,
+    snapshots: Vec<FingerprintSnapshot>,
+}
+
+impl FingerprintLog {
+    /// Default log file, `~/.spdf/fingerprints.json`
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::home_dir().map(|home| home.join(".spdf").join("fingerprints.json"))
+    }
+
+    /// Load the log from `path`; a missing file means no snapshots yet
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let snapshots = if path.exists() {
+            let data =
+                fs::read(path).map_err(|e| format!("Failed to read fingerprint log: {}", e))?;
+            serde_json::from_slice(&data).map_err(|e| format!("Invalid fingerprint log: {}", e))?
+        } else {
+            Vec::new()
+        };
+        Ok(FingerprintLog {
+            path: path.to_path_buf(),
+            snapshots,
+        })
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create store dir: {}", e))?;
+        }
+        let json = serde_json::to_vec_pretty(&self.snapshots)
+            .map_err(|e| format!("Failed to serialize fingerprint log: {}", e))?;
+        fs::write(&self.path, json).map_err(|e| format!("Failed to write fingerprint log: {}", e))
+    }
+
+    pub fn snapshots(&self) -> &[FingerprintSnapshot] {
+        &self.snapshots
+    }
+
+    /// Append a snapshot of `hardware` taken at `taken_at` and save
+    pub fn record(
+        &mut self,
+        hardware: HardwareInfo,
+        taken_at: OffsetDateTime,
+    ) -> Result<FingerprintSnapshot, String> {
+        let device_hash = device_hash_of(&hardware);
+        let (drifted, hash_changed) = match self.snapshots.last() {
+            Some(previous) => (
+                drifted_components(&previous.hardware, &hardware),
+                !device_hashes_match(&previous.device_hash, &device_hash),
+            ),
+            None => (Vec::new(), false),
+        };
+
+        let snapshot = FingerprintSnapshot {
+            taken_at,
+            hardware,
+            device_hash,
+            drifted,
+            hash_changed,
+        };
+        self.snapshots.push(snapshot.clone());
+        if self.snapshots.len() > MAX_SNAPSHOTS {
+            let excess = self.snapshots.len() - MAX_SNAPSHOTS;
+            self.snapshots.drain(..excess);
+        }
+
+        self.save()?;
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Duration;
+
+    fn hardware() -> HardwareInfo {
+        HardwareInfo {
+            cpu_id: "Intel(R) Core(TM) i7-9750H-GenuineIntel".to_string(),
+            os_info: "Windows-10-19045".to_string(),
+            machine_id: "4c4c4544-0042-3610-8052-b2c04f4d3732".to_string(),
+            hostname: "laptop".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_component_drift() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fingerprints.json");
+        let start = OffsetDateTime::from_unix_timestamp(1_740_000_000).unwrap();
+
+        let mut log = FingerprintLog::load(&path).unwrap();
+        let first = log.record(hardware(), start).unwrap();
+        assert!(first.drifted.is_empty());
+        assert!(!first.hash_changed);
+
+        // An OS update changes a hashed component
+        let mut updated = hardware();
+        updated.os_info = "Windows-10-19046".to_string();
+        let second = log.record(updated.clone(), start + Duration::days(1)).unwrap();
+        assert_eq!(second.drifted, ["os_info"]);
+        assert!(second.hash_changed);
+        assert_ne!(second.device_hash, first.device_hash);
+
+        // A rename does not affect the hash
+        updated.hostname = "laptop-2".to_string();
+        let third = log.record(updated, start + Duration::days(2)).unwrap();
+        assert_eq!(third.drifted, ["hostname"]);
+        assert!(!third.hash_changed);
+
+        let reloaded = FingerprintLog::load(&path).unwrap();
+        assert_eq!(reloaded.snapshots(), [first, second, third]);
+    }
+}
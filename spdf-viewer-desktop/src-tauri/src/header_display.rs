@@ -0,0 +1,121 @@
+// Header Display Module - Safe copies of header strings for the UI
+//
+// Header strings are attacker-controlled. Control characters, bidi
+// overrides (e.g. U+202E, which can make "gpj.exe" read as "exe.jpg") and
+// invisible characters are stripped from a display copy of the header,
+// with one warning per affected field. The parsed header, and the signed
+// bytes it came from, are never modified. Malformed watermark templates
+// are reported the same way.
+
+use crate::policy::validate_watermark_template;
+use crate::spdf_parser::SpdfHeader;
+
+/// Characters that can reorder or hide text without being visible
+fn is_dangerous(c: char) -> bool {
+    c.is_control()
+        || matches!(
+            c,
+            // Bidi embeddings, overrides and isolates, and the implicit marks
+            '\u{202A}'..='\u{202E}'
+                | '\u{2066}'..='\u{2069}'
+                | '\u{200E}'
+                | '\u{200F}'
+                | '\u{061C}'
+                // Zero-width characters and the BOM
+                | '\u{200B}'..='\u{200D}'
+                | '\u{2060}'
+                | '\u{FEFF}'
+        )
+}
+
+/// Strip dangerous characters from `value`, recording a warning for `field`
+fn sanitize_field(field: &str, value: &mut String, warnings: &mut Vec<String>) {
+    let removed: Vec<char> = value.chars().filter(|c| is_dangerous(*c)).collect();
+    if removed.is_empty() {
+        return;
+    }
+
+    value.retain(|c| !is_dangerous(c));
+    let codepoints: Vec<String> = removed
+        .iter()
+        .map(|c| format!("U+{:04X}", *c as u32))
+        .collect();
+    warnings.push(format!(
+        "{}: removed {} control or bidi character(s) ({})",
+        field,
+        removed.len(),
+        codepoints.join(", ")
+    ));
+}
+
+/// Display copy of `header` with dangerous characters removed from every
+/// user-visible string, plus a warning for each field that was changed
+pub fn sanitize_header_display(header: &SpdfHeader) -> (SpdfHeader, Vec<String>) {
+    let mut display = header.clone();
+    let mut warnings = Vec::new();
+
+    sanitize_field("title", &mut display.title, &mut warnings);
+    sanitize_field("doc_id", &mut display.doc_id, &mut warnings);
+    sanitize_field("org_id", &mut display.org_id, &mut warnings);
+    sanitize_field("server_url", &mut display.server_url, &mut warnings);
+    sanitize_field("created_at", &mut display.created_at, &mut warnings);
+    sanitize_field("watermark.text", &mut display.watermark.text, &mut warnings);
+    if let Err(errors) = validate_watermark_template(&header.watermark.text) {
+        for error in errors {
+            warnings.push(format!("watermark.text: {}", error));
+        }
+    }
+
+    if let Some(metadata) = display.metadata.as_object_mut() {
+        for (key, value) in metadata.iter_mut() {
+            if let serde_json::Value::String(text) = value {
+                sanitize_field(&format!("metadata.{}", key), text, &mut warnings);
+            }
+        }
+    }
+
+    (display, warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spdf_parser::SpdfFile;
+    use crate::test_support::{build_spdf_with, sample_header, DEFAULT_FLAGS};
+
+    #[test]
+    fn test_rtl_override_in_title() {
+        let mut header = sample_header();
+        header["title"] = serde_json::json!("invoice\u{202E}fdp.exe");
+        header["metadata"] = serde_json::json!({ "author": "Mallory\u{0}", "pages": 3 });
+        let spdf = SpdfFile::parse(&build_spdf_with(&header, DEFAULT_FLAGS, b"%PDF")).unwrap();
+
+        let (display, warnings) = sanitize_header_display(&spdf.header);
+        assert_eq!(display.title, "invoicefdp.exe");
+        assert_eq!(display.metadata["author"], "Mallory");
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].starts_with("title:"));
+        assert!(warnings[0].contains("U+202E"));
+        assert!(warnings[1].starts_with("metadata.author:"));
+
+        // The parsed header (and the signature over it) is untouched
+        assert_eq!(spdf.header.title, "invoice\u{202E}fdp.exe");
+        assert!(crate::verify::verify_signature(&spdf).is_ok());
+    }
+
+    #[test]
+    fn test_clean_header_unchanged() {
+        let header: SpdfHeader = serde_json::from_value(sample_header()).unwrap();
+        let (display, warnings) = sanitize_header_display(&header);
+        assert!(warnings.is_empty());
+        assert_eq!(display.title, header.title);
+    }
+
+    #[test]
+    fn test_bad_watermark_template_warns() {
+        let mut header: SpdfHeader = serde_json::from_value(sample_header()).unwrap();
+        header.watermark.text = "Copy for {{user_email".to_string();
+        let (_, warnings) = sanitize_header_display(&header);
+        assert_eq!(warnings, ["watermark.text: unclosed placeholder at 9"]);
+    }
+}
@@ -0,0 +1,104 @@
+// Header Schema Module - Organizational policy checks on SPDF headers
+//
+// Lets deployments enforce that headers conform to an expected JSON Schema
+// (required fields, value constraints) before a document is opened.
+
+use serde::{Deserialize, Serialize};
+
+/// A single schema violation found in a header
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaError {
+    /// JSON pointer to the offending value in the header (empty for the root)
+    pub instance_path: String,
+    /// JSON pointer to the schema keyword that rejected it
+    pub schema_path: String,
+    pub message: String,
+}
+
+impl SchemaError {
+    fn root(message: String) -> Self {
+        SchemaError {
+            instance_path: String::new(),
+            schema_path: String::new(),
+            message,
+        }
+    }
+}
+
+/// Validate raw header JSON against a JSON Schema document
+///
+/// Returns every violation rather than stopping at the first one. An
+/// unparseable header or schema is reported as a single root-level error.
+pub fn validate_header_schema(header_json: &[u8], schema: &str) -> Result<(), Vec<SchemaError>> {
+    let schema: serde_json::Value = serde_json::from_str(schema)
+        .map_err(|e| vec![SchemaError::root(format!("Invalid schema JSON: {}", e))])?;
+    let header: serde_json::Value = serde_json::from_slice(header_json)
+        .map_err(|e| vec![SchemaError::root(format!("Invalid header JSON: {}", e))])?;
+
+    let validator = jsonschema::validator_for(&schema)
+        .map_err(|e| vec![SchemaError::root(format!("Invalid schema: {}", e))])?;
+
+    let errors: Vec<SchemaError> = validator
+        .iter_errors(&header)
+        .map(|e| SchemaError {
+            instance_path: e.instance_path().to_string(),
+            schema_path: e.schema_path().to_string(),
+            message: e.to_string(),
+        })
+        .collect();
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY_SCHEMA: &str = r#"{
+        "type": "object",
+        "required": ["doc_id", "org_id", "title"],
+        "properties": {
+            "doc_id": { "type": "string", "minLength": 1 },
+            "permissions": {
+                "type": "object",
+                "properties": { "max_devices": { "maximum": 5 } }
+            }
+        }
+    }"#;
+
+    #[test]
+    fn test_valid_header() {
+        let header = br#"{"doc_id": "DOC-1", "org_id": "acme", "title": "Q3 Report"}"#;
+        assert!(validate_header_schema(header, POLICY_SCHEMA).is_ok());
+    }
+
+    #[test]
+    fn test_missing_required_field() {
+        let header = br#"{"doc_id": "DOC-1", "org_id": "acme"}"#;
+        let errors = validate_header_schema(header, POLICY_SCHEMA).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains("title"));
+        assert_eq!(errors[0].schema_path, "/required");
+    }
+
+    #[test]
+    fn test_reports_every_violation() {
+        let header = br#"{"doc_id": "", "org_id": "acme", "permissions": {"max_devices": 50}}"#;
+        let errors = validate_header_schema(header, POLICY_SCHEMA).unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert!(errors
+            .iter()
+            .any(|e| e.instance_path == "/permissions/max_devices"));
+    }
+
+    #[test]
+    fn test_invalid_schema() {
+        let errors = validate_header_schema(b"{}", "not json").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].instance_path.is_empty());
+    }
+}
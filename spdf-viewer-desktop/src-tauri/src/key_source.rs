@@ -0,0 +1,240 @@
+// Key Source Module - Where trusted organization keys are looked up
+//
+// Organization verifying keys normally live as PEM files in
+// ~/.spdf/keys/{org_id}_public.pem. Managed deployments can provision them
+// in the OS key store instead (macOS Keychain, Windows Credential Manager,
+// the Secret Service on Linux), where users cannot swap them out. Keys
+// there are stored as PEM under the label `key_label(org_id)`.
+//
+// The platform store is only compiled in with the `platform-keystore`
+// feature, and builds without it use the key files. A build with it
+// trusts only the store: an org the store has no key for has no trusted
+// key, and a store that cannot be reached is an error, so neither falls
+// back to files a user could have planted.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::local_store::validate_org_id;
+use crate::spdf_parser::{SpdfError, SpdfFile};
+use crate::verify::{verify_signature_with_key, verify_signature_with_pinned_key, VerifyFailure};
+
+/// Service name entries are stored under in the OS key store
+pub const KEYSTORE_SERVICE: &str = "spdf";
+
+/// Label of an org's verifying key in a key store
+pub fn key_label(org_id: &str) -> String {
+    format!("org-key/{}", org_id)
+}
+
+/// A store of PEM verifying keys addressed by label
+pub trait KeyStore: Send + Sync {
+    /// The key stored under `label`, or `None` if there is none
+    fn get(&self, label: &str) -> Result<Option<String>, SpdfError>;
+}
+
+/// Where trusted org keys come from
+pub enum KeySource {
+    /// `{keys_dir}/{org_id}_public.pem`
+    Files { keys_dir: PathBuf },
+    /// A key store, and nothing else
+    PlatformKeyStore { store: Box<dyn KeyStore> },
+}
+
+impl KeySource {
+    /// The OS key store when this build has one (`keys_dir` is then not
+    /// read), else the key files in `keys_dir`
+    #[cfg_attr(feature = "platform-keystore", allow(unused_variables))]
+    pub fn for_keys_dir(keys_dir: &Path) -> Self {
+        #[cfg(feature = "platform-keystore")]
+        {
+            KeySource::PlatformKeyStore {
+                store: Box::new(OsKeyStore),
+            }
+        }
+        #[cfg(not(feature = "platform-keystore"))]
+        {
+            KeySource::Files {
+                keys_dir: keys_dir.to_path_buf(),
+            }
+        }
+    }
+
+    /// The PEM verifying key trusted for `org_id`, if any
+    pub fn org_key_pem(&self, org_id: &str) -> Result<Option<String>, SpdfError> {
+        validate_org_id(org_id)?;
+        match self {
+            KeySource::Files { keys_dir } => read_key_file(keys_dir, org_id),
+            KeySource::PlatformKeyStore { store } => store.get(&key_label(org_id)),
+        }
+    }
+}
+
+/// `org_id` must already be validated, as it becomes part of the path
+fn read_key_file(keys_dir: &Path, org_id: &str) -> Result<Option<String>, SpdfError> {
+    let path = keys_dir.join(format!("{}_public.pem", org_id));
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(fs::read_to_string(path)?))
+}
+
+/// Verify `spdf` against the key `source` trusts for its org, ignoring
+/// the header's own `public_key`
+pub fn verify_with_source(spdf: &SpdfFile, source: &KeySource) -> Result<(), VerifyFailure> {
+    verify_signature_with_key(spdf, &trusted_key_pem(spdf, source)?)
+}
+
+/// Verify `spdf` against the key `trust_store` holds for its org, and
+/// fail if the header carries a different key of its own
+pub fn verify_signature_against_trust_store(
+    spdf: &SpdfFile,
+    trust_store: &KeySource,
+) -> Result<(), VerifyFailure> {
+    verify_signature_with_pinned_key(spdf, &trusted_key_pem(spdf, trust_store)?)
+}
+
+fn trusted_key_pem(spdf: &SpdfFile, source: &KeySource) -> Result<String, VerifyFailure> {
+    let org_id = &spdf.header.org_id;
+    source
+        .org_key_pem(org_id)
+        .map_err(|e| VerifyFailure::KeyParse(format!("Failed to load key for {}: {}", org_id, e)))?
+        .ok_or_else(|| VerifyFailure::KeyParse(format!("No trusted key for {}", org_id)))
+}
+
+/// The OS key store, through the `keyring` crate
+#[cfg(feature = "platform-keystore")]
+pub struct OsKeyStore;
+
+#[cfg(feature = "platform-keystore")]
+impl KeyStore for OsKeyStore {
+    fn get(&self, label: &str) -> Result<Option<String>, SpdfError> {
+        let entry = keyring::Entry::new(KEYSTORE_SERVICE, label)
+            .map_err(|e| SpdfError::FormatError(format!("Key store error: {}", e)))?;
+        match entry.get_password() {
+            Ok(pem) => Ok(Some(pem)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(SpdfError::FormatError(format!("Key store error: {}", e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{build_spdf, public_key_pem, public_key_pem_for};
+    use ed25519_dalek::SigningKey;
+    use std::collections::HashMap;
+
+    /// In-memory key store; `None` simulates an unreachable store
+    struct MockKeyStore(Option<HashMap<String, String>>);
+
+    impl KeyStore for MockKeyStore {
+        fn get(&self, label: &str) -> Result<Option<String>, SpdfError> {
+            match &self.0 {
+                Some(keys) => Ok(keys.get(label).cloned()),
+                None => Err(SpdfError::FormatError("Key store unavailable".to_string())),
+            }
+        }
+    }
+
+    fn platform(keys: Option<&[(&str, String)]>) -> KeySource {
+        let keys = keys.map(|keys| {
+            keys.iter()
+                .map(|(org_id, pem)| (key_label(org_id), pem.clone()))
+                .collect()
+        });
+        KeySource::PlatformKeyStore {
+            store: Box::new(MockKeyStore(keys)),
+        }
+    }
+
+    #[test]
+    fn test_platform_store_key_verifies() {
+        let spdf = SpdfFile::parse(&build_spdf(b"%PDF")).unwrap();
+
+        let source = platform(Some(&[("test_org", public_key_pem())]));
+        assert!(verify_with_source(&spdf, &source).is_ok());
+
+        // A different key in the store wins over the header's own key
+        let other = public_key_pem_for(&SigningKey::from_bytes(&[0x33; 32]));
+        let source = platform(Some(&[("test_org", other)]));
+        assert!(matches!(
+            verify_with_source(&spdf, &source),
+            Err(VerifyFailure::VerifyFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_platform_store_does_not_fall_back_to_key_files() {
+        let spdf = SpdfFile::parse(&build_spdf(b"%PDF")).unwrap();
+
+        // Store has no entry for the org
+        assert_eq!(
+            verify_with_source(&spdf, &platform(Some(&[]))),
+            Err(VerifyFailure::KeyParse(
+                "No trusted key for test_org".to_string()
+            ))
+        );
+        // Store cannot be reached
+        assert!(matches!(
+            verify_with_source(&spdf, &platform(None)),
+            Err(VerifyFailure::KeyParse(msg)) if msg.starts_with("Failed to load key")
+        ));
+    }
+
+    #[test]
+    fn test_key_files() {
+        let spdf = SpdfFile::parse(&build_spdf(b"%PDF")).unwrap();
+        let keys_dir = tempfile::tempdir().unwrap();
+        let files = KeySource::Files {
+            keys_dir: keys_dir.path().to_path_buf(),
+        };
+        assert!(verify_with_source(&spdf, &files).is_err());
+
+        fs::write(keys_dir.path().join("test_org_public.pem"), public_key_pem()).unwrap();
+        assert!(verify_with_source(&spdf, &files).is_ok());
+
+        // An org_id cannot name a file outside the keys directory
+        assert!(files.org_key_pem("../test_org").is_err());
+        assert!(files.org_key_pem("").is_err());
+    }
+
+    #[test]
+    fn test_trust_store_rejects_substituted_header_key() {
+        let keys_dir = tempfile::tempdir().unwrap();
+        fs::write(keys_dir.path().join("test_org_public.pem"), public_key_pem()).unwrap();
+        let trust_store = KeySource::Files {
+            keys_dir: keys_dir.path().to_path_buf(),
+        };
+
+        let mut spdf = SpdfFile::parse(&build_spdf(b"%PDF")).unwrap();
+        assert!(verify_signature_against_trust_store(&spdf, &trust_store).is_ok());
+
+        // A file re-signed by someone else, carrying their key, verifies
+        // on its own but not against the pinned key
+        let forger = SigningKey::from_bytes(&[0x44; 32]);
+        let mut header = spdf.header.clone();
+        header.public_key = public_key_pem_for(&forger);
+        let forged = SpdfFile::create(&crate::spdf_parser::WriteOptions {
+            header: &header,
+            flags: spdf.flags,
+            plaintext: b"%PDF forged",
+            doc_key: &[0x55; 32],
+            wrapped_key: &spdf.wrapped_key,
+            signing_key: &forger,
+        })
+        .unwrap();
+        assert!(crate::verify::verify_signature(&forged).is_ok());
+        assert_eq!(
+            verify_signature_against_trust_store(&forged, &trust_store),
+            Err(VerifyFailure::KeyMismatch {
+                org_id: "test_org".to_string()
+            })
+        );
+
+        // Without a key of its own the header defers to the pinned key
+        spdf.header.public_key.clear();
+        assert!(verify_signature_against_trust_store(&spdf, &trust_store).is_ok());
+    }
+}
@@ -4,17 +4,81 @@
 
 // Module declarations
 pub mod auth;
+pub mod base64_stream;
+pub mod capability;
 pub mod device_id;
+pub mod device_limit;
+pub mod device_manifest;
 pub mod decrypt;
+pub mod decrypt_throttle;
+pub mod encrypt;
+pub mod expiry;
+pub mod fingerprint_log;
+pub mod header_display;
+pub mod header_schema;
+pub mod key_source;
+pub mod library;
+pub mod local_store;
+pub mod offline;
+pub mod pdf;
+pub mod platform;
+pub mod policy;
+pub mod recipient;
+pub mod redact;
+pub mod reissue;
+pub mod revocation;
+pub mod sandbox;
+pub mod screen_protection;
 pub mod spdf;
 pub mod spdf_parser;
+pub mod telemetry;
+pub mod timestamp;
+pub mod trust;
 pub mod verify;
+pub mod view_limit;
+pub mod watermark;
 
-use crate::spdf_parser::SpdfFile;
-use crate::device_id::{generate_device_hash, get_device_name};
-use crate::verify::verify_signature;
-use crate::decrypt::decrypt_content_slice;
+#[cfg(test)]
+mod test_support;
+
+use crate::spdf_parser::{SpdfError, SpdfFile};
+use crate::device_id::{
+    device_hash_digest, generate_device_hash, get_device_name, HardwareInfo, HardwareInfoInput,
+};
+use crate::fingerprint_log::{FingerprintLog, FingerprintSnapshot};
+use crate::verify::{analyze_integrity, IntegrityReport, VerifyFailure};
+use crate::decrypt::{
+    classify_decrypt_failure, decrypt_content_with_progress, decrypted_matches_sha256,
+    unwrap_file_key, DecryptFailureKind,
+};
+use crate::decrypt_throttle::DecryptThrottle;
+use crate::capability::{
+    ActionRequirement, CryptoProfile, DocumentCapabilities, OpenCapability, UserAction,
+};
+use crate::device_manifest::DeviceManifest;
+use crate::header_display::sanitize_header_display;
+use crate::key_source::{verify_signature_against_trust_store, KeySource};
+use crate::header_schema::{validate_header_schema, SchemaError};
+use crate::spdf_parser::{
+    DocumentMetadata, SpdfHeader, SpdfPermissions, DEFAULT_WRAP_SCHEME, WRAPPED_KEY_LENGTH,
+};
+use crate::library::LibraryEntry;
+use crate::local_store::{device_kek, LocalStore, StoreAudit, TrustedKeyInfo};
+use crate::offline::{
+    offline_limit, offline_readiness, OfflineLimit, OfflineReadiness, OfflineStatus,
+    KEY_CACHE_TTL,
+};
+use crate::policy::{NormalizedTemplate, SecurityPolicy, TemplateError, WatermarkContext};
+use crate::encrypt::parse_ed25519_private_key_pem;
+use crate::expiry::ExtensionCache;
+use crate::reissue::reissue_spdf;
+use crate::revocation::{CrlStatus, RevocationList};
+use crate::sandbox::decrypt_sandboxed;
+use crate::screen_protection::ScreenshotProtection;
+use crate::trust::{PinCheck, TrustStore};
+use crate::view_limit::ViewCounter;
 use serde::{Deserialize, Serialize};
+use tauri::{Emitter, Manager};
 
 // Response types for Tauri commands
 #[derive(Serialize, Deserialize)]
@@ -28,16 +92,99 @@ pub struct SpdfInfo {
     pub allow_copy: bool,
     pub max_devices: u32,
     pub requires_device_binding: bool,
+    /// Author, page count and language from the header's `metadata`
+    pub metadata: DocumentMetadata,
+    /// Fields whose control or bidi characters were stripped for display
+    pub display_warnings: Vec<String>,
+}
+
+impl SpdfInfo {
+    /// Summary of a parsed file, with display strings sanitized
+    pub fn of(spdf: &SpdfFile) -> Self {
+        let (display, display_warnings) = sanitize_header_display(&spdf.header);
+        let metadata = display.document_metadata();
+        SpdfInfo {
+            doc_id: display.doc_id,
+            title: display.title,
+            org_id: display.org_id,
+            server_url: display.server_url,
+            created_at: display.created_at,
+            allow_print: spdf.header.permissions.allow_print,
+            allow_copy: spdf.header.permissions.allow_copy,
+            max_devices: spdf.header.permissions.max_devices,
+            requires_device_binding: spdf.requires_device_binding(),
+            metadata,
+            display_warnings,
+        }
+    }
+}
+
+/// Header with display strings sanitized, and what was stripped
+#[derive(Serialize, Deserialize)]
+pub struct DisplayHeader {
+    pub header: SpdfHeader,
+    pub warnings: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct DeviceInfo {
+    /// The bare hex digest, without a `v1:` prefix: the key server matches
+    /// registered devices by exact string, so this is what goes on the wire
     pub device_hash: String,
     pub device_name: String,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct DecryptResult {
+    pub success: bool,
+    /// The plaintext lives only until the result is serialized to the
+    /// frontend; the webview holds the only copy after that
+    pub pdf_data: Option<Vec<u8>>,
+    pub error: Option<String>,
+    /// Heuristic cause when decryption itself failed
+    pub failure_kind: Option<DecryptFailureKind>,
+}
+
+/// Event `decrypt_spdf` emits while it decrypts
+pub const DECRYPT_PROGRESS_EVENT: &str = "decrypt-progress";
+
+/// Payload of `DECRYPT_PROGRESS_EVENT`; byte counts are of ciphertext
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DecryptProgress {
+    pub file_path: String,
+    pub processed: u64,
+    pub total: u64,
+}
+
+/// Progress callback emitting `DECRYPT_PROGRESS_EVENT` for `file_path`
+///
+/// Emits at most once per whole percent, so large files do not flood the
+/// frontend; completion always differs from the last report and is sent.
+pub fn progress_emitter(app: &tauri::AppHandle, file_path: &str) -> impl FnMut(u64, u64) {
+    let (app, file_path) = (app.clone(), file_path.to_string());
+    let mut last_percent = None;
+    move |processed, total| {
+        let percent = processed * 100 / total.max(1);
+        if last_percent == Some(percent) {
+            return;
+        }
+        last_percent = Some(percent);
+        let progress = DecryptProgress {
+            file_path: file_path.clone(),
+            processed,
+            total,
+        };
+        if let Err(e) = app.emit(DECRYPT_PROGRESS_EVENT, progress) {
+            println!("Warning: decrypt progress not sent: {}", e);
+        }
+    }
+}
+
+/// Decryption run by `open_sandboxed`
+#[derive(Serialize, Deserialize)]
+pub struct SandboxResult {
+    /// Whether filesystem and network access were actually dropped
+    pub sandboxed: bool,
     pub success: bool,
     pub pdf_data: Option<Vec<u8>>,
     pub error: Option<String>,
@@ -53,18 +200,32 @@ fn greet(name: &str) -> String {
 #[tauri::command]
 fn get_spdf_info(file_path: &str) -> Result<SpdfInfo, String> {
     let spdf = SpdfFile::read(file_path).map_err(|e| e.to_string())?;
-    
-    Ok(SpdfInfo {
-        doc_id: spdf.header.doc_id.clone(),
-        title: spdf.header.title.clone(),
-        org_id: spdf.header.org_id.clone(),
-        server_url: spdf.header.server_url.clone(),
-        created_at: spdf.header.created_at.clone(),
-        allow_print: spdf.header.permissions.allow_print,
-        allow_copy: spdf.header.permissions.allow_copy,
-        max_devices: spdf.header.permissions.max_devices,
-        requires_device_binding: spdf.requires_device_binding(),
-    })
+    Ok(SpdfInfo::of(&spdf))
+}
+
+/// Full header with control and bidi-override characters stripped from
+/// its display strings
+#[tauri::command]
+fn get_display_header(file_path: &str) -> Result<DisplayHeader, String> {
+    let spdf = SpdfFile::read(file_path).map_err(|e| e.to_string())?;
+    let (header, warnings) = sanitize_header_display(&spdf.header);
+    Ok(DisplayHeader { header, warnings })
+}
+
+/// Every integrity check on a file, passed or failed, for explaining why
+/// it was rejected
+///
+/// The signature is checked against the trusted org key.
+#[tauri::command]
+fn get_integrity_report(file_path: &str) -> Result<IntegrityReport, String> {
+    let spdf = SpdfFile::read(file_path).map_err(|e| e.to_string())?;
+    Ok(analyze_integrity(&spdf, &default_trust_store()?))
+}
+
+/// Org keys installed under the default local store
+fn default_trust_store() -> Result<KeySource, String> {
+    let root = LocalStore::default_root().ok_or("Could not determine home directory")?;
+    Ok(KeySource::for_keys_dir(&LocalStore::new(root).keys_dir()))
 }
 
 #[tauri::command]
@@ -73,62 +234,795 @@ fn get_device_info() -> Result<DeviceInfo, String> {
     let device_name = get_device_name();
     
     Ok(DeviceInfo {
-        device_hash,
+        device_hash: device_hash_digest(&device_hash).to_string(),
         device_name,
     })
 }
 
+/// Check a watermark template's placeholders and normalize it
+#[tauri::command]
+fn validate_watermark_template(template: &str) -> Result<NormalizedTemplate, Vec<TemplateError>> {
+    policy::validate_watermark_template(template)
+}
+
+/// The labeled components behind the device hash, for diagnosing a device
+/// binding that no longer matches
+#[tauri::command]
+fn get_device_fingerprint_components() -> Result<Vec<(String, String)>, String> {
+    device_id::device_fingerprint_components().map_err(|e| e.to_string())
+}
+
+/// Check a server-provided device hash test vector
+#[tauri::command]
+fn verify_device_hash_vector(hardware: HardwareInfoInput, salt: &str, expected: &str) -> bool {
+    device_id::verify_device_hash_vector(hardware, salt, expected)
+}
+
+/// Anonymous per-install id for usage analytics (not the device hash)
+#[tauri::command]
+fn telemetry_id(app_handle: tauri::AppHandle) -> Result<String, String> {
+    let app_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data dir: {}", e))?;
+    telemetry::telemetry_id(&app_dir)
+}
+
+/// Append the current hardware info and device hash to the fingerprint
+/// log; collection is slow, so it runs off the main thread
+#[tauri::command]
+async fn record_fingerprint_snapshot() -> Result<FingerprintSnapshot, String> {
+    tauri::async_runtime::spawn_blocking(|| {
+        let hardware = HardwareInfo::collect().map_err(|e| e.to_string())?;
+        let path = FingerprintLog::default_path().ok_or("Could not determine home directory")?;
+        FingerprintLog::load(&path)?.record(hardware, time::OffsetDateTime::now_utc())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Recorded fingerprint snapshots, oldest first
+#[tauri::command]
+fn fingerprint_history() -> Result<Vec<FingerprintSnapshot>, String> {
+    let path = FingerprintLog::default_path().ok_or("Could not determine home directory")?;
+    Ok(FingerprintLog::load(&path)?.snapshots().to_vec())
+}
+
+/// Pre-flight: can this build open the file, and if not, why
+#[tauri::command]
+fn can_open(file_path: &str) -> Result<OpenCapability, String> {
+    capability::can_open(file_path)
+}
+
+/// Audit summary of the cryptographic primitives a file uses
+#[tauri::command]
+fn crypto_profile(file_path: &str) -> Result<CryptoProfile, String> {
+    let spdf = SpdfFile::read(file_path).map_err(|e| e.to_string())?;
+    Ok(capability::crypto_profile(&spdf))
+}
+
+/// Whether the document permits `action` ("print", "copy" or "offline"),
+/// which flag or permission decides it, and an explanation for tooltips
+#[tauri::command]
+fn action_requirement(file_path: &str, action: &str) -> Result<ActionRequirement, String> {
+    let action = UserAction::parse(action)?;
+    let spdf = SpdfFile::read(file_path).map_err(|e| e.to_string())?;
+    Ok(capability::action_requirement(&spdf, action))
+}
+
+/// Check the file's signing key against the pinned key for its org,
+/// accepting a rotation the pinned server vouches for
+#[tauri::command]
+async fn check_key_pin(file_path: String, auth_token: String) -> Result<PinCheck, String> {
+    let spdf = SpdfFile::read(&file_path).map_err(|e| e.to_string())?;
+    let pins_path = TrustStore::default_path().ok_or("Could not find home directory")?;
+    let mut store = TrustStore::load(&pins_path).map_err(|e| e.to_string())?;
+    trust::enforce_pin(&reqwest::Client::new(), &mut store, &spdf.header, &auth_token)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Permissions and the watermark to render for a document, after
+/// applying the local security policy
+#[tauri::command]
+fn get_capabilities(
+    file_path: &str,
+    user_email: String,
+    device_id: String,
+) -> Result<DocumentCapabilities, String> {
+    let spdf = SpdfFile::read(file_path).map_err(|e| e.to_string())?;
+    let policy = match SecurityPolicy::default_path() {
+        Some(path) => SecurityPolicy::load(&path).map_err(|e| e.to_string())?,
+        None => SecurityPolicy::default(),
+    };
+    let now = time::OffsetDateTime::now_utc();
+    let context = WatermarkContext::for_open(&spdf.header, user_email, device_id, now);
+    Ok(capability::document_capabilities(&spdf, &context, &policy))
+}
+
+/// Whether the file's signature is valid
+///
+/// The signature is checked against the key installed for the file's org,
+/// never the header's own.
 #[tauri::command]
 fn verify_spdf(file_path: &str) -> Result<bool, String> {
     let spdf = SpdfFile::read(file_path).map_err(|e| e.to_string())?;
-    verify_signature(&spdf).map_err(|e| e.to_string())?;
+    verify_signature_against_trust_store(&spdf, &default_trust_store()?)
+        .map_err(|e| e.to_string())?;
     Ok(true)
 }
 
+/// Like `verify_spdf`, but reports the step a failed verification stopped
+/// at instead of a message; `None` means the signature is valid
 #[tauri::command]
-fn decrypt_spdf(file_path: &str, doc_key_hex: &str) -> Result<DecryptResult, String> {
+fn verify_spdf_detailed(file_path: &str) -> Result<Option<VerifyFailure>, String> {
+    let spdf = SpdfFile::read(file_path).map_err(|e| e.to_string())?;
+    Ok(verify_signature_against_trust_store(&spdf, &default_trust_store()?).err())
+}
+
+/// Same check as `verify_spdf_detailed`, kept for callers that asked for
+/// the trusted key by name
+#[tauri::command]
+fn verify_spdf_trusted(file_path: &str) -> Result<Option<VerifyFailure>, String> {
+    verify_spdf_detailed(file_path)
+}
+
+/// Whether a signed device manifest lets `device_hash`, which need not
+/// be this device's, open the file offline. The manifest is checked
+/// against the key installed for the file's org.
+#[tauri::command]
+fn would_accept_device(
+    file_path: &str,
+    device_hash: &str,
+    manifest: DeviceManifest,
+) -> Result<bool, String> {
+    let spdf = SpdfFile::read(file_path).map_err(|e| e.to_string())?;
+    let root = LocalStore::default_root().ok_or("Could not determine home directory")?;
+    let org_id = &spdf.header.org_id;
+    let pem = KeySource::for_keys_dir(&LocalStore::new(root).keys_dir())
+        .org_key_pem(org_id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("No public key installed for {}", org_id))?;
+    let org_key = crate::verify::parse_ed25519_public_key_pem(&pem)
+        .map_err(|e| format!("Invalid org key: {}", e))?;
+    device_manifest::would_accept_device(&spdf, device_hash, &manifest, &org_key)
+}
+
+/// Decrypt with k_doc unwrapped locally from the file's AES-KW
+/// `wrapped_key`, so the document key itself never crosses the wire
+///
+/// Files whose `wrap_scheme` is not AES-KW are refused.
+///
+/// Reports progress as `DECRYPT_PROGRESS_EVENT` events while decrypting.
+/// Fails with "too many decryption attempts" once a file has had
+/// `MAX_FAILED_ATTEMPTS` unsuccessful attempts within a minute.
+#[tauri::command]
+fn decrypt_spdf(
+    app: tauri::AppHandle,
+    throttle: tauri::State<'_, DecryptThrottle>,
+    file_path: &str,
+    kek_hex: &str,
+) -> Result<DecryptResult, String> {
+    decrypt_with_kek(
+        &default_trust_store()?,
+        &throttle,
+        file_path,
+        kek_hex,
+        progress_emitter(&app, file_path),
+    )
+}
+
+/// `decrypt_spdf` against an explicit trust store, reporting progress to
+/// `progress`
+fn decrypt_with_kek(
+    trust_store: &KeySource,
+    throttle: &DecryptThrottle,
+    file_path: &str,
+    kek_hex: &str,
+    progress: impl FnMut(u64, u64),
+) -> Result<DecryptResult, String> {
     // Parse SPDF file
     let spdf = SpdfFile::read(file_path).map_err(|e| e.to_string())?;
     
-    // Verify signature first
-    if let Err(e) = verify_signature(&spdf) {
+    // Verify signature first, against the org key we trust
+    if let Err(e) = verify_signature_against_trust_store(&spdf, trust_store) {
         return Ok(DecryptResult {
             success: false,
             pdf_data: None,
             error: Some(format!("Signature verification failed: {}", e)),
+            failure_kind: None,
         });
     }
     
-    // Parse the hex key
-    let doc_key = hex::decode(doc_key_hex).map_err(|e| format!("Invalid key hex: {}", e))?;
-    
+    // Unwrap k_doc with the KEK
+    let kek = zeroize::Zeroizing::new(
+        hex::decode(kek_hex).map_err(|e| format!("Invalid KEK hex: {}", e))?,
+    );
+    let kek: &[u8; 32] = kek
+        .as_slice()
+        .try_into()
+        .map_err(|_| format!("Invalid KEK length: expected 32, got {}", kek.len()))?;
+
+    // Throttled per file, whatever path reaches it
+    let file_key = throttle_key(file_path);
+    throttle.begin(&file_key, std::time::Instant::now())?;
+    let doc_key = match unwrap_file_key(&spdf, kek) {
+        Ok(doc_key) => doc_key,
+        // The signature covers the wrapped key, so a well-formed one that
+        // fails the integrity check means the KEK is wrong
+        Err(e) => {
+            let well_formed = spdf.header.wrap_scheme() == DEFAULT_WRAP_SCHEME
+                && spdf.wrapped_key.len() == WRAPPED_KEY_LENGTH;
+            return Ok(DecryptResult {
+                success: false,
+                pdf_data: None,
+                error: Some(e.to_string()),
+                failure_kind: well_formed.then_some(DecryptFailureKind::LikelyWrongKey),
+            });
+        }
+    };
+
     // Decrypt
-    match decrypt_content_slice(&spdf, &doc_key) {
-        Ok(pdf_data) => Ok(DecryptResult {
+    match decrypt_content_with_progress(&spdf, &doc_key, progress) {
+        Ok(pdf_data) => {
+            throttle.succeeded(&file_key);
+            Ok(DecryptResult {
+                success: true,
+                pdf_data: Some(pdf_data),
+                error: None,
+                failure_kind: None,
+            })
+        }
+        Err(e) => Ok(DecryptResult {
+            success: false,
+            pdf_data: None,
+            error: Some(e.to_string()),
+            // Only a failed decryption says anything about the key
+            failure_kind: matches!(e, SpdfError::DecryptionError(_))
+                .then(|| classify_decrypt_failure(doc_key.as_slice())),
+        }),
+    }
+}
+
+/// Name a file is throttled under, so one file reached by two paths
+/// shares a count
+fn throttle_key(file_path: &str) -> String {
+    std::fs::canonicalize(file_path)
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|_| file_path.to_string())
+}
+
+/// Count an attempt with a caller-supplied key on `file_path`, clearing
+/// the file's count if the attempt succeeds
+fn throttled<T, E>(
+    throttle: &DecryptThrottle,
+    file_path: &str,
+    attempt: impl FnOnce() -> Result<T, E>,
+) -> Result<Result<T, E>, String> {
+    let file_key = throttle_key(file_path);
+    throttle.begin(&file_key, std::time::Instant::now())?;
+    let result = attempt();
+    if result.is_ok() {
+        throttle.succeeded(&file_key);
+    }
+    Ok(result)
+}
+
+/// Decrypt with filesystem and network access dropped for the duration
+///
+/// For high-security review; `sandboxed` is false on platforms without a
+/// thread-level sandbox, where the decrypt runs unrestricted.
+#[tauri::command]
+fn open_sandboxed(
+    throttle: tauri::State<'_, DecryptThrottle>,
+    file_path: &str,
+    doc_key_hex: &str,
+) -> Result<SandboxResult, String> {
+    let spdf = SpdfFile::read(file_path).map_err(|e| e.to_string())?;
+    if let Err(e) = verify_signature_against_trust_store(&spdf, &default_trust_store()?) {
+        return Err(format!("Signature verification failed: {}", e));
+    }
+
+    let doc_key = zeroize::Zeroizing::new(
+        hex::decode(doc_key_hex).map_err(|e| format!("Invalid key hex: {}", e))?,
+    );
+    let file_key = throttle_key(file_path);
+    throttle.begin(&file_key, std::time::Instant::now())?;
+    let (decrypted, sandboxed) = decrypt_sandboxed(spdf, doc_key)?;
+    if decrypted.is_ok() {
+        throttle.succeeded(&file_key);
+    }
+    Ok(match decrypted {
+        Ok(pdf_data) => SandboxResult {
+            sandboxed,
             success: true,
             pdf_data: Some(pdf_data),
             error: None,
+        },
+        Err(e) => SandboxResult {
+            sandboxed,
+            success: false,
+            pdf_data: None,
+            error: Some(e.to_string()),
+        },
+    })
+}
+
+/// Re-encrypt a document for one recipient and write the copy to `out_path`
+///
+/// The copy opens with the recipient's X25519 private key rather than a
+/// key-server grant. Refused unless the document allows copying.
+#[tauri::command]
+fn export_for_recipient(
+    throttle: tauri::State<'_, DecryptThrottle>,
+    file_path: &str,
+    doc_key_hex: &str,
+    recipient_public_key_pem: &str,
+    out_path: &str,
+) -> Result<(), String> {
+    let spdf = SpdfFile::read(file_path).map_err(|e| e.to_string())?;
+    if let Err(e) = verify_signature_against_trust_store(&spdf, &default_trust_store()?) {
+        return Err(format!("Signature verification failed: {}", e));
+    }
+
+    let doc_key = zeroize::Zeroizing::new(
+        hex::decode(doc_key_hex).map_err(|e| format!("Invalid key hex: {}", e))?,
+    );
+    let doc_key: &[u8; 32] = doc_key.as_slice().try_into().map_err(|_| {
+        format!("Invalid key length: expected 32, got {}", doc_key.len())
+    })?;
+    let exported = throttled(&throttle, file_path, || {
+        recipient::export_for_recipient(&spdf, doc_key, recipient_public_key_pem)
+    })?
+    .map_err(|e| e.to_string())?;
+    std::fs::write(out_path, exported).map_err(|e| e.to_string())
+}
+
+/// Reissue a document with new permissions, signed with `signing_key_pem`
+/// (PKCS#8), and write it to `out_path`
+///
+/// Returns the hex key the reissue is encrypted under. Register it with
+/// the key server before distributing the file; the viewer cannot wrap it.
+#[tauri::command]
+fn reissue(
+    throttle: tauri::State<'_, DecryptThrottle>,
+    file_path: &str,
+    doc_key_hex: &str,
+    new_permissions: SpdfPermissions,
+    signing_key_pem: &str,
+    out_path: &str,
+) -> Result<String, String> {
+    let spdf = SpdfFile::read(file_path).map_err(|e| e.to_string())?;
+    if let Err(e) = verify_signature_against_trust_store(&spdf, &default_trust_store()?) {
+        return Err(format!("Signature verification failed: {}", e));
+    }
+
+    let doc_key = zeroize::Zeroizing::new(
+        hex::decode(doc_key_hex).map_err(|e| format!("Invalid key hex: {}", e))?,
+    );
+    let doc_key: &[u8; 32] = doc_key.as_slice().try_into().map_err(|_| {
+        format!("Invalid key length: expected 32, got {}", doc_key.len())
+    })?;
+    let signing_key = parse_ed25519_private_key_pem(signing_key_pem).map_err(|e| e.to_string())?;
+    let reissued = throttled(&throttle, file_path, || {
+        reissue_spdf(&spdf, doc_key, new_permissions, &signing_key)
+    })?
+    .map_err(|e| e.to_string())?;
+    std::fs::write(out_path, &reissued.data).map_err(|e| e.to_string())?;
+    Ok(hex::encode(*reissued.doc_key))
+}
+
+/// Audit check: does the decrypted document match an approved SHA-256?
+///
+/// Only the match result is returned, never the plaintext.
+#[tauri::command]
+fn verify_decrypted_against(
+    throttle: tauri::State<'_, DecryptThrottle>,
+    file_path: &str,
+    doc_key_hex: &str,
+    expected_sha256: &str,
+) -> Result<bool, String> {
+    let spdf = SpdfFile::read(file_path).map_err(|e| e.to_string())?;
+    let doc_key = zeroize::Zeroizing::new(
+        hex::decode(doc_key_hex).map_err(|e| format!("Invalid key hex: {}", e))?,
+    );
+    throttled(&throttle, file_path, || {
+        decrypted_matches_sha256(&spdf, &doc_key, expected_sha256)
+    })?
+    .map_err(|e| e.to_string())
+}
+
+/// Whether two files share a document key, checked only after each key
+/// decrypts its own file
+#[tauri::command]
+fn same_doc_key(
+    throttle: tauri::State<'_, DecryptThrottle>,
+    file_a: &str,
+    key_a_hex: &str,
+    file_b: &str,
+    key_b_hex: &str,
+) -> Result<bool, String> {
+    let spdf_a = SpdfFile::read(file_a).map_err(|e| e.to_string())?;
+    let spdf_b = SpdfFile::read(file_b).map_err(|e| e.to_string())?;
+    let key_a = zeroize::Zeroizing::new(
+        hex::decode(key_a_hex).map_err(|e| format!("Invalid key A hex: {}", e))?,
+    );
+    let key_b = zeroize::Zeroizing::new(
+        hex::decode(key_b_hex).map_err(|e| format!("Invalid key B hex: {}", e))?,
+    );
+    // Both files are counted: either key may be the one being guessed
+    throttled(&throttle, file_b, || {
+        throttled(&throttle, file_a, || {
+            decrypt::same_doc_key(&spdf_a, &key_a, &spdf_b, &key_b)
+        })?
+        .map_err(|e| e.to_string())
+    })?
+}
+
+#[tauri::command]
+fn decrypt_spdf_segment(
+    throttle: tauri::State<'_, DecryptThrottle>,
+    file_path: &str,
+    doc_key_hex: &str,
+    index: usize,
+) -> Result<DecryptResult, String> {
+    let spdf = SpdfFile::read(file_path).map_err(|e| e.to_string())?;
+
+    if let Err(e) = verify_signature_against_trust_store(&spdf, &default_trust_store()?) {
+        return Ok(DecryptResult {
+            success: false,
+            pdf_data: None,
+            error: Some(format!("Signature verification failed: {}", e)),
+            failure_kind: None,
+        });
+    }
+
+    let doc_key = zeroize::Zeroizing::new(
+        hex::decode(doc_key_hex).map_err(|e| format!("Invalid key hex: {}", e))?,
+    );
+    let doc_key: &[u8; 32] =
+        doc_key.as_slice().try_into().map_err(|_| "Invalid key length".to_string())?;
+
+    match throttled(&throttle, file_path, || spdf.decrypt_segment(index, doc_key))? {
+        Ok(segment) => Ok(DecryptResult {
+            success: true,
+            pdf_data: Some(segment),
+            error: None,
+            failure_kind: None,
         }),
         Err(e) => Ok(DecryptResult {
             success: false,
             pdf_data: None,
             error: Some(e.to_string()),
+            // Only a failed decryption says anything about the key
+            failure_kind: matches!(e, SpdfError::DecryptionError(_))
+                .then(|| classify_decrypt_failure(doc_key)),
         }),
     }
 }
 
+/// Check a file's header against an organizational JSON Schema
+///
+/// Returns the list of violations; an empty list means the header conforms.
+#[tauri::command]
+fn validate_spdf_header(file_path: &str, schema: &str) -> Result<Vec<SchemaError>, String> {
+    let spdf = SpdfFile::read(file_path).map_err(|e| e.to_string())?;
+    let header_json = spdf.header_json().map_err(|e| e.to_string())?;
+    Ok(validate_header_schema(&header_json, schema).err().unwrap_or_default())
+}
+
+/// Latest time the document could stay viewable offline, and which
+/// constraint (offline_days, expires_at or cache TTL) sets it
+///
+/// The grant time is taken as now, i.e. the answer assumes the key is
+/// fetched on the next online open.
+#[tauri::command]
+fn max_offline_until(file_path: &str, doc_id: &str) -> Result<Option<OfflineLimit>, String> {
+    let spdf = SpdfFile::read(file_path).map_err(|e| e.to_string())?;
+    if spdf.doc_id() != doc_id {
+        return Err(format!("Document ID mismatch: file is '{}'", spdf.doc_id()));
+    }
+    let root = LocalStore::default_root().ok_or("Could not determine home directory")?;
+    let store = LocalStore::new(root);
+    let extensions = ExtensionCache::load(&store.expiry_path())?;
+    let extension = extensions.get(spdf.doc_id());
+    let trust_store = KeySource::for_keys_dir(&store.keys_dir());
+    let now = time::OffsetDateTime::now_utc();
+    offline_limit(&spdf, extension, &trust_store, now, KEY_CACHE_TTL).map_err(|e| e.to_string())
+}
+
+/// Whether the document would open offline right now, and if not, why
+#[tauri::command]
+fn can_open_offline(file_path: &str, doc_id: &str) -> Result<OfflineReadiness, String> {
+    let spdf = SpdfFile::read(file_path).map_err(|e| e.to_string())?;
+    if spdf.doc_id() != doc_id {
+        return Err(format!("Document ID mismatch: file is '{}'", spdf.doc_id()));
+    }
+    let root = LocalStore::default_root().ok_or("Could not determine home directory")?;
+    let store = LocalStore::new(root);
+    let policy = SecurityPolicy::load(&store.policy_path()).map_err(|e| e.to_string())?;
+    let device_hash = generate_device_hash().map_err(|e| e.to_string())?;
+    offline_readiness(
+        &spdf,
+        &store,
+        &device_kek(&device_hash),
+        &device_hash,
+        &policy,
+        time::OffsetDateTime::now_utc(),
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// Whether the offline grant recorded when `doc_id` last opened online
+/// still covers now, with `offline_days` as the document's budget
+///
+/// Refuses a clock set back past the last check.
+#[tauri::command]
+fn check_offline_validity(doc_id: &str, offline_days: u32) -> Result<OfflineStatus, String> {
+    let root = LocalStore::default_root().ok_or("Could not determine home directory")?;
+    let device_hash = generate_device_hash().map_err(|e| e.to_string())?;
+    offline::check_offline_validity(
+        &LocalStore::new(root),
+        &device_kek(&device_hash),
+        &device_hash,
+        doc_id,
+        offline_days,
+        time::OffsetDateTime::now_utc(),
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// How current the cached revocation list for `org_id` is, so the UI can
+/// warn when it is stale
+#[tauri::command]
+fn crl_status(org_id: &str) -> Result<CrlStatus, String> {
+    let root = LocalStore::default_root().ok_or("Could not determine home directory")?;
+    let store = LocalStore::new(root);
+    let policy = SecurityPolicy::load(&store.policy_path()).map_err(|e| e.to_string())?;
+    let list = RevocationList::load_for_org(&store, org_id).map_err(|e| e.to_string())?;
+    Ok(list.status(org_id, time::OffsetDateTime::now_utc(), policy.crl_max_age()))
+}
+
+#[tauri::command]
+fn scan_library(dir: &str) -> Result<Vec<LibraryEntry>, String> {
+    library::scan_library(std::path::Path::new(dir)).map_err(|e| e.to_string())
+}
+
+/// Scan `dir` and report doc_ids claimed by more than one file
+#[tauri::command]
+fn find_duplicate_doc_ids(dir: &str) -> Result<Vec<(String, Vec<std::path::PathBuf>)>, String> {
+    let entries = library::scan_library(std::path::Path::new(dir)).map_err(|e| e.to_string())?;
+    Ok(library::find_duplicate_doc_ids(&entries))
+}
+
+/// Verify every SPDF file in `dir` (recursing if asked) against the
+/// trusted org keys, in parallel; each path comes with its error, or
+/// `None` if the signature is valid
+#[tauri::command]
+fn verify_directory(
+    dir: &str,
+    recursive: bool,
+) -> Result<Vec<(std::path::PathBuf, Option<String>)>, String> {
+    let root = LocalStore::default_root().ok_or("Could not determine home directory")?;
+    let trust_store = KeySource::for_keys_dir(&LocalStore::new(root).keys_dir());
+    Ok(library::verify_directory(std::path::Path::new(dir), recursive, &trust_store)
+        .into_iter()
+        .map(|(path, result)| (path, result.err().map(|e| e.to_string())))
+        .collect())
+}
+
+/// Scan `dir_path` and write one CSV row per document to `out_path`;
+/// returns the number of rows
+#[tauri::command]
+fn export_library_csv(dir_path: &str, out_path: &str) -> Result<usize, String> {
+    let (dir, out) = (std::path::Path::new(dir_path), std::path::Path::new(out_path));
+    library::export_csv(dir, out).map_err(|e| e.to_string())
+}
+
+/// Copy of the file with its ciphertext zeroed, for attaching to bug reports
+///
+/// Signature verification fails on the copy by design.
+#[tauri::command]
+fn make_redacted_sample(file_path: &str) -> Result<Vec<u8>, String> {
+    redact::make_redacted_sample(file_path)
+}
+
+/// Check ~/.spdf for corrupt keys, cached keys this device cannot open,
+/// world-readable files and files the viewer does not know about
+#[tauri::command]
+fn audit_local_store() -> Result<StoreAudit, String> {
+    let root = LocalStore::default_root().ok_or("Could not determine home directory")?;
+    let device_hash = generate_device_hash().map_err(|e| e.to_string())?;
+    LocalStore::new(root)
+        .audit(&device_kek(&device_hash))
+        .map_err(|e| e.to_string())
+}
+
+/// Stage an organization public key and return its fingerprint for the
+/// user to compare with the one the organization published
+#[tauri::command]
+fn import_org_key(org_id: &str, public_key_pem: &str) -> Result<String, String> {
+    let root = LocalStore::default_root().ok_or("Could not determine home directory")?;
+    LocalStore::new(root)
+        .import_org_key(org_id, public_key_pem)
+        .map_err(|e| e.to_string())
+}
+
+/// Trust a staged key only if its fingerprint matches the published one
+#[tauri::command]
+fn confirm_key_fingerprint(org_id: &str, fingerprint: &str, expected: &str) -> Result<(), String> {
+    let root = LocalStore::default_root().ok_or("Could not determine home directory")?;
+    LocalStore::new(root)
+        .confirm_key_fingerprint(org_id, fingerprint, expected)
+        .map_err(|e| e.to_string())
+}
+
+/// Trust every organization key in a PEM bundle file
+#[tauri::command]
+fn import_trust_bundle(path: &str) -> Result<Vec<TrustedKeyInfo>, String> {
+    let bundle = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let root = LocalStore::default_root().ok_or("Could not determine home directory")?;
+    LocalStore::new(root)
+        .import_trust_bundle(&bundle)
+        .map_err(|e| e.to_string())
+}
+
+/// Write every trusted organization key to a PEM bundle file
+#[tauri::command]
+fn export_trust_bundle(out_path: &str) -> Result<Vec<TrustedKeyInfo>, String> {
+    let root = LocalStore::default_root().ok_or("Could not determine home directory")?;
+    let (bundle, keys) = LocalStore::new(root)
+        .export_trust_bundle()
+        .map_err(|e| e.to_string())?;
+    std::fs::write(out_path, bundle).map_err(|e| e.to_string())?;
+    Ok(keys)
+}
+
+/// Distinct devices this installation has seen open `doc_id`, for the
+/// local `max_devices` check
+#[tauri::command]
+fn local_device_count(doc_id: &str) -> u32 {
+    device_limit::local_device_count(doc_id)
+}
+
+/// Opens left for a `max_opens` document; `None` means unlimited (or not
+/// opened on this machine yet)
+#[tauri::command]
+fn remaining_opens(doc_id: &str) -> Result<Option<u32>, String> {
+    let path = ViewCounter::default_path().ok_or("Could not determine home directory")?;
+    Ok(ViewCounter::load(&path)?.remaining_opens(doc_id))
+}
+
+/// Whether `set_screenshot_protection` can hide windows on this platform
+#[tauri::command]
+pub fn screenshot_protection_available() -> ScreenshotProtection {
+    screen_protection::screenshot_protection_available()
+}
+
+/// Toggle capture protection on the calling window
+///
+/// Returns `Unsupported` without touching the window on platforms that
+/// have no no-capture flag. Public so the viewer binary can register it.
+#[tauri::command]
+pub fn set_screenshot_protection(
+    window: tauri::WebviewWindow,
+    enabled: bool,
+) -> Result<ScreenshotProtection, String> {
+    let support = screen_protection::screenshot_protection_available();
+    if support.is_supported() {
+        window
+            .set_content_protected(enabled)
+            .map_err(|e| format!("Failed to set screenshot protection: {}", e))?;
+    }
+    Ok(support)
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_dialog::init())
+        .manage(DecryptThrottle::default())
         .invoke_handler(tauri::generate_handler![
             greet,
             get_spdf_info,
+            get_display_header,
+            get_integrity_report,
+            can_open,
+            crypto_profile,
+            action_requirement,
+            get_capabilities,
             get_device_info,
+            get_device_fingerprint_components,
+            record_fingerprint_snapshot,
+            fingerprint_history,
+            verify_device_hash_vector,
+            validate_watermark_template,
+            telemetry_id,
             verify_spdf,
-            decrypt_spdf
+            verify_spdf_detailed,
+            verify_spdf_trusted,
+            would_accept_device,
+            check_key_pin,
+            decrypt_spdf,
+            decrypt_spdf_segment,
+            open_sandboxed,
+            export_for_recipient,
+            reissue,
+            verify_decrypted_against,
+            same_doc_key,
+            screenshot_protection_available,
+            set_screenshot_protection,
+            validate_spdf_header,
+            max_offline_until,
+            can_open_offline,
+            check_offline_validity,
+            crl_status,
+            make_redacted_sample,
+            scan_library,
+            verify_directory,
+            export_library_csv,
+            find_duplicate_doc_ids,
+            audit_local_store,
+            remaining_opens,
+            local_device_count,
+            import_org_key,
+            confirm_key_fingerprint,
+            import_trust_bundle,
+            export_trust_bundle
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use aes_kw::KekAes256;
+    use ed25519_dalek::SigningKey;
+    use std::fs;
+
+    const KEK: [u8; 32] = [0x07; 32];
+
+    #[test]
+    fn test_decrypt_spdf_requires_trusted_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys_dir = dir.path().join("keys");
+        fs::create_dir_all(&keys_dir).unwrap();
+        let mut wrapped = [0u8; WRAPPED_KEY_LENGTH];
+        KekAes256::from(KEK).wrap(&test_support::DOC_KEY, &mut wrapped).unwrap();
+        let data = test_support::build_spdf_with_wrapped_key(
+            &test_support::sample_header(),
+            test_support::DEFAULT_FLAGS,
+            &wrapped,
+            b"%PDF-1.7 trusted",
+        );
+        let path = dir.path().join("doc.spdf");
+        fs::write(&path, data).unwrap();
+        let trust_store = KeySource::for_keys_dir(&keys_dir);
+        let decrypt = || {
+            let throttle = DecryptThrottle::default();
+            let path = path.to_str().unwrap();
+            decrypt_with_kek(&trust_store, &throttle, path, &hex::encode(KEK), |_, _| {}).unwrap()
+        };
+        let refused = |result: DecryptResult| {
+            assert!(!result.success);
+            assert!(result.pdf_data.is_none());
+            let error = result.error.unwrap();
+            assert!(error.starts_with("Signature verification failed"), "{}", error);
+        };
+
+        // The header carries the signer's own key, which vouches for nothing
+        refused(decrypt());
+        let other = SigningKey::from_bytes(&[0x99; 32]);
+        let key_file = keys_dir.join("test_org_public.pem");
+        fs::write(&key_file, test_support::public_key_pem_for(&other)).unwrap();
+        refused(decrypt());
+
+        fs::write(&key_file, test_support::public_key_pem()).unwrap();
+        let opened = decrypt();
+        assert!(opened.success, "{:?}", opened.error);
+        assert_eq!(opened.pdf_data.unwrap(), b"%PDF-1.7 trusted");
+    }
+}
@@ -0,0 +1,389 @@
+// Library Module - Scanning folders of SPDF documents
+//
+// Scanning reads only the fixed prefix and the JSON header of each file,
+// so large distribution folders can be indexed without loading any
+// ciphertext. Files that are not SPDF, or whose header cannot be read,
+// are skipped.
+//
+// A scan can be exported as CSV for cataloguing in a spreadsheet.
+//
+// A folder can also be audited: every SPDF file in it is read in full and
+// its signature verified against the org key the trust store holds,
+// spread across CPUs with rayon.
+//
+// Symlinked directories are not followed, so a link back up the tree
+// cannot make a walk loop forever.
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::key_source::{verify_signature_against_trust_store, KeySource};
+use crate::spdf_parser::{
+    read_raw_header_and_flags, validate_magic, SpdfError, SpdfFile, SpdfHeader,
+};
+
+/// One SPDF document found while scanning a library folder
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LibraryEntry {
+    pub path: PathBuf,
+    pub doc_id: String,
+    pub org_id: String,
+    pub title: String,
+    pub version: u8,
+    pub flags: u16,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+}
+
+/// Recursively scan `dir` for SPDF files, sorted by path
+pub fn scan_library(dir: &Path) -> Result<Vec<LibraryEntry>, SpdfError> {
+    let mut entries = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        for item in fs::read_dir(&dir)? {
+            let item = item?;
+            let path = item.path();
+            if item.file_type()?.is_dir() {
+                pending.push(path);
+            } else if let Some(entry) = read_entry(&path) {
+                entries.push(entry);
+            }
+        }
+    }
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+/// Read the header of a single file, or `None` if it is not a readable SPDF
+fn read_entry(path: &Path) -> Option<LibraryEntry> {
+    let (version, flags, header_json) = read_raw_header_and_flags(path.to_str()?).ok()?;
+    let header: SpdfHeader = serde_json::from_slice(&header_json).ok()?;
+
+    Some(LibraryEntry {
+        path: path.to_path_buf(),
+        doc_id: header.doc_id,
+        org_id: header.org_id,
+        title: header.title,
+        version,
+        flags,
+        created_at: header.created_at,
+        expires_at: header.expires_at,
+    })
+}
+
+/// Column names of the CSV written by `write_csv`
+pub const CSV_COLUMNS: [&str; 7] = [
+    "doc_id",
+    "title",
+    "org_id",
+    "created_at",
+    "flags",
+    "expires_at",
+    "path",
+];
+
+/// Write `entries` as CSV with a header row, one row per entry
+///
+/// Flags are written as hex (`0x0003`); a document without an expiry has
+/// an empty `expires_at`.
+pub fn write_csv<W: std::io::Write>(entries: &[LibraryEntry], writer: W) -> Result<(), SpdfError> {
+    let mut csv = csv::Writer::from_writer(writer);
+    csv.write_record(CSV_COLUMNS).map_err(csv_error)?;
+    for entry in entries {
+        csv.write_record([
+            entry.doc_id.as_str(),
+            &entry.title,
+            &entry.org_id,
+            &entry.created_at,
+            &format!("{:#06x}", entry.flags),
+            entry.expires_at.as_deref().unwrap_or(""),
+            &entry.path.to_string_lossy(),
+        ])
+        .map_err(csv_error)?;
+    }
+    csv.flush()?;
+    Ok(())
+}
+
+/// Scan `dir` and write the result to `out_path` as CSV; returns the
+/// number of rows written, not counting the header
+pub fn export_csv(dir: &Path, out_path: &Path) -> Result<usize, SpdfError> {
+    let entries = scan_library(dir)?;
+    write_csv(&entries, fs::File::create(out_path)?)?;
+    Ok(entries.len())
+}
+
+fn csv_error(err: csv::Error) -> SpdfError {
+    SpdfError::FormatError(format!("CSV write error: {}", err))
+}
+
+/// Every doc_id claimed by more than one file, with the paths claiming it
+///
+/// Sorted by doc_id; paths keep the order of `entries`.
+pub fn find_duplicate_doc_ids(entries: &[LibraryEntry]) -> Vec<(String, Vec<PathBuf>)> {
+    let mut by_doc_id: BTreeMap<&str, Vec<PathBuf>> = BTreeMap::new();
+    for entry in entries {
+        by_doc_id
+            .entry(entry.doc_id.as_str())
+            .or_default()
+            .push(entry.path.clone());
+    }
+
+    by_doc_id
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .map(|(doc_id, paths)| (doc_id.to_string(), paths))
+        .collect()
+}
+
+/// Verify the signature of every SPDF file in `dir`, and in its
+/// subdirectories if `recursive`, against the org keys in `trust_store`,
+/// sorted by path
+///
+/// Files without the SPDF magic are skipped. A file that has it but does
+/// not parse or verify, or whose org has no trusted key, is reported with
+/// its error, as is a file or directory that cannot be read.
+pub fn verify_directory(
+    dir: &Path,
+    recursive: bool,
+    trust_store: &KeySource,
+) -> Vec<(PathBuf, Result<(), SpdfError>)> {
+    let mut results = Vec::new();
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let items = match fs::read_dir(&dir) {
+            Ok(items) => items,
+            Err(e) => {
+                results.push((dir, Err(e.into())));
+                continue;
+            }
+        };
+        for item in items {
+            let item = item.and_then(|item| Ok((item.path(), item.file_type()?)));
+            match item {
+                Ok((path, file_type)) if file_type.is_dir() => {
+                    if recursive {
+                        pending.push(path);
+                    }
+                }
+                // A link to a directory is neither walked nor a file
+                Ok((path, file_type)) if file_type.is_symlink() && path.is_dir() => {}
+                Ok((path, _)) => files.push(path),
+                Err(e) => results.push((dir.clone(), Err(e.into()))),
+            }
+        }
+    }
+
+    results.par_extend(
+        files
+            .into_par_iter()
+            .filter_map(|path| verify_file(&path, trust_store).map(|result| (path, result))),
+    );
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+    results
+}
+
+/// Parse and verify one file, or `None` if it is not an SPDF file
+fn verify_file(path: &Path, trust_store: &KeySource) -> Option<Result<(), SpdfError>> {
+    let mut magic = [0u8; 4];
+    match fs::File::open(path).and_then(|mut file| file.read_exact(&mut magic)) {
+        Ok(()) if validate_magic(&magic) => {}
+        Ok(()) => return None,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return None,
+        Err(e) => return Some(Err(e.into())),
+    }
+
+    let verified = fs::read(path)
+        .map_err(SpdfError::from)
+        .and_then(|data| SpdfFile::parse(&data))
+        .and_then(|spdf| {
+            verify_signature_against_trust_store(&spdf, trust_store).map_err(SpdfError::from)
+        });
+    Some(verified)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{build_spdf_with, public_key_pem, sample_header, DEFAULT_FLAGS};
+
+    fn temp_library() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("nested")).unwrap();
+        dir
+    }
+
+    fn write_doc(path: &Path, doc_id: &str) {
+        let mut header = sample_header();
+        header["doc_id"] = serde_json::json!(doc_id);
+        fs::write(path, build_spdf_with(&header, DEFAULT_FLAGS, b"%PDF")).unwrap();
+    }
+
+    #[test]
+    fn test_scan_library() {
+        let library = temp_library();
+        let dir = library.path();
+        write_doc(&dir.join("a.spdf"), "DOC-A");
+        write_doc(&dir.join("nested").join("b.spdf"), "DOC-B");
+        fs::write(dir.join("notes.txt"), b"not an spdf").unwrap();
+        fs::write(dir.join("broken.spdf"), b"SPDF\x01\x00\x00\x00\x00\x00\x10{").unwrap();
+
+        let entries = scan_library(dir).unwrap();
+        let doc_ids: Vec<&str> = entries.iter().map(|e| e.doc_id.as_str()).collect();
+        assert_eq!(doc_ids, ["DOC-A", "DOC-B"]);
+        assert_eq!(entries[0].title, "Test Document");
+        assert_eq!(entries[1].path, dir.join("nested").join("b.spdf"));
+    }
+
+    #[test]
+    fn test_find_duplicate_doc_ids() {
+        let library = temp_library();
+        let dir = library.path();
+        write_doc(&dir.join("a.spdf"), "DOC-A");
+        write_doc(&dir.join("a-copy.spdf"), "DOC-A");
+        write_doc(&dir.join("nested").join("a-old.spdf"), "DOC-A");
+        write_doc(&dir.join("b.spdf"), "DOC-B");
+
+        let entries = scan_library(dir).unwrap();
+        let duplicates = find_duplicate_doc_ids(&entries);
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].0, "DOC-A");
+        assert_eq!(
+            duplicates[0].1,
+            [
+                dir.join("a-copy.spdf"),
+                dir.join("a.spdf"),
+                dir.join("nested").join("a-old.spdf"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_export_csv_escapes_titles() {
+        let library = temp_library();
+        let dir = library.path();
+        let titles = [
+            ("DOC-A", "Q3 Report, Final"),
+            ("DOC-B", "The \"Blue\" Book"),
+        ];
+        for (doc_id, title) in titles {
+            let mut header = sample_header();
+            header["doc_id"] = serde_json::json!(doc_id);
+            header["title"] = serde_json::json!(title);
+            header["expires_at"] = serde_json::json!("2030-01-01T00:00:00Z");
+            let data = build_spdf_with(&header, DEFAULT_FLAGS, b"%PDF");
+            fs::write(dir.join(format!("{}.spdf", doc_id)), data).unwrap();
+        }
+        write_doc(&dir.join("nested").join("c.spdf"), "DOC-C");
+
+        let out_path = dir.join("library.csv");
+        assert_eq!(export_csv(dir, &out_path).unwrap(), 3);
+
+        let csv = fs::read_to_string(&out_path).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines[0],
+            "doc_id,title,org_id,created_at,flags,expires_at,path"
+        );
+        assert!(lines[1].starts_with("DOC-A,\"Q3 Report, Final\",test_org,"));
+        assert!(lines[2].starts_with("DOC-B,\"The \"\"Blue\"\" Book\",test_org,"));
+
+        let mut reader = csv::Reader::from_path(&out_path).unwrap();
+        let rows: Vec<csv::StringRecord> = reader.records().map(|r| r.unwrap()).collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(&rows[0][1], "Q3 Report, Final");
+        assert_eq!(&rows[1][1], "The \"Blue\" Book");
+        assert_eq!(&rows[0][4], format!("{:#06x}", DEFAULT_FLAGS));
+        assert_eq!(&rows[0][5], "2030-01-01T00:00:00Z");
+        assert_eq!(&rows[2][5], "");
+        assert_eq!(
+            &rows[2][6],
+            dir.join("nested").join("c.spdf").to_str().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_no_duplicates() {
+        let entries: Vec<LibraryEntry> = ["DOC-A", "DOC-B"]
+            .iter()
+            .map(|doc_id| LibraryEntry {
+                path: PathBuf::from(format!("{}.spdf", doc_id)),
+                doc_id: doc_id.to_string(),
+                org_id: "test_org".to_string(),
+                title: String::new(),
+                version: 1,
+                flags: DEFAULT_FLAGS,
+                created_at: String::new(),
+                expires_at: None,
+            })
+            .collect();
+        assert!(find_duplicate_doc_ids(&entries).is_empty());
+    }
+
+    #[test]
+    fn test_verify_directory() {
+        let library = temp_library();
+        let dir = library.path();
+        let keys = temp_library();
+        let keys_dir = keys.path();
+        fs::write(keys_dir.join("test_org_public.pem"), public_key_pem()).unwrap();
+        let trust_store = KeySource::Files { keys_dir: keys_dir.to_path_buf() };
+        write_doc(&dir.join("a.spdf"), "DOC-A");
+        write_doc(&dir.join("nested").join("b.spdf"), "DOC-B");
+        write_doc(&dir.join("tampered.spdf"), "DOC-T");
+        let mut tampered = fs::read(dir.join("tampered.spdf")).unwrap();
+        *tampered.last_mut().unwrap() ^= 0x01;
+        fs::write(dir.join("tampered.spdf"), tampered).unwrap();
+        fs::write(dir.join("notes.txt"), b"not an spdf").unwrap();
+        fs::write(dir.join("tiny"), b"SP").unwrap();
+        // Validly signed, but with a key nobody trusts for this org
+        let mut header = sample_header();
+        header["org_id"] = serde_json::json!("rogue_org");
+        fs::write(dir.join("untrusted.spdf"), build_spdf_with(&header, DEFAULT_FLAGS, b"%PDF"))
+            .unwrap();
+
+        let results = verify_directory(dir, true, &trust_store);
+        let paths: Vec<&Path> = results.iter().map(|(path, _)| path.as_path()).collect();
+        assert_eq!(
+            paths,
+            [
+                dir.join("a.spdf"),
+                dir.join("nested").join("b.spdf"),
+                dir.join("tampered.spdf"),
+                dir.join("untrusted.spdf"),
+            ]
+        );
+        assert!(results[0].1.is_ok());
+        assert!(results[1].1.is_ok());
+        assert!(matches!(results[2].1, Err(SpdfError::SignatureError(_))));
+        let untrusted = results[3].1.as_ref().unwrap_err().to_string();
+        assert!(untrusted.contains("No trusted key for rogue_org"), "{}", untrusted);
+
+        // Not descending into `nested`
+        let results = verify_directory(dir, false, &trust_store);
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|(path, _)| path.parent() == Some(dir)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_loop_not_followed() {
+        let library = temp_library();
+        let dir = library.path();
+        write_doc(&dir.join("nested").join("b.spdf"), "DOC-B");
+        std::os::unix::fs::symlink(dir, dir.join("nested").join("loop")).unwrap();
+
+        let entries = scan_library(dir).unwrap();
+        assert_eq!(entries.len(), 1);
+        let trust_store = KeySource::Files { keys_dir: dir.join("keys") };
+        assert_eq!(verify_directory(dir, true, &trust_store).len(), 1);
+    }
+}
@@ -0,0 +1,773 @@
+// Local Store Module - Layout and hygiene of the ~/.spdf directory
+//
+// Everything the viewer persists outside the app data dir lives here:
+//
+//   ~/.spdf/
+//     keys/{org_id}_public.pem   organization signing keys
+//     keys/pending/              imported keys awaiting fingerprint confirmation
+//     offline/{doc_id}.key       cached document keys, sealed with the KEK
+//     offline/{doc_id}.grant     when each cached key was granted (offline)
+//     pins.json                  trust-on-first-use key pins
+//     opens.json                 per-document open counts (view_limit)
+//     expiry.json                server-granted expiry extensions (expiry)
+//     crl/{org_id}.json          last fetched revocation list per organization
+//     crl.json                   single list from before per-org lists, read
+//                                for orgs that have none of their own yet
+//     fingerprints.json          device hash snapshots (fingerprint_log)
+//     policy.json                local security policy
+//
+// Cached document keys are sealed with AES-256-GCM under a key-encryption
+// key (KEK) derived from the device hash, with the doc_id as associated
+// data, so a cache copied to another machine (or renamed to another
+// document) does not open. Offline grants are sealed the same way, so
+// their timestamps cannot be edited, and so is the login session the
+// viewer keeps in its app data dir, under a key that also takes a
+// per-install salt.
+
+use aes_gcm::{
+    aead::{rand_core::RngCore, Aead, KeyInit, OsRng, Payload},
+    Aes256Gcm, Nonce,
+};
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::fs;
+use std::path::{Path, PathBuf};
+use subtle::ConstantTimeEq;
+use zeroize::Zeroizing;
+
+use crate::device_id::device_hash_digest;
+use crate::revocation::RevocationList;
+use crate::spdf_parser::{SpdfError, NONCE_LENGTH};
+use crate::trust::{key_fingerprint, normalize_fingerprint};
+use crate::verify::parse_ed25519_public_key_pem;
+
+/// HKDF info string for the device KEK
+const DEVICE_KEK_INFO: &[u8] = b"spdf_device_kek_v1";
+
+/// Extension of sealed document keys in `offline/`
+const CACHED_KEY_EXTENSION: &str = "key";
+
+/// Extension of sealed offline grants in `offline/`
+const GRANT_EXTENSION: &str = "grant";
+
+/// Associated data prefix of sealed grants, so a grant never opens as a
+/// key or the other way round
+const GRANT_AAD_PREFIX: &[u8] = b"spdf-offline-grant-v1\n";
+
+/// HKDF info string for the session key
+const SESSION_KEK_INFO: &[u8] = b"spdf_session_kek_v1";
+
+/// Associated data of the sealed session
+const SESSION_AAD: &[u8] = b"spdf-session-v1";
+
+/// Derive the key-encryption key for cached document keys on this device
+///
+/// Keyed on the hash's digest, not its version prefix, so keys cached
+/// under a legacy bare-hex hash still open.
+pub fn device_kek(device_hash: &str) -> Zeroizing<[u8; 32]> {
+    let hkdf = Hkdf::<Sha256>::new(None, device_hash_digest(device_hash).as_bytes());
+    let mut kek = Zeroizing::new([0u8; 32]);
+    hkdf.expand(DEVICE_KEK_INFO, kek.as_mut())
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    kek
+}
+
+/// NONCE || AES-GCM(kek, msg) with `aad` as associated data
+fn seal(kek: &[u8; 32], aad: &[u8], msg: &[u8]) -> Result<Vec<u8>, aes_gcm::Error> {
+    let mut nonce = [0u8; NONCE_LENGTH];
+    OsRng.fill_bytes(&mut nonce);
+
+    let sealed =
+        Aes256Gcm::new(kek.into()).encrypt(Nonce::from_slice(&nonce), Payload { msg, aad })?;
+    let mut entry = nonce.to_vec();
+    entry.extend_from_slice(&sealed);
+    Ok(entry)
+}
+
+/// Open an entry written by `seal`; `None` if it is too short or does not
+/// authenticate
+fn open(kek: &[u8; 32], aad: &[u8], entry: &[u8]) -> Option<Zeroizing<Vec<u8>>> {
+    if entry.len() < NONCE_LENGTH {
+        return None;
+    }
+    let (nonce, sealed) = entry.split_at(NONCE_LENGTH);
+    Aes256Gcm::new(kek.into())
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: sealed, aad })
+        .ok()
+        .map(Zeroizing::new)
+}
+
+/// Seal a document key for the offline cache: NONCE || AES-GCM(kek, doc_key)
+pub fn seal_cached_key(
+    kek: &[u8; 32],
+    doc_id: &str,
+    doc_key: &[u8; 32],
+) -> Result<Vec<u8>, SpdfError> {
+    seal(kek, doc_id.as_bytes(), doc_key)
+        .map_err(|e| SpdfError::EncryptionError(format!("Failed to seal cached key: {}", e)))
+}
+
+/// Open a sealed cache entry written by `seal_cached_key`
+pub fn open_cached_key(
+    kek: &[u8; 32],
+    doc_id: &str,
+    entry: &[u8],
+) -> Result<Zeroizing<[u8; 32]>, SpdfError> {
+    if entry.len() < NONCE_LENGTH {
+        return Err(SpdfError::DecryptionError("Cached key entry too short".to_string()));
+    }
+    let plaintext = open(kek, doc_id.as_bytes(), entry).ok_or_else(|| {
+        SpdfError::DecryptionError("Cached key does not open with this device's key".to_string())
+    })?;
+
+    let mut doc_key = Zeroizing::new([0u8; 32]);
+    if plaintext.len() != doc_key.len() {
+        return Err(SpdfError::DecryptionError("Cached key has the wrong length".to_string()));
+    }
+    doc_key.copy_from_slice(&plaintext);
+    Ok(doc_key)
+}
+
+fn grant_aad(doc_id: &str) -> Vec<u8> {
+    [GRANT_AAD_PREFIX, doc_id.as_bytes()].concat()
+}
+
+/// Seal a serialized offline grant (see `offline::OfflineGrant`) the way
+/// cached keys are sealed
+pub fn seal_grant(kek: &[u8; 32], doc_id: &str, grant: &[u8]) -> Result<Vec<u8>, SpdfError> {
+    seal(kek, &grant_aad(doc_id), grant)
+        .map_err(|e| SpdfError::EncryptionError(format!("Failed to seal offline grant: {}", e)))
+}
+
+/// Open a grant written by `seal_grant`
+pub fn open_grant(kek: &[u8; 32], doc_id: &str, entry: &[u8]) -> Result<Vec<u8>, SpdfError> {
+    open(kek, &grant_aad(doc_id), entry)
+        .map(|grant| grant.to_vec())
+        .ok_or_else(|| {
+            SpdfError::DecryptionError(
+                "Offline grant does not open with this device's key".to_string(),
+            )
+        })
+}
+
+/// Derive the key that seals the login session at rest
+///
+/// Keyed on the device hash's digest, like `device_kek`, with
+/// `install_salt` as the HKDF salt: a session copied to another device, or
+/// to another install on this one, does not open.
+pub fn session_kek(device_hash: &str, install_salt: &[u8]) -> Zeroizing<[u8; 32]> {
+    let hkdf = Hkdf::<Sha256>::new(Some(install_salt), device_hash_digest(device_hash).as_bytes());
+    let mut kek = Zeroizing::new([0u8; 32]);
+    hkdf.expand(SESSION_KEK_INFO, kek.as_mut())
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    kek
+}
+
+/// Seal a serialized login session: NONCE || AES-GCM(kek, session)
+pub fn seal_session(kek: &[u8; 32], session: &[u8]) -> Result<Vec<u8>, SpdfError> {
+    seal(kek, SESSION_AAD, session)
+        .map_err(|e| SpdfError::EncryptionError(format!("Failed to seal session: {}", e)))
+}
+
+/// Open a session written by `seal_session`
+pub fn open_session(kek: &[u8; 32], entry: &[u8]) -> Result<Zeroizing<Vec<u8>>, SpdfError> {
+    open(kek, SESSION_AAD, entry).ok_or_else(|| {
+        SpdfError::DecryptionError("Session does not open with this device's key".to_string())
+    })
+}
+
+/// Comment that names the organization of the PEM block after it
+const BUNDLE_ORG_PREFIX: &str = "# org_id:";
+
+/// An organization key written to or read from a trust bundle
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrustedKeyInfo {
+    pub org_id: String,
+    /// Hex SHA-256 of the raw public key, as `trust::key_fingerprint`
+    pub fingerprint: String,
+}
+
+/// Split a trust bundle into (org_id, PEM) pairs
+///
+/// A bundle is concatenated `PUBLIC KEY` blocks, each preceded by a
+/// `# org_id: <id>` line. Other comment and blank lines are ignored.
+pub fn parse_trust_bundle(bundle: &str) -> Result<Vec<(String, String)>, SpdfError> {
+    let mut keys: Vec<(String, String)> = Vec::new();
+    let mut org_id: Option<String> = None;
+    let mut block: Option<String> = None;
+
+    for (number, line) in bundle.lines().enumerate() {
+        let line = line.trim();
+        if let Some(pem) = block.as_mut() {
+            pem.push_str(line);
+            pem.push('\n');
+            if line == "-----END PUBLIC KEY-----" {
+                let org = org_id.take().ok_or_else(|| {
+                    SpdfError::FormatError(format!(
+                        "Key ending on line {} has no '{}' comment",
+                        number + 1,
+                        BUNDLE_ORG_PREFIX
+                    ))
+                })?;
+                if keys.iter().any(|(existing, _)| *existing == org) {
+                    return Err(SpdfError::FormatError(format!(
+                        "Bundle has more than one key for {}",
+                        org
+                    )));
+                }
+                keys.push((org, block.take().unwrap_or_default()));
+            }
+        } else if let Some(org) = line.strip_prefix(BUNDLE_ORG_PREFIX) {
+            org_id = Some(org.trim().to_string());
+        } else if line == "-----BEGIN PUBLIC KEY-----" {
+            block = Some(format!("{}\n", line));
+        } else if !line.is_empty() && !line.starts_with('#') {
+            return Err(SpdfError::FormatError(format!(
+                "Unexpected content on line {} of trust bundle",
+                number + 1
+            )));
+        }
+    }
+
+    if block.is_some() {
+        return Err(SpdfError::FormatError("Trust bundle ends inside a key".to_string()));
+    }
+    Ok(keys)
+}
+
+/// Findings of `LocalStore::audit`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoreAudit {
+    /// Number of files inspected
+    pub checked: usize,
+    /// Key PEMs or JSON state files that do not parse
+    pub corrupt: Vec<PathBuf>,
+    /// Cached keys that do not open with the current device KEK
+    pub undecryptable: Vec<PathBuf>,
+    /// Files readable by other users (Unix only)
+    pub world_readable: Vec<PathBuf>,
+    /// Files the viewer did not write and does not know about
+    pub orphaned: Vec<PathBuf>,
+}
+
+impl StoreAudit {
+    pub fn is_clean(&self) -> bool {
+        self.corrupt.is_empty()
+            && self.undecryptable.is_empty()
+            && self.world_readable.is_empty()
+            && self.orphaned.is_empty()
+    }
+}
+
+/// The ~/.spdf directory (or a stand-in root for tests)
+#[derive(Debug, Clone)]
+pub struct LocalStore {
+    root: PathBuf,
+}
+
+impl LocalStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalStore { root: root.into() }
+    }
+
+    /// `~/.spdf`
+    pub fn default_root() -> Option<PathBuf> {
+        dirs::home_dir().map(|home| home.join(".spdf"))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn keys_dir(&self) -> PathBuf {
+        self.root.join("keys")
+    }
+
+    pub fn offline_dir(&self) -> PathBuf {
+        self.root.join("offline")
+    }
+
+    pub fn pins_path(&self) -> PathBuf {
+        self.root.join("pins.json")
+    }
+
+    pub fn policy_path(&self) -> PathBuf {
+        self.root.join("policy.json")
+    }
+
+    pub fn expiry_path(&self) -> PathBuf {
+        self.root.join("expiry.json")
+    }
+
+    pub fn crl_dir(&self) -> PathBuf {
+        self.root.join("crl")
+    }
+
+    pub fn crl_path(&self, org_id: &str) -> Result<PathBuf, SpdfError> {
+        validate_org_id(org_id)?;
+        Ok(self.crl_dir().join(format!("{}.json", org_id)))
+    }
+
+    /// Where revocation lists were cached before they were kept per org
+    pub fn legacy_crl_path(&self) -> PathBuf {
+        self.root.join("crl.json")
+    }
+
+    pub fn org_key_path(&self, org_id: &str) -> PathBuf {
+        self.keys_dir().join(format!("{}_public.pem", org_id))
+    }
+
+    pub fn cached_key_path(&self, doc_id: &str) -> Result<PathBuf, SpdfError> {
+        validate_id("doc_id", doc_id)?;
+        Ok(self.offline_dir().join(format!("{}.{}", doc_id, CACHED_KEY_EXTENSION)))
+    }
+
+    pub fn grant_path(&self, doc_id: &str) -> Result<PathBuf, SpdfError> {
+        validate_id("doc_id", doc_id)?;
+        Ok(self.offline_dir().join(format!("{}.{}", doc_id, GRANT_EXTENSION)))
+    }
+
+    fn pending_key_path(&self, org_id: &str) -> PathBuf {
+        self.keys_dir().join("pending").join(format!("{}_public.pem", org_id))
+    }
+
+    /// Stage an organization key for import and return its fingerprint
+    ///
+    /// The key is not trusted until `confirm_key_fingerprint` succeeds.
+    pub fn import_org_key(&self, org_id: &str, public_key_pem: &str) -> Result<String, SpdfError> {
+        validate_org_id(org_id)?;
+        let fingerprint = key_fingerprint(public_key_pem)?;
+
+        let pending = self.pending_key_path(org_id);
+        if let Some(parent) = pending.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&pending, public_key_pem)?;
+        Ok(fingerprint)
+    }
+
+    /// Activate a staged key if its fingerprint matches the one the
+    /// organization published
+    ///
+    /// `fingerprint` is the value `import_org_key` returned and `expected`
+    /// the published one; both are normalized and compared in constant
+    /// time against the staged key. On a mismatch the staged key is
+    /// discarded.
+    pub fn confirm_key_fingerprint(
+        &self,
+        org_id: &str,
+        fingerprint: &str,
+        expected: &str,
+    ) -> Result<(), SpdfError> {
+        validate_org_id(org_id)?;
+        let pending = self.pending_key_path(org_id);
+        if !pending.exists() {
+            return Err(SpdfError::FormatError(format!("No pending key import for {}", org_id)));
+        }
+
+        let staged = normalize_fingerprint(&key_fingerprint(&fs::read_to_string(&pending)?)?)?;
+        let shown = normalize_fingerprint(fingerprint)?;
+        let published = normalize_fingerprint(expected)?;
+        let matches = staged.ct_eq(&shown) & staged.ct_eq(&published);
+        if !bool::from(matches) {
+            fs::remove_file(&pending)?;
+            return Err(SpdfError::SignatureError(format!(
+                "Fingerprint of the imported key for {} does not match the published one",
+                org_id
+            )));
+        }
+
+        fs::rename(&pending, self.org_key_path(org_id))?;
+        Ok(())
+    }
+
+    /// Trust every key in a bundle, replacing existing keys for its orgs
+    ///
+    /// All keys are validated before any is written, so a bad bundle
+    /// changes nothing. Unlike `import_org_key` there is no fingerprint
+    /// step: a bundle is distributed by the administrator who would
+    /// otherwise publish the fingerprints.
+    pub fn import_trust_bundle(&self, bundle: &str) -> Result<Vec<TrustedKeyInfo>, SpdfError> {
+        let keys = parse_trust_bundle(bundle)?;
+        let mut imported = Vec::with_capacity(keys.len());
+        for (org_id, pem) in &keys {
+            validate_org_id(org_id)?;
+            imported.push(TrustedKeyInfo {
+                org_id: org_id.clone(),
+                fingerprint: key_fingerprint(pem)?,
+            });
+        }
+
+        fs::create_dir_all(self.keys_dir())?;
+        for (org_id, pem) in &keys {
+            fs::write(self.org_key_path(org_id), pem)?;
+        }
+        Ok(imported)
+    }
+
+    /// Every trusted organization key as a bundle `import_trust_bundle`
+    /// accepts, ordered by org_id
+    pub fn export_trust_bundle(&self) -> Result<(String, Vec<TrustedKeyInfo>), SpdfError> {
+        let mut bundle = String::new();
+        let mut exported = Vec::new();
+        for path in list_files(&self.keys_dir())? {
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            let Some(org_id) = name.strip_suffix("_public.pem") else {
+                continue;
+            };
+            let pem = fs::read_to_string(&path)?;
+            exported.push(TrustedKeyInfo {
+                org_id: org_id.to_string(),
+                fingerprint: key_fingerprint(&pem)?,
+            });
+            bundle.push_str(&format!("{} {}\n{}", BUNDLE_ORG_PREFIX, org_id, pem));
+            if !pem.ends_with('\n') {
+                bundle.push('\n');
+            }
+        }
+        Ok((bundle, exported))
+    }
+
+    /// Check every file under the store
+    ///
+    /// Missing directories are fine (nothing cached yet); unreadable ones
+    /// are an error.
+    pub fn audit(&self, kek: &[u8; 32]) -> Result<StoreAudit, SpdfError> {
+        let mut audit = StoreAudit::default();
+
+        for path in list_files(&self.root)? {
+            audit.checked += 1;
+            check_permissions(&path, &mut audit);
+
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            let parseable = match name {
+                "pins.json" | "policy.json" | "opens.json" | "fingerprints.json"
+                | "expiry.json" => {
+                    serde_json::from_slice::<serde_json::Value>(&fs::read(&path)?).is_ok()
+                }
+                "crl.json" => serde_json::from_slice::<RevocationList>(&fs::read(&path)?).is_ok(),
+                _ => {
+                    audit.orphaned.push(path);
+                    continue;
+                }
+            };
+            if !parseable {
+                audit.corrupt.push(path);
+            }
+        }
+
+        for path in list_files(&self.keys_dir())? {
+            audit.checked += 1;
+            check_permissions(&path, &mut audit);
+
+            if path.extension().and_then(|e| e.to_str()) != Some("pem") {
+                audit.orphaned.push(path);
+                continue;
+            }
+            let pem = fs::read_to_string(&path).unwrap_or_default();
+            if parse_ed25519_public_key_pem(&pem).is_err() {
+                audit.corrupt.push(path);
+            }
+        }
+
+        for path in list_files(&self.crl_dir())? {
+            audit.checked += 1;
+            check_permissions(&path, &mut audit);
+
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                audit.orphaned.push(path);
+                continue;
+            }
+            if serde_json::from_slice::<RevocationList>(&fs::read(&path)?).is_err() {
+                audit.corrupt.push(path);
+            }
+        }
+
+        for path in list_files(&self.offline_dir())? {
+            audit.checked += 1;
+            check_permissions(&path, &mut audit);
+
+            let extension = path.extension().and_then(|e| e.to_str());
+            let doc_id = match extension {
+                Some(CACHED_KEY_EXTENSION | GRANT_EXTENSION) => {
+                    path.file_stem().and_then(|s| s.to_str())
+                }
+                _ => None,
+            };
+            let Some(doc_id) = doc_id else {
+                audit.orphaned.push(path);
+                continue;
+            };
+            let entry = fs::read(&path)?;
+            let opens = match extension {
+                Some(GRANT_EXTENSION) => open_grant(kek, doc_id, &entry).is_ok(),
+                _ => open_cached_key(kek, doc_id, &entry).is_ok(),
+            };
+            if !opens {
+                audit.undecryptable.push(path);
+            }
+        }
+
+        Ok(audit)
+    }
+}
+
+pub(crate) fn validate_org_id(org_id: &str) -> Result<(), SpdfError> {
+    validate_id("org_id", org_id)
+}
+
+/// Org and document ids become file names, so keep them to the server's
+/// charset
+fn validate_id(field: &str, id: &str) -> Result<(), SpdfError> {
+    let valid = !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if valid {
+        Ok(())
+    } else {
+        Err(SpdfError::FormatError(format!("Invalid {} '{}'", field, id)))
+    }
+}
+
+/// Regular files directly inside `dir`, sorted; empty if `dir` is missing
+fn list_files(dir: &Path) -> Result<Vec<PathBuf>, SpdfError> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(unix)]
+fn check_permissions(path: &Path, audit: &mut StoreAudit) {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Ok(metadata) = fs::metadata(path) {
+        if metadata.permissions().mode() & 0o004 != 0 {
+            audit.world_readable.push(path.to_path_buf());
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path, _audit: &mut StoreAudit) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{public_key_pem, DOC_KEY};
+
+    /// A store in a fresh directory, removed when the `TempDir` drops
+    fn temp_store() -> (tempfile::TempDir, LocalStore) {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("keys")).unwrap();
+        fs::create_dir_all(root.path().join("offline")).unwrap();
+        let store = LocalStore::new(root.path().to_path_buf());
+        (root, store)
+    }
+
+    #[cfg(unix)]
+    fn set_mode(path: &Path, mode: u32) {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    #[cfg(not(unix))]
+    fn set_mode(_path: &Path, _mode: u32) {}
+
+    #[test]
+    fn test_cached_key_round_trip() {
+        let kek = device_kek("device-a");
+        let entry = seal_cached_key(&kek, "DOC-1", &DOC_KEY).unwrap();
+        assert_eq!(*open_cached_key(&kek, "DOC-1", &entry).unwrap(), DOC_KEY);
+
+        // Bound to the device and to the document
+        assert!(open_cached_key(&device_kek("device-b"), "DOC-1", &entry).is_err());
+        assert!(open_cached_key(&kek, "DOC-2", &entry).is_err());
+
+        // Grants and keys are sealed apart and never open as each other
+        let grant = seal_grant(&kek, "DOC-1", b"{}").unwrap();
+        assert_eq!(open_grant(&kek, "DOC-1", &grant).unwrap(), b"{}");
+        assert!(open_grant(&kek, "DOC-1", &entry).is_err());
+        assert!(open_cached_key(&kek, "DOC-1", &grant).is_err());
+    }
+
+    #[test]
+    fn test_session_round_trip() {
+        let kek = session_kek("device-a", b"install-1");
+        let entry = seal_session(&kek, b"{\"access_token\":\"t\"}").unwrap();
+        assert_eq!(&**open_session(&kek, &entry).unwrap(), b"{\"access_token\":\"t\"}");
+
+        // Bound to the device and the install
+        assert!(open_session(&session_kek("device-b", b"install-1"), &entry).is_err());
+        assert!(open_session(&session_kek("device-a", b"install-2"), &entry).is_err());
+        assert!(open_session(&kek, b"{\"access_token\":\"t\"}").is_err());
+
+        // Never opens as a cached key sealed under the device KEK
+        let cached = seal_cached_key(&device_kek("device-a"), "DOC-1", &DOC_KEY).unwrap();
+        assert!(open_session(&kek, &cached).is_err());
+    }
+
+    #[test]
+    fn test_clean_store() {
+        let (_root, store) = temp_store();
+        let kek = device_kek("device-a");
+        fs::write(store.org_key_path("test_org"), public_key_pem()).unwrap();
+        fs::write(store.pins_path(), b"{}").unwrap();
+        let entry = seal_cached_key(&kek, "DOC-1", &DOC_KEY).unwrap();
+        fs::write(store.cached_key_path("DOC-1").unwrap(), entry).unwrap();
+        let files = [
+            store.org_key_path("test_org"),
+            store.pins_path(),
+            store.cached_key_path("DOC-1").unwrap(),
+        ];
+        for path in &files {
+            set_mode(path, 0o600);
+        }
+
+        let audit = store.audit(&kek).unwrap();
+        assert_eq!(audit.checked, 3);
+        assert!(audit.is_clean(), "{:?}", audit);
+    }
+
+    #[test]
+    fn test_corrupt_key_file() {
+        let (_root, store) = temp_store();
+        let corrupt = store.org_key_path("broken_org");
+        let pem = "-----BEGIN PUBLIC KEY-----\n!!!\n-----END PUBLIC KEY-----\n";
+        fs::write(&corrupt, pem).unwrap();
+        set_mode(&corrupt, 0o600);
+        let crl = store.crl_path("broken_org").unwrap();
+        fs::create_dir_all(store.crl_dir()).unwrap();
+        fs::write(&crl, br#"{"revoked": "DOC-1"}"#).unwrap();
+        set_mode(&crl, 0o600);
+
+        let audit = store.audit(&device_kek("device-a")).unwrap();
+        assert_eq!(audit.corrupt, [corrupt, crl]);
+    }
+
+    #[test]
+    fn test_cache_entries() {
+        let (_root, store) = temp_store();
+        let kek = device_kek("device-a");
+
+        // Sealed on another device
+        let foreign = store.cached_key_path("DOC-1").unwrap();
+        let entry = seal_cached_key(&device_kek("device-b"), "DOC-1", &DOC_KEY).unwrap();
+        fs::write(&foreign, entry).unwrap();
+        let stray = store.offline_dir().join("DOC-2.tmp");
+        fs::write(&stray, b"partial").unwrap();
+        set_mode(&foreign, 0o600);
+        set_mode(&stray, 0o600);
+
+        let audit = store.audit(&kek).unwrap();
+        assert_eq!(audit.undecryptable, [foreign]);
+        assert_eq!(audit.orphaned, [stray]);
+
+        // Document ids never reach outside `offline/`
+        for doc_id in ["../keys/acme_public", "DOC/1", "", "DOC.1"] {
+            assert!(store.cached_key_path(doc_id).is_err(), "{}", doc_id);
+            assert!(store.grant_path(doc_id).is_err(), "{}", doc_id);
+        }
+    }
+
+    #[test]
+    fn test_import_matching_fingerprint() {
+        let (_root, store) = temp_store();
+        let fingerprint = store.import_org_key("acme", &public_key_pem()).unwrap();
+        assert!(!store.org_key_path("acme").exists());
+
+        let published = fingerprint.to_uppercase();
+        store.confirm_key_fingerprint("acme", &fingerprint, &published).unwrap();
+        assert_eq!(fs::read_to_string(store.org_key_path("acme")).unwrap(), public_key_pem());
+    }
+
+    #[test]
+    fn test_import_mismatched_fingerprint() {
+        let (_root, store) = temp_store();
+        let fingerprint = store.import_org_key("acme", &public_key_pem()).unwrap();
+
+        let spoofed = hex::encode([0x5a; 32]);
+        assert!(matches!(
+            store.confirm_key_fingerprint("acme", &fingerprint, &spoofed),
+            Err(SpdfError::SignatureError(_))
+        ));
+        assert!(!store.org_key_path("acme").exists());
+        // The staged key is gone, so a retry cannot activate it
+        assert!(store.confirm_key_fingerprint("acme", &fingerprint, &fingerprint).is_err());
+
+        assert!(store.import_org_key("../acme", &public_key_pem()).is_err());
+    }
+
+    #[test]
+    fn test_trust_bundle_round_trip() {
+        use crate::test_support::public_key_pem_for;
+        use ed25519_dalek::SigningKey;
+
+        let pems: Vec<String> = [1u8, 2, 3]
+            .iter()
+            .map(|seed| public_key_pem_for(&SigningKey::from_bytes(&[*seed; 32])))
+            .collect();
+        let bundle = format!(
+            "# Keys for the Acme tenants\n\n# org_id: acme_hr\n{}\n\
+             # org_id: acme-legal\n{}# org_id: acme_ops\n{}",
+            pems[0], pems[1], pems[2]
+        );
+
+        let (_root, store) = temp_store();
+        let imported = store.import_trust_bundle(&bundle).unwrap();
+        let orgs: Vec<&str> = imported.iter().map(|k| k.org_id.as_str()).collect();
+        assert_eq!(orgs, ["acme_hr", "acme-legal", "acme_ops"]);
+        assert_eq!(imported[1].fingerprint, key_fingerprint(&pems[1]).unwrap());
+        assert_eq!(fs::read_to_string(store.org_key_path("acme_ops")).unwrap(), pems[2]);
+
+        let (exported, keys) = store.export_trust_bundle().unwrap();
+        assert_eq!(keys.len(), 3);
+        let (_other_root, other) = temp_store();
+        let mut reimported = other.import_trust_bundle(&exported).unwrap();
+        reimported.sort_by(|a, b| a.org_id.cmp(&b.org_id));
+        let mut expected = imported.clone();
+        expected.sort_by(|a, b| a.org_id.cmp(&b.org_id));
+        assert_eq!(reimported, expected);
+    }
+
+    #[test]
+    fn test_trust_bundle_rejected_whole() {
+        let (_root, store) = temp_store();
+        let pem = public_key_pem();
+
+        // Second key has no org comment; nothing is written
+        let unlabeled = format!("# org_id: acme\n{}{}", pem, pem);
+        assert!(store.import_trust_bundle(&unlabeled).is_err());
+        assert!(!store.org_key_path("acme").exists());
+
+        let duplicate = format!("# org_id: acme\n{}# org_id: acme\n{}", pem, pem);
+        assert!(store.import_trust_bundle(&duplicate).is_err());
+
+        let traversal = format!("# org_id: ../acme\n{}", pem);
+        assert!(store.import_trust_bundle(&traversal).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_world_readable_cache() {
+        let (_root, store) = temp_store();
+        let kek = device_kek("device-a");
+        let path = store.cached_key_path("DOC-1").unwrap();
+        fs::write(&path, seal_cached_key(&kek, "DOC-1", &DOC_KEY).unwrap()).unwrap();
+        set_mode(&path, 0o644);
+
+        let audit = store.audit(&kek).unwrap();
+        assert_eq!(audit.world_readable, [path]);
+        assert!(audit.undecryptable.is_empty());
+    }
+}
@@ -1,15 +1,82 @@
-mod auth;
-mod spdf;
+mod clock_skew;
+#[cfg(feature = "dev_mode")]
+mod dev_mode;
+mod key_client;
+mod session;
 
 use base64::{engine::general_purpose, Engine as _};
+use futures_util::future::{BoxFuture, FutureExt, Shared};
+use key_client::{KeyClient, KeyFetchError};
 use serde::{Deserialize, Serialize};
+use session::Session;
+use spdf_viewer_desktop_lib::key_source::KeySource;
+use spdf_viewer_desktop_lib::local_store::{
+    device_kek, open_session, seal_session, session_kek, LocalStore,
+};
+use spdf_viewer_desktop_lib::policy::{render_watermark, SecurityPolicy, WatermarkContext};
+use spdf_viewer_desktop_lib::device_limit::DeviceRegistry;
+use spdf_viewer_desktop_lib::{
+    auth, base64_stream, device_id, expiry, offline, pdf, revocation, spdf, trust, verify,
+    view_limit,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
 use std::fs;
+use std::path::{Path, PathBuf};
 use tauri::Manager;
 use std::sync::Mutex;
+use std::time::Instant;
+use tokio_util::sync::CancellationToken;
+use zeroize::Zeroizing;
 
 // App State to store JWT token
 struct AppState {
-    auth_token: Mutex<Option<String>>,
+    session: Mutex<Option<Session>>,
+    /// Every request to a key server, so certificate pins always apply;
+    /// `Err` if the policy's pins did not load, and every request fails
+    keys: Result<KeyClient, String>,
+    /// Key fetches in progress, by doc_id, so concurrent opens of one
+    /// document make a single request
+    key_fetches: InFlightKeyFetches,
+    /// Cancelled by `cancel_key_fetches`, then replaced with a fresh one
+    key_fetch_cancel: Mutex<CancellationToken>,
+}
+
+type KeyFetch = Shared<BoxFuture<'static, Result<KeyOutcome, String>>>;
+type InFlightKeyFetches = Mutex<HashMap<String, KeyFetch>>;
+
+impl AppState {
+    fn new(keys: Result<KeyClient, String>) -> Self {
+        AppState {
+            session: Mutex::new(None),
+            keys,
+            key_fetches: Mutex::new(HashMap::new()),
+            key_fetch_cancel: Mutex::new(CancellationToken::new()),
+        }
+    }
+
+    fn keys(&self) -> Result<&KeyClient, String> {
+        self.keys.as_ref().map_err(Clone::clone)
+    }
+
+    /// The client for requests to `url` (see `KeyClient::http_for`)
+    fn http_for(&self, url: &str) -> Result<&reqwest::Client, String> {
+        self.keys()?.http_for(url).map_err(|e| e.to_string())
+    }
+
+    /// Token that `cancel_key_fetches` cancels, for a key fetch starting now
+    fn key_fetch_cancel(&self) -> CancellationToken {
+        self.key_fetch_cancel.lock().unwrap_or_else(|p| p.into_inner()).clone()
+    }
+
+    /// Drop every in-memory secret; `Session` wipes its tokens on drop
+    fn scrub(&self) {
+        let session = match self.session.lock() {
+            Ok(mut guard) => guard.take(),
+            Err(poisoned) => poisoned.into_inner().take(),
+        };
+        drop(session);
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -20,6 +87,45 @@ struct OpenFileResult {
     pdf_base64: Option<String>,
     needs_login: bool,
     watermark_data: Option<serde_json::Value>,
+    /// The header's watermark template filled in from `watermark_data`;
+    /// set when the document has a watermark
+    watermark_text: Option<String>,
+    /// Signature check against the installed org key; set once a key is granted
+    verify_report: Option<VerifyReport>,
+    /// The stricter of the header's and the key server's permissions
+    effective_permissions: Option<spdf::SpdfPermissions>,
+    /// Whether the key server granted the key to this device
+    device_bound: Option<bool>,
+    /// Set when the local clock is far enough off the key server's to
+    /// throw off the document's expiry check
+    #[serde(default)]
+    clock_skew_warning: Option<String>,
+    /// Set when the open was refused because this installation has already
+    /// seen `max_devices` other devices open the document. A local check
+    /// on top of the key server's, which stays the authority.
+    #[serde(default)]
+    device_limit_reached: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum VerifyStatus {
+    Verified,
+    Failed,
+    /// No public key installed for the document's org
+    NoOrgKey,
+}
+
+/// Outcome of checking a document's signature during an open
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct VerifyReport {
+    status: VerifyStatus,
+    org_id: String,
+    detail: Option<String>,
+    /// PEM of the key the signature verified against, installed or from
+    /// the key server; other signed data for the document must match it
+    #[serde(skip)]
+    verified_key: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -28,11 +134,25 @@ struct LoginResult {
     message: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct KeyResponse {
     k_doc: String, // base64
     permissions: spdf::SpdfPermissions,
     watermark_data: serde_json::Value,
+    /// Server-side count for `max_opens` documents; overrides the local one
+    #[serde(default)]
+    opens_remaining: Option<u32>,
+    /// Signed later `expires_at`, checked against the org key
+    #[serde(default)]
+    expiry_extension: Option<expiry::ExpiryExtension>,
+    /// PEM keys the server trusts for the document's org; only consulted
+    /// when the header carries no `public_key` and none is installed
+    #[serde(default)]
+    org_keys: Vec<String>,
+    /// The device the server registered this grant to; absent for grants
+    /// that are not tied to a device
+    #[serde(default)]
+    bound_device_id: Option<String>,
 }
 
 #[tauri::command]
@@ -42,14 +162,154 @@ async fn login(
     server_url: String,
     license_key: String,
 ) -> Result<LoginResult, String> {
-    println!("Attempting login with license key to: {}", server_url);
+    let request_id = new_request_id();
+    println!("[{}] Attempting login with license key to: {}", request_id, server_url);
+
+    let http = state.http_for(&server_url).map_err(|e| with_reference(e, &request_id))?;
+    let outcome = login_with_key(http, &server_url, &license_key, &request_id)
+        .await
+        .map_err(|e| with_reference(e, &request_id))?;
+    let login_res = match outcome {
+        LoginOutcome::Authenticated(login_res) => login_res,
+        LoginOutcome::Rejected(message) => {
+            println!("[{}] Login rejected: {}", request_id, message);
+            return Ok(LoginResult {
+                success: false,
+                message: with_reference(message, &request_id),
+            });
+        }
+    };
+
+    let session = login_res.into_session(&server_url, None);
+
+    // Persist the session to disk, then keep it in memory
+    store_token(&app_handle, &session)?;
+    let user_email = session.user_email.clone().unwrap_or_default();
+    *state.session.lock().unwrap() = Some(session);
+
+    println!("[{}] Login successful for user: {}", request_id, user_email);
+
+    Ok(LoginResult {
+        success: true,
+        message: format!("Authenticated as {}", user_email),
+    })
+}
 
-    let client = reqwest::Client::new();
+fn app_dir(app_handle: &tauri::AppHandle) -> Result<PathBuf, String> {
+    let app_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data dir: {}", e))?;
+    if !app_dir.exists() {
+        fs::create_dir_all(&app_dir).map_err(|e| format!("Failed to create app dir: {}", e))?;
+    }
+    Ok(app_dir)
+}
+
+/// Key the persisted session is sealed with: this device's hash and the
+/// install's salt
+///
+/// The hash leaves out the kernel version (`FingerprintPolicy::default`),
+/// so a system update does not log the user out.
+fn session_key(app_dir: &Path) -> Result<Zeroizing<[u8; 32]>, String> {
+    let policy = device_id::FingerprintPolicy::default();
+    let device_hash =
+        device_id::generate_device_hash_with_policy(policy).map_err(|e| e.to_string())?;
+    let salt = auth::install_salt(app_dir)?;
+    Ok(session_kek(&device_hash, salt.as_bytes()))
+}
+
+/// Persist `session`, sealed so it only opens on this device and install
+fn store_token(app_handle: &tauri::AppHandle, session: &Session) -> Result<(), String> {
+    let app_dir = app_dir(app_handle)?;
+    let stored = Zeroizing::new(session.to_json());
+    let key = session_key(&app_dir)?;
+    let sealed = seal_session(&key, stored.as_bytes()).map_err(|e| e.to_string())?;
+    fs::write(app_dir.join("token"), sealed).map_err(|e| format!("Failed to save token: {}", e))
+}
+
+/// The session persisted by `store_token`
+///
+/// A file that does not open, because it was copied from another device
+/// or install or holds a plaintext token from an older version, is
+/// deleted.
+fn load_stored_token(app_handle: &tauri::AppHandle) -> Option<Session> {
+    let app_dir = app_dir(app_handle).ok()?;
+    let token_path = app_dir.join("token");
+    let sealed = fs::read(&token_path).ok()?;
+    let key = session_key(&app_dir).ok()?;
+    match open_session(&key, &sealed) {
+        Ok(stored) => Session::parse(std::str::from_utf8(&stored).ok()?),
+        Err(_) => {
+            let _ = fs::remove_file(&token_path);
+            None
+        }
+    }
+}
+
+/// Header carrying the client-generated id of a server call, so support can
+/// match client and server logs
+const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+fn new_request_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// Append the request id to a message shown to the user
+fn with_reference(message: String, request_id: &str) -> String {
+    format!("{} (reference: {})", message, request_id)
+}
+
+/// Response of `POST /auth/login-with-key` and `POST /auth/refresh`
+/// (fields the viewer uses)
+#[derive(Deserialize)]
+struct LoginResponse {
+    access_token: String,
+    #[serde(default)]
+    user_email: Option<String>,
+    /// Only from servers that support `/auth/refresh`
+    #[serde(default)]
+    refresh_token: Option<String>,
+}
+
+impl LoginResponse {
+    /// The session this response opens; a refresh that leaves out the
+    /// user or the refresh token keeps `previous`'s
+    fn into_session(self, server_url: &str, previous: Option<&Session>) -> Session {
+        let mut session = Session::new(self.access_token, self.user_email);
+        session.server_url = Some(server_url.to_string());
+        session.refresh_token = self.refresh_token;
+        if let Some(previous) = previous {
+            let user_email = session.user_email.take();
+            session.user_email = user_email.or_else(|| previous.user_email.clone());
+            let refresh_token = session.refresh_token.take();
+            session.refresh_token = refresh_token.or_else(|| previous.refresh_token.clone());
+        }
+        session
+    }
+}
+
+/// Outcome of a license key login
+enum LoginOutcome {
+    Authenticated(LoginResponse),
+    /// The server refused the key
+    Rejected(String),
+}
+
+/// Exchange a license key for a session token; `Err` means the server
+/// could not be reached or answered garbage
+async fn login_with_key(
+    client: &reqwest::Client,
+    server_url: &str,
+    license_key: &str,
+    request_id: &str,
+) -> Result<LoginOutcome, String> {
     let login_url = format!("{}/auth/login-with-key", server_url.trim_end_matches('/'));
 
     // Call the license key authentication endpoint
     let res = client
         .post(&login_url)
+        .header(REQUEST_ID_HEADER, request_id)
         .json(&serde_json::json!({
             "license_key": license_key
         }))
@@ -60,103 +320,404 @@ async fn login(
     if !res.status().is_success() {
         let status = res.status();
         let text = res.text().await.unwrap_or_default();
-        return Ok(LoginResult {
-            success: false,
-            message: format!("Authentication failed: {} - {}", status, text),
-        });
+        return Ok(LoginOutcome::Rejected(format!(
+            "Authentication failed: {} - {}",
+            status, text
+        )));
     }
 
-    // Parse response
-    #[derive(serde::Deserialize)]
-    struct LoginResponse {
-        access_token: String,
-        token_type: String,
-        user_email: String,
-        doc_id: String,
+    let login_res = res.json().await.map_err(|e| format!("Invalid response: {}", e))?;
+    Ok(LoginOutcome::Authenticated(login_res))
+}
+
+/// Trade `session`'s refresh token for a new session
+///
+/// `Ok(None)` when there is nothing to refresh with, or the server refuses
+/// (including servers without `/auth/refresh`): a full login is needed.
+async fn refresh_session(
+    client: &reqwest::Client,
+    session: &Session,
+    request_id: &str,
+) -> Result<Option<Session>, String> {
+    let (Some(server_url), Some(refresh_token)) = (&session.server_url, &session.refresh_token)
+    else {
+        return Ok(None);
+    };
+    let refresh_url = format!("{}/auth/refresh", server_url.trim_end_matches('/'));
+
+    let res = client
+        .post(&refresh_url)
+        .header(REQUEST_ID_HEADER, request_id)
+        .json(&serde_json::json!({
+            "refresh_token": refresh_token
+        }))
+        .send()
+        .await
+        .map_err(|e| format!("Network error: {}", e))?;
+
+    if !res.status().is_success() {
+        println!("[{}] Token refresh refused: {}", request_id, res.status());
+        return Ok(None);
     }
 
-    let login_res: LoginResponse = res.json().await.map_err(|e| format!("Invalid response: {}", e))?;
+    let login_res: LoginResponse =
+        res.json().await.map_err(|e| format!("Invalid response: {}", e))?;
+    Ok(Some(login_res.into_session(server_url, Some(session))))
+}
 
-    // Store token in memory
-    {
-        let mut guard = state.auth_token.lock().unwrap();
-        *guard = Some(login_res.access_token.clone());
+/// Outcome of asking the key server for one document's key
+#[derive(Clone)]
+enum KeyOutcome {
+    Granted(KeyResponse),
+    /// 401: the session has to be renewed
+    Unauthorized,
+    Denied(String),
+}
+
+/// Response of `POST /keys/get-batch`; results map 1:1 to the doc_ids sent
+#[derive(Debug, Deserialize)]
+struct BatchKeyResponse {
+    results: Vec<BatchKeyResult>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchKeyResult {
+    doc_id: String,
+    status: u16,
+    key: Option<KeyResponse>,
+    detail: Option<String>,
+}
+
+impl OpenFileResult {
+    fn failure(header: Option<spdf::SpdfHeader>, message: String, needs_login: bool) -> Self {
+        OpenFileResult {
+            success: false,
+            message,
+            header,
+            pdf_base64: None,
+            needs_login,
+            watermark_data: None,
+            watermark_text: None,
+            verify_report: None,
+            effective_permissions: None,
+            device_bound: None,
+            clock_skew_warning: None,
+            device_limit_reached: false,
+        }
     }
 
-    // Persist token to disk
-    let app_dir = app_handle.path().app_data_dir().unwrap();
-    if !app_dir.exists() {
-        fs::create_dir_all(&app_dir).map_err(|e| format!("Failed to create app dir: {}", e))?;
+    /// Tag a failure with the id of the request that produced it
+    fn with_reference(mut self, request_id: &str) -> Self {
+        if !self.success {
+            self.message = with_reference(self.message, request_id);
+        }
+        self
     }
-    let token_path = app_dir.join("token");
-    fs::write(token_path, &login_res.access_token).map_err(|e| format!("Failed to save token: {}", e))?;
+}
 
-    println!("Login successful for user: {}", login_res.user_email);
+/// Check a document's signature against its org's trusted key: from the
+/// OS key store in builds that have one, else `keys_dir/{org_id}_public.pem`.
+/// A header carrying a different key of its own fails.
+///
+/// With no key installed, the document may instead verify against any of
+/// `server_keys`, the org's keys as the key server reports them; a header
+/// key must then be one of those.
+fn verify_with_org_key(
+    spdf_file: &spdf::SpdfFile,
+    keys_dir: &Path,
+    server_keys: &[String],
+) -> VerifyReport {
+    let org_id = spdf_file.header.org_id.clone();
+    let pem = match KeySource::for_keys_dir(keys_dir).org_key_pem(&org_id) {
+        Ok(Some(pem)) => pem,
+        Ok(None) | Err(_) if !server_keys.is_empty() => {
+            return verify_with_server_keys(spdf_file, server_keys)
+        }
+        Ok(None) | Err(_) => {
+            return VerifyReport {
+                status: VerifyStatus::NoOrgKey,
+                detail: Some(format!("No public key installed for {}", org_id)),
+                org_id,
+                verified_key: None,
+            }
+        }
+    };
 
-    Ok(LoginResult {
-        success: true,
-        message: format!("Authenticated as {}", login_res.user_email),
-    })
+    match verify::verify_signature_with_pinned_key(spdf_file, &pem) {
+        Ok(()) => VerifyReport {
+            status: VerifyStatus::Verified,
+            org_id,
+            detail: None,
+            verified_key: Some(pem),
+        },
+        Err(e) => VerifyReport {
+            status: VerifyStatus::Failed,
+            org_id,
+            detail: Some(e.to_string()),
+            verified_key: None,
+        },
+    }
 }
 
-#[tauri::command]
-async fn open_spdf_file(
-    app_handle: tauri::AppHandle,
-    state: tauri::State<'_, AppState>,
-    file_path: String,
-) -> Result<OpenFileResult, String> {
-    println!("Opening SPDF file: {}", file_path);
+fn verify_with_server_keys(spdf_file: &spdf::SpdfFile, server_keys: &[String]) -> VerifyReport {
+    let org_id = spdf_file.header.org_id.clone();
+    let mut failures = Vec::new();
+    for pem in server_keys {
+        match verify::verify_signature_with_pinned_key(spdf_file, pem) {
+            Ok(()) => {
+                return VerifyReport {
+                    status: VerifyStatus::Verified,
+                    org_id,
+                    detail: Some("Verified with a key provided by the key server".to_string()),
+                    verified_key: Some(pem.clone()),
+                }
+            }
+            Err(e) => failures.push(e.to_string()),
+        }
+    }
+    VerifyReport {
+        status: VerifyStatus::Failed,
+        detail: Some(format!(
+            "No key provided by the server for {} verifies: {}",
+            org_id,
+            failures.join("; ")
+        )),
+        org_id,
+        verified_key: None,
+    }
+}
 
-    // 1. Read SPDF file structure
-    let spdf_file = spdf::SpdfFile::read(&file_path).map_err(|e| format!("{:?}", e))?;
-    println!("SPDF header: {:?}", spdf_file.header);
+/// Permissions allowed by both the signed header and the key server
+fn effective_permissions(
+    header: &spdf::SpdfPermissions,
+    server: &spdf::SpdfPermissions,
+) -> spdf::SpdfPermissions {
+    spdf::SpdfPermissions {
+        allow_print: header.allow_print && server.allow_print,
+        allow_copy: header.allow_copy && server.allow_copy,
+        max_devices: header.max_devices.min(server.max_devices),
+        // Key grants do not carry an offline budget; the header's applies
+        offline_days: header.offline_days,
+        max_opens: match (header.max_opens, server.max_opens) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        },
+    }
+}
 
-    // 2. Check for Auth Token
-    let token = {
-        let guard = state.auth_token.lock().unwrap();
-        guard.clone()
+/// Token from memory, falling back to the session persisted at login
+///
+/// An expired token is refreshed if the server handed out a refresh token;
+/// otherwise there is no token and the user has to log in again.
+async fn load_token(
+    app_handle: &tauri::AppHandle,
+    state: &AppState,
+    request_id: &str,
+) -> Option<Zeroizing<String>> {
+    let cached = state.session.lock().unwrap().clone();
+    let session = match cached {
+        Some(session) => session,
+        None => load_stored_token(app_handle)?,
     };
 
-    // Try to load from disk if not in memory
-    let token = if token.is_none() {
-        let app_dir = app_handle.path().app_data_dir().unwrap();
-        let token_path = app_dir.join("token");
-        if token_path.exists() {
-             fs::read_to_string(token_path).ok()
-        } else {
-            None
+    let session = if session.is_expired() {
+        let server_url = session.server_url.clone().unwrap_or_default();
+        let refreshed = match state.http_for(&server_url) {
+            Ok(http) => refresh_session(http, &session, request_id).await,
+            Err(e) => Err(e),
+        };
+        match refreshed {
+            Ok(Some(refreshed)) => {
+                println!("[{}] Session token refreshed", request_id);
+                if let Err(e) = store_token(app_handle, &refreshed) {
+                    println!("[{}] {}", request_id, e);
+                }
+                refreshed
+            }
+            Ok(None) => {
+                println!("[{}] Session token expired", request_id);
+                return None;
+            }
+            Err(e) => {
+                println!("[{}] Token refresh failed: {}", request_id, e);
+                return None;
+            }
         }
     } else {
-        token
+        session
     };
 
-    if token.is_none() {
-        return Ok(OpenFileResult {
-            success: false,
-            message: "Authentication required".to_string(),
-            header: Some(spdf_file.header),
-            pdf_base64: None,
-            needs_login: true,
-            watermark_data: None,
-        });
+    let token = Zeroizing::new(session.access_token.clone());
+    *state.session.lock().unwrap() = Some(session);
+    Some(token)
+}
+
+/// `~/.spdf/keys`, where org public keys are installed
+fn org_keys_dir() -> Result<PathBuf, String> {
+    dirs::home_dir()
+        .map(|home| home.join(".spdf").join("keys"))
+        .ok_or_else(|| "Could not determine home directory".to_string())
+}
+
+fn load_view_counter() -> Result<view_limit::ViewCounter, String> {
+    let path = view_limit::ViewCounter::default_path().ok_or("Could not determine home directory")?;
+    view_limit::ViewCounter::load(&path)
+}
+
+fn load_device_registry() -> Result<DeviceRegistry, String> {
+    let path = DeviceRegistry::default_path().ok_or("Could not determine home directory")?;
+    DeviceRegistry::load(&path)
+}
+
+fn load_policy() -> Result<SecurityPolicy, String> {
+    let root = LocalStore::default_root().ok_or("Could not determine home directory")?;
+    SecurityPolicy::load(&LocalStore::new(root).policy_path()).map_err(|e| e.to_string())
+}
+
+/// Key client pinned to the policy's `pinned_certs`
+///
+/// An unreadable policy pins nothing, but every open then fails loading
+/// it. A pin that cannot be loaded is an error, for every key request to
+/// report, rather than a fall back to the system roots for that host.
+fn pinned_key_client(http: reqwest::Client) -> Result<KeyClient, String> {
+    let pins = match load_policy() {
+        Ok(policy) => policy.pinned_certs,
+        Err(e) => {
+            println!("Warning: no certificate pins loaded: {}", e);
+            BTreeMap::new()
+        }
+    };
+    pins.iter().try_fold(KeyClient::new(http), |keys, (host, pem)| {
+        keys.with_pinned_cert(host, pem.as_bytes())
+    })
+}
+
+/// Memory the OS reports available, in bytes; `None` where it reports none
+fn available_memory() -> Option<u64> {
+    let mut sys = sysinfo::System::new();
+    sys.refresh_memory();
+    Some(sys.available_memory()).filter(|&bytes| bytes > 0)
+}
+
+fn load_extension_cache() -> Result<expiry::ExtensionCache, String> {
+    let path = expiry::ExtensionCache::default_path().ok_or("Could not determine home directory")?;
+    expiry::ExtensionCache::load(&path)
+}
+
+/// Ask the document's key server for its key; `Err` means the server could
+/// not be reached, or the request was cancelled
+async fn fetch_key(
+    keys: &KeyClient,
+    token: &str,
+    header: &spdf::SpdfHeader,
+    device_info: &auth::DeviceInfo,
+    request_id: &str,
+    cancel: &CancellationToken,
+) -> Result<KeyOutcome, String> {
+    let key_url = format!("{}/keys/get", header.server_url.trim_end_matches('/'));
+    println!("[{}] Requesting key from: {}", request_id, key_url);
+
+    let body = serde_json::json!({
+        "doc_id": header.doc_id,
+        "device_id": device_info.device_id,
+        "device_name": device_info.device_name
+    });
+    let request = |http: &reqwest::Client| {
+        http.post(&key_url)
+            .bearer_auth(token)
+            .header(REQUEST_ID_HEADER, request_id)
+            .json(&body)
+    };
+    match keys.fetch(request, cancel).await {
+        Ok(key_res) => Ok(KeyOutcome::Granted(key_res)),
+        // Keeps `needs_login` on the open result in step with the client
+        Err(e) if e.needs_login() => Ok(KeyOutcome::Unauthorized),
+        Err(KeyFetchError::AccessDenied(message)) => Ok(KeyOutcome::Denied(message)),
+        Err(e) => Err(e.to_string()),
     }
-    let token = token.unwrap();
+}
 
-    // 3. Get Device Info
-    let device_info = auth::get_device_info(&app_handle).map_err(|e| format!("Device info error: {}", e))?;
+/// Await `fetch` for `doc_id`, or the fetch already in flight for it
+///
+/// Every caller waiting on the same fetch gets a clone of its result; the
+/// entry is dropped once it completes, so the next open asks again.
+async fn fetch_key_shared<F>(
+    in_flight: &InFlightKeyFetches,
+    doc_id: &str,
+    fetch: F,
+) -> Result<KeyOutcome, String>
+where
+    F: Future<Output = Result<KeyOutcome, String>> + Send + 'static,
+{
+    let shared = in_flight
+        .lock()
+        .unwrap()
+        .entry(doc_id.to_string())
+        .or_insert_with(|| fetch.boxed().shared())
+        .clone();
+    let result = shared.clone().await;
+
+    let mut in_flight = in_flight.lock().unwrap();
+    if in_flight.get(doc_id).is_some_and(|current| current.ptr_eq(&shared)) {
+        in_flight.remove(doc_id);
+    }
+    result
+}
 
-    // 4. Fetch Key from Server
-    let client = reqwest::Client::new();
-    let server_url = spdf_file.header.server_url.trim_end_matches('/');
-    let key_url = format!("{}/keys/get", server_url);
+/// Most doc_ids the server takes in one `/keys/get-batch` request
+const MAX_BATCH_DOC_IDS: usize = 50;
+
+/// Keys for several documents on one server, `MAX_BATCH_DOC_IDS` per
+/// round trip, each round trip under a request id of its own
+///
+/// Each outcome comes with the id of the request that fetched it; an error
+/// already names the id of the request that failed. `Ok(None)` if the
+/// server has no `/keys/get-batch` endpoint.
+async fn fetch_keys_batch(
+    keys: &KeyClient,
+    server_url: &str,
+    token: &str,
+    doc_ids: &[&str],
+    device_info: &auth::DeviceInfo,
+    batch_id: &str,
+) -> Result<Option<Vec<(String, KeyOutcome)>>, String> {
+    let mut outcomes = Vec::with_capacity(doc_ids.len());
+    for chunk in doc_ids.chunks(MAX_BATCH_DOC_IDS) {
+        let request_id = new_request_id();
+        println!("[{}] Requesting {} keys as {}", batch_id, chunk.len(), request_id);
+        let fetched = fetch_keys_chunk(keys, server_url, token, chunk, device_info, &request_id)
+            .await
+            .map_err(|e| with_reference(e, &request_id))?;
+        match fetched {
+            Some(chunk_outcomes) => outcomes.extend(
+                chunk_outcomes.into_iter().map(|outcome| (request_id.clone(), outcome)),
+            ),
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(outcomes))
+}
 
-    println!("Requesting key from: {}", key_url);
+/// One `/keys/get-batch` request, for at most `MAX_BATCH_DOC_IDS` doc_ids
+async fn fetch_keys_chunk(
+    keys: &KeyClient,
+    server_url: &str,
+    token: &str,
+    doc_ids: &[&str],
+    device_info: &auth::DeviceInfo,
+    request_id: &str,
+) -> Result<Option<Vec<KeyOutcome>>, String> {
+    let batch_url = format!("{}/keys/get-batch", server_url.trim_end_matches('/'));
+    println!("[{}] Requesting {} keys from: {}", request_id, doc_ids.len(), batch_url);
 
-    let res = client
-        .post(&key_url)
-        .header("Authorization", format!("Bearer {}", token))
+    let res = keys
+        .http_for(server_url)
+        .map_err(|e| e.to_string())?
+        .post(&batch_url)
+        .bearer_auth(token)
+        .header(REQUEST_ID_HEADER, request_id)
         .json(&serde_json::json!({
-            "doc_id": spdf_file.header.doc_id,
+            "doc_ids": doc_ids,
             "device_id": device_info.device_id,
             "device_name": device_info.device_name
         }))
@@ -164,78 +725,1927 @@ async fn open_spdf_file(
         .await
         .map_err(|e| format!("Network error: {}", e))?;
 
-    if !res.status().is_success() {
-        let status = res.status();
+    let status = res.status();
+    if status == reqwest::StatusCode::NOT_FOUND
+        || status == reqwest::StatusCode::METHOD_NOT_ALLOWED
+    {
+        return Ok(None);
+    }
+    if status == reqwest::StatusCode::UNAUTHORIZED {
+        return Ok(Some(doc_ids.iter().map(|_| KeyOutcome::Unauthorized).collect()));
+    }
+    if !status.is_success() {
         let text = res.text().await.unwrap_or_default();
-        
-        if status == reqwest::StatusCode::UNAUTHORIZED {
-             return Ok(OpenFileResult {
-                success: false,
-                message: "Session expired. Please login again.".to_string(),
-                header: Some(spdf_file.header),
-                pdf_base64: None,
-                needs_login: true,
-                watermark_data: None,
-            });
-        }
+        let message = format!("Server denied access: {} - {}", status, text);
+        return Ok(Some(doc_ids.iter().map(|_| KeyOutcome::Denied(message.clone())).collect()));
+    }
 
-        return Ok(OpenFileResult {
-            success: false,
-            message: format!("Server denied access: {} - {}", status, text),
-            header: Some(spdf_file.header),
-            pdf_base64: None,
-            needs_login: false,
-            watermark_data: None,
-        });
+    let batch: BatchKeyResponse =
+        res.json().await.map_err(|e| format!("Invalid server response: {}", e))?;
+    let in_order = batch.results.len() == doc_ids.len()
+        && batch.results.iter().zip(doc_ids).all(|(result, doc_id)| result.doc_id == *doc_id);
+    if !in_order {
+        return Err("Invalid server response: batch results do not match request".to_string());
+    }
+
+    Ok(Some(batch.results.into_iter().map(KeyOutcome::from).collect()))
+}
+
+impl From<BatchKeyResult> for KeyOutcome {
+    fn from(result: BatchKeyResult) -> Self {
+        match (result.status, result.key) {
+            (200, Some(key)) => KeyOutcome::Granted(key),
+            (401, _) => KeyOutcome::Unauthorized,
+            (status, _) => {
+                let status = reqwest::StatusCode::from_u16(status)
+                    .map(|s| s.to_string())
+                    .unwrap_or_else(|_| status.to_string());
+                KeyOutcome::Denied(format!(
+                    "Server denied access: {} - {}",
+                    status,
+                    result.detail.unwrap_or_default()
+                ))
+            }
+        }
     }
+}
 
-    let key_res: KeyResponse = res.json().await.map_err(|e| format!("Invalid server response: {}", e))?;
+/// Turn a key server answer into the result for one document: on a grant,
+/// check the signature, decrypt, then count the open against the view limit
+fn resolve_open(
+    mut spdf_file: spdf::SpdfFile,
+    outcome: KeyOutcome,
+    counter: &mut view_limit::ViewCounter,
+    extensions: &mut expiry::ExtensionCache,
+    keys_dir: &Path,
+    device_id: &str,
+) -> OpenFileResult {
+    let key_res = match outcome {
+        KeyOutcome::Granted(key_res) => key_res,
+        KeyOutcome::Unauthorized => {
+            return OpenFileResult::failure(
+                Some(spdf_file.header),
+                "Session expired. Please login again.".to_string(),
+                true,
+            )
+        }
+        KeyOutcome::Denied(message) => {
+            return OpenFileResult::failure(Some(spdf_file.header), message, false)
+        }
+    };
 
     // 5. Decode K_doc
-    let k_doc_bytes = general_purpose::STANDARD.decode(&key_res.k_doc).map_err(|e| format!("Invalid key encoding: {}", e))?;
-    if k_doc_bytes.len() != 32 {
-        return Err("Invalid key length from server".to_string());
+    let k_doc = match decode_k_doc(&key_res.k_doc) {
+        Ok(k_doc) => k_doc,
+        Err(message) => return OpenFileResult::failure(Some(spdf_file.header), message, false),
+    };
+
+    // 6. Verify Signature against a trusted org key; the header's own key
+    // vouches for nothing, since whoever swapped the content could swap it too
+    let verify_report = verify_with_org_key(&spdf_file, keys_dir, &key_res.org_keys);
+    let refusal = match verify_report.status {
+        VerifyStatus::Verified => None,
+        VerifyStatus::Failed => Some(format!(
+            "Signature verification failed: {}",
+            verify_report.detail.as_deref().unwrap_or("unknown error")
+        )),
+        VerifyStatus::NoOrgKey => Some(format!(
+            "No trusted public key is installed or provided by the server for {}",
+            spdf_file.header.org_id
+        )),
+    };
+    if let Some(message) = refusal {
+        let mut result = OpenFileResult::failure(Some(spdf_file.header), message, false);
+        result.verify_report = Some(verify_report);
+        return result;
     }
-    let mut k_doc = [0u8; 32];
-    k_doc.copy_from_slice(&k_doc_bytes);
+    // A signature over an empty ciphertext is valid but opens nothing
+    if !spdf_file.has_content() {
+        return OpenFileResult::failure(
+            Some(spdf_file.header),
+            "document has no content".to_string(),
+            false,
+        );
+    }
+    let extension = key_res.expiry_extension.as_ref();
+    let verified_key = verify_report.verified_key.as_deref();
+    if let Err(message) = check_expiry(&spdf_file.header, extension, extensions, verified_key) {
+        return OpenFileResult::failure(Some(spdf_file.header), message, false);
+    }
+    let effective_permissions =
+        effective_permissions(&spdf_file.header.permissions, &key_res.permissions);
+    let device_bound = key_res.bound_device_id.as_deref() == Some(device_id);
 
-    // 6. Verify Signature (using Org Public Key) - Optional for now
-    let home_dir = dirs::home_dir().unwrap();
-    let public_key_path = home_dir.join(".spdf").join("keys").join(format!("{}_public.pem", spdf_file.header.org_id));
-    
-    if public_key_path.exists() {
-        if let Ok(pem) = fs::read_to_string(public_key_path) {
-             if let Err(e) = spdf_file.verify_signature(&pem) {
-                 println!("Warning: Signature verification failed: {:?}", e);
-                 // Continue anyway for testing
-             }
+    // 7. Decrypt in place
+    let mut pdf_bytes = match spdf_file.take_decrypted(&k_doc) {
+        Ok(pdf_bytes) => Zeroizing::new(pdf_bytes),
+        Err(e) => {
+            return OpenFileResult::failure(Some(spdf_file.header), format!("{:?}", e), false)
         }
-    } else {
-        println!("Warning: Public key not found. Skipping signature verification.");
+    };
+
+    // 8. Count the open against max_opens now that it has decrypted; the
+    // server's count wins if it sent one. A refused open wipes the plaintext.
+    let doc_id = spdf_file.header.doc_id.clone();
+    let max_opens = spdf_file.header.permissions.max_opens;
+    if let (Some(max), Some(remaining)) = (max_opens, key_res.opens_remaining) {
+        counter.sync_remaining(&doc_id, max, remaining);
+    }
+    if let Err(message) = counter.record_open(&doc_id, max_opens) {
+        return OpenFileResult::failure(Some(spdf_file.header), message, false);
     }
 
-    // 7. Decrypt
-    let pdf_bytes = spdf_file.decrypt(&k_doc).map_err(|e| format!("{:?}", e))?;
-    let pdf_base64 = general_purpose::STANDARD.encode(&pdf_bytes);
+    // 9. Encode in chunks, so ciphertext, plaintext and base64 are never all
+    // held at full size
+    let pdf_base64 = base64_stream::encode_base64_consuming(std::mem::take(&mut *pdf_bytes));
+    let watermark_text = spdf_file
+        .has_watermark()
+        .then(|| watermark_text(&spdf_file.header, &key_res.watermark_data));
 
-    Ok(OpenFileResult {
+    OpenFileResult {
         success: true,
         message: "Document opened successfully".to_string(),
         header: Some(spdf_file.header),
         pdf_base64: Some(pdf_base64),
         needs_login: false,
         watermark_data: Some(key_res.watermark_data),
-    })
+        watermark_text,
+        verify_report: Some(verify_report),
+        effective_permissions: Some(effective_permissions),
+        device_bound: Some(device_bound),
+        clock_skew_warning: None,
+        device_limit_reached: false,
+    }
+}
+
+/// Render the header's watermark template with the user and device the key
+/// server granted the key to
+fn watermark_text(header: &spdf::SpdfHeader, watermark_data: &serde_json::Value) -> String {
+    let field = |name: &str| watermark_data.get(name).and_then(|value| value.as_str());
+    let user_email = field("user_email").or_else(|| field("user_id")).unwrap_or_default();
+    let device_id = field("device_id").unwrap_or_default();
+    let now = time::OffsetDateTime::now_utc();
+    let context =
+        WatermarkContext::for_open(header, user_email.to_string(), device_id.to_string(), now);
+    render_watermark(&header.watermark.text, &context)
+}
+
+/// Refuse an expired document, honouring a server-granted extension
+///
+/// The extension must verify against `org_key_pem`, the key that verified
+/// the document itself; a verified one is cached so offline checks see the
+/// extended date too.
+fn check_expiry(
+    header: &spdf::SpdfHeader,
+    extension: Option<&expiry::ExpiryExtension>,
+    extensions: &mut expiry::ExtensionCache,
+    org_key_pem: Option<&str>,
+) -> Result<(), String> {
+    let original = header.expires_at.as_deref().map(expiry::parse_expires_at).transpose()?;
+    let expires = match extension {
+        Some(extension) => {
+            let pem = org_key_pem
+                .ok_or_else(|| format!("No verified public key for {}", header.org_id))?;
+            let org_key = verify::parse_ed25519_public_key_pem(pem)
+                .map_err(|e| format!("Invalid org key: {}", e))?;
+            let extended = extension
+                .verify(&header.doc_id, original, &org_key)
+                .map_err(|e| format!("Invalid expiry extension: {}", e))?;
+            extensions.insert(&header.doc_id, extension.clone())?;
+            Some(extended)
+        }
+        None => original,
+    };
+
+    if expires.is_some_and(|expires| expires <= time::OffsetDateTime::now_utc()) {
+        return Err("Document has expired".to_string());
+    }
+    Ok(())
+}
+
+fn decode_k_doc(k_doc_b64: &str) -> Result<Zeroizing<[u8; 32]>, String> {
+    let k_doc_bytes = Zeroizing::new(
+        general_purpose::STANDARD
+            .decode(k_doc_b64)
+            .map_err(|e| format!("Invalid key encoding: {}", e))?,
+    );
+    if k_doc_bytes.len() != 32 {
+        return Err("Invalid key length from server".to_string());
+    }
+    let mut k_doc = Zeroizing::new([0u8; 32]);
+    k_doc.copy_from_slice(&k_doc_bytes);
+    Ok(k_doc)
+}
+
+/// Local records an open checks and updates, loaded once per command
+struct OpenStores {
+    counter: view_limit::ViewCounter,
+    extensions: expiry::ExtensionCache,
+    devices: DeviceRegistry,
+    keys_dir: PathBuf,
+    /// Largest estimated open the policy allows in the memory available
+    memory_limit: Option<u64>,
+}
+
+impl OpenStores {
+    fn load() -> Result<Self, String> {
+        Ok(OpenStores {
+            counter: load_view_counter()?,
+            extensions: load_extension_cache()?,
+            devices: load_device_registry()?,
+            keys_dir: org_keys_dir()?,
+            memory_limit: load_policy()?.open_memory_limit(available_memory()),
+        })
+    }
+
+    /// Why `spdf_file` must not be opened on `device_id`, decided before
+    /// its key is requested
+    ///
+    /// Refused are files that would not fit in memory once decrypted and
+    /// encoded for the UI, headers no key request can be built from, and
+    /// devices past `max_devices`. Only devices sharing this installation
+    /// are seen; the server still checks.
+    fn refusal(&self, spdf_file: &spdf::SpdfFile, device_id: &str) -> Option<OpenFileResult> {
+        let header = &spdf_file.header;
+        let needed = spdf_file.estimated_open_memory() as u64;
+        if let Some(limit) = self.memory_limit.filter(|&limit| needed > limit) {
+            let message = format!(
+                "Not enough memory to open this document: needs about {} MB, {} MB available",
+                needed >> 20,
+                limit >> 20
+            );
+            return Some(OpenFileResult::failure(Some(header.clone()), message, false));
+        }
+        if let Some(message) = invalid_header_message(header) {
+            return Some(OpenFileResult::failure(Some(header.clone()), message, false));
+        }
+        let max_devices = header.permissions.max_devices;
+        if self.devices.check(&header.doc_id, device_id, max_devices).limit_reached {
+            let message = format!(
+                "Device limit reached: this document has already been opened on {} devices",
+                max_devices
+            );
+            let mut result = OpenFileResult::failure(Some(header.clone()), message, false);
+            result.device_limit_reached = true;
+            return Some(result);
+        }
+        None
+    }
+
+    /// `resolve_open`, recording `device_id` against the document once it
+    /// has opened
+    fn resolve(
+        &mut self,
+        spdf_file: spdf::SpdfFile,
+        outcome: KeyOutcome,
+        device_id: &str,
+        request_id: &str,
+    ) -> OpenFileResult {
+        let doc_id = spdf_file.header.doc_id.clone();
+        let (counter, extensions) = (&mut self.counter, &mut self.extensions);
+        let keys_dir = &self.keys_dir;
+        let result = resolve_open(spdf_file, outcome, counter, extensions, keys_dir, device_id);
+        if result.success {
+            if let Err(e) = self.devices.record(&doc_id, device_id) {
+                println!("[{}] Warning: device not recorded: {}", request_id, e);
+            }
+        }
+        result
+    }
+}
+
+/// What the opens made by one command share
+struct OpenContext<'a> {
+    state: &'a AppState,
+    token: &'a str,
+    device_info: &'a auth::DeviceInfo,
+    /// Cancels the command's key fetches
+    cancel: CancellationToken,
+}
+
+/// Everything an open does once its key fetch has returned, for
+/// `open_spdf_file` and `open_batch` alike
+///
+/// Falls back to a key cached for offline use when the server is out of
+/// reach, fetches the org's keys when none is installed, compares clocks,
+/// resolves the open, then records the offline grant and refreshes the
+/// revocation list. `Err` when the fetch was cancelled, or failed with no
+/// offline fallback.
+async fn complete_open(
+    ctx: &OpenContext<'_>,
+    stores: &mut OpenStores,
+    spdf_file: spdf::SpdfFile,
+    fetched: Result<KeyOutcome, String>,
+    request_id: &str,
+) -> Result<OpenFileResult, String> {
+    let state = ctx.state;
+    // Out of the server's reach, a key cached by an earlier open will do
+    let (mut outcome, opened_offline) = match fetched {
+        Ok(outcome) => (outcome, false),
+        Err(e) if ctx.cancel.is_cancelled() => return Err(e),
+        Err(e) => match offline_outcome(&spdf_file) {
+            Ok(outcome) => {
+                println!("[{}] Key server unreachable ({}), opening offline", request_id, e);
+                (outcome, true)
+            }
+            Err(reason) => return Err(format!("{}. {}", e, reason)),
+        },
+    };
+
+    // With no org key installed, verification falls to the org's keys as
+    // the server reports them, unless the grant already carried them
+    let header = &spdf_file.header;
+    if let KeyOutcome::Granted(key_res) = &mut outcome {
+        let installed = KeySource::for_keys_dir(&stores.keys_dir).org_key_pem(&header.org_id);
+        let unverifiable = key_res.org_keys.is_empty() && !matches!(installed, Ok(Some(_)));
+        if unverifiable && !opened_offline {
+            let (server_url, org_id) = (&header.server_url, &header.org_id);
+            let fetched = match state.http_for(server_url) {
+                Ok(http) => trust::fetch_trusted_keys(http, server_url, org_id, ctx.token)
+                    .await
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e),
+            };
+            match fetched {
+                Ok(keys) => key_res.org_keys = keys,
+                Err(e) => println!("[{}] Could not fetch org keys: {}", request_id, e),
+            }
+        }
+    }
+
+    // Expiry is judged by the local clock; check it against the server's
+    // for documents that have one. Best effort: no answer, no warning.
+    let clock_skew_warning = match spdf_file.header.expires_at {
+        Some(_) if opened_offline => None,
+        Some(_) => match state.http_for(&spdf_file.header.server_url) {
+            Ok(http) => clock_skew::check_clock_skew(http, &spdf_file.header.server_url)
+                .await
+                .ok()
+                .and_then(|report| report.warning),
+            Err(_) => None,
+        },
+        None => None,
+    };
+
+    // Documents with an offline budget get a grant recorded once they open
+    // online; an offline open must not restart the window
+    let offline_days = spdf_file.header.permissions.offline_days;
+    let offline_grant = match &outcome {
+        _ if opened_offline => None,
+        KeyOutcome::Granted(key_res) if spdf_file.allows_offline() && offline_days > 0 => {
+            Some((spdf_file.header.doc_id.clone(), Zeroizing::new(key_res.k_doc.clone())))
+        }
+        _ => None,
+    };
+    // Same documents: their later offline opens check the cached list
+    let crl_refresh = match &outcome {
+        KeyOutcome::Granted(key_res) if offline_grant.is_some() => Some((
+            spdf_file.header.server_url.clone(),
+            spdf_file.header.org_id.clone(),
+            key_res.org_keys.clone(),
+        )),
+        _ => None,
+    };
+
+    let device_id = &ctx.device_info.device_id;
+    let mut result = stores.resolve(spdf_file, outcome, device_id, request_id);
+    result.clock_skew_warning = clock_skew_warning;
+    if !result.success {
+        println!("[{}] Open failed: {}", request_id, result.message);
+    } else if let Some((doc_id, k_doc)) = offline_grant {
+        let device_bound = result.device_bound == Some(true);
+        match record_offline_grant(&doc_id, &k_doc, offline_days, device_bound) {
+            Ok(true) => {}
+            Ok(false) => println!(
+                "[{}] Offline grant not recorded: key was not bound to this device",
+                request_id
+            ),
+            Err(e) => println!("[{}] Warning: offline grant not recorded: {}", request_id, e),
+        }
+        if let Some((server_url, org_id, org_keys)) = crl_refresh {
+            let refreshed =
+                refresh_revocation_list(state, &server_url, &org_id, ctx.token, &org_keys).await;
+            if let Err(e) = refreshed {
+                println!("[{}] Warning: revocation list not refreshed: {}", request_id, e);
+            }
+        }
+    }
+    Ok(result)
+}
+
+#[tauri::command]
+async fn open_spdf_file(
+    app_handle: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+    file_path: String,
+) -> Result<OpenFileResult, String> {
+    let request_id = new_request_id();
+    println!("[{}] Opening SPDF file: {}", request_id, file_path);
+
+    // 1. Read SPDF file structure
+    let spdf_file = spdf::SpdfFile::read(&file_path).map_err(|e| format!("{:?}", e))?;
+    println!("SPDF header: {:?}", spdf_file.header);
+
+    // 2. Get Device Info, and refuse what must not reach the key server
+    let device_info = auth::get_device_info(&app_handle).map_err(|e| format!("Device info error: {}", e))?;
+    let device_id = &device_info.device_id;
+    let mut stores = OpenStores::load()?;
+    if let Some(result) = stores.refusal(&spdf_file, device_id) {
+        return Ok(result.with_reference(&request_id));
+    }
+
+    // Development builds only: keys from a local mock, no login or server
+    #[cfg(feature = "dev_mode")]
+    if let Some(provider) = dev_mode::MockKeyProvider::from_env() {
+        println!("[{}] Dev mode: using mock key provider", request_id);
+        let outcome = provider.key_outcome(&spdf_file.header, &device_info);
+        return Ok(stores.resolve(spdf_file, outcome, device_id, &request_id));
+    }
+
+    // 3. Check for Auth Token
+    let Some(token) = load_token(&app_handle, &state, &request_id).await else {
+        return Ok(OpenFileResult::failure(
+            Some(spdf_file.header),
+            "Authentication required".to_string(),
+            true,
+        ));
+    };
+
+    // 4. Fetch Key from Server, joining an open of the same doc in another
+    // window if there is one
+    let cancel = state.key_fetch_cancel();
+    let fetch = {
+        let keys = state.keys().map_err(|e| with_reference(e, &request_id))?.clone();
+        let header = spdf_file.header.clone();
+        let (device_info, request_id) = (device_info.clone(), request_id.clone());
+        let (token, cancel) = (token.clone(), cancel.clone());
+        async move { fetch_key(&keys, &token, &header, &device_info, &request_id, &cancel).await }
+    };
+    let fetched = fetch_key_shared(&state.key_fetches, &spdf_file.header.doc_id, fetch).await;
+
+    let ctx = OpenContext {
+        state: &state,
+        token: &token,
+        device_info: &device_info,
+        cancel,
+    };
+    let result = complete_open(&ctx, &mut stores, spdf_file, fetched, &request_id)
+        .await
+        .map_err(|e| with_reference(e, &request_id))?;
+    Ok(result.with_reference(&request_id))
+}
+
+/// Fetch `org_id`'s revocation list and cache it for offline opens
+///
+/// The list must be signed by the installed org key or, with none
+/// installed, one of `org_keys` as the server reported them.
+async fn refresh_revocation_list(
+    state: &AppState,
+    server_url: &str,
+    org_id: &str,
+    token: &str,
+    org_keys: &[String],
+) -> Result<(), String> {
+    let installed = KeySource::for_keys_dir(&org_keys_dir()?)
+        .org_key_pem(org_id)
+        .map_err(|e| e.to_string())?;
+    let pems = match installed {
+        Some(pem) => vec![pem],
+        None => org_keys.to_vec(),
+    };
+    let trusted: Vec<[u8; 32]> = pems
+        .iter()
+        .filter_map(|pem| verify::parse_ed25519_public_key_pem(pem).ok())
+        .collect();
+    if trusted.is_empty() {
+        return Err(format!("No trusted public key for {}", org_id));
+    }
+
+    let http = state.http_for(server_url)?;
+    let list = revocation::fetch_revocation_list(http, server_url, org_id, token, &trusted)
+        .await
+        .map_err(|e| e.to_string())?;
+    let root = LocalStore::default_root().ok_or("Could not determine home directory")?;
+    let path = LocalStore::new(root).crl_path(org_id).map_err(|e| e.to_string())?;
+    list.save(&path).map_err(|e| e.to_string())
+}
+
+/// Cache the key of a document that just opened online, with its offline
+/// budget, for `check_offline_validity`
+///
+/// With `require_device_binding` set in the policy, a grant the server did
+/// not bind to this device is not kept; returns whether it was.
+fn record_offline_grant(
+    doc_id: &str,
+    k_doc_b64: &str,
+    offline_days: u32,
+    device_bound: bool,
+) -> Result<bool, String> {
+    let root = LocalStore::default_root().ok_or("Could not determine home directory")?;
+    let store = LocalStore::new(root);
+    let policy = SecurityPolicy::load(&store.policy_path()).map_err(|e| e.to_string())?;
+    if policy.require_device_binding && !device_bound {
+        return Ok(false);
+    }
+    let k_doc = decode_k_doc(k_doc_b64)?;
+    let device_hash = device_id::generate_device_hash().map_err(|e| e.to_string())?;
+    offline::record_offline_grant(
+        &store,
+        &device_kek(&device_hash),
+        &device_hash,
+        doc_id,
+        &k_doc,
+        offline_days,
+        time::OffsetDateTime::now_utc(),
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+/// A grant of the key cached for `spdf_file` by an earlier online open,
+/// for opening it while the key server is out of reach; `Err` says why
+/// it will not open offline
+fn offline_outcome(spdf_file: &spdf::SpdfFile) -> Result<KeyOutcome, String> {
+    let root = LocalStore::default_root().ok_or("Could not determine home directory")?;
+    let store = LocalStore::new(root);
+    let policy = SecurityPolicy::load(&store.policy_path()).map_err(|e| e.to_string())?;
+    let device_hash = device_id::generate_device_hash().map_err(|e| e.to_string())?;
+    let kek = device_kek(&device_hash);
+    let now = time::OffsetDateTime::now_utc();
+    match offline::cached_key_for_open(spdf_file, &store, &kek, &device_hash, &policy, now) {
+        Ok(offline::OfflineKey::Ready(doc_key)) => Ok(KeyOutcome::Granted(KeyResponse {
+            k_doc: general_purpose::STANDARD.encode(&doc_key[..]),
+            permissions: spdf_file.header.permissions.clone(),
+            watermark_data: serde_json::json!({}),
+            opens_remaining: None,
+            expiry_extension: None,
+            org_keys: Vec::new(),
+            bound_device_id: None,
+        })),
+        Ok(offline::OfflineKey::Blocked(blocker)) => Err(blocker.reason().to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Compare the local clock with the `Date` reported by a key server
+#[tauri::command]
+async fn check_clock_skew(
+    state: tauri::State<'_, AppState>,
+    server_url: String,
+) -> Result<clock_skew::SkewReport, String> {
+    clock_skew::check_clock_skew(state.http_for(&server_url)?, &server_url).await
+}
+
+/// Stop every key fetch in progress; the opens waiting on them fail with
+/// "Key request cancelled". Later fetches are unaffected.
+#[tauri::command]
+fn cancel_key_fetches(state: tauri::State<'_, AppState>) {
+    let mut cancel = state.key_fetch_cancel.lock().unwrap_or_else(|p| p.into_inner());
+    std::mem::take(&mut *cancel).cancel();
+}
+
+/// Open several documents with one token, one HTTP client and one device
+/// lookup
+///
+/// Each file passes the same checks as with `open_spdf_file`. Documents
+/// on the same server are fetched with a single `/keys/get-batch` call when
+/// the server supports it, otherwise one `/keys/get` call each. Results
+/// map 1:1 to `file_paths`; a file that fails gets an unsuccessful result
+/// instead of failing the batch.
+#[tauri::command]
+async fn open_batch(
+    app_handle: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+    file_paths: Vec<String>,
+) -> Result<Vec<OpenFileResult>, String> {
+    let files: Vec<Result<spdf::SpdfFile, String>> = file_paths
+        .iter()
+        .map(|path| spdf::SpdfFile::read(path).map_err(|e| format!("{:?}", e)))
+        .collect();
+
+    let request_id = new_request_id();
+    let Some(token) = load_token(&app_handle, &state, &request_id).await else {
+        return Ok(files
+            .into_iter()
+            .map(|file| {
+                OpenFileResult::failure(
+                    file.ok().map(|file| file.header),
+                    "Authentication required".to_string(),
+                    true,
+                )
+            })
+            .collect());
+    };
+
+    println!("[{}] Opening {} SPDF files", request_id, file_paths.len());
+
+    // The device id is per install, so one lookup serves every org
+    let device_info = auth::get_device_info(&app_handle).map_err(|e| format!("Device info error: {}", e))?;
+    let mut stores = OpenStores::load()?;
+    let refusals = batch_refusals(&files, &stores, &device_info.device_id);
+    let ctx = OpenContext {
+        state: &state,
+        token: &token,
+        device_info: &device_info,
+        cancel: state.key_fetch_cancel(),
+    };
+
+    let mut outcomes: BatchOutcomes = files.iter().map(|_| None).collect();
+    // The id of the key request behind each result, for its reference;
+    // `None` once the message names it. Files no key was requested for
+    // keep the batch's id.
+    let mut references: Vec<Option<String>> =
+        files.iter().map(|_| Some(request_id.clone())).collect();
+    for (server_url, indices) in group_by_server(&files, &refusals) {
+        let headers: Vec<&spdf::SpdfHeader> = indices
+            .iter()
+            .filter_map(|&i| files[i].as_ref().ok().map(|file| &file.header))
+            .collect();
+
+        let batch = if headers.len() > 1 {
+            let doc_ids: Vec<&str> = headers.iter().map(|h| h.doc_id.as_str()).collect();
+            match state.keys() {
+                Ok(keys) => {
+                    fetch_keys_batch(keys, server_url, &token, &doc_ids, &device_info, &request_id)
+                        .await
+                }
+                Err(e) => Err(with_reference(e, &request_id)),
+            }
+        } else {
+            Ok(None)
+        };
+
+        match batch {
+            Ok(Some(batch)) => {
+                for (&index, (key_request_id, outcome)) in indices.iter().zip(batch) {
+                    outcomes[index] = Some(Ok(outcome));
+                    references[index] = Some(key_request_id);
+                }
+            }
+            Ok(None) => {
+                for (&index, header) in indices.iter().zip(&headers) {
+                    let key_request_id = new_request_id();
+                    let doc_id = &header.doc_id;
+                    let key_id = &key_request_id;
+                    println!("[{}] Requesting key for {} as {}", request_id, doc_id, key_id);
+                    let outcome = match state.keys() {
+                        Ok(keys) => {
+                            fetch_key(keys, &token, header, &device_info, key_id, &ctx.cancel).await
+                        }
+                        Err(e) => Err(e),
+                    };
+                    outcomes[index] = Some(outcome);
+                    references[index] = Some(key_request_id);
+                }
+            }
+            Err(e) => {
+                for &index in &indices {
+                    outcomes[index] = Some(Err(e.clone()));
+                    references[index] = None;
+                }
+            }
+        }
+    }
+
+    let batch = BatchOpen {
+        files,
+        refusals,
+        outcomes,
+        references,
+    };
+    Ok(finish_batch(&ctx, &mut stores, batch, &request_id).await)
+}
+
+/// Why `header` cannot be used to request a key, if it cannot
+fn invalid_header_message(header: &spdf::SpdfHeader) -> Option<String> {
+    let errors = header.validate_header().err()?;
+    let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    Some(format!("Invalid document header: {}", errors.join("; ")))
+}
+
+/// Key outcome of each file in a batch, in input order; `None` for files
+/// no key was requested for
+type BatchOutcomes = Vec<Option<Result<KeyOutcome, String>>>;
+
+/// `OpenStores::refusal` of each readable file in a batch, in input order
+fn batch_refusals(
+    files: &[Result<spdf::SpdfFile, String>],
+    stores: &OpenStores,
+    device_id: &str,
+) -> Vec<Option<OpenFileResult>> {
+    files
+        .iter()
+        .map(|file| file.as_ref().ok().and_then(|file| stores.refusal(file, device_id)))
+        .collect()
+}
+
+/// Group readable files that were not refused by key server
+fn group_by_server<'a>(
+    files: &'a [Result<spdf::SpdfFile, String>],
+    refusals: &[Option<OpenFileResult>],
+) -> BTreeMap<&'a str, Vec<usize>> {
+    let mut by_server: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (index, (file, refusal)) in files.iter().zip(refusals).enumerate() {
+        if let (Ok(file), None) = (file, refusal) {
+            by_server.entry(file.header.server_url.as_str()).or_default().push(index);
+        }
+    }
+    by_server
+}
+
+/// A batch once its keys have been requested; every field is in input order
+struct BatchOpen {
+    files: Vec<Result<spdf::SpdfFile, String>>,
+    refusals: Vec<Option<OpenFileResult>>,
+    outcomes: BatchOutcomes,
+    references: Vec<Option<String>>,
+}
+
+/// Pair each input file with its refusal or its completed open, in input
+/// order
+async fn finish_batch(
+    ctx: &OpenContext<'_>,
+    stores: &mut OpenStores,
+    batch: BatchOpen,
+    request_id: &str,
+) -> Vec<OpenFileResult> {
+    let mut results = Vec::with_capacity(batch.files.len());
+    let files = batch.files.into_iter().zip(batch.refusals);
+    let fetches = batch.outcomes.into_iter().zip(batch.references);
+    for ((file, refusal), (outcome, reference)) in files.zip(fetches) {
+        let result = match (file, refusal, outcome) {
+            (Err(message), _, _) => OpenFileResult::failure(None, message, false),
+            (Ok(_), Some(refusal), _) => refusal,
+            (Ok(file), None, Some(fetched)) => {
+                let header = file.header.clone();
+                let key_request_id = reference.as_deref().unwrap_or(request_id);
+                complete_open(ctx, stores, file, fetched, key_request_id)
+                    .await
+                    .unwrap_or_else(|message| OpenFileResult::failure(Some(header), message, false))
+            }
+            (Ok(file), None, None) => OpenFileResult::failure(
+                Some(file.header),
+                "No key requested".to_string(),
+                false,
+            ),
+        };
+        results.push(match reference {
+            Some(reference) => result.with_reference(&reference),
+            None => result,
+        });
+    }
+    results
+}
+
+/// One stage of `e2e_check`
+#[derive(Debug, Serialize, Deserialize)]
+struct E2eStage {
+    name: String,
+    passed: bool,
+    duration_ms: u64,
+    /// What was checked, or why it failed; never key or document bytes
+    detail: String,
+}
+
+/// Result of `e2e_check`; stages stop at the first failure
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct E2eReport {
+    passed: bool,
+    stages: Vec<E2eStage>,
+}
+
+impl E2eReport {
+    /// Record a stage that began at `started`; returns whether it passed
+    fn record(&mut self, name: &str, started: Instant, result: Result<String, String>) -> bool {
+        let passed = result.is_ok();
+        self.stages.push(E2eStage {
+            name: name.to_string(),
+            passed,
+            duration_ms: started.elapsed().as_millis() as u64,
+            detail: result.unwrap_or_else(|message| message),
+        });
+        self.passed = self.stages.iter().all(|stage| stage.passed);
+        passed
+    }
+}
+
+/// The `e2e_check` stages that need no server: signature against the
+/// installed org key, decryption, and a PDF sanity check. The plaintext is
+/// wiped before returning.
+fn e2e_local_stages(
+    report: &mut E2eReport,
+    mut spdf_file: spdf::SpdfFile,
+    k_doc: &[u8; 32],
+    keys_dir: &Path,
+) {
+    let started = Instant::now();
+    let verify_report = verify_with_org_key(&spdf_file, keys_dir, &[]);
+    let verified = match verify_report.status {
+        VerifyStatus::Verified => Ok(format!("Signed by {}", verify_report.org_id)),
+        _ => Err(verify_report.detail.unwrap_or_default()),
+    };
+    if !report.record("verify_signature", started, verified) {
+        return;
+    }
+
+    let started = Instant::now();
+    let pdf_bytes = match spdf_file.take_decrypted(k_doc) {
+        Ok(pdf_bytes) => Zeroizing::new(pdf_bytes),
+        Err(e) => {
+            report.record("decrypt", started, Err(format!("{:?}", e)));
+            return;
+        }
+    };
+    report.record("decrypt", started, Ok(format!("{} bytes", pdf_bytes.len())));
+
+    let started = Instant::now();
+    let valid = if !pdf_bytes.starts_with(b"%PDF-") {
+        Err("Decrypted content is not a PDF".to_string())
+    } else {
+        match pdf::pdf_page_count(&pdf_bytes) {
+            Some(pages) => Ok(format!("PDF, {} page(s)", pages)),
+            None => Ok("PDF (page count unknown)".to_string()),
+        }
+    };
+    report.record("validate_pdf", started, valid);
+}
+
+/// Smoke test of the whole open path against a real server, starting from
+/// a license key: log in, fetch the key, verify, decrypt and check the PDF
+///
+/// Nothing is persisted: the session token is not saved, the open is not
+/// counted against `max_opens`, and the plaintext is wiped. The report
+/// carries timings and pass/fail only.
+#[tauri::command]
+async fn e2e_check(
+    app_handle: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+    file_path: String,
+    server_url: String,
+    license_key: String,
+) -> Result<E2eReport, String> {
+    let mut report = E2eReport::default();
+
+    let started = Instant::now();
+    let spdf_file = match spdf::SpdfFile::read(&file_path) {
+        Ok(spdf_file) => spdf_file,
+        Err(e) => {
+            report.record("read", started, Err(format!("{:?}", e)));
+            return Ok(report);
+        }
+    };
+    report.record("read", started, Ok(format!("Document {}", spdf_file.header.doc_id)));
+
+    let request_id = new_request_id();
+    println!("[{}] Running end-to-end check against: {}", request_id, server_url);
+
+    let started = Instant::now();
+    let login = match state.http_for(&server_url) {
+        Ok(http) => login_with_key(http, &server_url, &license_key, &request_id).await,
+        Err(e) => Err(e),
+    };
+    let token = match login {
+        Ok(LoginOutcome::Authenticated(login_res)) => {
+            let user_email = login_res.user_email.unwrap_or_default();
+            let detail = format!("Authenticated as {}", user_email);
+            report.record("login", started, Ok(detail));
+            Zeroizing::new(login_res.access_token)
+        }
+        Ok(LoginOutcome::Rejected(message)) | Err(message) => {
+            report.record("login", started, Err(with_reference(message, &request_id)));
+            return Ok(report);
+        }
+    };
+
+    let started = Instant::now();
+    let device_info =
+        auth::get_device_info(&app_handle).map_err(|e| format!("Device info error: {}", e))?;
+    let header = &spdf_file.header;
+    let cancel = state.key_fetch_cancel();
+    let k_doc = match fetch_key(state.keys()?, &token, header, &device_info, &request_id, &cancel)
+        .await
+    {
+        Ok(KeyOutcome::Granted(key_res)) => decode_k_doc(&key_res.k_doc),
+        Ok(KeyOutcome::Unauthorized) => Err("Server rejected the new session".to_string()),
+        Ok(KeyOutcome::Denied(message)) | Err(message) => Err(message),
+    };
+    let k_doc = match k_doc {
+        Ok(k_doc) => {
+            report.record("fetch_key", started, Ok("Key granted".to_string()));
+            k_doc
+        }
+        Err(message) => {
+            report.record("fetch_key", started, Err(with_reference(message, &request_id)));
+            return Ok(report);
+        }
+    };
+
+    e2e_local_stages(&mut report, spdf_file, &k_doc, &org_keys_dir()?);
+    Ok(report)
 }
 
 fn main() {
     tauri::Builder::default()
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_opener::init())
-        .manage(AppState {
-            auth_token: Mutex::new(None),
+        .manage(AppState::new(pinned_key_client(reqwest::Client::new())))
+        .invoke_handler(tauri::generate_handler![
+            open_spdf_file,
+            open_batch,
+            login,
+            e2e_check,
+            check_clock_skew,
+            cancel_key_fetches,
+            spdf_viewer_desktop_lib::screenshot_protection_available,
+            spdf_viewer_desktop_lib::set_screenshot_protection
+        ])
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|app_handle, event| on_run_event(&app_handle.state::<AppState>(), &event));
+}
+
+/// Scrub secrets once the session is ending: when a window closes, when
+/// exit is requested and when the event loop exits, whichever comes first
+fn on_run_event(state: &AppState, event: &tauri::RunEvent) {
+    let ending = matches!(
+        event,
+        tauri::RunEvent::Exit
+            | tauri::RunEvent::ExitRequested { .. }
+            | tauri::RunEvent::WindowEvent {
+                event: tauri::WindowEvent::CloseRequested { .. } | tauri::WindowEvent::Destroyed,
+                ..
+            }
+    );
+    if ending {
+        state.scrub();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scrub_clears_token() {
+        let state = AppState::new(Ok(KeyClient::new(reqwest::Client::new())));
+        *state.session.lock().unwrap() = Some(Session::new("secret-token".to_string(), None));
+        on_run_event(&state, &tauri::RunEvent::Ready);
+        assert!(state.session.lock().unwrap().is_some());
+        on_run_event(&state, &tauri::RunEvent::Exit);
+        assert!(state.session.lock().unwrap().is_none());
+    }
+
+    const DOC_KEY: [u8; 32] = [0x42; 32];
+
+    /// Signs the files the tests write; its key is the one in the header
+    fn writer_signing_key() -> ed25519_dalek::SigningKey {
+        ed25519_dalek::SigningKey::from_bytes(&[0x22; 32])
+    }
+
+    fn test_header(doc_id: &str) -> serde_json::Value {
+        let writer_key = writer_signing_key().verifying_key();
+        let public_key = verify::ed25519_public_key_pem(writer_key.as_bytes());
+        serde_json::json!({
+            "spdf_version": "1.0",
+            "doc_id": doc_id,
+            "org_id": "batch_org",
+            "server_url": "https://spdf.example.com",
+            "created_at": "2025-01-01T00:00:00Z",
+            "public_key": public_key,
+            "permissions": { "allow_print": false, "allow_copy": false, "max_devices": 2 },
+            "watermark": { "enabled": false, "text": "" }
         })
-        .invoke_handler(tauri::generate_handler![open_spdf_file, login])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+    }
+
+    fn write_spdf(dir: &Path, doc_id: &str, plaintext: &[u8]) -> String {
+        write_spdf_with_key(dir, doc_id, plaintext, &DOC_KEY)
+    }
+
+    fn write_spdf_with_key(dir: &Path, doc_id: &str, plaintext: &[u8], key: &[u8; 32]) -> String {
+        write_spdf_with_header(dir, test_header(doc_id), plaintext, key)
+    }
+
+    /// Version 1 file as the encoder writes it, signed by a key that is not
+    /// the org's; `sign_and_install` re-signs it with the org key
+    fn write_spdf_with_header(
+        dir: &Path,
+        header: serde_json::Value,
+        plaintext: &[u8],
+        key: &[u8; 32],
+    ) -> String {
+        let header: spdf::SpdfHeader = serde_json::from_value(header).unwrap();
+        let spdf_file = spdf::SpdfFile::create(&spdf::WriteOptions {
+            header: &header,
+            flags: 0,
+            plaintext,
+            doc_key: key,
+            wrapped_key: &[0u8; spdf::WRAPPED_KEY_LENGTH],
+            signing_key: &writer_signing_key(),
+        })
+        .unwrap();
+
+        let path = dir.join(format!("{}.spdf", header.doc_id));
+        fs::write(&path, spdf_file.encode().unwrap()).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    const TEST_DEVICE: &str = "TEST-DEVICE";
+
+    fn granted(k_doc: &[u8]) -> KeyOutcome {
+        KeyOutcome::Granted(KeyResponse {
+            k_doc: general_purpose::STANDARD.encode(k_doc),
+            permissions: spdf::SpdfPermissions {
+                allow_print: false,
+                allow_copy: false,
+                max_devices: 2,
+                offline_days: 0,
+                max_opens: None,
+            },
+            watermark_data: serde_json::json!({ "device_id": TEST_DEVICE }),
+            opens_remaining: None,
+            expiry_extension: None,
+            org_keys: Vec::new(),
+            bound_device_id: Some(TEST_DEVICE.to_string()),
+        })
+    }
+
+    /// Open records kept under `dir`, with no memory limit
+    fn test_stores(dir: &Path) -> OpenStores {
+        OpenStores {
+            counter: view_limit::ViewCounter::load(&dir.join("opens.json")).unwrap(),
+            extensions: expiry::ExtensionCache::load(&dir.join("expiry.json")).unwrap(),
+            devices: DeviceRegistry::load(&dir.join("devices.json")).unwrap(),
+            keys_dir: dir.join("keys"),
+            memory_limit: None,
+        }
+    }
+
+    fn test_device() -> auth::DeviceInfo {
+        auth::DeviceInfo {
+            device_id: TEST_DEVICE.to_string(),
+            device_name: "test".to_string(),
+        }
+    }
+
+    /// `finish_batch` for files whose keys were fetched as `outcomes`
+    fn finish_test_batch(
+        stores: &mut OpenStores,
+        files: Vec<Result<spdf::SpdfFile, String>>,
+        outcomes: BatchOutcomes,
+    ) -> Vec<OpenFileResult> {
+        let state = AppState::new(Ok(KeyClient::new(reqwest::Client::new())));
+        let device_info = test_device();
+        let ctx = OpenContext {
+            state: &state,
+            token: "token",
+            device_info: &device_info,
+            cancel: CancellationToken::new(),
+        };
+        let batch = BatchOpen {
+            refusals: batch_refusals(&files, stores, TEST_DEVICE),
+            references: files.iter().map(|_| Some("req".to_string())).collect(),
+            files,
+            outcomes,
+        };
+        tauri::async_runtime::block_on(finish_batch(&ctx, stores, batch, "req"))
+    }
+
+    #[test]
+    fn test_batch_groups_only_valid_headers() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut header = test_header("DOC-BAD-URL");
+        header["server_url"] = serde_json::json!("file:///etc");
+        let paths = [
+            write_spdf(dir.path(), "DOC-OK", b"%PDF-1.4 ok"),
+            write_spdf_with_header(dir.path(), header, b"%PDF-1.4 bad", &DOC_KEY),
+        ];
+        let files: Vec<_> = paths
+            .iter()
+            .map(|path| spdf::SpdfFile::read(path).map_err(|e| format!("{:?}", e)))
+            .collect();
+
+        let refusals = batch_refusals(&files, &test_stores(dir.path()), TEST_DEVICE);
+        let by_server = group_by_server(&files, &refusals);
+        assert_eq!(by_server.len(), 1);
+        assert_eq!(by_server["https://spdf.example.com"], vec![0]);
+        assert!(refusals[0].is_none());
+        match &refusals[1] {
+            Some(result) => assert!(result.message.starts_with("Invalid document header")),
+            None => panic!("invalid header was not reported"),
+        }
+    }
+
+    #[test]
+    fn test_batch_mixed_results() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = [
+            write_spdf(dir.path(), "DOC-OK", b"%PDF-1.4 ok"),
+            dir.path().join("missing.spdf").to_str().unwrap().to_string(),
+            write_spdf(dir.path(), "DOC-DENIED", b"%PDF-1.4 denied"),
+            write_spdf(dir.path(), "DOC-WRONG-KEY", b"%PDF-1.4 wrong key"),
+        ];
+        let keys_dir = dir.path().join("keys");
+        let files: Vec<_> = paths
+            .iter()
+            .map(|path| {
+                let mut spdf_file = spdf::SpdfFile::read(path).map_err(|e| format!("{:?}", e))?;
+                sign_and_install(&mut spdf_file, &keys_dir);
+                Ok(spdf_file)
+            })
+            .collect();
+        let outcomes = vec![
+            Some(Ok(granted(&DOC_KEY))),
+            None,
+            Some(Ok(KeyOutcome::Denied("Server denied access: 403 Forbidden".to_string()))),
+            Some(Ok(granted(&[0x01; 32]))),
+        ];
+
+        let results = finish_test_batch(&mut test_stores(dir.path()), files, outcomes);
+        assert_eq!(results.len(), 4);
+
+        assert!(results[0].success);
+        let pdf_base64 = results[0].pdf_base64.as_ref().unwrap();
+        let pdf = general_purpose::STANDARD.decode(pdf_base64).unwrap();
+        assert_eq!(pdf, b"%PDF-1.4 ok");
+
+        assert!(!results[1].success);
+        assert!(results[1].header.is_none());
+
+        assert!(!results[2].success);
+        assert_eq!(results[2].header.as_ref().unwrap().doc_id, "DOC-DENIED");
+        assert!(results[2].message.contains("403"));
+
+        assert!(!results[3].success);
+        assert!(results[3].message.contains("Decryption"));
+    }
+
+    #[test]
+    fn test_batch_enforces_device_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys_dir = dir.path().join("keys");
+        let paths = [
+            write_spdf(dir.path(), "DOC-FULL", b"%PDF-1.4 full"),
+            write_spdf(dir.path(), "DOC-FREE", b"%PDF-1.4 free"),
+        ];
+        let files: Vec<_> = paths
+            .iter()
+            .map(|path| {
+                let mut spdf_file = spdf::SpdfFile::read(path).map_err(|e| format!("{:?}", e))?;
+                sign_and_install(&mut spdf_file, &keys_dir);
+                Ok(spdf_file)
+            })
+            .collect();
+        let mut stores = test_stores(dir.path());
+        // max_devices is 2, both already taken on this installation
+        stores.devices.record("DOC-FULL", "OTHER-1").unwrap();
+        stores.devices.record("DOC-FULL", "OTHER-2").unwrap();
+
+        let refusals = batch_refusals(&files, &stores, TEST_DEVICE);
+        let by_server = group_by_server(&files, &refusals);
+        assert_eq!(by_server["https://spdf.example.com"], vec![1]);
+
+        let outcomes = vec![None, Some(Ok(granted(&DOC_KEY)))];
+        let results = finish_test_batch(&mut stores, files, outcomes);
+        assert!(!results[0].success);
+        assert!(results[0].device_limit_reached);
+        assert!(results[0].message.starts_with("Device limit reached"));
+        assert!(results[1].success, "{}", results[1].message);
+
+        assert_eq!(stores.devices.device_count("DOC-FULL"), 2);
+        assert_eq!(stores.devices.device_count("DOC-FREE"), 1);
+        let reloaded = DeviceRegistry::load(&dir.path().join("devices.json")).unwrap();
+        assert_eq!(reloaded.device_count("DOC-FREE"), 1);
+    }
+
+    #[test]
+    fn test_encoder_output_reads_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let plaintext = b"%PDF-1.4 round trip";
+        let header: spdf::SpdfHeader = serde_json::from_value(test_header("DOC-RT")).unwrap();
+        let written = spdf::SpdfFile::create(&spdf::WriteOptions {
+            header: &header,
+            flags: spdf::FLAG_PRINT_ALLOWED,
+            plaintext,
+            doc_key: &DOC_KEY,
+            wrapped_key: &[0x5A; spdf::WRAPPED_KEY_LENGTH],
+            signing_key: &org_signing_key(),
+        })
+        .unwrap();
+        let path = dir.path().join("DOC-RT.spdf");
+        let bytes = written.encode().unwrap();
+        fs::write(&path, &bytes).unwrap();
+
+        let mut read = spdf::SpdfFile::read(path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            serde_json::to_value(&read.header).unwrap(),
+            serde_json::to_value(&written.header).unwrap()
+        );
+        assert_eq!(read.flags, spdf::FLAG_PRINT_ALLOWED);
+        assert_eq!(read.wrapped_key, written.wrapped_key);
+        assert_eq!(read.nonce, written.nonce);
+        assert_eq!(read.ciphertext, written.ciphertext);
+        assert_eq!(read.auth_tag, written.auth_tag);
+        assert_eq!(read.signature, written.signature);
+        assert_eq!(read.encode().unwrap(), bytes);
+
+        let pem = verify::ed25519_public_key_pem(org_signing_key().verifying_key().as_bytes());
+        assert!(verify::verify_signature_with_key(&read, &pem).is_ok());
+        assert_eq!(read.take_decrypted(&DOC_KEY).unwrap(), plaintext);
+    }
+
+    fn org_signing_key() -> ed25519_dalek::SigningKey {
+        ed25519_dalek::SigningKey::from_bytes(&[0x11; 32])
+    }
+
+    /// Re-sign `spdf_file` with the org key, naming that key in its
+    /// header, and install the public key in `keys_dir`
+    fn sign_and_install(spdf_file: &mut spdf::SpdfFile, keys_dir: &Path) {
+        use ed25519_dalek::Signer;
+        use sha2::{Digest, Sha256};
+
+        let key = org_signing_key();
+        let pem = verify::ed25519_public_key_pem(key.verifying_key().as_bytes());
+        let mut header = spdf_file.header.clone();
+        header.public_key = pem.clone();
+        let header_bytes = spdf::encode_header(&header, spdf_file.header_encoding()).unwrap();
+
+        // Swap the header into the signed bytes: MAGIC, VERSION and FLAGS,
+        // then HEADER_LEN and the header, then the rest as it was
+        let unsigned = spdf_file.build_unsigned_data();
+        let mut data = unsigned[..7].to_vec();
+        data.extend_from_slice(&(header_bytes.len() as u32).to_be_bytes());
+        data.extend_from_slice(&header_bytes);
+        data.extend_from_slice(&unsigned[11 + spdf_file.header_bytes().len()..]);
+        let digest = Sha256::digest(&data);
+        data.extend_from_slice(&key.sign(&digest).to_bytes());
+        *spdf_file = spdf::SpdfFile::parse(&data).unwrap();
+
+        fs::create_dir_all(keys_dir).unwrap();
+        let org_id = &spdf_file.header.org_id;
+        fs::write(keys_dir.join(format!("{}_public.pem", org_id)), pem).unwrap();
+    }
+
+    #[test]
+    fn test_keyless_header_verifies_with_server_key() {
+        use ed25519_dalek::Signer;
+        use sha2::{Digest, Sha256};
+
+        let dir = tempfile::tempdir().unwrap();
+        let keys_dir = dir.path().join("keys");
+        let mut header = test_header("DOC-KEYLESS");
+        header.as_object_mut().unwrap().remove("public_key");
+        let path = write_spdf_with_header(dir.path(), header, b"%PDF-1.4 keyless", &DOC_KEY);
+        let mut counter = view_limit::ViewCounter::load(&dir.path().join("opens.json")).unwrap();
+        let mut extensions = expiry::ExtensionCache::load(&dir.path().join("expiry.json")).unwrap();
+        // Signed by the org key, which is not installed locally
+        let mut open = |server_keys: Vec<String>| {
+            let mut spdf_file = spdf::SpdfFile::read(&path).unwrap();
+            assert!(spdf_file.header.public_key.is_empty());
+            let digest = Sha256::digest(&spdf_file.unsigned_data);
+            spdf_file.signature = org_signing_key().sign(&digest).to_bytes().to_vec();
+            let outcome = match granted(&DOC_KEY) {
+                KeyOutcome::Granted(mut key_res) => {
+                    key_res.org_keys = server_keys;
+                    KeyOutcome::Granted(key_res)
+                }
+                _ => unreachable!(),
+            };
+            let (counter, extensions) = (&mut counter, &mut extensions);
+            resolve_open(spdf_file, outcome, counter, extensions, &keys_dir, TEST_DEVICE)
+        };
+
+        let org_pem = verify::ed25519_public_key_pem(org_signing_key().verifying_key().as_bytes());
+        let other_pem =
+            verify::ed25519_public_key_pem(writer_signing_key().verifying_key().as_bytes());
+        let result = open(vec![other_pem.clone(), org_pem]);
+        assert!(result.success, "{}", result.message);
+        assert_eq!(result.verify_report.unwrap().status, VerifyStatus::Verified);
+
+        // Server keys that do not match: refused
+        let result = open(vec![other_pem]);
+        assert!(!result.success);
+        assert!(result.pdf_base64.is_none());
+        assert!(result.message.starts_with("Signature verification failed"));
+        assert_eq!(result.verify_report.unwrap().status, VerifyStatus::Failed);
+
+        // No key from anywhere: refused
+        let result = open(Vec::new());
+        assert!(!result.success);
+        assert_eq!(
+            result.message,
+            "No trusted public key is installed or provided by the server for batch_org"
+        );
+        assert_eq!(result.verify_report.unwrap().status, VerifyStatus::NoOrgKey);
+    }
+
+    #[test]
+    fn test_watermark_text_from_grant() {
+        let mut header = test_header("DOC-WM");
+        header["watermark"]["text"] = serde_json::json!("{{user_id}} | {{device_id}} | {{doc_id}}");
+        let header: spdf::SpdfHeader = serde_json::from_value(header).unwrap();
+
+        let data = serde_json::json!({ "user_id": "alice@example.com", "device_id": TEST_DEVICE });
+        let expected = format!("alice@example.com | {} | DOC-WM", TEST_DEVICE);
+        assert_eq!(watermark_text(&header, &data), expected);
+        // Fields the server left out render empty
+        assert_eq!(watermark_text(&header, &serde_json::json!({})), " |  | DOC-WM");
+    }
+
+    #[test]
+    fn test_failed_decryption_not_counted() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys_dir = dir.path().join("keys");
+        let mut header = test_header("DOC-ONCE");
+        header["permissions"]["max_opens"] = serde_json::json!(1);
+        let path = write_spdf_with_header(dir.path(), header, b"%PDF-1.4 once", &DOC_KEY);
+        let mut counter = view_limit::ViewCounter::load(&dir.path().join("opens.json")).unwrap();
+        let mut extensions = expiry::ExtensionCache::load(&dir.path().join("expiry.json")).unwrap();
+        let (counter, extensions) = (&mut counter, &mut extensions);
+
+        // A key that does not decrypt the document uses up no opens
+        let mut spdf_file = spdf::SpdfFile::read(&path).unwrap();
+        sign_and_install(&mut spdf_file, &keys_dir);
+        let outcome = granted(&[0x01; 32]);
+        let result = resolve_open(spdf_file, outcome, counter, extensions, &keys_dir, TEST_DEVICE);
+        assert!(!result.success);
+        assert_eq!(counter.remaining_opens("DOC-ONCE"), None);
+
+        let mut spdf_file = spdf::SpdfFile::read(&path).unwrap();
+        sign_and_install(&mut spdf_file, &keys_dir);
+        let outcome = granted(&DOC_KEY);
+        let result = resolve_open(spdf_file, outcome, counter, extensions, &keys_dir, TEST_DEVICE);
+        assert!(result.success, "{}", result.message);
+        assert_eq!(counter.remaining_opens("DOC-ONCE"), Some(0));
+
+        let mut spdf_file = spdf::SpdfFile::read(&path).unwrap();
+        sign_and_install(&mut spdf_file, &keys_dir);
+        let outcome = granted(&DOC_KEY);
+        let result = resolve_open(spdf_file, outcome, counter, extensions, &keys_dir, TEST_DEVICE);
+        assert_eq!(result.message, view_limit::VIEW_LIMIT_REACHED);
+        assert!(result.pdf_base64.is_none());
+    }
+
+    #[test]
+    fn test_open_result_reports_verification_and_permissions() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys_dir = dir.path().join("keys");
+        let path = write_spdf(dir.path(), "DOC-OPEN", b"%PDF-1.4 open");
+        let mut spdf_file = spdf::SpdfFile::read(&path).unwrap();
+        sign_and_install(&mut spdf_file, &keys_dir);
+        let mut counter = view_limit::ViewCounter::load(&dir.path().join("opens.json")).unwrap();
+        let mut extensions = expiry::ExtensionCache::load(&dir.path().join("expiry.json")).unwrap();
+
+        let outcome = match granted(&DOC_KEY) {
+            KeyOutcome::Granted(mut key_res) => {
+                key_res.permissions.allow_print = true;
+                key_res.permissions.max_devices = 5;
+                key_res.permissions.max_opens = Some(3);
+                KeyOutcome::Granted(key_res)
+            }
+            _ => unreachable!(),
+        };
+        let (counter, extensions) = (&mut counter, &mut extensions);
+        let result = resolve_open(spdf_file, outcome, counter, extensions, &keys_dir, TEST_DEVICE);
+        assert!(result.success, "{}", result.message);
+
+        let verify_report = result.verify_report.unwrap();
+        assert_eq!(verify_report.status, VerifyStatus::Verified);
+        assert_eq!(verify_report.org_id, "batch_org");
+        // Header denies printing and allows 2 devices; the server caps opens
+        let permissions = result.effective_permissions.unwrap();
+        assert!(!permissions.allow_print);
+        assert_eq!(permissions.max_devices, 2);
+        assert_eq!(permissions.max_opens, Some(3));
+        assert_eq!(result.device_bound, Some(true));
+
+        // Granted to another device: opens, but reported as not bound here
+        let mut spdf_file = spdf::SpdfFile::read(&path).unwrap();
+        sign_and_install(&mut spdf_file, &keys_dir);
+        let outcome = granted(&DOC_KEY);
+        let result = resolve_open(spdf_file, outcome, counter, extensions, &keys_dir, "OTHER");
+        assert!(result.success, "{}", result.message);
+        assert_eq!(result.device_bound, Some(false));
+
+        // Naming this device in the watermark does not bind the grant to it
+        let mut spdf_file = spdf::SpdfFile::read(&path).unwrap();
+        sign_and_install(&mut spdf_file, &keys_dir);
+        let outcome = match granted(&DOC_KEY) {
+            KeyOutcome::Granted(mut key_res) => {
+                key_res.bound_device_id = None;
+                KeyOutcome::Granted(key_res)
+            }
+            _ => unreachable!(),
+        };
+        let result = resolve_open(spdf_file, outcome, counter, extensions, &keys_dir, TEST_DEVICE);
+        assert!(result.success, "{}", result.message);
+        assert_eq!(result.device_bound, Some(false));
+
+        // Not signed by the installed org key: refused, with the report
+        let spdf_file = spdf::SpdfFile::read(&path).unwrap();
+        let outcome = granted(&DOC_KEY);
+        let result = resolve_open(spdf_file, outcome, counter, extensions, &keys_dir, TEST_DEVICE);
+        assert!(!result.success);
+        assert!(result.pdf_base64.is_none());
+        assert_eq!(result.verify_report.unwrap().status, VerifyStatus::Failed);
+    }
+
+    /// Version 2 file whose only segment encrypts nothing, leaving just the
+    /// tag; version 1 files cannot have empty content at all
+    fn empty_segmented_spdf(doc_id: &str) -> Vec<u8> {
+        use aes_gcm::{aead::Aead, Aes256Gcm, KeyInit, Nonce};
+
+        let nonce = [0x07; 12];
+        let area = Aes256Gcm::new((&DOC_KEY).into())
+            .encrypt(Nonce::from_slice(&nonce), &b""[..])
+            .unwrap();
+        let mut header = test_header(doc_id);
+        header["spdf_version"] = serde_json::json!("2.0");
+        header["segments"] = serde_json::json!([
+            { "offset": 0, "length": area.len(), "nonce": hex::encode(nonce) }
+        ]);
+        let header_json = serde_json::to_vec(&header).unwrap();
+
+        let mut data = spdf::MAGIC.to_vec();
+        data.push(spdf::VERSION_SEGMENTED);
+        data.extend_from_slice(&0u16.to_be_bytes());
+        data.extend_from_slice(&(header_json.len() as u32).to_be_bytes());
+        data.extend_from_slice(&header_json);
+        data.extend_from_slice(&[0u8; spdf::WRAPPED_KEY_LENGTH]);
+        data.extend_from_slice(&area);
+        data.extend_from_slice(&[0u8; spdf::SIGNATURE_LENGTH]);
+        data
+    }
+
+    #[test]
+    fn test_signed_empty_content_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys_dir = dir.path().join("keys");
+        let mut spdf_file = spdf::SpdfFile::parse(&empty_segmented_spdf("DOC-EMPTY")).unwrap();
+        sign_and_install(&mut spdf_file, &keys_dir);
+        let pem = fs::read_to_string(keys_dir.join("batch_org_public.pem")).unwrap();
+        assert!(verify::verify_signature_with_key(&spdf_file, &pem).is_ok());
+        let mut counter = view_limit::ViewCounter::load(&dir.path().join("opens.json")).unwrap();
+        let mut extensions = expiry::ExtensionCache::load(&dir.path().join("expiry.json")).unwrap();
+
+        let outcome = granted(&DOC_KEY);
+        let (counter, extensions) = (&mut counter, &mut extensions);
+        let result = resolve_open(spdf_file, outcome, counter, extensions, &keys_dir, TEST_DEVICE);
+        assert!(!result.success);
+        assert_eq!(result.message, "document has no content");
+        assert!(result.pdf_base64.is_none());
+    }
+
+    /// A grant for `DOC-EXT` extending it to `new_expires_at`, signed by `key`
+    fn granted_with_extension(key: &ed25519_dalek::SigningKey, new_expires_at: &str) -> KeyOutcome {
+        use ed25519_dalek::Signer;
+
+        let signature = key.sign(&expiry::extension_message("DOC-EXT", new_expires_at));
+        match granted(&DOC_KEY) {
+            KeyOutcome::Granted(mut key_res) => {
+                key_res.expiry_extension = Some(expiry::ExpiryExtension {
+                    new_expires_at: new_expires_at.to_string(),
+                    signature: general_purpose::STANDARD.encode(signature.to_bytes()),
+                });
+                KeyOutcome::Granted(key_res)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// A signed `DOC-EXT` that expired in 2020
+    fn expired_spdf(dir: &Path, keys_dir: &Path) -> spdf::SpdfFile {
+        let mut header = test_header("DOC-EXT");
+        header["expires_at"] = serde_json::json!("2020-01-01T00:00:00Z");
+        let path = write_spdf_with_header(dir, header, b"%PDF-1.4 extended", &DOC_KEY);
+        let mut spdf_file = spdf::SpdfFile::read(&path).unwrap();
+        sign_and_install(&mut spdf_file, keys_dir);
+        spdf_file
+    }
+
+    #[test]
+    fn test_expiry_extension_applied() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys_dir = dir.path().join("keys");
+        let mut counter = view_limit::ViewCounter::load(&dir.path().join("opens.json")).unwrap();
+        let mut extensions = expiry::ExtensionCache::load(&dir.path().join("expiry.json")).unwrap();
+        let (counter, extensions) = (&mut counter, &mut extensions);
+
+        let spdf_file = expired_spdf(dir.path(), &keys_dir);
+        let outcome = granted(&DOC_KEY);
+        let result = resolve_open(spdf_file, outcome, counter, extensions, &keys_dir, TEST_DEVICE);
+        assert!(!result.success);
+        assert_eq!(result.message, "Document has expired");
+
+        let spdf_file = expired_spdf(dir.path(), &keys_dir);
+        let outcome = granted_with_extension(&org_signing_key(), "2099-01-01T00:00:00Z");
+        let result = resolve_open(spdf_file, outcome, counter, extensions, &keys_dir, TEST_DEVICE);
+        assert!(result.success, "{}", result.message);
+
+        // Cached for offline checks
+        let cached = expiry::ExtensionCache::load(&dir.path().join("expiry.json")).unwrap();
+        assert_eq!(cached.get("DOC-EXT").unwrap().new_expires_at, "2099-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn test_forged_expiry_extension_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys_dir = dir.path().join("keys");
+        let mut counter = view_limit::ViewCounter::load(&dir.path().join("opens.json")).unwrap();
+        let mut extensions = expiry::ExtensionCache::load(&dir.path().join("expiry.json")).unwrap();
+        let (counter, extensions) = (&mut counter, &mut extensions);
+
+        let spdf_file = expired_spdf(dir.path(), &keys_dir);
+        let forger = ed25519_dalek::SigningKey::from_bytes(&[0x12; 32]);
+        let outcome = granted_with_extension(&forger, "2099-01-01T00:00:00Z");
+        let result = resolve_open(spdf_file, outcome, counter, extensions, &keys_dir, TEST_DEVICE);
+        assert!(!result.success);
+        assert_eq!(
+            result.message,
+            "Invalid expiry extension: expiry extension signature does not verify"
+        );
+        assert!(result.pdf_base64.is_none());
+        assert!(extensions.get("DOC-EXT").is_none());
+    }
+
+    #[test]
+    fn test_expiry_extension_checked_against_server_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys_dir = dir.path().join("keys");
+        let mut counter = view_limit::ViewCounter::load(&dir.path().join("opens.json")).unwrap();
+        let mut extensions = expiry::ExtensionCache::load(&dir.path().join("expiry.json")).unwrap();
+        let (counter, extensions) = (&mut counter, &mut extensions);
+        let org_pem = verify::ed25519_public_key_pem(org_signing_key().verifying_key().as_bytes());
+        // Signed by the org key, which only the key server provides
+        let signed_with = |key: &ed25519_dalek::SigningKey| {
+            let spdf_file = expired_spdf(dir.path(), &keys_dir);
+            fs::remove_file(keys_dir.join("batch_org_public.pem")).unwrap();
+            let outcome = match granted_with_extension(key, "2099-01-01T00:00:00Z") {
+                KeyOutcome::Granted(mut key_res) => {
+                    key_res.org_keys = vec![org_pem.clone()];
+                    KeyOutcome::Granted(key_res)
+                }
+                _ => unreachable!(),
+            };
+            (spdf_file, outcome)
+        };
+
+        let forger = ed25519_dalek::SigningKey::from_bytes(&[0x12; 32]);
+        let (spdf_file, outcome) = signed_with(&forger);
+        let result = resolve_open(spdf_file, outcome, counter, extensions, &keys_dir, TEST_DEVICE);
+        assert!(!result.success);
+        assert_eq!(
+            result.message,
+            "Invalid expiry extension: expiry extension signature does not verify"
+        );
+
+        let (spdf_file, outcome) = signed_with(&org_signing_key());
+        let result = resolve_open(spdf_file, outcome, counter, extensions, &keys_dir, TEST_DEVICE);
+        assert!(result.success, "{}", result.message);
+        assert_eq!(extensions.get("DOC-EXT").unwrap().new_expires_at, "2099-01-01T00:00:00Z");
+    }
+
+    #[cfg(feature = "dev_mode")]
+    #[test]
+    fn test_dev_mode_opens_locally_signed_file() {
+        use dev_mode::{MockKeyProvider, DEV_DOC_KEY};
+
+        let dir = tempfile::tempdir().unwrap();
+        let keys_dir = dir.path().join("keys");
+        let path = write_spdf_with_key(dir.path(), "DOC-DEV", b"%PDF-1.4 dev", &DEV_DOC_KEY);
+        let mut spdf_file = spdf::SpdfFile::read(&path).unwrap();
+        sign_and_install(&mut spdf_file, &keys_dir);
+        let mut counter = view_limit::ViewCounter::load(&dir.path().join("opens.json")).unwrap();
+        let mut extensions = expiry::ExtensionCache::load(&dir.path().join("expiry.json")).unwrap();
+
+        let device_info = auth::DeviceInfo {
+            device_id: TEST_DEVICE.to_string(),
+            device_name: "test".to_string(),
+        };
+        let outcome = MockKeyProvider.key_outcome(&spdf_file.header, &device_info);
+        let (counter, extensions) = (&mut counter, &mut extensions);
+        let result = resolve_open(spdf_file, outcome, counter, extensions, &keys_dir, TEST_DEVICE);
+        assert!(result.success, "{}", result.message);
+        let pdf = general_purpose::STANDARD.decode(result.pdf_base64.unwrap()).unwrap();
+        assert_eq!(pdf, b"%PDF-1.4 dev");
+        assert_eq!(result.verify_report.unwrap().status, VerifyStatus::Verified);
+        assert_eq!(result.device_bound, Some(true));
+    }
+
+    fn stage_names(report: &E2eReport) -> Vec<&str> {
+        report.stages.iter().map(|stage| stage.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_e2e_local_stages() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys_dir = dir.path().join("keys");
+        let plaintext = b"%PDF-1.4\n3 0 obj << /Type /Page >> endobj\n%%EOF\n";
+        let path = write_spdf(dir.path(), "DOC-E2E", plaintext);
+
+        // No org key installed: stops at the signature
+        let mut report = E2eReport::default();
+        e2e_local_stages(&mut report, spdf::SpdfFile::read(&path).unwrap(), &DOC_KEY, &keys_dir);
+        assert!(!report.passed);
+        assert_eq!(stage_names(&report), ["verify_signature"]);
+
+        let mut spdf_file = spdf::SpdfFile::read(&path).unwrap();
+        sign_and_install(&mut spdf_file, &keys_dir);
+        let mut report = E2eReport::default();
+        e2e_local_stages(&mut report, spdf_file, &DOC_KEY, &keys_dir);
+        assert!(report.passed, "{:?}", report);
+        assert_eq!(stage_names(&report), ["verify_signature", "decrypt", "validate_pdf"]);
+        assert_eq!(report.stages[2].detail, "PDF, 1 page(s)");
+
+        // Neither the key nor the plaintext ends up in the report
+        let json = serde_json::to_string(&report).unwrap();
+        assert!(!json.contains(&general_purpose::STANDARD.encode(DOC_KEY)));
+        assert!(!json.contains(&hex::encode(DOC_KEY)));
+        assert!(!json.contains("/Type /Page"));
+
+        let mut spdf_file = spdf::SpdfFile::read(&path).unwrap();
+        sign_and_install(&mut spdf_file, &keys_dir);
+        let mut report = E2eReport::default();
+        e2e_local_stages(&mut report, spdf_file, &[0x01; 32], &keys_dir);
+        assert_eq!(stage_names(&report), ["verify_signature", "decrypt"]);
+        assert!(!report.stages[1].passed);
+    }
+
+    #[test]
+    fn test_batch_result_outcome() {
+        let result = BatchKeyResult {
+            doc_id: "DOC-1".to_string(),
+            status: 401,
+            key: None,
+            detail: None,
+        };
+        assert!(matches!(KeyOutcome::from(result), KeyOutcome::Unauthorized));
+
+        let result = BatchKeyResult {
+            doc_id: "DOC-1".to_string(),
+            status: 403,
+            key: None,
+            detail: Some("No license for this document".to_string()),
+        };
+        match KeyOutcome::from(result) {
+            KeyOutcome::Denied(message) => assert_eq!(
+                message,
+                "Server denied access: 403 Forbidden - No license for this document"
+            ),
+            _ => panic!("expected Denied"),
+        }
+    }
+
+    /// Answer one HTTP request with `response` and hand back the raw request
+    fn serve_once(response: &'static str) -> (String, std::thread::JoinHandle<String>) {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            while !String::from_utf8_lossy(&request).contains("\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            String::from_utf8_lossy(&request).to_lowercase()
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn test_batch_fetch_chunked() {
+        use std::io::{BufRead, BufReader, Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let server_url = format!("http://{}", listener.local_addr().unwrap());
+        // Answers each request with a 403 per doc_id, in order
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for _ in 0..3 {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut content_length = 0;
+                let mut request_id = String::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        } else if name.eq_ignore_ascii_case(REQUEST_ID_HEADER) {
+                            request_id = value.trim().to_string();
+                        }
+                    }
+                }
+                let mut body = vec![0u8; content_length];
+                reader.read_exact(&mut body).unwrap();
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                let results: Vec<_> = body["doc_ids"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|doc_id| serde_json::json!({ "doc_id": doc_id, "status": 403 }))
+                    .collect();
+                requests.push((request_id, results.len()));
+                let response = serde_json::json!({ "results": results }).to_string();
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\n\
+                     content-length: {}\r\nconnection: close\r\n\r\n{}",
+                    response.len(),
+                    response
+                )
+                .unwrap();
+            }
+            requests
+        });
+
+        let doc_ids: Vec<String> = (0..120).map(|i| format!("DOC-{}", i)).collect();
+        let doc_ids: Vec<&str> = doc_ids.iter().map(String::as_str).collect();
+        let device_info = auth::DeviceInfo {
+            device_id: TEST_DEVICE.to_string(),
+            device_name: "test".to_string(),
+        };
+        let keys = KeyClient::new(reqwest::Client::new());
+        let outcomes = tauri::async_runtime::block_on(fetch_keys_batch(
+            &keys,
+            &server_url,
+            "token",
+            &doc_ids,
+            &device_info,
+            "batch",
+        ))
+        .unwrap()
+        .unwrap();
+
+        let requests = server.join().unwrap();
+        let sizes: Vec<usize> = requests.iter().map(|(_, size)| *size).collect();
+        assert_eq!(sizes, [50, 50, 20]);
+        assert_eq!(outcomes.len(), 120);
+        assert!(outcomes.iter().all(|(_, outcome)| matches!(outcome, KeyOutcome::Denied(_))));
+
+        // Each request has an id of its own, reported with its documents
+        let ids: std::collections::HashSet<_> = requests.iter().map(|(id, _)| id).collect();
+        assert_eq!(ids.len(), 3);
+        assert!(!ids.contains(&"batch".to_string()));
+        assert_eq!(outcomes[0].0, requests[0].0);
+        assert_eq!(outcomes[50].0, requests[1].0);
+        assert_eq!(outcomes[119].0, requests[2].0);
+    }
+
+    #[test]
+    fn test_refresh_session() {
+        let http = reqwest::Client::new();
+        let mut session = Session::new("old-token".to_string(), Some("a@example.com".to_string()));
+        // Nothing to refresh with
+        let refreshed = tauri::async_runtime::block_on(refresh_session(&http, &session, "req"));
+        assert!(refreshed.unwrap().is_none());
+
+        let (server_url, server) = serve_once(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 28\r\n\
+             connection: close\r\n\r\n{\"access_token\":\"new-token\"}",
+        );
+        session.server_url = Some(server_url);
+        session.refresh_token = Some("refresh-1".to_string());
+        let refreshed = tauri::async_runtime::block_on(refresh_session(&http, &session, "req"))
+            .unwrap()
+            .unwrap();
+        let request = server.join().unwrap();
+        assert!(request.starts_with("post /auth/refresh "), "{}", request);
+        assert_eq!(refreshed.access_token, "new-token");
+        // Kept from the old session when the server leaves them out
+        assert_eq!(refreshed.user_email.as_deref(), Some("a@example.com"));
+        assert_eq!(refreshed.refresh_token.as_deref(), Some("refresh-1"));
+
+        // A server without the endpoint
+        let (server_url, server) = serve_once(
+            "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+        );
+        session.server_url = Some(server_url);
+        let refreshed = tauri::async_runtime::block_on(refresh_session(&http, &session, "req"));
+        server.join().unwrap();
+        assert!(refreshed.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_request_id_sent_and_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let (server_url, server) = serve_once(
+            "HTTP/1.1 403 Forbidden\r\ncontent-length: 10\r\nconnection: close\r\n\r\nNo license",
+        );
+        let path = write_spdf(dir.path(), "DOC-REQ", b"%PDF");
+        let mut spdf_file = spdf::SpdfFile::read(&path).unwrap();
+        spdf_file.header.server_url = server_url;
+        let device_info = auth::DeviceInfo {
+            device_id: TEST_DEVICE.to_string(),
+            device_name: "test".to_string(),
+        };
+
+        let request_id = new_request_id();
+        let keys = KeyClient::new(reqwest::Client::new());
+        let outcome = tauri::async_runtime::block_on(fetch_key(
+            &keys,
+            "token",
+            &spdf_file.header,
+            &device_info,
+            &request_id,
+            &CancellationToken::new(),
+        ))
+        .unwrap();
+        let request = server.join().unwrap();
+        assert!(request.contains(&format!("x-request-id: {}", request_id)), "{}", request);
+
+        let mut counter = view_limit::ViewCounter::load(&dir.path().join("opens.json")).unwrap();
+        let mut extensions = expiry::ExtensionCache::load(&dir.path().join("expiry.json")).unwrap();
+        let (counter, extensions) = (&mut counter, &mut extensions);
+        let result = resolve_open(spdf_file, outcome, counter, extensions, dir.path(), TEST_DEVICE)
+            .with_reference(&request_id);
+        assert!(!result.success);
+        assert!(result.message.contains("403"));
+        assert!(result.message.ends_with(&format!("(reference: {})", request_id)));
+    }
+
+    #[test]
+    fn test_concurrent_opens_share_one_fetch() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let dir = tempfile::tempdir().unwrap();
+        // Answers a single request; a second fetch would be refused
+        let (server_url, server) = serve_once(
+            "HTTP/1.1 403 Forbidden\r\ncontent-length: 10\r\nconnection: close\r\n\r\nNo license",
+        );
+        let path = write_spdf(dir.path(), "DOC-SHARED", b"%PDF");
+        let mut spdf_file = spdf::SpdfFile::read(&path).unwrap();
+        spdf_file.header.server_url = server_url;
+        let device_info = auth::DeviceInfo {
+            device_id: TEST_DEVICE.to_string(),
+            device_name: "test".to_string(),
+        };
+
+        let in_flight = InFlightKeyFetches::default();
+        let keys = KeyClient::new(reqwest::Client::new());
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let open = || {
+            let (keys, header) = (keys.clone(), spdf_file.header.clone());
+            let (device_info, fetches) = (device_info.clone(), fetches.clone());
+            let fetch = async move {
+                fetches.fetch_add(1, Ordering::SeqCst);
+                // Stay in flight until the second open has joined
+                let mut yielded = false;
+                futures_util::future::poll_fn(|cx| {
+                    if std::mem::replace(&mut yielded, true) {
+                        return std::task::Poll::Ready(());
+                    }
+                    cx.waker().wake_by_ref();
+                    std::task::Poll::Pending
+                })
+                .await;
+                let cancel = CancellationToken::new();
+                fetch_key(&keys, "token", &header, &device_info, "req", &cancel).await
+            };
+            fetch_key_shared(&in_flight, "DOC-SHARED", fetch)
+        };
+
+        let (first, second) =
+            tauri::async_runtime::block_on(futures_util::future::join(open(), open()));
+        server.join().unwrap();
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        for outcome in [first, second] {
+            match outcome {
+                Ok(KeyOutcome::Denied(message)) => assert!(message.contains("403")),
+                _ => panic!("expected the shared 403"),
+            }
+        }
+        assert!(in_flight.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_key_fetch_releases_token() {
+        use std::sync::Arc;
+
+        // The open command moves its copy of the session token into the
+        // shared fetch; the copy must be gone, and so wiped, once it returns
+        let in_flight = InFlightKeyFetches::default();
+        let token = Arc::new(Zeroizing::new("secret-token".to_string()));
+        let held = Arc::downgrade(&token);
+        let fetch = async move {
+            assert_eq!(token.as_str(), "secret-token");
+            Ok(granted(&DOC_KEY))
+        };
+        let outcome =
+            tauri::async_runtime::block_on(fetch_key_shared(&in_flight, "DOC-TOKEN", fetch));
+        assert!(matches!(outcome, Ok(KeyOutcome::Granted(_))));
+        assert!(in_flight.lock().unwrap().is_empty());
+        assert!(held.upgrade().is_none());
+    }
 }
@@ -0,0 +1,763 @@
+// Offline Module - Offline viewing windows
+//
+// A document can be viewed without the server only while every constraint
+// on it holds: the license's `offline_days` budget since the key was
+// granted, the document's own `expires_at` (or the date a cached
+// server-granted extension moved it to), and how long a cached key is
+// kept. This module combines them into a single deadline for the UI, into
+// a pre-flight answer to "will this open offline right now?", and into the
+// cached key itself when the key server is out of reach.
+//
+// A successful online open records an offline grant: the key, sealed
+// under the device KEK, and a sealed record of when it was granted and
+// with what `offline_days` budget for which device. The record also
+// remembers the latest time it was checked, so setting the clock back
+// cannot reopen a window that has already closed.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use time::{Duration, OffsetDateTime};
+use zeroize::Zeroizing;
+
+use crate::decrypt::verify_key;
+use crate::device_id::device_hash_digest;
+use crate::expiry::{ExpiryExtension, ExtensionCache};
+use crate::key_source::KeySource;
+use crate::local_store::{open_cached_key, open_grant, seal_cached_key, seal_grant, LocalStore};
+use crate::policy::SecurityPolicy;
+use crate::revocation::RevocationList;
+use crate::spdf_parser::{SpdfError, SpdfFile};
+use crate::verify::parse_ed25519_public_key_pem;
+
+/// How long a cached document key stays usable
+pub const KEY_CACHE_TTL: Duration = Duration::days(30);
+
+/// How far the clock may read behind a grant's `last_seen` before it
+/// counts as set back (NTP corrections, a late-synced RTC)
+pub const CLOCK_ROLLBACK_TOLERANCE: Duration = Duration::minutes(5);
+
+/// Which constraint ends the offline window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OfflineConstraint {
+    OfflineDays,
+    Expiry,
+    CacheTtl,
+}
+
+/// The effective end of the offline window and what imposes it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OfflineLimit {
+    #[serde(with = "time::serde::rfc3339")]
+    pub until: OffsetDateTime,
+    pub constraint: OfflineConstraint,
+}
+
+/// `expires_at`, or the later date a server-granted `extension` sets
+///
+/// The extension is re-verified against the org key in `trust_store`, not
+/// the header's own key; one that does not verify, or whose org has no
+/// trusted key, is ignored and the original date applies.
+pub fn effective_expiry(
+    spdf: &SpdfFile,
+    extension: Option<&ExpiryExtension>,
+    trust_store: &KeySource,
+) -> Result<Option<OffsetDateTime>, SpdfError> {
+    let original = spdf.header.expiry()?;
+    let Some(extension) = extension else {
+        return Ok(original);
+    };
+    let Some(pem) = trust_store.org_key_pem(spdf.org_id())? else {
+        return Ok(original);
+    };
+    let Ok(org_key) = parse_ed25519_public_key_pem(&pem) else {
+        return Ok(original);
+    };
+    Ok(extension
+        .verify(spdf.doc_id(), original, &org_key)
+        .ok()
+        .or(original))
+}
+
+/// Compute the earliest of (grant_time + offline_days), `expires_at` (as
+/// extended by `extension`, see `effective_expiry`) and
+/// (grant_time + cache_ttl)
+///
+/// Returns `None` if the document cannot be viewed offline at all.
+pub fn offline_limit(
+    spdf: &SpdfFile,
+    extension: Option<&ExpiryExtension>,
+    trust_store: &KeySource,
+    grant_time: OffsetDateTime,
+    cache_ttl: Duration,
+) -> Result<Option<OfflineLimit>, SpdfError> {
+    let offline_days = spdf.header.permissions.offline_days;
+    if !spdf.allows_offline() || offline_days == 0 {
+        return Ok(None);
+    }
+
+    let mut limit = OfflineLimit {
+        until: grant_time + Duration::days(offline_days as i64),
+        constraint: OfflineConstraint::OfflineDays,
+    };
+
+    if let Some(expiry) = effective_expiry(spdf, extension, trust_store)? {
+        if expiry < limit.until {
+            limit = OfflineLimit {
+                until: expiry,
+                constraint: OfflineConstraint::Expiry,
+            };
+        }
+    }
+
+    let cache_expiry = grant_time + cache_ttl;
+    if cache_expiry < limit.until {
+        limit = OfflineLimit {
+            until: cache_expiry,
+            constraint: OfflineConstraint::CacheTtl,
+        };
+    }
+
+    Ok(Some(limit))
+}
+
+/// When a cached key was granted, and for how long it may be used offline
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OfflineGrant {
+    #[serde(with = "time::serde::rfc3339")]
+    pub granted_at: OffsetDateTime,
+    pub offline_days: u32,
+    /// Hash of the device the grant was recorded on; it is not used on any
+    /// other
+    pub device_hash: String,
+    /// Latest time the grant was checked at; the clock may not go back
+    /// past it
+    #[serde(with = "time::serde::rfc3339")]
+    pub last_seen: OffsetDateTime,
+}
+
+impl OfflineGrant {
+    fn clock_rolled_back(&self, now: OffsetDateTime) -> bool {
+        now + CLOCK_ROLLBACK_TOLERANCE < self.last_seen
+    }
+}
+
+/// Cache `doc_key` and record an offline grant for `doc_id` at `now`
+///
+/// Called after a successful online open on the device `device_hash`;
+/// replaces any earlier grant.
+pub fn record_offline_grant(
+    store: &LocalStore,
+    kek: &[u8; 32],
+    device_hash: &str,
+    doc_id: &str,
+    doc_key: &[u8; 32],
+    offline_days: u32,
+    now: OffsetDateTime,
+) -> Result<(), SpdfError> {
+    fs::create_dir_all(store.offline_dir())?;
+    let entry = seal_cached_key(kek, doc_id, doc_key)?;
+    fs::write(store.cached_key_path(doc_id)?, entry)?;
+    let grant = OfflineGrant {
+        granted_at: now,
+        offline_days,
+        device_hash: device_hash.to_string(),
+        last_seen: now,
+    };
+    save_grant(store, kek, doc_id, &grant)
+}
+
+/// The grant recorded for `doc_id`, if any; a grant that does not open
+/// with `kek` is an error
+pub fn load_grant(
+    store: &LocalStore,
+    kek: &[u8; 32],
+    doc_id: &str,
+) -> Result<Option<OfflineGrant>, SpdfError> {
+    let path = store.grant_path(doc_id)?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    let grant = open_grant(kek, doc_id, &fs::read(path)?)?;
+    Ok(Some(serde_json::from_slice(&grant)?))
+}
+
+fn save_grant(
+    store: &LocalStore,
+    kek: &[u8; 32],
+    doc_id: &str,
+    grant: &OfflineGrant,
+) -> Result<(), SpdfError> {
+    let sealed = seal_grant(kek, doc_id, &serde_json::to_vec(grant)?)?;
+    fs::write(store.grant_path(doc_id)?, sealed)?;
+    Ok(())
+}
+
+/// Whether the offline grant for a document is still usable
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum OfflineStatus {
+    /// Within the window, which ends at `until`
+    Valid {
+        #[serde(with = "time::serde::rfc3339")]
+        until: OffsetDateTime,
+    },
+    /// The window ended at `until`
+    Expired {
+        #[serde(with = "time::serde::rfc3339")]
+        until: OffsetDateTime,
+    },
+    /// Never opened online on this device
+    NoGrant,
+    /// Recorded for a different device
+    WrongDevice,
+    /// The clock reads earlier than the grant was last checked at
+    ClockRolledBack {
+        #[serde(with = "time::serde::rfc3339")]
+        last_seen: OffsetDateTime,
+    },
+}
+
+/// Check the offline grant for `doc_id` at `now`
+///
+/// The window is `offline_days` from the grant, capped by the budget
+/// recorded with it. Each check moves the grant's `last_seen` forward, and
+/// a clock set back past it is refused rather than given the time again.
+/// A grant recorded for a device other than `device_hash` is refused.
+pub fn check_offline_validity(
+    store: &LocalStore,
+    kek: &[u8; 32],
+    device_hash: &str,
+    doc_id: &str,
+    offline_days: u32,
+    now: OffsetDateTime,
+) -> Result<OfflineStatus, SpdfError> {
+    let Some(mut grant) = load_grant(store, kek, doc_id)? else {
+        return Ok(OfflineStatus::NoGrant);
+    };
+    if device_hash_digest(&grant.device_hash) != device_hash_digest(device_hash) {
+        return Ok(OfflineStatus::WrongDevice);
+    }
+    if grant.clock_rolled_back(now) {
+        return Ok(OfflineStatus::ClockRolledBack {
+            last_seen: grant.last_seen,
+        });
+    }
+    if now > grant.last_seen {
+        grant.last_seen = now;
+        save_grant(store, kek, doc_id, &grant)?;
+    }
+
+    let days = grant.offline_days.min(offline_days);
+    let until = grant.granted_at + Duration::days(days as i64);
+    if now < until {
+        Ok(OfflineStatus::Valid { until })
+    } else {
+        Ok(OfflineStatus::Expired { until })
+    }
+}
+
+/// Why a document would not open offline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OfflineBlocker {
+    /// The flags or `offline_days` forbid offline viewing
+    NotAllowed,
+    Expired,
+    /// Listed in the cached revocation list
+    Revoked,
+    /// Strict policy, and the cached revocation list is stale or missing
+    StaleRevocationList,
+    NoCachedKey,
+    /// The cached key was sealed on another device, is damaged, or does
+    /// not open this file
+    CachedKeyUnusable,
+    /// The cached key was granted to another device
+    DeviceMismatch,
+    /// More than `offline_days` have passed since the key was cached
+    OfflineDaysUsed,
+    /// The cached key is older than the cache TTL
+    CacheExpired,
+    /// The clock reads earlier than the grant was last checked at
+    ClockRolledBack,
+}
+
+impl OfflineBlocker {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            OfflineBlocker::NotAllowed => "Offline viewing is not allowed for this document",
+            OfflineBlocker::Expired => "Document has expired",
+            OfflineBlocker::Revoked => "Document has been revoked",
+            OfflineBlocker::StaleRevocationList => {
+                "Revocation data is out of date; go online to refresh it"
+            }
+            OfflineBlocker::NoCachedKey => "No key cached on this device; open it online first",
+            OfflineBlocker::CachedKeyUnusable => "Cached key cannot be used on this device",
+            OfflineBlocker::DeviceMismatch => "Cached key was granted to a different device",
+            OfflineBlocker::OfflineDaysUsed => "Offline viewing period has ended",
+            OfflineBlocker::CacheExpired => "Cached key has expired",
+            OfflineBlocker::ClockRolledBack => {
+                "System clock is earlier than when this document was last checked"
+            }
+        }
+    }
+}
+
+/// Pre-flight answer for an offline open
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OfflineReadiness {
+    pub ready: bool,
+    pub blocker: Option<OfflineBlocker>,
+    pub reason: Option<String>,
+    /// End of the offline window when `ready`
+    pub limit: Option<OfflineLimit>,
+}
+
+impl OfflineReadiness {
+    fn blocked(blocker: OfflineBlocker) -> Self {
+        OfflineReadiness {
+            ready: false,
+            blocker: Some(blocker),
+            reason: Some(blocker.reason().to_string()),
+            limit: None,
+        }
+    }
+}
+
+/// Check whether `spdf` would open offline at `now` from the key cached in
+/// `store`
+///
+/// The grant time of a cached key comes from its sealed offline grant,
+/// which must have been recorded for `device_hash`, and a cached expiry
+/// extension counts. This is a read-only check: it refuses a clock set
+/// back past the grant's `last_seen` but does not move it forward.
+/// Revocation is only as current as the cached list; a strict `policy`
+/// refuses to rely on a stale one. The cached key must also open `spdf`,
+/// which authenticates all of its content.
+pub fn offline_readiness(
+    spdf: &SpdfFile,
+    store: &LocalStore,
+    kek: &[u8; 32],
+    device_hash: &str,
+    policy: &SecurityPolicy,
+    now: OffsetDateTime,
+) -> Result<OfflineReadiness, SpdfError> {
+    if !spdf.allows_offline() || spdf.header.permissions.offline_days == 0 {
+        return Ok(OfflineReadiness::blocked(OfflineBlocker::NotAllowed));
+    }
+    let extensions = ExtensionCache::load(&store.expiry_path()).map_err(SpdfError::FormatError)?;
+    let extension = extensions.get(spdf.doc_id());
+    let trust_store = KeySource::for_keys_dir(&store.keys_dir());
+    if effective_expiry(spdf, extension, &trust_store)?.is_some_and(|expiry| expiry <= now) {
+        return Ok(OfflineReadiness::blocked(OfflineBlocker::Expired));
+    }
+    let crl = RevocationList::load_for_org(store, spdf.org_id())?;
+    if crl.is_revoked(spdf.doc_id()) {
+        return Ok(OfflineReadiness::blocked(OfflineBlocker::Revoked));
+    }
+    if policy.require_fresh_crl && crl.status(spdf.org_id(), now, policy.crl_max_age()).stale {
+        return Ok(OfflineReadiness::blocked(OfflineBlocker::StaleRevocationList));
+    }
+
+    let cache_path = store.cached_key_path(spdf.doc_id())?;
+    if !cache_path.exists() {
+        return Ok(OfflineReadiness::blocked(OfflineBlocker::NoCachedKey));
+    }
+    let opened = open_cached_key(kek, spdf.doc_id(), &fs::read(&cache_path)?);
+    if !opened.is_ok_and(|doc_key| verify_key(spdf, &doc_key).is_ok()) {
+        return Ok(OfflineReadiness::blocked(OfflineBlocker::CachedKeyUnusable));
+    }
+    let grant = match load_grant(store, kek, spdf.doc_id()) {
+        Ok(Some(grant)) => grant,
+        Ok(None) => return Ok(OfflineReadiness::blocked(OfflineBlocker::NoCachedKey)),
+        Err(_) => return Ok(OfflineReadiness::blocked(OfflineBlocker::CachedKeyUnusable)),
+    };
+    if device_hash_digest(&grant.device_hash) != device_hash_digest(device_hash) {
+        return Ok(OfflineReadiness::blocked(OfflineBlocker::DeviceMismatch));
+    }
+    if grant.clock_rolled_back(now) {
+        return Ok(OfflineReadiness::blocked(OfflineBlocker::ClockRolledBack));
+    }
+
+    let grant_time = grant.granted_at;
+    let Some(limit) = offline_limit(spdf, extension, &trust_store, grant_time, KEY_CACHE_TTL)?
+    else {
+        return Ok(OfflineReadiness::blocked(OfflineBlocker::NotAllowed));
+    };
+    if limit.until <= now {
+        let blocker = match limit.constraint {
+            OfflineConstraint::OfflineDays => OfflineBlocker::OfflineDaysUsed,
+            OfflineConstraint::Expiry => OfflineBlocker::Expired,
+            OfflineConstraint::CacheTtl => OfflineBlocker::CacheExpired,
+        };
+        return Ok(OfflineReadiness::blocked(blocker));
+    }
+
+    Ok(OfflineReadiness {
+        ready: true,
+        blocker: None,
+        reason: None,
+        limit: Some(limit),
+    })
+}
+
+/// The cached key for an offline open, or why there is none
+pub enum OfflineKey {
+    Ready(Zeroizing<[u8; 32]>),
+    Blocked(OfflineBlocker),
+}
+
+/// The key cached for `spdf`, if `offline_readiness` finds it would open
+/// offline at `now`
+///
+/// Unlike the pre-flight check this is an open, so the grant's
+/// `last_seen` moves forward to `now`.
+pub fn cached_key_for_open(
+    spdf: &SpdfFile,
+    store: &LocalStore,
+    kek: &[u8; 32],
+    device_hash: &str,
+    policy: &SecurityPolicy,
+    now: OffsetDateTime,
+) -> Result<OfflineKey, SpdfError> {
+    let readiness = offline_readiness(spdf, store, kek, device_hash, policy, now)?;
+    if let Some(blocker) = readiness.blocker {
+        return Ok(OfflineKey::Blocked(blocker));
+    }
+    let doc_id = spdf.doc_id();
+    let doc_key = open_cached_key(kek, doc_id, &fs::read(store.cached_key_path(doc_id)?)?)?;
+    if let Some(mut grant) = load_grant(store, kek, doc_id)? {
+        if now > grant.last_seen {
+            grant.last_seen = now;
+            save_grant(store, kek, doc_id, &grant)?;
+        }
+    }
+    Ok(OfflineKey::Ready(doc_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spdf_parser::FLAG_OFFLINE_ALLOWED;
+    use crate::test_support::{build_spdf_with, sample_header, DEFAULT_FLAGS};
+    use time::format_description::well_known::Rfc3339;
+
+    fn grant_time() -> OffsetDateTime {
+        OffsetDateTime::parse("2025-03-01T00:00:00Z", &Rfc3339).unwrap()
+    }
+
+    /// Offline limit for a key granted at `grant_time`, with no extension
+    fn limit_at_grant(spdf: &SpdfFile) -> Option<OfflineLimit> {
+        let no_keys = KeySource::Files { keys_dir: std::env::temp_dir().join("spdf-no-keys") };
+        offline_limit(spdf, None, &no_keys, grant_time(), KEY_CACHE_TTL).unwrap()
+    }
+
+    fn offline_file(offline_days: u32, expires_at: Option<&str>, flags: u16) -> SpdfFile {
+        let mut header = sample_header();
+        header["permissions"]["offline_days"] = serde_json::json!(offline_days);
+        if let Some(expires_at) = expires_at {
+            header["expires_at"] = serde_json::json!(expires_at);
+        }
+        SpdfFile::parse(&build_spdf_with(&header, flags, b"%PDF")).unwrap()
+    }
+
+    #[test]
+    fn test_offline_days_binding() {
+        let spdf = offline_file(7, Some("2025-12-31T00:00:00Z"), DEFAULT_FLAGS | FLAG_OFFLINE_ALLOWED);
+        let limit = limit_at_grant(&spdf).unwrap();
+        assert_eq!(limit.constraint, OfflineConstraint::OfflineDays);
+        assert_eq!(limit.until, grant_time() + Duration::days(7));
+    }
+
+    #[test]
+    fn test_expiry_binding() {
+        let spdf = offline_file(7, Some("2025-03-03T12:00:00Z"), DEFAULT_FLAGS | FLAG_OFFLINE_ALLOWED);
+        let limit = limit_at_grant(&spdf).unwrap();
+        assert_eq!(limit.constraint, OfflineConstraint::Expiry);
+        assert_eq!(limit.until, spdf.header.expiry().unwrap().unwrap());
+    }
+
+    #[test]
+    fn test_cache_ttl_binding() {
+        let spdf = offline_file(90, None, DEFAULT_FLAGS | FLAG_OFFLINE_ALLOWED);
+        let limit = limit_at_grant(&spdf).unwrap();
+        assert_eq!(limit.constraint, OfflineConstraint::CacheTtl);
+        assert_eq!(limit.until, grant_time() + KEY_CACHE_TTL);
+    }
+
+    struct CachedDoc {
+        store: LocalStore,
+        kek: zeroize::Zeroizing<[u8; 32]>,
+        _root: tempfile::TempDir,
+    }
+
+    impl CachedDoc {
+        /// Store with the key for `DOC-TEST-001` granted just now
+        fn new() -> Self {
+            let root = tempfile::tempdir().unwrap();
+            let store = LocalStore::new(root.path());
+            let kek = crate::local_store::device_kek("device-a");
+            let now = OffsetDateTime::now_utc();
+            let doc_key = &crate::test_support::DOC_KEY;
+            record_offline_grant(&store, &kek, "device-a", "DOC-TEST-001", doc_key, 7, now)
+                .unwrap();
+            CachedDoc {
+                store,
+                kek,
+                _root: root,
+            }
+        }
+
+        fn check(&self, spdf: &SpdfFile, now: OffsetDateTime) -> OfflineReadiness {
+            let policy = SecurityPolicy::default();
+            offline_readiness(spdf, &self.store, &self.kek, "device-a", &policy, now).unwrap()
+        }
+    }
+
+    fn blocker(readiness: OfflineReadiness) -> Option<OfflineBlocker> {
+        assert_eq!(readiness.ready, readiness.blocker.is_none());
+        readiness.blocker
+    }
+
+    #[test]
+    fn test_readiness_ready() {
+        let cached = CachedDoc::new();
+        let now = OffsetDateTime::now_utc();
+        let spdf = offline_file(7, None, DEFAULT_FLAGS | FLAG_OFFLINE_ALLOWED);
+
+        let readiness = cached.check(&spdf, now);
+        assert!(readiness.ready);
+        assert_eq!(readiness.limit.unwrap().constraint, OfflineConstraint::OfflineDays);
+    }
+
+    #[test]
+    fn test_cached_key_for_open() {
+        let cached = CachedDoc::new();
+        let (store, kek) = (&cached.store, &cached.kek);
+        let policy = SecurityPolicy::default();
+        let now = OffsetDateTime::now_utc() + Duration::days(1);
+        let open = |spdf: &SpdfFile, now| {
+            cached_key_for_open(spdf, store, kek, "device-a", &policy, now).unwrap()
+        };
+
+        let spdf = offline_file(7, None, DEFAULT_FLAGS | FLAG_OFFLINE_ALLOWED);
+        match open(&spdf, now) {
+            OfflineKey::Ready(doc_key) => assert_eq!(*doc_key, crate::test_support::DOC_KEY),
+            OfflineKey::Blocked(blocker) => panic!("blocked: {:?}", blocker),
+        }
+        // The open counts as a check: the clock cannot go back before it
+        let earlier = now - CLOCK_ROLLBACK_TOLERANCE - Duration::hours(1);
+        assert!(matches!(
+            open(&spdf, earlier),
+            OfflineKey::Blocked(OfflineBlocker::ClockRolledBack)
+        ));
+
+        let spdf = offline_file(7, None, DEFAULT_FLAGS);
+        assert!(matches!(open(&spdf, now), OfflineKey::Blocked(OfflineBlocker::NotAllowed)));
+    }
+
+    #[test]
+    fn test_cached_expiry_extension() {
+        use crate::expiry::extension_message;
+        use base64::{engine::general_purpose, Engine as _};
+        use ed25519_dalek::{Signer, SigningKey};
+
+        let cached = CachedDoc::new();
+        let now = OffsetDateTime::now_utc();
+        let allowed = DEFAULT_FLAGS | FLAG_OFFLINE_ALLOWED;
+        let spdf = offline_file(7, Some("2025-01-01T00:00:00Z"), allowed);
+        let extend = |key: &SigningKey| {
+            let new_expires_at = "2099-01-01T00:00:00Z";
+            let signature = key.sign(&extension_message("DOC-TEST-001", new_expires_at));
+            let extension = ExpiryExtension {
+                new_expires_at: new_expires_at.to_string(),
+                signature: general_purpose::STANDARD.encode(signature.to_bytes()),
+            };
+            let mut extensions = ExtensionCache::load(&cached.store.expiry_path()).unwrap();
+            extensions.insert("DOC-TEST-001", extension).unwrap();
+        };
+
+        // A forged entry in the cache does not count
+        extend(&SigningKey::from_bytes(&[0x12; 32]));
+        assert_eq!(blocker(cached.check(&spdf, now)), Some(OfflineBlocker::Expired));
+
+        // Nor does a real one while no org key is trusted
+        extend(&crate::test_support::signing_key());
+        assert_eq!(blocker(cached.check(&spdf, now)), Some(OfflineBlocker::Expired));
+
+        let org_key_path = cached.store.org_key_path("test_org");
+        fs::create_dir_all(org_key_path.parent().unwrap()).unwrap();
+        fs::write(&org_key_path, crate::test_support::public_key_pem()).unwrap();
+        let readiness = cached.check(&spdf, now);
+        assert!(readiness.ready);
+        assert_eq!(readiness.limit.unwrap().constraint, OfflineConstraint::OfflineDays);
+    }
+
+    #[test]
+    fn test_readiness_blockers() {
+        let cached = CachedDoc::new();
+        let now = OffsetDateTime::now_utc();
+        let allowed = DEFAULT_FLAGS | FLAG_OFFLINE_ALLOWED;
+
+        let spdf = offline_file(7, None, DEFAULT_FLAGS);
+        assert_eq!(blocker(cached.check(&spdf, now)), Some(OfflineBlocker::NotAllowed));
+
+        let spdf = offline_file(7, Some("2025-01-01T00:00:00Z"), allowed);
+        assert_eq!(blocker(cached.check(&spdf, now)), Some(OfflineBlocker::Expired));
+
+        let spdf = offline_file(7, None, allowed);
+        let later = now + Duration::days(8);
+        assert_eq!(blocker(cached.check(&spdf, later)), Some(OfflineBlocker::OfflineDaysUsed));
+
+        let spdf = offline_file(90, None, allowed);
+        let later = now + KEY_CACHE_TTL + Duration::days(1);
+        assert_eq!(blocker(cached.check(&spdf, later)), Some(OfflineBlocker::CacheExpired));
+
+        // Sealed for another device
+        let spdf = offline_file(7, None, allowed);
+        let other = crate::local_store::device_kek("device-b");
+        let policy = SecurityPolicy::default();
+        let readiness =
+            offline_readiness(&spdf, &cached.store, &other, "device-b", &policy, now).unwrap();
+        assert_eq!(blocker(readiness), Some(OfflineBlocker::CachedKeyUnusable));
+
+        // A key that does not open the file
+        let stale = CachedDoc::new();
+        let wrong_key = [0x22; 32];
+        let (store, kek) = (&stale.store, &stale.kek);
+        record_offline_grant(store, kek, "device-a", "DOC-TEST-001", &wrong_key, 7, now).unwrap();
+        assert_eq!(blocker(stale.check(&spdf, now)), Some(OfflineBlocker::CachedKeyUnusable));
+
+        let list = RevocationList {
+            fetched_at: Some(now),
+            revoked: ["DOC-TEST-001".to_string()].into_iter().collect(),
+            ..RevocationList::default()
+        };
+        let crl_path = cached.store.crl_path("test_org").unwrap();
+        list.save(&crl_path).unwrap();
+        let readiness = cached.check(&spdf, now);
+        assert_eq!(readiness.reason.as_deref(), Some("Document has been revoked"));
+        assert_eq!(blocker(readiness), Some(OfflineBlocker::Revoked));
+
+        fs::remove_file(&crl_path).unwrap();
+        fs::remove_file(cached.store.cached_key_path("DOC-TEST-001").unwrap()).unwrap();
+        assert_eq!(blocker(cached.check(&spdf, now)), Some(OfflineBlocker::NoCachedKey));
+    }
+
+    #[test]
+    fn test_offline_validity_window() {
+        let cached = CachedDoc::new();
+        let (store, kek) = (&cached.store, &cached.kek);
+        let granted = load_grant(store, kek, "DOC-TEST-001").unwrap().unwrap();
+        assert_eq!(granted.offline_days, 7);
+        let check = |offline_days, now| {
+            check_offline_validity(store, kek, "device-a", "DOC-TEST-001", offline_days, now)
+                .unwrap()
+        };
+
+        let until = granted.granted_at + Duration::days(7);
+        let now = granted.granted_at + Duration::days(2);
+        assert_eq!(check(7, now), OfflineStatus::Valid { until });
+        // The budget recorded with the grant caps a larger one
+        assert_eq!(check(30, now), OfflineStatus::Valid { until });
+        let until_3 = granted.granted_at + Duration::days(3);
+        assert_eq!(check(3, now), OfflineStatus::Valid { until: until_3 });
+        assert_eq!(check(7, until), OfflineStatus::Expired { until });
+
+        assert_eq!(
+            check_offline_validity(store, kek, "device-a", "DOC-OTHER", 7, now).unwrap(),
+            OfflineStatus::NoGrant
+        );
+        let other_kek = crate::local_store::device_kek("device-b");
+        let other = check_offline_validity(store, &other_kek, "device-b", "DOC-TEST-001", 7, now);
+        assert!(other.is_err());
+    }
+
+    #[test]
+    fn test_clock_rollback_refused() {
+        let cached = CachedDoc::new();
+        let (store, kek) = (&cached.store, &cached.kek);
+        let granted_at = load_grant(store, kek, "DOC-TEST-001").unwrap().unwrap().granted_at;
+        let check =
+            |now| check_offline_validity(store, kek, "device-a", "DOC-TEST-001", 7, now).unwrap();
+
+        // Seen after the window closed...
+        let closed = granted_at + Duration::days(8);
+        assert!(matches!(check(closed), OfflineStatus::Expired { .. }));
+        // ...so winding the clock back into the window does not reopen it
+        let rolled_back = granted_at + Duration::days(1);
+        assert_eq!(check(rolled_back), OfflineStatus::ClockRolledBack { last_seen: closed });
+        let spdf = offline_file(7, None, DEFAULT_FLAGS | FLAG_OFFLINE_ALLOWED);
+        assert_eq!(
+            blocker(cached.check(&spdf, rolled_back)),
+            Some(OfflineBlocker::ClockRolledBack)
+        );
+
+        // Small corrections are tolerated
+        let corrected = closed - Duration::minutes(1);
+        assert!(matches!(check(corrected), OfflineStatus::Expired { .. }));
+    }
+
+    #[test]
+    fn test_grant_bound_to_other_device_not_used() {
+        let cached = CachedDoc::new();
+        let (store, kek) = (&cached.store, &cached.kek);
+        let mut grant = load_grant(store, kek, "DOC-TEST-001").unwrap().unwrap();
+        // Sealed with this device's key, but recorded for another device
+        grant.device_hash = "device-b".to_string();
+        save_grant(store, kek, "DOC-TEST-001", &grant).unwrap();
+
+        let now = grant.granted_at + Duration::days(1);
+        assert_eq!(
+            check_offline_validity(store, kek, "device-a", "DOC-TEST-001", 7, now).unwrap(),
+            OfflineStatus::WrongDevice
+        );
+        let spdf = offline_file(7, None, DEFAULT_FLAGS | FLAG_OFFLINE_ALLOWED);
+        let readiness = cached.check(&spdf, now);
+        assert_eq!(
+            readiness.reason.as_deref(),
+            Some("Cached key was granted to a different device")
+        );
+        assert_eq!(blocker(readiness), Some(OfflineBlocker::DeviceMismatch));
+    }
+
+    #[test]
+    fn test_strict_policy_needs_fresh_crl() {
+        let cached = CachedDoc::new();
+        let now = OffsetDateTime::now_utc();
+        let spdf = offline_file(7, None, DEFAULT_FLAGS | FLAG_OFFLINE_ALLOWED);
+        let strict = SecurityPolicy {
+            require_fresh_crl: true,
+            ..SecurityPolicy::default()
+        };
+        let check = |policy: &SecurityPolicy| {
+            offline_readiness(&spdf, &cached.store, &cached.kek, "device-a", policy, now).unwrap()
+        };
+
+        // Never fetched: only the strict policy cares
+        assert!(check(&SecurityPolicy::default()).ready);
+        assert_eq!(blocker(check(&strict)), Some(OfflineBlocker::StaleRevocationList));
+
+        let crl_path = cached.store.crl_path("test_org").unwrap();
+        let mut list = RevocationList {
+            fetched_at: Some(now),
+            issued_at: Some(now - Duration::days(10)),
+            ..RevocationList::default()
+        };
+        list.save(&crl_path).unwrap();
+        assert_eq!(blocker(check(&strict)), Some(OfflineBlocker::StaleRevocationList));
+
+        list.issued_at = Some(now - Duration::days(1));
+        list.save(&crl_path).unwrap();
+        assert!(check(&strict).ready);
+    }
+
+    #[test]
+    fn test_offline_not_allowed() {
+        let spdf = offline_file(7, None, DEFAULT_FLAGS);
+        assert_eq!(limit_at_grant(&spdf), None);
+
+        let spdf = offline_file(0, None, DEFAULT_FLAGS | FLAG_OFFLINE_ALLOWED);
+        assert_eq!(limit_at_grant(&spdf), None);
+    }
+}
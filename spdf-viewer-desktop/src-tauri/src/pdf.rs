@@ -0,0 +1,73 @@
+// PDF Module - Lightweight inspection of decrypted PDF bytes
+//
+// No PDF parser is bundled; these helpers scan the raw bytes for the few
+// structures the viewer needs to check before handing the document to
+// the renderer.
+
+/// Number of page objects (`/Type /Page`, not `/Pages`) in a PDF
+///
+/// Returns `None` when no page object is visible, e.g. when every page
+/// lives in a compressed object stream; callers that need a count must
+/// treat that as unknown rather than zero.
+pub fn pdf_page_count(pdf: &[u8]) -> Option<u32> {
+    const TYPE: &[u8] = b"/Type";
+    const PAGE: &[u8] = b"/Page";
+
+    let mut count = 0u32;
+    let mut pos = 0;
+    while let Some(offset) = find(&pdf[pos..], TYPE) {
+        pos += offset + TYPE.len();
+
+        let value = pos + pdf[pos..]
+            .iter()
+            .take_while(|b| b.is_ascii_whitespace())
+            .count();
+        if !pdf[value..].starts_with(PAGE) {
+            continue;
+        }
+        // `/Page` must end the name: `/Pages`, `/PageLabel` etc. don't count
+        let next = pdf.get(value + PAGE.len()).copied();
+        if next.is_none_or(is_name_delimiter) {
+            count += 1;
+        }
+    }
+
+    (count > 0).then_some(count)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Whitespace or a PDF delimiter, i.e. a byte that ends a name token
+fn is_name_delimiter(byte: u8) -> bool {
+    byte.is_ascii_whitespace() || b"()<>[]{}/%".contains(&byte)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Minimal uncompressed PDF with `pages` page objects
+    pub(crate) fn pdf_with_pages(pages: u32) -> Vec<u8> {
+        let mut pdf = b"%PDF-1.4\n1 0 obj << /Type /Catalog /Pages 2 0 R >> endobj\n".to_vec();
+        pdf.extend_from_slice(
+            format!("2 0 obj << /Type /Pages /Count {} >> endobj\n", pages).as_bytes(),
+        );
+        for i in 0..pages {
+            pdf.extend_from_slice(
+                format!("{} 0 obj << /Type/Page /Parent 2 0 R >> endobj\n", i + 3).as_bytes(),
+            );
+        }
+        pdf.extend_from_slice(b"%%EOF\n");
+        pdf
+    }
+
+    #[test]
+    fn test_page_count() {
+        assert_eq!(pdf_page_count(&pdf_with_pages(3)), Some(3));
+        assert_eq!(pdf_page_count(&pdf_with_pages(1)), Some(1));
+        assert_eq!(pdf_page_count(b"%PDF-1.4 /Type /Pages /Count 4"), None);
+        assert_eq!(pdf_page_count(b"/Type /PageLabel /Type\n/Page>>"), Some(1));
+    }
+}
@@ -0,0 +1,96 @@
+// Platform Module - The OS's own identifier for this machine
+//
+// Both the device hash (`device_id`) and the per-install device id sent at
+// login (`auth`) start from the machine identifier. Reading it in one place
+// keeps the two tied to the same identifier on every platform, whichever
+// entry point runs.
+
+#[cfg(target_os = "windows")]
+use winreg::enums::HKEY_LOCAL_MACHINE;
+#[cfg(target_os = "windows")]
+use winreg::RegKey;
+
+/// The OS's stable identifier for this machine
+///
+/// * Windows: the registry MachineGuid, falling back to the SMBIOS UUID
+///   from PowerShell `Get-CimInstance` (`wmic` is gone from Windows 11)
+/// * Linux: `/etc/machine-id`, falling back to `/var/lib/dbus/machine-id`
+/// * macOS: the IOPlatformUUID from `ioreg`
+///
+/// Other platforms get a hash of the hostname.
+#[cfg(target_os = "windows")]
+pub fn stable_machine_id() -> Result<String, String> {
+    use std::process::Command;
+
+    let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
+    if let Ok(key) = hklm.open_subkey("SOFTWARE\\Microsoft\\Cryptography") {
+        if let Ok(guid) = key.get_value::<String, _>("MachineGuid") {
+            return Ok(guid);
+        }
+    }
+
+    let output = Command::new("powershell")
+        .args([
+            "-NoProfile",
+            "-NonInteractive",
+            "-Command",
+            "(Get-CimInstance -ClassName Win32_ComputerSystemProduct).UUID",
+        ])
+        .output()
+        .map_err(|e| format!("Could not run PowerShell: {}", e))?;
+    non_empty(String::from_utf8_lossy(&output.stdout).trim())
+        .ok_or_else(|| "Could not get Windows machine ID".to_string())
+}
+
+#[cfg(target_os = "linux")]
+pub fn stable_machine_id() -> Result<String, String> {
+    ["/etc/machine-id", "/var/lib/dbus/machine-id"]
+        .iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .find_map(|id| non_empty(id.trim()))
+        .ok_or_else(|| "Could not get Linux machine ID".to_string())
+}
+
+#[cfg(target_os = "macos")]
+pub fn stable_machine_id() -> Result<String, String> {
+    use std::process::Command;
+
+    let output = Command::new("ioreg")
+        .args(["-rd1", "-c", "IOPlatformExpertDevice"])
+        .output()
+        .map_err(|e| format!("Could not run ioreg: {}", e))?;
+    // "IOPlatformUUID" = "XXXXXXXX-XXXX-..."
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| line.contains("IOPlatformUUID"))
+        .find_map(|line| line.split('"').nth(3).and_then(non_empty))
+        .ok_or_else(|| "Could not get macOS hardware UUID".to_string())
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
+pub fn stable_machine_id() -> Result<String, String> {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    sysinfo::System::host_name().hash(&mut hasher);
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+#[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
+fn non_empty(id: &str) -> Option<String> {
+    (!id.is_empty()).then(|| id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stable_machine_id() {
+        let id = stable_machine_id().unwrap();
+        assert!(!id.is_empty());
+        assert_eq!(id, id.trim());
+        assert_eq!(stable_machine_id().unwrap(), id);
+    }
+}
@@ -0,0 +1,513 @@
+// Policy Module - Local security policy for the viewer
+//
+// Deployment-wide settings that decide how the viewer reacts when a
+// document's own configuration is incomplete. Loaded from
+// ~/.spdf/policy.json; a missing file means the defaults below.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::{Duration, OffsetDateTime};
+
+use crate::spdf_parser::{SpdfError, SpdfFile, SpdfHeader};
+
+/// What to do when a document demands a watermark but its template
+/// resolves to nothing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmptyWatermarkAction {
+    /// Render the policy's default template instead
+    ApplyDefault,
+    /// Render nothing but report a warning to the UI
+    Warn,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SecurityPolicy {
+    /// Template used for `EmptyWatermarkAction::ApplyDefault`
+    pub default_watermark_template: String,
+    pub empty_watermark: EmptyWatermarkAction,
+    /// Age in days after which a cached revocation list counts as stale
+    pub crl_max_age_days: u32,
+    /// Strict mode: refuse offline opens while the document's revocation
+    /// list is stale (or was never fetched)
+    pub require_fresh_crl: bool,
+    /// Only keep keys for offline use when the key server bound the grant
+    /// to this device; shared grants still open online
+    pub require_device_binding: bool,
+    /// Cap, in MiB, on the memory one open may need; 0 leaves only the
+    /// memory the OS reports available
+    pub max_open_memory_mb: u64,
+    /// Key server host -> PEM certificate its TLS chain must end at; the
+    /// system roots are not trusted for these hosts
+    pub pinned_certs: BTreeMap<String, String>,
+}
+
+impl Default for SecurityPolicy {
+    fn default() -> Self {
+        SecurityPolicy {
+            default_watermark_template: "{{user_email}}".to_string(),
+            empty_watermark: EmptyWatermarkAction::ApplyDefault,
+            crl_max_age_days: 7,
+            require_fresh_crl: false,
+            require_device_binding: false,
+            max_open_memory_mb: 0,
+            pinned_certs: BTreeMap::new(),
+        }
+    }
+}
+
+impl SecurityPolicy {
+    /// Default policy file, `~/.spdf/policy.json`
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::home_dir().map(|home| home.join(".spdf").join("policy.json"))
+    }
+
+    /// Load a policy file; a missing file yields the default policy
+    pub fn load(path: &Path) -> Result<Self, SpdfError> {
+        if !path.exists() {
+            return Ok(SecurityPolicy::default());
+        }
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    /// Freshness window for cached revocation lists
+    pub fn crl_max_age(&self) -> Duration {
+        Duration::days(self.crl_max_age_days as i64)
+    }
+
+    /// Most bytes one open may need: `available` (when the OS reports it),
+    /// capped by `max_open_memory_mb`; `None` when there is no limit
+    pub fn open_memory_limit(&self, available: Option<u64>) -> Option<u64> {
+        let cap = (self.max_open_memory_mb > 0).then_some(self.max_open_memory_mb << 20);
+        match (available, cap) {
+            (Some(available), Some(cap)) => Some(available.min(cap)),
+            (available, cap) => available.or(cap),
+        }
+    }
+}
+
+/// Values substituted into watermark templates
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WatermarkContext {
+    pub user_email: String,
+    pub device_id: String,
+    pub doc_id: String,
+    pub org_id: String,
+    /// Time of the open, as it should appear on the page
+    pub timestamp: String,
+}
+
+impl WatermarkContext {
+    /// Context for `user_email` opening the document `header` describes on
+    /// `device_id` at `now`; the timestamp is RFC 3339 to the second
+    pub fn for_open(
+        header: &SpdfHeader,
+        user_email: String,
+        device_id: String,
+        now: OffsetDateTime,
+    ) -> Self {
+        let now = now.replace_nanosecond(0).unwrap_or(now);
+        WatermarkContext {
+            user_email,
+            device_id,
+            doc_id: header.doc_id.clone(),
+            org_id: header.org_id.clone(),
+            timestamp: now.format(&Rfc3339).unwrap_or_default(),
+        }
+    }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        match name {
+            "user_email" | "user_id" => Some(&self.user_email),
+            "device_id" => Some(&self.device_id),
+            "doc_id" => Some(&self.doc_id),
+            "org_id" => Some(&self.org_id),
+            "timestamp" => Some(&self.timestamp),
+            _ => None,
+        }
+    }
+}
+
+/// Watermark the viewer should render for a document
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EffectiveWatermark {
+    pub text: String,
+    pub image_base64: Option<String>,
+    /// Set when the document's own watermark was unusable
+    pub warning: Option<String>,
+}
+
+/// Substitute `{{user_email}}` (alias `{{user_id}}`), `{{device_id}}`,
+/// `{{doc_id}}`, `{{org_id}}` and `{{timestamp}}` in one pass
+///
+/// Unknown placeholders are left as written, and `\{\{` renders as a
+/// literal `{{`. Substituted values are not themselves expanded.
+pub fn render_watermark(template: &str, context: &WatermarkContext) -> String {
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '\\']) {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("\\{\\{") {
+            text.push_str("{{");
+            rest = after;
+        } else if let Some((name, after)) = rest
+            .strip_prefix("{{")
+            .and_then(|inner| inner.split_once("}}"))
+            .filter(|(name, _)| !name.contains('{'))
+        {
+            match context.value(name) {
+                Some(value) => text.push_str(value),
+                None => text.push_str(&rest[..name.len() + 4]),
+            }
+            rest = after;
+        } else {
+            text.push_str(&rest[..1]);
+            rest = &rest[1..];
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+/// A watermark template that passed `validate_watermark_template`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NormalizedTemplate {
+    /// Placeholder names in order of first use, without repeats
+    pub placeholders: Vec<String>,
+    /// The template with whitespace inside placeholders trimmed, so
+    /// `{{ user_email }}` becomes `{{user_email}}`
+    pub normalized: String,
+}
+
+/// Problem found in a watermark template; `position` is the byte offset
+/// of the offending braces
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TemplateError {
+    /// `{{` with no matching `}}`
+    Unclosed { position: usize },
+    /// `}}` with no `{{` before it
+    UnmatchedClose { position: usize },
+    /// `{{}}` or a placeholder of only whitespace
+    Empty { position: usize },
+    /// Braces inside a placeholder, as in `{{a{{b}}}}`
+    Nested { position: usize },
+}
+
+impl std::fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemplateError::Unclosed { position } => {
+                write!(f, "unclosed placeholder at {}", position)
+            }
+            TemplateError::UnmatchedClose { position } => {
+                write!(f, "unmatched '}}}}' at {}", position)
+            }
+            TemplateError::Empty { position } => write!(f, "empty placeholder at {}", position),
+            TemplateError::Nested { position } => {
+                write!(f, "braces inside placeholder at {}", position)
+            }
+        }
+    }
+}
+
+/// Check that a watermark template's `{{ }}` are balanced and every
+/// placeholder is a non-empty name, and normalize it
+///
+/// Escaped `\{\{` (with the `}}` after it) is literal text, as in
+/// `render_watermark`. Unknown names are not errors; they render as
+/// written.
+pub fn validate_watermark_template(
+    template: &str,
+) -> Result<NormalizedTemplate, Vec<TemplateError>> {
+    let mut placeholders: Vec<String> = Vec::new();
+    let mut normalized = String::with_capacity(template.len());
+    let mut errors = Vec::new();
+    let mut pos = 0;
+    while let Some(offset) = template[pos..].find(['{', '}', '\\']) {
+        let start = pos + offset;
+        normalized.push_str(&template[pos..start]);
+        let rest = &template[start..];
+        if let Some(after) = rest.strip_prefix("\\{\\{") {
+            // Literal text through the `}}` that closes it, if any
+            let end = match (after.find("}}"), after.find('{')) {
+                (Some(close), open) if open.is_none_or(|open| close < open) => close + 2,
+                _ => 0,
+            };
+            normalized.push_str(&rest[..4 + end]);
+            pos = start + 4 + end;
+        } else if rest.starts_with("{{") {
+            let Some((end, nested)) = placeholder_end(template, start) else {
+                errors.push(TemplateError::Unclosed { position: start });
+                pos = start;
+                break;
+            };
+            let name = template[start + 2..end - 2].trim();
+            if nested || name.contains(['{', '}']) {
+                errors.push(TemplateError::Nested { position: start });
+                normalized.push_str(&template[start..end]);
+            } else if name.is_empty() {
+                errors.push(TemplateError::Empty { position: start });
+            } else {
+                if !placeholders.iter().any(|known| known == name) {
+                    placeholders.push(name.to_string());
+                }
+                normalized.push_str(&format!("{{{{{}}}}}", name));
+            }
+            pos = end;
+        } else if rest.starts_with("}}") {
+            errors.push(TemplateError::UnmatchedClose { position: start });
+            pos = start + 2;
+        } else {
+            // A lone brace or backslash; all three are one byte
+            normalized.push_str(&rest[..1]);
+            pos = start + 1;
+        }
+    }
+    normalized.push_str(&template[pos..]);
+
+    if errors.is_empty() {
+        Ok(NormalizedTemplate {
+            placeholders,
+            normalized,
+        })
+    } else {
+        Err(errors)
+    }
+}
+
+/// Byte offset just past the `}}` closing the placeholder opened at
+/// `start`, and whether another `{{` opened inside it; `None` if the
+/// placeholder never closes
+fn placeholder_end(template: &str, start: usize) -> Option<(usize, bool)> {
+    let bytes = template.as_bytes();
+    let (mut depth, mut nested) = (0, false);
+    let mut i = start;
+    while i + 1 < bytes.len() {
+        match &bytes[i..i + 2] {
+            b"{{" => {
+                depth += 1;
+                nested |= depth > 1;
+                i += 2;
+            }
+            b"}}" => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return Some((i, nested));
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// Resolve the watermark for a document under a policy
+///
+/// Returns `None` when the document does not require a watermark. A
+/// required watermark whose text resolves to whitespace (and that has no
+/// image) is never silently dropped: the policy either substitutes its
+/// default template or attaches a warning.
+pub fn effective_watermark(
+    spdf: &SpdfFile,
+    context: &WatermarkContext,
+    policy: &SecurityPolicy,
+) -> Option<EffectiveWatermark> {
+    if !spdf.has_watermark() {
+        return None;
+    }
+
+    let watermark = &spdf.header.watermark;
+    let text = match validate_watermark_template(&watermark.text) {
+        Ok(template) => render_watermark(&template.normalized, context),
+        Err(_) => render_watermark(&watermark.text, context),
+    };
+    if !text.trim().is_empty() || watermark.image_base64.is_some() {
+        return Some(EffectiveWatermark {
+            text,
+            image_base64: watermark.image_base64.clone(),
+            warning: None,
+        });
+    }
+
+    Some(match policy.empty_watermark {
+        EmptyWatermarkAction::ApplyDefault => {
+            let text = render_watermark(&policy.default_watermark_template, context);
+            let warning = if text.trim().is_empty() {
+                "Watermark required but both the document and policy templates are empty"
+            } else {
+                "Watermark template is empty; applied policy default"
+            };
+            EffectiveWatermark {
+                text,
+                image_base64: None,
+                warning: Some(warning.to_string()),
+            }
+        }
+        EmptyWatermarkAction::Warn => EffectiveWatermark {
+            text: String::new(),
+            image_base64: None,
+            warning: Some("Watermark required but template is empty".to_string()),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spdf_parser::FLAG_WATERMARK_ENABLED;
+    use crate::test_support::{build_spdf_with, sample_header, DEFAULT_FLAGS};
+
+    fn context() -> WatermarkContext {
+        WatermarkContext {
+            user_email: "alice@example.com".to_string(),
+            device_id: "DEVICE-1".to_string(),
+            doc_id: "DOC-1".to_string(),
+            org_id: "acme".to_string(),
+            timestamp: "2025-03-01T12:00:00Z".to_string(),
+        }
+    }
+
+    fn file_with_template(template: &str, flags: u16) -> SpdfFile {
+        let mut header = sample_header();
+        header["watermark"]["text"] = serde_json::json!(template);
+        SpdfFile::parse(&build_spdf_with(&header, flags, b"%PDF")).unwrap()
+    }
+
+    #[test]
+    fn test_template_resolved() {
+        let spdf = file_with_template("{{user_email}} | {{device_id}}", DEFAULT_FLAGS);
+        let watermark = effective_watermark(&spdf, &context(), &SecurityPolicy::default()).unwrap();
+        assert_eq!(watermark.text, "alice@example.com | DEVICE-1");
+        assert_eq!(watermark.warning, None);
+    }
+
+    #[test]
+    fn test_render_watermark() {
+        let render = |template| render_watermark(template, &context());
+        assert_eq!(
+            render("{{org_id}}/{{doc_id}} {{user_id}} @ {{timestamp}}"),
+            "acme/DOC-1 alice@example.com @ 2025-03-01T12:00:00Z"
+        );
+
+        // Unknown, unterminated and escaped placeholders stay literal
+        assert_eq!(render("{{page}} {{device_id}}"), "{{page}} DEVICE-1");
+        assert_eq!(render("{{device_id"), "{{device_id");
+        assert_eq!(render("{{{device_id}}"), "{DEVICE-1");
+        assert_eq!(render(r"\{\{device_id}} {{device_id}}"), "{{device_id}} DEVICE-1");
+        assert_eq!(render(r"C:\spdf {x}"), r"C:\spdf {x}");
+
+        // Values are not expanded again
+        let context = WatermarkContext {
+            user_email: "{{device_id}}".to_string(),
+            ..context()
+        };
+        assert_eq!(render_watermark("{{user_email}}", &context), "{{device_id}}");
+
+        let spdf = file_with_template("", DEFAULT_FLAGS);
+        let now = OffsetDateTime::parse("2025-03-01T12:00:00.25Z", &Rfc3339).unwrap();
+        let context = WatermarkContext::for_open(&spdf.header, String::new(), String::new(), now);
+        assert_eq!(context.doc_id, spdf.header.doc_id);
+        assert_eq!(context.timestamp, "2025-03-01T12:00:00Z");
+    }
+
+    #[test]
+    fn test_validate_watermark_template() {
+        let template = validate_watermark_template(
+            r"{{ user_email }} / {{doc_id}} \{\{literal}} {{user_email}} {x}",
+        )
+        .unwrap();
+        assert_eq!(template.placeholders, ["user_email", "doc_id"]);
+        assert_eq!(
+            template.normalized,
+            r"{{user_email}} / {{doc_id}} \{\{literal}} {{user_email}} {x}"
+        );
+        assert_eq!(
+            render_watermark(&template.normalized, &context()),
+            "alice@example.com / DOC-1 {{literal}} alice@example.com {x}"
+        );
+
+        // Normalized templates are what gets rendered
+        let spdf = file_with_template("{{ device_id }}", DEFAULT_FLAGS);
+        let watermark = effective_watermark(&spdf, &context(), &SecurityPolicy::default()).unwrap();
+        assert_eq!(watermark.text, "DEVICE-1");
+    }
+
+    #[test]
+    fn test_invalid_watermark_templates() {
+        use TemplateError::*;
+        let errors = |template| validate_watermark_template(template).unwrap_err();
+
+        assert_eq!(errors("Copy for {{user_email"), [Unclosed { position: 9 }]);
+        assert_eq!(errors("{{device_id}} {{"), [Unclosed { position: 14 }]);
+        assert_eq!(errors("a {{}} b {{  }}"), [Empty { position: 2 }, Empty { position: 9 }]);
+        assert_eq!(errors("{{a{{b}}}}"), [Nested { position: 0 }]);
+        assert_eq!(errors("{{{device_id}}"), [Nested { position: 0 }]);
+        assert_eq!(errors("x}} {{doc_id}}"), [UnmatchedClose { position: 1 }]);
+        assert_eq!(errors("{{}}").first().unwrap().to_string(), "empty placeholder at 0");
+    }
+
+    #[test]
+    fn test_empty_template_applies_default() {
+        // Placeholders only, and nothing to fill them with
+        let spdf = file_with_template("{{device_id}}", DEFAULT_FLAGS);
+        let context = WatermarkContext {
+            device_id: String::new(),
+            ..context()
+        };
+
+        let watermark = effective_watermark(&spdf, &context, &SecurityPolicy::default()).unwrap();
+        assert_eq!(watermark.text, "alice@example.com");
+        assert!(watermark.warning.is_some());
+    }
+
+    #[test]
+    fn test_empty_template_warns() {
+        let spdf = file_with_template("  ", DEFAULT_FLAGS);
+        let policy = SecurityPolicy {
+            empty_watermark: EmptyWatermarkAction::Warn,
+            ..SecurityPolicy::default()
+        };
+
+        let watermark = effective_watermark(&spdf, &context(), &policy).unwrap();
+        assert!(watermark.text.is_empty());
+        assert!(watermark.warning.is_some());
+    }
+
+    #[test]
+    fn test_no_watermark_flag() {
+        let spdf = file_with_template("", DEFAULT_FLAGS & !FLAG_WATERMARK_ENABLED);
+        assert_eq!(effective_watermark(&spdf, &context(), &SecurityPolicy::default()), None);
+    }
+
+    #[test]
+    fn test_policy_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("policy.json");
+        assert_eq!(SecurityPolicy::load(&path).unwrap(), SecurityPolicy::default());
+
+        fs::write(&path, br#"{"empty_watermark": "warn"}"#).unwrap();
+        let policy = SecurityPolicy::load(&path).unwrap();
+        assert_eq!(policy.empty_watermark, EmptyWatermarkAction::Warn);
+        assert_eq!(policy.default_watermark_template, "{{user_email}}");
+        assert_eq!(policy.open_memory_limit(None), None);
+        assert_eq!(policy.open_memory_limit(Some(4096)), Some(4096));
+
+        fs::write(&path, br#"{"max_open_memory_mb": 2}"#).unwrap();
+        let policy = SecurityPolicy::load(&path).unwrap();
+        assert_eq!(policy.open_memory_limit(None), Some(2 << 20));
+        assert_eq!(policy.open_memory_limit(Some(4096)), Some(4096));
+        assert_eq!(policy.open_memory_limit(Some(8 << 20)), Some(2 << 20));
+
+        fs::write(&path, br#"{"pinned_certs": {"keys.example.com": "PEM"}}"#).unwrap();
+        let policy = SecurityPolicy::load(&path).unwrap();
+        assert_eq!(policy.pinned_certs["keys.example.com"], "PEM");
+    }
+}
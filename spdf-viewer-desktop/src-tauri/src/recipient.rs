@@ -0,0 +1,306 @@
+// Recipient Module - Exports bound to a recipient's X25519 key
+//
+// An export re-encrypts a document under a fresh key and wraps that key to
+// a single recipient, so the copy opens with the recipient's private key
+// instead of a key-server grant. The "X25519-HKDF" wrap is:
+// - an ephemeral X25519 key pair, agreed with the recipient's public key
+// - KEK = HKDF-SHA256(shared secret, salt = ephemeral || recipient public)
+// - wrapped_key = ephemeral public key || AES-256-GCM(KEK, zero nonce, k_doc)
+// Each KEK wraps exactly one key, so the fixed nonce is never reused.
+//
+// The org's signing key never reaches the viewer, so exports are signed
+// with a throwaway Ed25519 key whose public half replaces `public_key`.
+// That shows the copy was not altered after export, not that the org
+// issued it.
+
+use aes_gcm::{
+    aead::{rand_core::RngCore, Aead, KeyInit, OsRng},
+    Aes256Gcm, Nonce,
+};
+use base64::{engine::general_purpose, Engine as _};
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use ed25519_dalek::SigningKey;
+use hkdf::Hkdf;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::decrypt::decrypt_content;
+use crate::encrypt::{deflate_content, encrypt_content_with_alg, frame_signed_v1};
+use crate::spdf_parser::{SpdfError, SpdfFile, NONCE_LENGTH, RECIPIENT_WRAP_SCHEME};
+use crate::verify::ed25519_public_key_pem;
+
+/// DER prefix of an X25519 SubjectPublicKeyInfo
+const X25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x6e, 0x03, 0x21, 0x00,
+];
+const KEK_INFO: &[u8] = b"spdf recipient key wrap v1";
+
+/// Parse an X25519 public key from SubjectPublicKeyInfo PEM
+pub fn parse_x25519_public_key_pem(pem: &str) -> Result<[u8; 32], SpdfError> {
+    let body: String = pem
+        .replace("-----BEGIN PUBLIC KEY-----", "")
+        .replace("-----END PUBLIC KEY-----", "")
+        .split_whitespace()
+        .collect();
+    let der = general_purpose::STANDARD
+        .decode(body)
+        .map_err(|e| SpdfError::FormatError(format!("Invalid recipient key base64: {}", e)))?;
+
+    match der.strip_prefix(&X25519_SPKI_PREFIX[..]) {
+        Some(key) if key.len() == 32 => Ok(key.try_into().expect("length checked")),
+        _ => Err(SpdfError::FormatError(
+            "recipient key is not an X25519 public key".to_string(),
+        )),
+    }
+}
+
+/// PEM-encode an X25519 public key
+pub fn x25519_public_key_pem(key: &[u8; 32]) -> String {
+    let mut der = X25519_SPKI_PREFIX.to_vec();
+    der.extend_from_slice(key);
+    format!(
+        "-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----\n",
+        general_purpose::STANDARD.encode(der)
+    )
+}
+
+/// X25519 public key for a private key
+pub fn x25519_public_key(secret: &[u8; 32]) -> [u8; 32] {
+    MontgomeryPoint::mul_base_clamped(*secret).to_bytes()
+}
+
+/// Derive the key-encryption key from an agreed secret
+///
+/// An all-zero secret means the peer key was a low-order point, which
+/// would make the KEK predictable.
+fn derive_kek(
+    shared: MontgomeryPoint,
+    ephemeral_public: &[u8; 32],
+    recipient_public: &[u8; 32],
+) -> Result<Zeroizing<[u8; 32]>, SpdfError> {
+    let shared = Zeroizing::new(shared.to_bytes());
+    if shared.iter().all(|&b| b == 0) {
+        return Err(SpdfError::FormatError(
+            "recipient key is a low-order point".to_string(),
+        ));
+    }
+
+    let mut salt = [0u8; 64];
+    salt[..32].copy_from_slice(ephemeral_public);
+    salt[32..].copy_from_slice(recipient_public);
+
+    let mut kek = Zeroizing::new([0u8; 32]);
+    Hkdf::<Sha256>::new(Some(&salt), shared.as_slice())
+        .expand(KEK_INFO, kek.as_mut_slice())
+        .map_err(|e| SpdfError::EncryptionError(format!("KEK derivation failed: {}", e)))?;
+    Ok(kek)
+}
+
+/// Wrap `doc_key` so only the holder of `recipient_public`'s private key
+/// can recover it
+pub fn wrap_for_recipient(
+    doc_key: &[u8; 32],
+    recipient_public: &[u8; 32],
+) -> Result<Vec<u8>, SpdfError> {
+    let mut ephemeral_secret = Zeroizing::new([0u8; 32]);
+    OsRng.fill_bytes(ephemeral_secret.as_mut_slice());
+    let ephemeral_public = x25519_public_key(&ephemeral_secret);
+
+    let shared = MontgomeryPoint(*recipient_public).mul_clamped(*ephemeral_secret);
+    let kek = derive_kek(shared, &ephemeral_public, recipient_public)?;
+
+    let sealed = Aes256Gcm::new(kek.as_slice().into())
+        .encrypt(Nonce::from_slice(&[0u8; NONCE_LENGTH]), doc_key.as_slice())
+        .map_err(|e| SpdfError::EncryptionError(format!("Key wrap failed: {}", e)))?;
+
+    let mut wrapped = ephemeral_public.to_vec();
+    wrapped.extend_from_slice(&sealed);
+    Ok(wrapped)
+}
+
+/// Recover a document key wrapped with `wrap_for_recipient`
+pub fn unwrap_for_recipient(
+    wrapped: &[u8],
+    recipient_secret: &[u8; 32],
+) -> Result<Zeroizing<[u8; 32]>, SpdfError> {
+    let (ephemeral_public, sealed) = wrapped
+        .split_first_chunk::<32>()
+        .ok_or_else(|| SpdfError::DecryptionError("Wrapped key too short".to_string()))?;
+
+    let shared = MontgomeryPoint(*ephemeral_public).mul_clamped(*recipient_secret);
+    let kek = derive_kek(
+        shared,
+        ephemeral_public,
+        &x25519_public_key(recipient_secret),
+    )?;
+
+    let doc_key = Zeroizing::new(
+        Aes256Gcm::new(kek.as_slice().into())
+            .decrypt(Nonce::from_slice(&[0u8; NONCE_LENGTH]), sealed)
+            .map_err(|_| {
+                SpdfError::DecryptionError("Key unwrap failed: wrong recipient key".to_string())
+            })?,
+    );
+    let mut key = Zeroizing::new([0u8; 32]);
+    key.copy_from_slice(&doc_key);
+    Ok(key)
+}
+
+/// Re-encrypt a decryptable document for one recipient, returning the
+/// bytes of the new file
+///
+/// Refused with a license error unless the document allows copying. The
+/// export is always a version 1 file; segmented documents are re-encrypted
+/// as a single blob. A compressed document stays compressed.
+pub fn export_for_recipient(
+    spdf: &SpdfFile,
+    doc_key: &[u8; 32],
+    recipient_public_key_pem: &str,
+) -> Result<Vec<u8>, SpdfError> {
+    if !spdf.header.permissions.allow_copy || !spdf.allows_copy() {
+        return Err(SpdfError::LicenseError(
+            "copying is not allowed for this document".to_string(),
+        ));
+    }
+    let recipient_public = parse_x25519_public_key_pem(recipient_public_key_pem)?;
+
+    // `decrypt_content` inflates, so deflate again for the same flags
+    let mut plaintext = Zeroizing::new(decrypt_content(spdf, doc_key)?);
+    if spdf.is_compressed() {
+        plaintext = deflate_content(&plaintext)?;
+    }
+    let mut export_key = Zeroizing::new([0u8; 32]);
+    OsRng.fill_bytes(export_key.as_mut_slice());
+    let encrypted = encrypt_content_with_alg(
+        spdf.header.enc_alg(),
+        &plaintext,
+        &export_key,
+    )?;
+    let wrapped_key = wrap_for_recipient(&export_key, &recipient_public)?;
+
+    let mut seed = Zeroizing::new([0u8; 32]);
+    OsRng.fill_bytes(seed.as_mut_slice());
+    let signing_key = SigningKey::from_bytes(&seed);
+
+    let mut header = spdf.header.clone();
+    header.wrap_scheme = Some(RECIPIENT_WRAP_SCHEME.to_string());
+    header.public_key = ed25519_public_key_pem(signing_key.verifying_key().as_bytes());
+    header.key_id = None;
+    header.timestamp_token = None;
+    if spdf.is_segmented() {
+        header.spdf_version = "1.0".to_string();
+        header.segments.clear();
+    }
+
+    frame_signed_v1(
+        spdf.flags,
+        &header,
+        spdf.header_encoding(),
+        &wrapped_key,
+        &encrypted,
+        &signing_key,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spdf_parser::{FLAG_COPY_ALLOWED, FLAG_PRINT_ALLOWED};
+    use crate::test_support::{build_spdf, build_spdf_with, sample_header, DEFAULT_FLAGS, DOC_KEY};
+    use crate::verify::verify_signature;
+
+    const RECIPIENT_SECRET: [u8; 32] = [0x42; 32];
+
+    fn copyable_spdf(plaintext: &[u8]) -> SpdfFile {
+        let mut header = sample_header();
+        header["permissions"]["allow_copy"] = serde_json::json!(true);
+        let flags = DEFAULT_FLAGS | FLAG_COPY_ALLOWED | FLAG_PRINT_ALLOWED;
+        SpdfFile::parse(&build_spdf_with(&header, flags, plaintext)).unwrap()
+    }
+
+    #[test]
+    fn test_export_round_trip() {
+        let plaintext = b"%PDF-1.7 shared with one recipient";
+        let source = copyable_spdf(plaintext);
+        let recipient_pem = x25519_public_key_pem(&x25519_public_key(&RECIPIENT_SECRET));
+
+        let exported =
+            SpdfFile::parse(&export_for_recipient(&source, &DOC_KEY, &recipient_pem).unwrap())
+                .unwrap();
+        assert_eq!(exported.header.wrap_scheme(), RECIPIENT_WRAP_SCHEME);
+        assert_eq!(exported.header.doc_id, source.header.doc_id);
+        assert_eq!(exported.flags, source.flags);
+        assert!(verify_signature(&exported).is_ok());
+
+        let export_key = unwrap_for_recipient(&exported.wrapped_key, &RECIPIENT_SECRET).unwrap();
+        assert_ne!(*export_key, DOC_KEY);
+        assert_eq!(decrypt_content(&exported, &export_key).unwrap(), plaintext);
+
+        // Anyone else's key fails to unwrap
+        assert!(matches!(
+            unwrap_for_recipient(&exported.wrapped_key, &[0x43; 32]),
+            Err(SpdfError::DecryptionError(_))
+        ));
+    }
+
+    #[test]
+    fn test_export_keeps_compression() {
+        use crate::spdf_parser::{WriteOptions, FLAG_COMPRESSED, WRAPPED_KEY_LENGTH};
+        use crate::test_support::signing_key;
+
+        let mut header = sample_header();
+        header["permissions"]["allow_copy"] = serde_json::json!(true);
+        let header = serde_json::from_value(header).unwrap();
+        let plaintext = b"%PDF-1.7 ".repeat(4096);
+        let source = SpdfFile::create(&WriteOptions {
+            header: &header,
+            flags: DEFAULT_FLAGS | FLAG_COPY_ALLOWED | FLAG_COMPRESSED,
+            plaintext: &plaintext,
+            doc_key: &DOC_KEY,
+            wrapped_key: &[0xAA; WRAPPED_KEY_LENGTH],
+            signing_key: &signing_key(),
+        })
+        .unwrap();
+        let recipient_pem = x25519_public_key_pem(&x25519_public_key(&RECIPIENT_SECRET));
+
+        let exported =
+            SpdfFile::parse(&export_for_recipient(&source, &DOC_KEY, &recipient_pem).unwrap())
+                .unwrap();
+        assert!(exported.is_compressed());
+        assert!(exported.ciphertext.len() < plaintext.len() / 10);
+        let export_key = unwrap_for_recipient(&exported.wrapped_key, &RECIPIENT_SECRET).unwrap();
+        assert_eq!(decrypt_content(&exported, &export_key).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn test_export_refused_without_copy_permission() {
+        let recipient_pem = x25519_public_key_pem(&x25519_public_key(&RECIPIENT_SECRET));
+        let source = SpdfFile::parse(&build_spdf(b"%PDF-1.7")).unwrap();
+        assert!(!source.header.permissions.allow_copy);
+
+        match export_for_recipient(&source, &DOC_KEY, &recipient_pem) {
+            Err(SpdfError::LicenseError(msg)) => {
+                assert_eq!(msg, "copying is not allowed for this document")
+            }
+            other => panic!("expected license error, got {:?}", other.map(|d| d.len())),
+        }
+    }
+
+    #[test]
+    fn test_rejects_non_x25519_and_low_order_keys() {
+        let source = copyable_spdf(b"%PDF-1.7");
+        let ed25519_pem = crate::test_support::public_key_pem();
+        assert!(matches!(
+            export_for_recipient(&source, &DOC_KEY, &ed25519_pem),
+            Err(SpdfError::FormatError(_))
+        ));
+
+        let identity_pem = x25519_public_key_pem(&[0u8; 32]);
+        match export_for_recipient(&source, &DOC_KEY, &identity_pem) {
+            Err(SpdfError::FormatError(msg)) => {
+                assert_eq!(msg, "recipient key is a low-order point")
+            }
+            other => panic!("expected format error, got {:?}", other.map(|d| d.len())),
+        }
+    }
+}
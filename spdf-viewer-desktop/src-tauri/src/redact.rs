@@ -0,0 +1,92 @@
+// Redact Module - Shareable samples for bug reports
+//
+// Produces a copy of an SPDF file with the encrypted content zeroed out.
+// Everything else (header, flags, wrapped key, nonces, auth tags and the
+// signature) is kept byte for byte, so structural and parsing problems
+// still reproduce while the document itself cannot be recovered.
+//
+// NOTE: the signature covers the ciphertext, so signature verification
+// (and decryption) will always fail on a redacted copy.
+
+use std::fs;
+
+use crate::spdf_parser::{SectionKind, SpdfError, SpdfFile, TAG_LENGTH};
+
+/// Read an SPDF file and return a redacted copy of its bytes
+///
+/// Signature verification WILL fail on the result; only use it to
+/// reproduce structural issues.
+pub fn make_redacted_sample(file_path: &str) -> Result<Vec<u8>, String> {
+    let data = fs::read(file_path).map_err(|e| format!("Failed to read file: {}", e))?;
+    redact_spdf(&data).map_err(|e| e.to_string())
+}
+
+/// Zero the ciphertext of already-loaded SPDF bytes
+///
+/// For segmented files each segment's ciphertext is zeroed and its
+/// trailing auth tag is kept.
+pub fn redact_spdf(data: &[u8]) -> Result<Vec<u8>, SpdfError> {
+    let spdf = SpdfFile::parse(data)?;
+    let mut redacted = data.to_vec();
+
+    for (kind, range) in spdf.section_map() {
+        match kind {
+            SectionKind::Ciphertext => redacted[range].fill(0),
+            SectionKind::Segment(_) => redacted[range.start..range.end - TAG_LENGTH].fill(0),
+            _ => {}
+        }
+    }
+
+    Ok(redacted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{build_segmented_spdf, build_spdf, public_key_pem, DOC_KEY};
+
+    #[test]
+    fn test_redacted_structure_preserved() {
+        let plaintext = b"%PDF-1.7 confidential body";
+        let data = build_spdf(plaintext);
+        let redacted = redact_spdf(&data).unwrap();
+        assert_eq!(redacted.len(), data.len());
+
+        let original = SpdfFile::parse(&data).unwrap();
+        let sample = SpdfFile::parse(&redacted).unwrap();
+        assert_eq!(sample.section_map(), original.section_map());
+        assert_eq!(sample.header_json().unwrap(), original.header_json().unwrap());
+        assert_eq!(sample.flags, original.flags);
+        assert_eq!(sample.wrapped_key, original.wrapped_key);
+        assert_eq!(sample.nonce, original.nonce);
+        assert_eq!(sample.auth_tag, original.auth_tag);
+        assert_eq!(sample.signature, original.signature);
+
+        assert!(sample.ciphertext.iter().all(|&b| b == 0));
+        assert_eq!(sample.ciphertext.len(), plaintext.len());
+    }
+
+    #[test]
+    fn test_redacted_sample_fails_verification_and_decryption() {
+        let redacted = redact_spdf(&build_spdf(b"%PDF-1.7")).unwrap();
+        let sample = SpdfFile::parse(&redacted).unwrap();
+
+        assert!(crate::verify::verify_signature_with_key(&sample, &public_key_pem()).is_err());
+        assert!(crate::decrypt::decrypt_content(&sample, &DOC_KEY).is_err());
+    }
+
+    #[test]
+    fn test_redacted_segments_keep_tags() {
+        let segments: [&[u8]; 2] = [b"first", b"second"];
+        let data = build_segmented_spdf(&segments);
+        let redacted = redact_spdf(&data).unwrap();
+        let original = SpdfFile::parse(&data).unwrap();
+        let sample = SpdfFile::parse(&redacted).unwrap();
+
+        for (i, plaintext) in segments.iter().enumerate() {
+            let (body, tag) = sample.segment_bytes(i).unwrap().split_at(plaintext.len());
+            assert!(body.iter().all(|&b| b == 0));
+            assert_eq!(tag, &original.segment_bytes(i).unwrap()[plaintext.len()..]);
+        }
+    }
+}
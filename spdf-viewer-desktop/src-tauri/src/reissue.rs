@@ -0,0 +1,186 @@
+// Reissue Module - Re-sign a document with new permissions
+//
+// An admin holding a document's key can reissue it, e.g. with printing
+// turned off: the content is decrypted, re-encrypted under a fresh key and
+// nonce, given the new permissions (and the flag bits that mirror them),
+// and signed with the org key the admin supplies.
+//
+// K_master never reaches the viewer, so the new key cannot be wrapped
+// here. The reissued file carries an all-zero wrapped key and the new key
+// is handed back for the admin to register with the key server.
+
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use ed25519_dalek::SigningKey;
+use zeroize::Zeroizing;
+
+use crate::decrypt::decrypt_content;
+use crate::encrypt::{deflate_content, encrypt_content_with_alg, frame_signed_v1};
+use crate::spdf_parser::{wrapped_key_length, SpdfError, SpdfFile, SpdfPermissions};
+use crate::verify::{ed25519_public_key_pem, parse_ed25519_public_key_pem};
+
+/// A reissued file and the key its content is now encrypted under
+pub struct Reissued {
+    pub data: Vec<u8>,
+    pub doc_key: Zeroizing<[u8; 32]>,
+}
+
+/// Re-encrypt and re-sign `spdf` with `permissions`
+///
+/// The reissue is always a version 1 file; segmented documents are
+/// re-encrypted as a single blob, and a compressed document stays
+/// compressed. A `key_id` is kept only if the document is re-signed with
+/// the key it was signed with before.
+pub fn reissue_spdf(
+    spdf: &SpdfFile,
+    doc_key: &[u8; 32],
+    permissions: SpdfPermissions,
+    signing_key: &SigningKey,
+) -> Result<Reissued, SpdfError> {
+    // `decrypt_content` inflates, so deflate again for the same flags
+    let mut plaintext = Zeroizing::new(decrypt_content(spdf, doc_key)?);
+    if spdf.is_compressed() {
+        plaintext = deflate_content(&plaintext)?;
+    }
+    let mut new_key = Zeroizing::new([0u8; 32]);
+    OsRng.fill_bytes(new_key.as_mut_slice());
+    let encrypted = encrypt_content_with_alg(spdf.header.enc_alg(), &plaintext, &new_key)?;
+
+    let public_key = signing_key.verifying_key();
+    let mut header = spdf.header.clone();
+    if parse_ed25519_public_key_pem(&header.public_key).ok() != Some(public_key.to_bytes()) {
+        header.key_id = None;
+    }
+    header.public_key = ed25519_public_key_pem(public_key.as_bytes());
+    header.permissions = permissions;
+    header.timestamp_token = None;
+    if spdf.is_segmented() {
+        header.spdf_version = "1.0".to_string();
+        header.segments.clear();
+    }
+    let flags = header.permissions.apply_to_flags(spdf.flags);
+    let wrapped_key = vec![0u8; wrapped_key_length(header.wrap_scheme())?];
+
+    let data = frame_signed_v1(
+        flags,
+        &header,
+        spdf.header_encoding(),
+        &wrapped_key,
+        &encrypted,
+        signing_key,
+    )?;
+    Ok(Reissued {
+        data,
+        doc_key: new_key,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spdf_parser::{FLAG_COPY_ALLOWED, FLAG_OFFLINE_ALLOWED, FLAG_PRINT_ALLOWED};
+    use crate::test_support::{
+        build_spdf_with, public_key_pem, sample_header, signing_key, DEFAULT_FLAGS, DOC_KEY,
+    };
+    use crate::verify::verify_signature_with_key;
+
+    #[test]
+    fn test_reissue_tightens_permissions() {
+        let plaintext = b"%PDF-1.7 reissued";
+        let mut header = sample_header();
+        header["permissions"]["allow_print"] = serde_json::json!(true);
+        header["permissions"]["offline_days"] = serde_json::json!(7);
+        let flags = DEFAULT_FLAGS | FLAG_PRINT_ALLOWED | FLAG_OFFLINE_ALLOWED;
+        let source = SpdfFile::parse(&build_spdf_with(&header, flags, plaintext)).unwrap();
+
+        let permissions = SpdfPermissions {
+            allow_print: false,
+            allow_copy: true,
+            max_devices: 1,
+            offline_days: 0,
+            max_opens: Some(3),
+        };
+        let reissued = reissue_spdf(&source, &DOC_KEY, permissions, &signing_key()).unwrap();
+        let spdf = SpdfFile::parse(&reissued.data).unwrap();
+
+        assert!(verify_signature_with_key(&spdf, &public_key_pem()).is_ok());
+        assert_eq!(
+            decrypt_content(&spdf, &reissued.doc_key).unwrap(),
+            plaintext
+        );
+        assert!(decrypt_content(&spdf, &DOC_KEY).is_err());
+
+        assert!(!spdf.header.permissions.allow_print && !spdf.allows_print());
+        assert!(spdf.header.permissions.allow_copy && spdf.allows_copy());
+        assert!(!spdf.allows_offline());
+        assert_eq!(spdf.header.permissions.max_opens, Some(3));
+        assert_eq!(spdf.flags & !FLAG_COPY_ALLOWED, DEFAULT_FLAGS);
+        assert_eq!(spdf.header.doc_id, source.header.doc_id);
+    }
+
+    #[test]
+    fn test_reissue_keeps_compression() {
+        use crate::spdf_parser::{WriteOptions, FLAG_COMPRESSED, WRAPPED_KEY_LENGTH};
+
+        let header = serde_json::from_value(sample_header()).unwrap();
+        let plaintext = b"%PDF-1.7 ".repeat(4096);
+        let source = SpdfFile::create(&WriteOptions {
+            header: &header,
+            flags: DEFAULT_FLAGS | FLAG_COMPRESSED,
+            plaintext: &plaintext,
+            doc_key: &DOC_KEY,
+            wrapped_key: &[0xAA; WRAPPED_KEY_LENGTH],
+            signing_key: &signing_key(),
+        })
+        .unwrap();
+
+        let reissued = reissue_spdf(&source, &DOC_KEY, Default::default(), &signing_key()).unwrap();
+        let spdf = SpdfFile::parse(&reissued.data).unwrap();
+        assert!(spdf.is_compressed());
+        assert!(spdf.ciphertext.len() < plaintext.len() / 10);
+        assert_eq!(decrypt_content(&spdf, &reissued.doc_key).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn test_reissue_keeps_cipher() {
+        use crate::spdf_parser::{WriteOptions, CHACHA20_POLY1305_ENC_ALG, WRAPPED_KEY_LENGTH};
+
+        let mut header: crate::spdf_parser::SpdfHeader =
+            serde_json::from_value(sample_header()).unwrap();
+        header.enc_alg = Some(CHACHA20_POLY1305_ENC_ALG.to_string());
+        let source = SpdfFile::create(&WriteOptions {
+            header: &header,
+            flags: DEFAULT_FLAGS,
+            plaintext: b"%PDF-1.7 chacha",
+            doc_key: &DOC_KEY,
+            wrapped_key: &[0xAA; WRAPPED_KEY_LENGTH],
+            signing_key: &signing_key(),
+        })
+        .unwrap();
+
+        let reissued = reissue_spdf(&source, &DOC_KEY, Default::default(), &signing_key()).unwrap();
+        let mut spdf = SpdfFile::parse(&reissued.data).unwrap();
+        assert_eq!(spdf.header.enc_alg(), CHACHA20_POLY1305_ENC_ALG);
+        assert_eq!(decrypt_content(&spdf, &reissued.doc_key).unwrap(), b"%PDF-1.7 chacha");
+        // Not AES-GCM under the same key
+        spdf.header.enc_alg = None;
+        assert!(decrypt_content(&spdf, &reissued.doc_key).is_err());
+    }
+
+    #[test]
+    fn test_key_id_dropped_for_a_different_signing_key() {
+        let mut header = sample_header();
+        header["key_id"] = serde_json::json!("org-2025");
+        let source =
+            SpdfFile::parse(&build_spdf_with(&header, DEFAULT_FLAGS, b"%PDF-1.7")).unwrap();
+
+        let same = reissue_spdf(&source, &DOC_KEY, Default::default(), &signing_key()).unwrap();
+        let same = SpdfFile::parse(&same.data).unwrap();
+        assert_eq!(same.header.key_id.as_deref(), Some("org-2025"));
+
+        let other_key = SigningKey::from_bytes(&[0x08; 32]);
+        let other = reissue_spdf(&source, &DOC_KEY, Default::default(), &other_key).unwrap();
+        let other = SpdfFile::parse(&other.data).unwrap();
+        assert_eq!(other.header.key_id, None);
+        assert!(crate::verify::verify_signature(&other).is_ok());
+    }
+}
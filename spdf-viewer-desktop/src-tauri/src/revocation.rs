@@ -0,0 +1,317 @@
+// Revocation Module - Cached list of revoked documents
+//
+// The key server refuses keys for revoked documents, but offline opens
+// never reach it. The last revocation list fetched from each org's server
+// is kept in ~/.spdf/crl/{org_id}.json so offline checks can still refuse
+// revoked doc_ids. A missing file means no list has been fetched yet, not
+// that nothing is revoked; `issued_at` and `fetched_at` say how current
+// the list is.
+//
+// The server signs the list with the org's key, and it is only cached
+// once that signature verifies against a trusted key, so `issued_at`
+// cannot be moved forward to make an old list look fresh. Installs from
+// before per-org lists have a single ~/.spdf/crl.json, which still
+// applies to any org without a list of its own.
+
+use base64::{engine::general_purpose, Engine as _};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use time::{Duration, OffsetDateTime};
+
+use crate::local_store::LocalStore;
+use crate::spdf_parser::SpdfError;
+
+/// Revoked doc_ids as of `fetched_at`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RevocationList {
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub fetched_at: Option<OffsetDateTime>,
+    /// When the server issued the list, from its signed body
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub issued_at: Option<OffsetDateTime>,
+    #[serde(default)]
+    pub revoked: BTreeSet<String>,
+}
+
+/// Response of `GET /keys/crl/{org_id}`: a `RevocationBody` as JSON, and
+/// the org key's Ed25519 signature over those bytes, both base64
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedRevocationList {
+    pub body: String,
+    pub signature: String,
+}
+
+/// What the server signs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevocationBody {
+    pub org_id: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub issued_at: OffsetDateTime,
+    #[serde(default)]
+    pub revoked: BTreeSet<String>,
+}
+
+/// How current an org's cached revocation list is
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrlStatus {
+    pub org_id: String,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub fetched_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub issued_at: Option<OffsetDateTime>,
+    /// Whole days since the list was issued (or fetched, if the server
+    /// gave no issue time); `None` if it was never fetched
+    pub age_days: Option<i64>,
+    /// Older than the freshness window, or never fetched
+    pub stale: bool,
+    /// Warning for the UI when `stale`
+    pub warning: Option<String>,
+}
+
+impl RevocationList {
+    /// Load the cached list; a missing file is an empty, never-fetched list
+    pub fn load(path: &Path) -> Result<Self, SpdfError> {
+        if !path.exists() {
+            return Ok(RevocationList::default());
+        }
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    /// The cached list for `org_id`, falling back to the single list kept
+    /// before lists were per org
+    pub fn load_for_org(store: &LocalStore, org_id: &str) -> Result<Self, SpdfError> {
+        let path = store.crl_path(org_id)?;
+        if path.exists() {
+            return Self::load(&path);
+        }
+        Self::load(&store.legacy_crl_path())
+    }
+
+    /// Check a list from `org_id`'s server against the org's trusted keys
+    /// (Ed25519, raw 32 bytes) and take it as fetched at `now`
+    pub fn from_signed(
+        signed: &SignedRevocationList,
+        org_id: &str,
+        trusted_keys: &[[u8; 32]],
+        now: OffsetDateTime,
+    ) -> Result<Self, SpdfError> {
+        let invalid = |what: &str| SpdfError::SignatureError(format!("Revocation list: {}", what));
+        let body = general_purpose::STANDARD
+            .decode(&signed.body)
+            .map_err(|_| invalid("body is not base64"))?;
+        let signature = general_purpose::STANDARD
+            .decode(&signed.signature)
+            .map_err(|_| invalid("signature is not base64"))?;
+        let signature = Signature::from_slice(&signature)
+            .map_err(|_| invalid("signature is not 64 bytes"))?;
+
+        let verified = trusted_keys.iter().any(|key| {
+            VerifyingKey::from_bytes(key).is_ok_and(|key| key.verify(&body, &signature).is_ok())
+        });
+        if !verified {
+            return Err(invalid("signature does not match a trusted key"));
+        }
+
+        let body: RevocationBody = serde_json::from_slice(&body)?;
+        if body.org_id != org_id {
+            return Err(invalid(&format!("issued for {}, expected {}", body.org_id, org_id)));
+        }
+        Ok(RevocationList {
+            fetched_at: Some(now),
+            issued_at: Some(body.issued_at),
+            revoked: body.revoked,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SpdfError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn is_revoked(&self, doc_id: &str) -> bool {
+        self.revoked.contains(doc_id)
+    }
+
+    /// Freshness of this list at `now` against a `max_age` window
+    ///
+    /// Age counts from `issued_at`, since a list fetched today can still
+    /// be an old one; `fetched_at` stands in when the server gave no time.
+    pub fn status(&self, org_id: &str, now: OffsetDateTime, max_age: Duration) -> CrlStatus {
+        let age = self.issued_at.or(self.fetched_at).map(|since| now - since);
+        let stale = age.is_none_or(|age| age > max_age);
+        let warning = match age {
+            None => Some("no revocation data has been fetched".to_string()),
+            Some(age) if stale => {
+                Some(format!("revocation data is {} days old", age.whole_days()))
+            }
+            Some(_) => None,
+        };
+
+        CrlStatus {
+            org_id: org_id.to_string(),
+            fetched_at: self.fetched_at,
+            issued_at: self.issued_at,
+            age_days: age.map(|age| age.whole_days()),
+            stale,
+            warning,
+        }
+    }
+}
+
+/// Fetch and check `org_id`'s current revocation list, over `client`
+/// (which should be the one pinned for that server, if any)
+pub async fn fetch_revocation_list(
+    client: &reqwest::Client,
+    server_url: &str,
+    org_id: &str,
+    auth_token: &str,
+    trusted_keys: &[[u8; 32]],
+) -> Result<RevocationList, SpdfError> {
+    let url = format!("{}/keys/crl/{}", server_url.trim_end_matches('/'), org_id);
+
+    let res = client
+        .get(&url)
+        .bearer_auth(auth_token)
+        .send()
+        .await
+        .map_err(|e| SpdfError::NetworkError(format!("Failed to fetch revocation list: {}", e)))?;
+
+    if !res.status().is_success() {
+        return Err(SpdfError::NetworkError(format!(
+            "Failed to fetch revocation list: {}",
+            res.status()
+        )));
+    }
+
+    let signed: SignedRevocationList = res.json().await.map_err(|e| {
+        SpdfError::NetworkError(format!("Invalid revocation list response: {}", e))
+    })?;
+    RevocationList::from_signed(&signed, org_id, trusted_keys, OffsetDateTime::now_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::signing_key;
+    use ed25519_dalek::Signer;
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("crl.json");
+        assert_eq!(RevocationList::load(&path).unwrap(), RevocationList::default());
+
+        let list = RevocationList {
+            fetched_at: Some(OffsetDateTime::from_unix_timestamp(1_740_000_000).unwrap()),
+            issued_at: Some(OffsetDateTime::from_unix_timestamp(1_739_990_000).unwrap()),
+            revoked: ["DOC-9".to_string()].into_iter().collect(),
+        };
+        list.save(&path).unwrap();
+        let loaded = RevocationList::load(&path).unwrap();
+        assert_eq!(loaded, list);
+        assert!(loaded.is_revoked("DOC-9"));
+        assert!(!loaded.is_revoked("DOC-1"));
+    }
+
+    #[test]
+    fn test_fresh_and_stale_status() {
+        let now = OffsetDateTime::from_unix_timestamp(1_750_000_000).unwrap();
+        let window = Duration::days(7);
+
+        let fresh = RevocationList {
+            fetched_at: Some(now - Duration::hours(1)),
+            issued_at: Some(now - Duration::days(2)),
+            ..RevocationList::default()
+        };
+        let status = fresh.status("acme", now, window);
+        assert_eq!(status.age_days, Some(2));
+        assert!(!status.stale);
+        assert_eq!(status.warning, None);
+
+        // Fetched just now, but the server issued it 10 days ago
+        let stale = RevocationList {
+            fetched_at: Some(now - Duration::minutes(5)),
+            issued_at: Some(now - Duration::days(10)),
+            ..RevocationList::default()
+        };
+        let status = stale.status("acme", now, window);
+        assert!(status.stale);
+        assert_eq!(status.warning.as_deref(), Some("revocation data is 10 days old"));
+
+        // Without an issue time the fetch time counts
+        let unissued = RevocationList {
+            fetched_at: Some(now - Duration::days(8)),
+            ..RevocationList::default()
+        };
+        assert!(unissued.status("acme", now, window).stale);
+
+        let never = RevocationList::default().status("acme", now, window);
+        assert!(never.stale);
+        assert_eq!(never.age_days, None);
+        assert_eq!(never.warning.as_deref(), Some("no revocation data has been fetched"));
+    }
+
+    fn signed_list(body: &serde_json::Value) -> SignedRevocationList {
+        let body = serde_json::to_vec(body).unwrap();
+        SignedRevocationList {
+            signature: general_purpose::STANDARD.encode(signing_key().sign(&body).to_bytes()),
+            body: general_purpose::STANDARD.encode(body),
+        }
+    }
+
+    #[test]
+    fn test_from_signed() {
+        let now = OffsetDateTime::from_unix_timestamp(1_750_000_000).unwrap();
+        let trusted = [signing_key().verifying_key().to_bytes()];
+        let body = serde_json::json!({
+            "org_id": "acme",
+            "issued_at": "2025-06-14T00:00:00Z",
+            "revoked": ["DOC-9"],
+        });
+
+        let list = RevocationList::from_signed(&signed_list(&body), "acme", &trusted, now).unwrap();
+        assert_eq!(list.fetched_at, Some(now));
+        assert_eq!(list.issued_at.unwrap().unix_timestamp(), 1_749_859_200);
+        assert!(list.is_revoked("DOC-9"));
+
+        // Another org's list, or an untrusted signer, is refused
+        assert!(RevocationList::from_signed(&signed_list(&body), "other", &trusted, now).is_err());
+        let other_key = ed25519_dalek::SigningKey::from_bytes(&[9; 32]);
+        let untrusted = [other_key.verifying_key().to_bytes()];
+        assert!(RevocationList::from_signed(&signed_list(&body), "acme", &untrusted, now).is_err());
+
+        // A forged issue time breaks the signature
+        let mut forged = signed_list(&body);
+        let fresher = serde_json::json!({
+            "org_id": "acme",
+            "issued_at": "2025-06-15T00:00:00Z",
+            "revoked": ["DOC-9"],
+        });
+        forged.body = signed_list(&fresher).body;
+        let err = RevocationList::from_signed(&forged, "acme", &trusted, now).unwrap_err();
+        assert!(err.to_string().contains("does not match a trusted key"));
+    }
+
+    #[test]
+    fn test_legacy_list_still_read() {
+        let root = tempfile::tempdir().unwrap();
+        let store = LocalStore::new(root.path());
+        let legacy = RevocationList {
+            revoked: ["DOC-1".to_string()].into_iter().collect(),
+            ..RevocationList::default()
+        };
+        legacy.save(&store.legacy_crl_path()).unwrap();
+        assert!(RevocationList::load_for_org(&store, "acme").unwrap().is_revoked("DOC-1"));
+
+        // An org's own list takes over once fetched
+        RevocationList::default().save(&store.crl_path("acme").unwrap()).unwrap();
+        assert!(!RevocationList::load_for_org(&store, "acme").unwrap().is_revoked("DOC-1"));
+        assert!(RevocationList::load_for_org(&store, "other").unwrap().is_revoked("DOC-1"));
+    }
+}
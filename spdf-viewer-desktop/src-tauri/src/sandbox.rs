@@ -0,0 +1,223 @@
+// Sandbox Module - Run a step with filesystem and network access dropped
+//
+// The work runs on a dedicated thread that restricts itself before running
+// it and exits afterwards, so the rest of the app is unaffected:
+// - Linux (x86_64, aarch64): a seccomp filter on that thread fails file
+//   opens, socket calls and exec with EPERM
+//
+// Elsewhere the work still runs, unrestricted, and is reported as not
+// sandboxed. Windows job objects restrict whole processes, not threads, so
+// they would need a helper process. Descriptors that are already open stay
+// usable under the filter.
+
+use std::thread;
+
+use zeroize::Zeroizing;
+
+use crate::decrypt::decrypt_content_slice;
+use crate::spdf_parser::{SpdfError, SpdfFile};
+
+/// Run `work` on a restricted thread; the flag says whether the
+/// restriction was actually applied
+pub fn run_sandboxed<T, F>(work: F) -> Result<(T, bool), String>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    thread::Builder::new()
+        .name("spdf-sandbox".to_string())
+        .spawn(move || {
+            let sandboxed = restrict_current_thread();
+            (work(), sandboxed)
+        })
+        .map_err(|e| format!("Failed to start sandbox thread: {}", e))?
+        .join()
+        .map_err(|_| "Sandboxed work panicked".to_string())
+}
+
+/// Decrypt on a restricted thread; the file must already be read and verified
+pub fn decrypt_sandboxed(
+    spdf: SpdfFile,
+    doc_key: Zeroizing<Vec<u8>>,
+) -> Result<(Result<Vec<u8>, SpdfError>, bool), String> {
+    run_sandboxed(move || decrypt_content_slice(&spdf, &doc_key))
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+fn restrict_current_thread() -> bool {
+    seccomp::install_filter()
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+fn restrict_current_thread() -> bool {
+    false
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod seccomp {
+    use libc::{
+        sock_filter, sock_fprog, BPF_ABS, BPF_JEQ, BPF_JGE, BPF_JMP, BPF_K, BPF_LD, BPF_RET, BPF_W,
+        SECCOMP_RET_ALLOW, SECCOMP_RET_ERRNO,
+    };
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xC000_003E;
+    #[cfg(target_arch = "x86_64")]
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xC000_00B7;
+
+    // Offsets into `struct seccomp_data`
+    const NR_OFFSET: u32 = 0;
+    const ARCH_OFFSET: u32 = 4;
+
+    /// Syscalls that reach new files, the network or other programs
+    fn denied_syscalls() -> Vec<libc::c_long> {
+        let mut denied = vec![
+            libc::SYS_openat,
+            libc::SYS_openat2,
+            libc::SYS_open_by_handle_at,
+            libc::SYS_socket,
+            libc::SYS_socketpair,
+            libc::SYS_connect,
+            libc::SYS_bind,
+            libc::SYS_listen,
+            libc::SYS_accept,
+            libc::SYS_accept4,
+            libc::SYS_sendto,
+            libc::SYS_sendmsg,
+            libc::SYS_sendmmsg,
+            libc::SYS_execve,
+            libc::SYS_execveat,
+        ];
+        #[cfg(target_arch = "x86_64")]
+        denied.extend([libc::SYS_open, libc::SYS_creat]);
+        denied
+    }
+
+    fn load_word(offset: u32) -> sock_filter {
+        sock_filter {
+            code: (BPF_LD | BPF_W | BPF_ABS) as u16,
+            jt: 0,
+            jf: 0,
+            k: offset,
+        }
+    }
+
+    /// Skip `jt` instructions if the loaded word compares true against `k`
+    /// under `op`, else `jf`
+    fn jump(op: u32, k: u32, jt: u8, jf: u8) -> sock_filter {
+        sock_filter {
+            code: (BPF_JMP | op | BPF_K) as u16,
+            jt,
+            jf,
+            k,
+        }
+    }
+
+    fn ret(k: u32) -> sock_filter {
+        sock_filter {
+            code: BPF_RET as u16,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    /// Install the filter on the calling thread only (no TSYNC)
+    pub fn install_filter() -> bool {
+        let deny = SECCOMP_RET_ERRNO | libc::EPERM as u32;
+        let mut program = vec![
+            // Syscall numbers of another ABI (e.g. i386) would slip past the list
+            load_word(ARCH_OFFSET),
+            jump(BPF_JEQ, AUDIT_ARCH, 1, 0),
+            ret(deny),
+            load_word(NR_OFFSET),
+        ];
+        // x32 calls share the x86_64 arch value but set this bit
+        #[cfg(target_arch = "x86_64")]
+        program.extend([jump(BPF_JGE, X32_SYSCALL_BIT, 0, 1), ret(deny)]);
+        for nr in denied_syscalls() {
+            program.push(jump(BPF_JEQ, nr as u32, 0, 1));
+            program.push(ret(deny));
+        }
+        program.push(ret(SECCOMP_RET_ALLOW));
+
+        let fprog = sock_fprog {
+            len: program.len() as u16,
+            filter: program.as_mut_ptr(),
+        };
+
+        // SAFETY: `fprog` points at `program`, which outlives both calls;
+        // the kernel copies the filter during PR_SET_SECCOMP
+        unsafe {
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                return false;
+            }
+            libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &fprog as *const sock_fprog,
+            ) == 0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn test_work_result_returned() {
+        let (value, _) = run_sandboxed(|| 6 * 7).unwrap();
+        assert_eq!(value, 42);
+    }
+
+    #[test]
+    fn test_sandbox_blocks_files_and_network() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret");
+        std::fs::write(&path, b"secret").unwrap();
+
+        let inner = path.clone();
+        let (blocked, sandboxed) = run_sandboxed(move || {
+            (
+                std::fs::read(&inner).is_err(),
+                std::net::TcpListener::bind("127.0.0.1:0").is_err(),
+            )
+        })
+        .unwrap();
+        if sandboxed {
+            assert_eq!(blocked, (true, true));
+        }
+
+        // Only the sandbox thread was restricted
+        assert_eq!(std::fs::read(&path).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn test_decrypt_matches_normal_path() {
+        let data = test_support::build_spdf(b"%PDF-1.7 sandboxed");
+        let expected =
+            decrypt_content_slice(&SpdfFile::parse(&data).unwrap(), &test_support::DOC_KEY);
+
+        let spdf = SpdfFile::parse(&data).unwrap();
+        let key = Zeroizing::new(test_support::DOC_KEY.to_vec());
+        let (decrypted, _) = decrypt_sandboxed(spdf, key).unwrap();
+        assert_eq!(decrypted.unwrap(), expected.unwrap());
+
+        let spdf = SpdfFile::parse(&data).unwrap();
+        let (decrypted, _) = decrypt_sandboxed(spdf, Zeroizing::new(vec![0x01; 32])).unwrap();
+        assert!(matches!(decrypted, Err(SpdfError::DecryptionError(_))));
+    }
+}
@@ -0,0 +1,49 @@
+// Screen Protection Module - OS-level screenshot blocking
+//
+// Some platforms let a window opt out of screen capture:
+// - Windows: SetWindowDisplayAffinity(WDA_EXCLUDEFROMCAPTURE)
+// - macOS: NSWindow.sharingType = NSWindowSharingNone
+//
+// Linux compositors offer no equivalent, so protection is reported as
+// unsupported there and watermarking remains the only deterrent.
+
+use serde::{Deserialize, Serialize};
+
+/// Whether the current platform can hide the viewer window from captures
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScreenshotProtection {
+    Supported,
+    Unsupported,
+}
+
+impl ScreenshotProtection {
+    pub fn is_supported(self) -> bool {
+        self == ScreenshotProtection::Supported
+    }
+}
+
+/// Report whether a no-capture window flag is available on this platform
+pub fn screenshot_protection_available() -> ScreenshotProtection {
+    if cfg!(any(target_os = "windows", target_os = "macos")) {
+        ScreenshotProtection::Supported
+    } else {
+        ScreenshotProtection::Unsupported
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_platform_support() {
+        let support = screenshot_protection_available();
+        if cfg!(target_os = "linux") {
+            assert_eq!(support, ScreenshotProtection::Unsupported);
+        }
+        assert_eq!(
+            support.is_supported(),
+            cfg!(any(target_os = "windows", target_os = "macos"))
+        );
+    }
+}
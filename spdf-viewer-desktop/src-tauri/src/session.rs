@@ -0,0 +1,168 @@
+// Session Module - The login token and what the viewer knows about it
+//
+// The key server's access token is a JWT. Its `exp` claim is read without
+// verifying the signature: the token is only ever sent back to the server
+// that issued it, which does verify it, so the claim is just a hint that
+// lets the viewer re-authenticate before a request is refused with 401.
+//
+// A session is persisted as JSON with the token's expiry and user, sealed
+// to the device (see `local_store::seal_session`).
+
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use zeroize::Zeroize;
+
+/// A token this close to its `exp` already counts as expired, so it does
+/// not run out between the check and the request
+pub const EXPIRY_LEEWAY_SECS: i64 = 60;
+
+/// A logged-in session; the tokens are wiped on drop and left out of
+/// `Debug` output
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub access_token: String,
+    #[serde(default)]
+    pub user_email: Option<String>,
+    /// The token's `exp` claim, in seconds since the Unix epoch
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    /// Server that issued the token, where it is refreshed
+    #[serde(default)]
+    pub server_url: Option<String>,
+    /// Set when the server supports `/auth/refresh`
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+}
+
+impl Session {
+    pub fn new(access_token: String, user_email: Option<String>) -> Self {
+        Session {
+            expires_at: token_expiry(&access_token),
+            access_token,
+            user_email,
+            server_url: None,
+            refresh_token: None,
+        }
+    }
+
+    /// Read a session written by `to_json`
+    pub fn parse(stored: &str) -> Option<Session> {
+        serde_json::from_str(stored).ok()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("session serializes")
+    }
+
+    /// Whether the token has expired, within `EXPIRY_LEEWAY_SECS`; a token
+    /// with no readable expiry is left for the server to judge
+    pub fn is_expired_at(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|exp| exp - EXPIRY_LEEWAY_SECS <= now)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.is_expired_at(now_unix())
+    }
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("access_token", &"<redacted>")
+            .field("user_email", &self.user_email)
+            .field("expires_at", &self.expires_at)
+            .field("server_url", &self.server_url)
+            .field("refresh_token", &self.refresh_token.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.access_token.zeroize();
+        if let Some(refresh_token) = &mut self.refresh_token {
+            refresh_token.zeroize();
+        }
+    }
+}
+
+/// The `exp` claim of a JWT, unverified; `None` if `token` is not a JWT or
+/// has no numeric `exp`
+pub fn token_expiry(token: &str) -> Option<i64> {
+    let payload = token.split('.').nth(1)?;
+    let payload = general_purpose::URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .ok()?;
+    let claims: serde_json::Value = serde_json::from_slice(&payload).ok()?;
+    let exp = &claims["exp"];
+    exp.as_i64().or_else(|| exp.as_f64().map(|exp| exp as i64))
+}
+
+fn now_unix() -> i64 {
+    time::OffsetDateTime::now_utc().unix_timestamp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jwt(claims: serde_json::Value) -> String {
+        let encode = |bytes: &[u8]| general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+        format!(
+            "{}.{}.signature",
+            encode(br#"{"alg":"HS256","typ":"JWT"}"#),
+            encode(claims.to_string().as_bytes())
+        )
+    }
+
+    fn token_is_expired(token: &str) -> bool {
+        Session::new(token.to_string(), None).is_expired()
+    }
+
+    #[test]
+    fn test_token_expiry() {
+        let now = now_unix();
+        let fresh = jwt(serde_json::json!({ "sub": "a@example.com", "exp": now + 3600 }));
+        let stale = jwt(serde_json::json!({ "sub": "a@example.com", "exp": now - 1 }));
+        let closing = jwt(serde_json::json!({ "exp": now + EXPIRY_LEEWAY_SECS / 2 }));
+
+        assert_eq!(token_expiry(&fresh), Some(now + 3600));
+        assert!(!token_is_expired(&fresh));
+        assert!(token_is_expired(&stale));
+        assert!(token_is_expired(&closing));
+
+        // No expiry to go on: the server decides
+        let no_exp = jwt(serde_json::json!({ "sub": "a@example.com" }));
+        for token in [no_exp.as_str(), "opaque-token", "a.!!!.c"] {
+            assert_eq!(token_expiry(token), None);
+            assert!(!token_is_expired(token));
+        }
+    }
+
+    #[test]
+    fn test_session_round_trip() {
+        let token = jwt(serde_json::json!({ "exp": 1_900_000_000 }));
+        let session = Session::new(token.clone(), Some("a@example.com".to_string()));
+        let stored = Session::parse(&session.to_json()).unwrap();
+        assert_eq!(stored.access_token, token);
+        assert_eq!(stored.user_email.as_deref(), Some("a@example.com"));
+        assert_eq!(stored.expires_at, Some(1_900_000_000));
+        assert!(stored.is_expired_at(1_900_000_000));
+        assert!(!stored.is_expired_at(1_800_000_000));
+
+        // A bare token, as written before sessions were stored
+        assert!(Session::parse(&token).is_none());
+        assert!(Session::parse("").is_none());
+        assert!(Session::parse("{\"user_email\":\"a\"}").is_none());
+    }
+
+    #[test]
+    fn test_debug_redacts_tokens() {
+        let mut session = Session::new("secret-access".to_string(), Some("a@example.com".into()));
+        session.refresh_token = Some("secret-refresh".to_string());
+        let debug = format!("{:?}", session);
+        assert!(!debug.contains("secret"), "{}", debug);
+        assert!(debug.contains("a@example.com"));
+    }
+}
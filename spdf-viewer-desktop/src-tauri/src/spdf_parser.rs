@@ -1,18 +1,82 @@
 // SPDF Parser Module - File parsing and validation
 //
 // This module provides functionality for parsing SPDF files according
-// to the v1.0 specification.
+// to the v1.0 specification, and for writing version 1 files in the same
+// layout.
 
-use serde::{Deserialize, Serialize};
+use ed25519_dalek::SigningKey;
+use serde::de::{DeserializeOwned, IgnoredAny, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::fs;
+use std::io::Read;
+use std::ops::Range;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::encrypt::{deflate_content, encrypt_content_with_alg, frame_signed_v1};
+use crate::watermark::{
+    validate_watermark_image, validate_watermark_opacity, WatermarkImageLayout,
+};
 
 // Constants matching the SPDF specification
 pub const MAGIC: &[u8] = b"SPDF";
 pub const VERSION: u8 = 0x01;
+pub const VERSION_SEGMENTED: u8 = 0x02;
+/// Header `spdf_version` strings this build understands
+pub const SUPPORTED_VERSIONS: &[&str] = &["1.0", "2.0"];
+/// Content cipher assumed when the header has no `enc_alg`
+pub const DEFAULT_ENC_ALG: &str = "AES-256-GCM";
+/// `enc_alg` of content encrypted with ChaCha20-Poly1305 (RFC 8439)
+pub const CHACHA20_POLY1305_ENC_ALG: &str = "ChaCha20-Poly1305";
+/// Signature algorithm assumed when the header has no `sig_alg`
+pub const DEFAULT_SIG_ALG: &str = "Ed25519";
+/// Header `enc_alg` values this build can decrypt
+pub const SUPPORTED_ENC_ALGS: &[&str] = &[DEFAULT_ENC_ALG, CHACHA20_POLY1305_ENC_ALG];
+/// Header `sig_alg` values this build can verify
+pub const SUPPORTED_SIG_ALGS: &[&str] = &[DEFAULT_SIG_ALG];
+/// Digest signed by the Ed25519 signature when the header has no `hash_alg`
+pub const DEFAULT_HASH_ALG: &str = "sha256";
+/// Header `hash_alg` values this build can verify
+#[cfg(feature = "blake3")]
+pub const SUPPORTED_HASH_ALGS: &[&str] = &[DEFAULT_HASH_ALG, "blake3"];
+#[cfg(not(feature = "blake3"))]
+pub const SUPPORTED_HASH_ALGS: &[&str] = &[DEFAULT_HASH_ALG];
 pub const SIGNATURE_LENGTH: usize = 64;
 pub const NONCE_LENGTH: usize = 12;
 pub const TAG_LENGTH: usize = 16;
+/// Wrapped key length under the default AES-KW scheme (and the shortest
+/// of any scheme)
 pub const WRAPPED_KEY_LENGTH: usize = 40;
+/// Key wrapping scheme assumed when the header has no `wrap_scheme`
+pub const DEFAULT_WRAP_SCHEME: &str = "AES-KW";
+/// Key wrapping of exports bound to a recipient's X25519 key (see the
+/// recipient module)
+pub const RECIPIENT_WRAP_SCHEME: &str = "X25519-HKDF";
+/// Key wrapping schemes this build can frame, with their wrapped key length
+///
+/// - AES-KW (RFC 3394): 32-byte key + 8-byte integrity block
+/// - AES-GCM-SIV: 12-byte nonce + 32-byte key + 16-byte tag
+/// - X25519-HKDF: 32-byte ephemeral public key + 32-byte key + 16-byte tag
+pub const WRAP_SCHEMES: &[(&str, usize)] = &[
+    (DEFAULT_WRAP_SCHEME, 40),
+    ("AES-GCM-SIV", 60),
+    (RECIPIENT_WRAP_SCHEME, 80),
+];
+/// Bytes needed to read MAGIC, VERSION, FLAGS and HEADER_LEN
+pub const PEEK_LENGTH: usize = 4 + 1 + 2 + 4;
+/// Error message for a file that stops short of its own framing
+pub const INCOMPLETE_FILE_MESSAGE: &str = "file appears incomplete — possibly still being written";
+/// Largest `HEADER_LEN` accepted; real headers are a few KiB, and the bound
+/// keeps a forged length from driving reads, allocations or overflowing
+/// offset math on 32-bit targets
+pub const MAX_HEADER_LEN: usize = 1024 * 1024;
+/// Largest body, everything after the header, `SpdfFile::from_reader`
+/// reads; keeps a stream of unknown length from growing the buffer without
+/// bound
+pub const MAX_BODY_LEN: u64 = 4 * 1024 * 1024 * 1024;
 
 // Flag bits
 pub const FLAG_DEVICE_BINDING: u16 = 0x0001;
@@ -20,6 +84,73 @@ pub const FLAG_OFFLINE_ALLOWED: u16 = 0x0002;
 pub const FLAG_PRINT_ALLOWED: u16 = 0x0004;
 pub const FLAG_COPY_ALLOWED: u16 = 0x0008;
 pub const FLAG_WATERMARK_ENABLED: u16 = 0x0010;
+/// Header is CBOR (RFC 8949) instead of JSON; the signature covers the
+/// CBOR bytes as stored
+pub const FLAG_CBOR_HEADER: u16 = 0x0020;
+/// Never serve this document's key from a local cache; always re-fetch it
+/// from the server and re-check the device binding
+pub const FLAG_NO_OFFLINE_CACHE: u16 = 0x0040;
+/// Plaintext was zlib-deflated before encryption and is inflated after the
+/// tag verifies, up to the header's `content_length`; segmented files
+/// compress the whole content, not each segment. Bit 7, since bit 5 (0x0020)
+/// was already `FLAG_CBOR_HEADER`
+pub const FLAG_COMPRESSED: u16 = 0x0080;
+
+/// Serialization of the header bytes, selected by `FLAG_CBOR_HEADER`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderEncoding {
+    Json,
+    Cbor,
+}
+
+impl HeaderEncoding {
+    pub fn from_flags(flags: u16) -> Self {
+        if flags & FLAG_CBOR_HEADER != 0 {
+            HeaderEncoding::Cbor
+        } else {
+            HeaderEncoding::Json
+        }
+    }
+}
+
+/// Serialize a header in `encoding`
+pub fn encode_header<T: Serialize>(
+    header: &T,
+    encoding: HeaderEncoding,
+) -> Result<Vec<u8>, SpdfError> {
+    match encoding {
+        HeaderEncoding::Json => Ok(serde_json::to_vec(header)?),
+        HeaderEncoding::Cbor => {
+            let mut bytes = Vec::new();
+            ciborium::into_writer(header, &mut bytes)
+                .map_err(|e| SpdfError::FormatError(format!("CBOR encode error: {}", e)))?;
+            Ok(bytes)
+        }
+    }
+}
+
+/// Deserialize header bytes stored in `encoding`
+pub fn decode_header<T: DeserializeOwned>(
+    bytes: &[u8],
+    encoding: HeaderEncoding,
+) -> Result<T, SpdfError> {
+    match encoding {
+        HeaderEncoding::Json => Ok(serde_json::from_slice(bytes)?),
+        HeaderEncoding::Cbor => ciborium::from_reader(bytes)
+            .map_err(|e| SpdfError::FormatError(format!("CBOR parse error: {}", e))),
+    }
+}
+
+/// Header bytes as JSON, converting CBOR headers
+pub fn header_to_json(bytes: &[u8], encoding: HeaderEncoding) -> Result<Cow<'_, [u8]>, SpdfError> {
+    match encoding {
+        HeaderEncoding::Json => Ok(Cow::Borrowed(bytes)),
+        HeaderEncoding::Cbor => {
+            let value: serde_json::Value = decode_header(bytes, encoding)?;
+            Ok(Cow::Owned(serde_json::to_vec(&value)?))
+        }
+    }
+}
 
 /// Errors that can occur during SPDF parsing
 #[derive(Debug)]
@@ -28,6 +159,7 @@ pub enum SpdfError {
     FormatError(String),
     SignatureError(String),
     DecryptionError(String),
+    EncryptionError(String),
     NetworkError(String),
     LicenseError(String),
 }
@@ -39,6 +171,7 @@ impl std::fmt::Display for SpdfError {
             SpdfError::FormatError(msg) => write!(f, "Format error: {}", msg),
             SpdfError::SignatureError(msg) => write!(f, "Signature error: {}", msg),
             SpdfError::DecryptionError(msg) => write!(f, "Decryption error: {}", msg),
+            SpdfError::EncryptionError(msg) => write!(f, "Encryption error: {}", msg),
             SpdfError::NetworkError(msg) => write!(f, "Network error: {}", msg),
             SpdfError::LicenseError(msg) => write!(f, "License error: {}", msg),
         }
@@ -59,6 +192,31 @@ impl From<serde_json::Error> for SpdfError {
     }
 }
 
+/// A header field that `SpdfHeader::validate_header` rejects
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum HeaderError {
+    /// `doc_id`, `org_id` or `server_url` is empty or only whitespace
+    EmptyField { field: String },
+    /// `server_url` is not an absolute http(s) URL with a host
+    InvalidServerUrl { server_url: String, reason: String },
+    UnsupportedVersion { spdf_version: String },
+}
+
+impl std::fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeaderError::EmptyField { field } => write!(f, "{} is empty", field),
+            HeaderError::InvalidServerUrl { server_url, reason } => {
+                write!(f, "server_url '{}' is invalid: {}", server_url, reason)
+            }
+            HeaderError::UnsupportedVersion { spdf_version } => {
+                write!(f, "unsupported spdf_version '{}'", spdf_version)
+            }
+        }
+    }
+}
+
 /// SPDF file permissions
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SpdfPermissions {
@@ -67,6 +225,9 @@ pub struct SpdfPermissions {
     pub max_devices: u32,
     #[serde(default)]
     pub offline_days: u32,
+    /// Opens allowed per user; `None` means unlimited
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_opens: Option<u32>,
 }
 
 impl Default for SpdfPermissions {
@@ -76,15 +237,43 @@ impl Default for SpdfPermissions {
             allow_copy: false,
             max_devices: 2,
             offline_days: 0,
+            max_opens: None,
         }
     }
 }
 
+impl SpdfPermissions {
+    /// `flags` with the print, copy and offline bits set to match these
+    /// permissions; other bits are kept
+    pub fn apply_to_flags(&self, flags: u16) -> u16 {
+        let mut flags = flags & !(FLAG_PRINT_ALLOWED | FLAG_COPY_ALLOWED | FLAG_OFFLINE_ALLOWED);
+        if self.allow_print {
+            flags |= FLAG_PRINT_ALLOWED;
+        }
+        if self.allow_copy {
+            flags |= FLAG_COPY_ALLOWED;
+        }
+        if self.offline_days > 0 {
+            flags |= FLAG_OFFLINE_ALLOWED;
+        }
+        flags
+    }
+}
+
 /// SPDF watermark configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SpdfWatermark {
     pub enabled: bool,
     pub text: String,
+    /// Optional base64 PNG drawn with (or instead of) the text; see the
+    /// watermark module for size limits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_base64: Option<String>,
+    #[serde(default)]
+    pub image_layout: WatermarkImageLayout,
+    /// Overlay opacity in 0.0..=1.0 (viewer default when unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opacity: Option<f32>,
 }
 
 impl Default for SpdfWatermark {
@@ -92,7 +281,23 @@ impl Default for SpdfWatermark {
         SpdfWatermark {
             enabled: true,
             text: "{{user_email}} | {{device_id}}".to_string(),
+            image_base64: None,
+            image_layout: WatermarkImageLayout::default(),
+            opacity: None,
+        }
+    }
+}
+
+impl SpdfWatermark {
+    /// Check the image and opacity against the watermark limits
+    pub fn validate(&self) -> Result<(), SpdfError> {
+        if let Some(image) = &self.image_base64 {
+            validate_watermark_image(image).map_err(SpdfError::FormatError)?;
+        }
+        if let Some(opacity) = self.opacity {
+            validate_watermark_opacity(opacity).map_err(SpdfError::FormatError)?;
         }
+        Ok(())
     }
 }
 
@@ -115,6 +320,252 @@ pub struct SpdfHeader {
     pub watermark: SpdfWatermark,
     #[serde(default)]
     pub metadata: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<SpdfSegment>,
+    /// RFC 3339 timestamp after which the document must not be opened
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+    /// Content cipher; `DEFAULT_ENC_ALG` when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enc_alg: Option<String>,
+    /// Signature algorithm; `DEFAULT_SIG_ALG` when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sig_alg: Option<String>,
+    /// Key wrapping scheme; `DEFAULT_WRAP_SCHEME` when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrap_scheme: Option<String>,
+    /// Digest the signature covers; `DEFAULT_HASH_ALG` when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_alg: Option<String>,
+    /// Oldest viewer release (semver) that may open the document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_viewer_version: Option<String>,
+    /// Page count of the plaintext PDF, checked after decryption
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_pages: Option<u32>,
+    /// Length of the plaintext before compression; required with
+    /// `FLAG_COMPRESSED`, and inflation stops there
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_length: Option<u64>,
+    /// `kid` of the signing key in the org's published JWKS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+    /// Hex SHA-256 of a wrapped key shipped in a separate keyfile; required
+    /// for `with_external_wrapped_key`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrapped_key_sha256: Option<String>,
+    /// Time-stamping authority's attestation of when the content existed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp_token: Option<TimestampToken>,
+}
+
+/// The `metadata` fields the viewer knows about
+///
+/// Read leniently: a field that is missing or of an unexpected type is
+/// `None`, and a `page_count` written as a string of digits is accepted.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "serde_json::Value")]
+pub struct DocumentMetadata {
+    pub author: Option<String>,
+    pub page_count: Option<u64>,
+    pub language: Option<String>,
+}
+
+impl DocumentMetadata {
+    fn read(metadata: &serde_json::Value) -> Self {
+        DocumentMetadata {
+            author: metadata_str(metadata, "author").map(str::to_string),
+            page_count: metadata_u64(metadata, "page_count"),
+            language: metadata_str(metadata, "language").map(str::to_string),
+        }
+    }
+}
+
+impl From<serde_json::Value> for DocumentMetadata {
+    fn from(metadata: serde_json::Value) -> Self {
+        DocumentMetadata::read(&metadata)
+    }
+}
+
+fn metadata_str<'a>(metadata: &'a serde_json::Value, key: &str) -> Option<&'a str> {
+    metadata.get(key)?.as_str()
+}
+
+fn metadata_u64(metadata: &serde_json::Value, key: &str) -> Option<u64> {
+    match metadata.get(key)? {
+        serde_json::Value::String(s) => s.trim().parse().ok(),
+        value => value.as_u64(),
+    }
+}
+
+impl SpdfHeader {
+    pub fn enc_alg(&self) -> &str {
+        self.enc_alg.as_deref().unwrap_or(DEFAULT_ENC_ALG)
+    }
+
+    pub fn sig_alg(&self) -> &str {
+        self.sig_alg.as_deref().unwrap_or(DEFAULT_SIG_ALG)
+    }
+
+    pub fn wrap_scheme(&self) -> &str {
+        self.wrap_scheme.as_deref().unwrap_or(DEFAULT_WRAP_SCHEME)
+    }
+
+    pub fn hash_alg(&self) -> &str {
+        self.hash_alg.as_deref().unwrap_or(DEFAULT_HASH_ALG)
+    }
+
+    /// String value of `metadata[key]`; `None` if absent or not a string
+    pub fn metadata_str(&self, key: &str) -> Option<&str> {
+        metadata_str(&self.metadata, key)
+    }
+
+    /// Non-negative integer value of `metadata[key]`, also accepted as a
+    /// string of digits
+    pub fn metadata_u64(&self, key: &str) -> Option<u64> {
+        metadata_u64(&self.metadata, key)
+    }
+
+    /// Keys of `metadata`, sorted; empty if it is not an object
+    pub fn metadata_keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = self
+            .metadata
+            .as_object()
+            .map(|metadata| metadata.keys().map(String::as_str).collect())
+            .unwrap_or_default();
+        keys.sort_unstable();
+        keys
+    }
+
+    pub fn document_metadata(&self) -> DocumentMetadata {
+        DocumentMetadata::read(&self.metadata)
+    }
+
+    /// Check the fields every open relies on: a non-empty `doc_id` and
+    /// `org_id`, an http(s) `server_url` and a known `spdf_version`
+    ///
+    /// Returns every problem found, not just the first.
+    pub fn validate_header(&self) -> Result<(), Vec<HeaderError>> {
+        let mut errors = Vec::new();
+        for (field, value) in [
+            ("doc_id", &self.doc_id),
+            ("org_id", &self.org_id),
+            ("server_url", &self.server_url),
+        ] {
+            if value.trim().is_empty() {
+                errors.push(HeaderError::EmptyField {
+                    field: field.to_string(),
+                });
+            }
+        }
+        if !self.server_url.trim().is_empty() {
+            let reason = match reqwest::Url::parse(&self.server_url) {
+                Ok(url) if !matches!(url.scheme(), "http" | "https") => {
+                    Some(format!("scheme '{}' is not http or https", url.scheme()))
+                }
+                Ok(url) if url.host_str().is_none() => Some("no host".to_string()),
+                Ok(_) => None,
+                Err(e) => Some(e.to_string()),
+            };
+            if let Some(reason) = reason {
+                errors.push(HeaderError::InvalidServerUrl {
+                    server_url: self.server_url.clone(),
+                    reason,
+                });
+            }
+        }
+        if !SUPPORTED_VERSIONS.contains(&self.spdf_version.as_str()) {
+            errors.push(HeaderError::UnsupportedVersion {
+                spdf_version: self.spdf_version.clone(),
+            });
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Parse `expires_at`, if the document has one
+    pub fn expiry(&self) -> Result<Option<OffsetDateTime>, SpdfError> {
+        self.expires_at
+            .as_deref()
+            .map(|s| {
+                OffsetDateTime::parse(s, &Rfc3339).map_err(|e| {
+                    SpdfError::FormatError(format!("Invalid expires_at '{}': {}", s, e))
+                })
+            })
+            .transpose()
+    }
+}
+
+/// Entry in the signed segment table of a version 2 file
+///
+/// `offset` is relative to the start of the segment area (the byte right
+/// after the wrapped key) and `length` covers the segment ciphertext plus
+/// its 16-byte auth tag.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpdfSegment {
+    pub offset: u64,
+    pub length: u64,
+    /// Hex-encoded 12-byte AES-GCM nonce for this segment
+    pub nonce: String,
+}
+
+/// Time-stamp token issued by a time-stamping authority (TSA)
+///
+/// Modelled on an RFC 3161 TSTInfo; see the timestamp module for what the
+/// TSA signs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimestampToken {
+    /// RFC 3339 time the TSA attests to
+    pub gen_time: String,
+    /// Hex SHA-256 of the time-stamped bytes
+    pub message_imprint: String,
+    /// Base64 Ed25519 signature of the TSA
+    pub signature: String,
+}
+
+/// A byte range of an SPDF file, as reported by `SpdfFile::section_map`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SectionKind {
+    Magic,
+    Version,
+    Flags,
+    HeaderLen,
+    Header,
+    WrappedKey,
+    Nonce,
+    Ciphertext,
+    AuthTag,
+    /// One encrypted segment (ciphertext plus tag) of a version 2 file
+    Segment(usize),
+    Signature,
+}
+
+/// Inputs for building a new version 1 file with `SpdfFile::create`
+pub struct WriteOptions<'a> {
+    pub header: &'a SpdfHeader,
+    /// Also selects the header encoding (`FLAG_CBOR_HEADER`)
+    pub flags: u16,
+    pub plaintext: &'a [u8],
+    pub doc_key: &'a [u8; 32],
+    /// `doc_key` wrapped for the key server; its length must match the
+    /// header's wrap scheme
+    pub wrapped_key: &'a [u8],
+    pub signing_key: &'a SigningKey,
+}
+
+/// How much `SpdfFile::parse_with_mode` checks beyond the file's framing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Accept files whose flags and header permissions disagree (the
+    /// flags are what the viewer enforces) and empty header fields
+    #[default]
+    Lenient,
+    /// Also require `SpdfHeader::validate_header` and
+    /// `validate_permission_consistency`
+    Strict,
 }
 
 /// Parsed SPDF file structure
@@ -128,26 +579,213 @@ pub struct SpdfFile {
     pub auth_tag: Vec<u8>,
     pub signature: Vec<u8>,
     pub unsigned_data: Vec<u8>,
+    /// Offsets of each section in the original file bytes
+    sections: Vec<(SectionKind, Range<usize>)>,
+}
+
+/// The parts of a file every format version shares, parsed: everything
+/// up to the end of the header
+struct Prelude {
+    version: u8,
+    flags: u16,
+    header: SpdfHeader,
+    header_len: usize,
+    /// Magic through header
+    sections: Vec<(SectionKind, Range<usize>)>,
+}
+
+/// Parses everything after the header for one format version; `parse`
+/// picks one by the version byte, so a new version is one more match arm
+type BodyParser = fn(&[u8], Prelude) -> Result<SpdfFile, SpdfError>;
+
+/// The wrapped key right after the header at `start`; its length is set by
+/// the wrap scheme, and it can use at most what is left in front of a
+/// `trailer` of fixed size
+fn read_wrapped_key(
+    data: &[u8],
+    start: usize,
+    header: &SpdfHeader,
+    trailer: usize,
+) -> Result<Vec<u8>, SpdfError> {
+    let length = wrapped_key_length(header.wrap_scheme())?;
+    let available = (data.len() - start).saturating_sub(trailer);
+    if available < length {
+        validate_wrapped_key_length(header.wrap_scheme(), available)?;
+    }
+    Ok(data[start..start + length].to_vec())
 }
 
 impl SpdfFile {
     /// Read and parse an SPDF file from disk
+    ///
+    /// `from_reader` over the file, with the buffer sized from its metadata.
     pub fn read(path: &str) -> Result<Self, SpdfError> {
-        let data = fs::read(path)?;
+        let file = fs::File::open(path)?;
+        let size_hint = file.metadata()?.len() as usize;
+        Self::from_reader_sized(file, size_hint)
+    }
+
+    /// Read and parse an SPDF file from any reader, e.g. an HTTP body
+    ///
+    /// The fixed prefix and header are read first, then at most
+    /// `MAX_BODY_LEN` bytes of body, all into one buffer that the parsed
+    /// file keeps as its signed bytes. Input that is not SPDF is rejected
+    /// after the prefix without reading the rest. Input that stops short of
+    /// its own framing (see `appears_incomplete`), e.g. a file a cloud sync
+    /// is still writing, fails with `INCOMPLETE_FILE_MESSAGE` so the caller
+    /// can offer to try again.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, SpdfError> {
+        Self::from_reader_sized(reader, 0)
+    }
+
+    fn from_reader_sized<R: Read>(reader: R, size_hint: usize) -> Result<Self, SpdfError> {
+        let mut data = read_framed(reader, size_hint, MAX_BODY_LEN)?;
+        let mut spdf = match Self::parse_layout(&data) {
+            Err(_) if appears_incomplete(&data) => {
+                return Err(SpdfError::FormatError(INCOMPLETE_FILE_MESSAGE.to_string()));
+            }
+            result => result?,
+        };
+        // The file keeps the buffer itself as its signed bytes, not a copy
+        data.truncate(data.len() - SIGNATURE_LENGTH);
+        spdf.unsigned_data = data;
+        Ok(spdf)
+    }
+
+    /// Read a file whose wrapped key ships separately, in a raw keyfile
+    ///
+    /// For air-gapped setups that keep the wrapped key apart from the
+    /// document; the embedded one (usually zeroed) is not used.
+    pub fn read_with_keyfile(spdf_path: &str, keyfile_path: &str) -> Result<Self, SpdfError> {
+        let mut spdf = Self::read(spdf_path)?;
+        spdf.with_external_wrapped_key(fs::read(keyfile_path)?)?;
+        Ok(spdf)
+    }
+
+    /// Use `wrapped` instead of the embedded wrapped key
+    ///
+    /// Its length must match the header's wrap scheme. The signature covers
+    /// the embedded bytes, not these, so they are only accepted if their
+    /// hash is the header's signed `wrapped_key_sha256`.
+    pub fn with_external_wrapped_key(&mut self, wrapped: Vec<u8>) -> Result<(), SpdfError> {
+        validate_wrapped_key_length(self.header.wrap_scheme(), wrapped.len())?;
+        let pinned = self.header.wrapped_key_sha256.as_deref().ok_or_else(|| {
+            SpdfError::FormatError("header does not pin an external wrapped key".to_string())
+        })?;
+        if !hex::encode(Sha256::digest(&wrapped)).eq_ignore_ascii_case(pinned.trim()) {
+            return Err(SpdfError::FormatError(
+                "external wrapped key does not match the header's wrapped_key_sha256".to_string(),
+            ));
+        }
+        self.wrapped_key = wrapped;
+        Ok(())
+    }
+
+    /// Encrypt and sign a new version 1 file
+    ///
+    /// The content is deflated first if `flags` has `FLAG_COMPRESSED`, with
+    /// its length recorded in the header's `content_length`, then encrypted
+    /// under `doc_key` with a fresh random nonce, using the cipher the
+    /// header's `enc_alg` names, and the signature covers the same bytes
+    /// `parse` hands the verifier, so the result verifies with the signing
+    /// key's public half.
+    pub fn create(options: &WriteOptions) -> Result<Self, SpdfError> {
+        validate_wrapped_key_length(options.header.wrap_scheme(), options.wrapped_key.len())?;
+        let compressed;
+        let mut header = Cow::Borrowed(options.header);
+        let plaintext = if options.flags & FLAG_COMPRESSED != 0 {
+            header.to_mut().content_length = Some(options.plaintext.len() as u64);
+            compressed = deflate_content(options.plaintext)?;
+            &compressed[..]
+        } else {
+            options.plaintext
+        };
+        let encrypted = encrypt_content_with_alg(header.enc_alg(), plaintext, options.doc_key)?;
+        let data = frame_signed_v1(
+            options.flags,
+            &header,
+            HeaderEncoding::from_flags(options.flags),
+            options.wrapped_key,
+            &encrypted,
+            options.signing_key,
+        )?;
         Self::parse(&data)
     }
 
+    /// Create a new version 1 file (see `create`) and write it to `path`
+    pub fn write(path: &str, options: &WriteOptions) -> Result<Self, SpdfError> {
+        let spdf = Self::create(options)?;
+        fs::write(path, spdf.encode()?)?;
+        Ok(spdf)
+    }
+
+    /// The bytes the signature covers, framed from the current fields
+    ///
+    /// The header is emitted as stored (`header_bytes`), not re-serialized
+    /// from `header`, so a parsed file frames back byte for byte. Version 2
+    /// files have no nonce or tag of their own; `ciphertext` holds the
+    /// whole segment area.
+    pub fn build_unsigned_data(&self) -> Vec<u8> {
+        let header_bytes = self.header_bytes();
+        let mut data = Vec::with_capacity(self.unsigned_data.len());
+        data.extend_from_slice(MAGIC);
+        data.push(self.version);
+        data.extend_from_slice(&self.flags.to_be_bytes());
+        data.extend_from_slice(&(header_bytes.len() as u32).to_be_bytes());
+        data.extend_from_slice(header_bytes);
+        data.extend_from_slice(&self.wrapped_key);
+        data.extend_from_slice(&self.nonce);
+        data.extend_from_slice(&self.ciphertext);
+        data.extend_from_slice(&self.auth_tag);
+        data
+    }
+
+    /// Serialize to file bytes: `build_unsigned_data` and the signature
+    pub fn encode(&self) -> Result<Vec<u8>, SpdfError> {
+        if self.signature.len() != SIGNATURE_LENGTH {
+            return Err(SpdfError::FormatError(format!(
+                "Invalid signature length: expected {}, got {}",
+                SIGNATURE_LENGTH,
+                self.signature.len()
+            )));
+        }
+        let mut data = self.build_unsigned_data();
+        data.extend_from_slice(&self.signature);
+        Ok(data)
+    }
+
     /// Parse SPDF data from bytes
     pub fn parse(data: &[u8]) -> Result<Self, SpdfError> {
+        Self::parse_with_mode(data, ParseMode::Lenient)
+    }
+
+    /// Parse SPDF data from bytes, in `ParseMode::Strict` also rejecting
+    /// invalid header fields and flags that contradict the header's
+    /// permissions
+    pub fn parse_with_mode(data: &[u8], mode: ParseMode) -> Result<Self, SpdfError> {
+        let mut spdf = Self::parse_layout(data)?;
+        spdf.unsigned_data = data[..data.len() - SIGNATURE_LENGTH].to_vec();
+        if mode == ParseMode::Strict {
+            spdf.header.validate_header().map_err(|errors| {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                SpdfError::FormatError(format!("invalid header: {}", errors.join("; ")))
+            })?;
+            spdf.validate_permission_consistency()?;
+        }
+        Ok(spdf)
+    }
+
+    /// Everything but `unsigned_data`, which every layout ends
+    /// `SIGNATURE_LENGTH` bytes short of the end of `data`
+    fn parse_layout(data: &[u8]) -> Result<Self, SpdfError> {
         let mut pos = 0;
 
-        // Minimum size check
-        let min_size = 4 + 1 + 2 + 4 + WRAPPED_KEY_LENGTH + NONCE_LENGTH + TAG_LENGTH + SIGNATURE_LENGTH;
-        if data.len() < min_size {
+        // The fixed prefix, up to HEADER_LEN; the full minimum depends on it
+        if data.len() < PEEK_LENGTH {
             return Err(SpdfError::FormatError(format!(
                 "File too short: {} bytes, minimum {} bytes",
                 data.len(),
-                min_size
+                PEEK_LENGTH
             )));
         }
 
@@ -161,14 +799,18 @@ impl SpdfFile {
         }
         pos += 4;
 
-        // Parse VERSION (1 byte)
+        // Parse VERSION (1 byte); it selects how the body is laid out
         let version = data[pos];
-        if version != VERSION {
-            return Err(SpdfError::FormatError(format!(
-                "Unsupported version: {}, expected {}",
-                version, VERSION
-            )));
-        }
+        let parse_body: BodyParser = match version {
+            VERSION => Self::parse_v1,
+            VERSION_SEGMENTED => Self::parse_v2,
+            _ => {
+                return Err(SpdfError::FormatError(format!(
+                    "Unsupported version: {}, expected {} or {}",
+                    version, VERSION, VERSION_SEGMENTED
+                )))
+            }
+        };
         pos += 1;
 
         // Parse FLAGS (2 bytes, big-endian)
@@ -176,34 +818,111 @@ impl SpdfFile {
         pos += 2;
 
         // Parse HEADER_LEN (4 bytes, big-endian)
-        let header_len = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let header_len_bytes = [data[pos], data[pos + 1], data[pos + 2], data[pos + 3]];
+        let header_len = u32::from_be_bytes(header_len_bytes) as usize;
         pos += 4;
 
-        // Validate header length
-        if pos + header_len > data.len() {
+        // Validate header length; bounded first so `pos + header_len`
+        // cannot wrap
+        if header_len > MAX_HEADER_LEN || pos + header_len > data.len() {
+            // A writer that got the byte order wrong produces a length far
+            // past the end of the file whose little-endian reading fits
+            if little_endian_header_len(header_len_bytes)
+                .is_some_and(|little_endian| pos + little_endian <= data.len())
+            {
+                return Err(SpdfError::FormatError(
+                    "header length looks little-endian; expected big-endian".to_string(),
+                ));
+            }
+            check_header_len(header_len)?;
             return Err(SpdfError::FormatError(format!(
                 "Invalid header length: {} exceeds file size",
                 header_len
             )));
         }
 
+        let sections = vec![
+            (SectionKind::Magic, 0..4),
+            (SectionKind::Version, 4..5),
+            (SectionKind::Flags, 5..7),
+            (SectionKind::HeaderLen, 7..11),
+            (SectionKind::Header, pos..pos + header_len),
+        ];
+
         // Parse HEADER_JSON
         let header_json = &data[pos..pos + header_len];
-        let header: SpdfHeader = serde_json::from_slice(header_json)?;
-        pos += header_len;
+        let encoding = HeaderEncoding::from_flags(flags);
+        reject_duplicate_keys(header_json, encoding)?;
+        let header: SpdfHeader = decode_header(header_json, encoding)?;
+        if !SUPPORTED_VERSIONS.contains(&header.spdf_version.as_str()) {
+            return Err(SpdfError::FormatError(format!(
+                "unsupported spdf_version '{}'",
+                header.spdf_version
+            )));
+        }
+        if !SUPPORTED_ENC_ALGS.contains(&header.enc_alg()) {
+            return Err(SpdfError::FormatError(format!(
+                "unsupported enc_alg '{}'",
+                header.enc_alg()
+            )));
+        }
+        if !SUPPORTED_SIG_ALGS.contains(&header.sig_alg()) {
+            return Err(SpdfError::FormatError(format!(
+                "unsupported sig_alg '{}'",
+                header.sig_alg()
+            )));
+        }
+        if !SUPPORTED_HASH_ALGS.contains(&header.hash_alg()) {
+            return Err(SpdfError::FormatError(format!(
+                "unsupported hash_alg '{}'",
+                header.hash_alg()
+            )));
+        }
+        header.watermark.validate()?;
+
+        parse_body(
+            data,
+            Prelude {
+                version,
+                flags,
+                header,
+                header_len,
+                sections,
+            },
+        )
+    }
 
-        // Parse WRAPPED_KEY (40 bytes)
-        if pos + WRAPPED_KEY_LENGTH > data.len() {
-            return Err(SpdfError::FormatError("File too short for wrapped key".to_string()));
+    /// Parse the body of a version 1 file: wrapped key, nonce, one
+    /// ciphertext, auth tag and signature
+    fn parse_v1(data: &[u8], prelude: Prelude) -> Result<Self, SpdfError> {
+        let Prelude {
+            version,
+            flags,
+            header,
+            header_len,
+            mut sections,
+        } = prelude;
+        if data.len() < minimum_file_size(header_len) {
+            return Err(SpdfError::FormatError(format!(
+                "File too short: {} bytes, minimum {} bytes for a {}-byte header",
+                data.len(),
+                minimum_file_size(header_len),
+                header_len
+            )));
         }
-        let wrapped_key = data[pos..pos + WRAPPED_KEY_LENGTH].to_vec();
-        pos += WRAPPED_KEY_LENGTH;
+
+        let trailer = NONCE_LENGTH + TAG_LENGTH + SIGNATURE_LENGTH;
+        let body_start = PEEK_LENGTH + header_len;
+        let wrapped_key = read_wrapped_key(data, body_start, &header, trailer)?;
+        let mut pos = body_start + wrapped_key.len();
+        sections.push((SectionKind::WrappedKey, body_start..pos));
 
         // Parse NONCE (12 bytes)
         if pos + NONCE_LENGTH > data.len() {
             return Err(SpdfError::FormatError("File too short for nonce".to_string()));
         }
         let nonce = data[pos..pos + NONCE_LENGTH].to_vec();
+        sections.push((SectionKind::Nonce, pos..pos + NONCE_LENGTH));
         pos += NONCE_LENGTH;
 
         // Signature is always last 64 bytes
@@ -212,7 +931,6 @@ impl SpdfFile {
         }
 
         let signature = data[data.len() - SIGNATURE_LENGTH..].to_vec();
-        let unsigned_data = data[..data.len() - SIGNATURE_LENGTH].to_vec();
 
         // Ciphertext is between nonce and (auth_tag + signature)
         let ciphertext_end = data.len() - SIGNATURE_LENGTH - TAG_LENGTH;
@@ -222,6 +940,9 @@ impl SpdfFile {
 
         let ciphertext = data[pos..ciphertext_end].to_vec();
         let auth_tag = data[ciphertext_end..ciphertext_end + TAG_LENGTH].to_vec();
+        sections.push((SectionKind::Ciphertext, pos..ciphertext_end));
+        sections.push((SectionKind::AuthTag, ciphertext_end..ciphertext_end + TAG_LENGTH));
+        sections.push((SectionKind::Signature, data.len() - SIGNATURE_LENGTH..data.len()));
 
         Ok(SpdfFile {
             version,
@@ -232,18 +953,199 @@ impl SpdfFile {
             ciphertext,
             auth_tag,
             signature,
-            unsigned_data,
+            unsigned_data: Vec::new(),
+            sections,
         })
     }
 
+    /// Parse the body of a version 2 file, where everything between the
+    /// wrapped key and the signature is a run of independently encrypted
+    /// segments described by `header.segments`
+    fn parse_v2(data: &[u8], prelude: Prelude) -> Result<Self, SpdfError> {
+        let Prelude {
+            version,
+            flags,
+            header,
+            header_len,
+            mut sections,
+        } = prelude;
+        let body_start = PEEK_LENGTH + header_len;
+        let wrapped_key = read_wrapped_key(data, body_start, &header, SIGNATURE_LENGTH)?;
+        let pos = body_start + wrapped_key.len();
+        sections.push((SectionKind::WrappedKey, body_start..pos));
+
+        if data.len() < pos + SIGNATURE_LENGTH {
+            return Err(SpdfError::FormatError("File too short for signature".to_string()));
+        }
+        let segment_area_end = data.len() - SIGNATURE_LENGTH;
+        let segment_area = &data[pos..segment_area_end];
+
+        validate_segment_table(&header.segments, segment_area.len())?;
+
+        for (i, segment) in header.segments.iter().enumerate() {
+            let start = pos + segment.offset as usize;
+            sections.push((SectionKind::Segment(i), start..start + segment.length as usize));
+        }
+        sections.push((SectionKind::Signature, segment_area_end..data.len()));
+
+        Ok(SpdfFile {
+            version,
+            flags,
+            header,
+            wrapped_key,
+            nonce: Vec::new(),
+            ciphertext: segment_area.to_vec(),
+            auth_tag: Vec::new(),
+            signature: data[segment_area_end..].to_vec(),
+            unsigned_data: Vec::new(),
+            sections,
+        })
+    }
+
+    /// Ordered byte ranges of every section against the original file
+    ///
+    /// The ranges are contiguous and cover the whole file. Version 2 files
+    /// report one `Segment` entry per segment instead of nonce, ciphertext
+    /// and auth tag.
+    pub fn section_map(&self) -> Vec<(SectionKind, Range<usize>)> {
+        self.sections.clone()
+    }
+
+    /// Byte range of the first section of the given kind
+    pub fn section_range(&self, kind: SectionKind) -> Option<Range<usize>> {
+        self.sections
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, range)| range.clone())
+    }
+
+    /// Raw header bytes exactly as signed, in `header_encoding()`
+    pub fn header_bytes(&self) -> &[u8] {
+        let range = self
+            .section_range(SectionKind::Header)
+            .expect("parsed files always have a header section");
+        &self.unsigned_data[range]
+    }
+
+    pub fn header_encoding(&self) -> HeaderEncoding {
+        HeaderEncoding::from_flags(self.flags)
+    }
+
+    /// Header as JSON: the signed bytes themselves for JSON headers, a
+    /// conversion for CBOR ones
+    pub fn header_json(&self) -> Result<Cow<'_, [u8]>, SpdfError> {
+        header_to_json(self.header_bytes(), self.header_encoding())
+    }
+
+    /// Check if the content is split into independently encrypted segments
+    pub fn is_segmented(&self) -> bool {
+        self.version == VERSION_SEGMENTED
+    }
+
+    /// Check that the binary version byte and `header.spdf_version` name
+    /// the same layout ("1.x" for version 1, "2.x" for version 2)
+    pub fn version_matches_header(&self) -> bool {
+        let major = self.header.spdf_version.split('.').next();
+        match self.version {
+            VERSION => major == Some("1"),
+            VERSION_SEGMENTED => major == Some("2"),
+            _ => false,
+        }
+    }
+
+    /// Number of independently decryptable segments (1 for version 1 files)
+    pub fn segment_count(&self) -> usize {
+        if self.is_segmented() {
+            self.header.segments.len()
+        } else {
+            1
+        }
+    }
+
+    /// Raw bytes (ciphertext followed by auth tag) of one segment of a
+    /// version 2 file
+    ///
+    /// A version 1 file has no segment table to index, whatever its header
+    /// lists, and a segment reaching past the content is an error.
+    pub fn segment_bytes(&self, index: usize) -> Result<&[u8], SpdfError> {
+        if !self.is_segmented() {
+            return Err(SpdfError::FormatError(format!(
+                "Version {} files have no segments",
+                self.version
+            )));
+        }
+        let segment = self.header.segments.get(index).ok_or_else(|| {
+            SpdfError::FormatError(format!(
+                "Segment index {} out of range ({} segments)",
+                index,
+                self.header.segments.len()
+            ))
+        })?;
+        usize::try_from(segment.offset)
+            .ok()
+            .zip(usize::try_from(segment.length).ok())
+            .and_then(|(start, length)| self.ciphertext.get(start..start.checked_add(length)?))
+            .ok_or_else(|| {
+                SpdfError::FormatError(format!("Segment {} lies outside the content", index))
+            })
+    }
+
+    /// Conservative estimate, in bytes, of the memory needed to open the
+    /// file: the ciphertext, the decrypted PDF (taken to be as large as
+    /// the ciphertext) and the base64 copy of it sent to the UI
+    ///
+    /// A compressed file also holds the deflated plaintext while it
+    /// inflates, and its PDF is as large as the header's `content_length`.
+    pub fn estimated_open_memory(&self) -> usize {
+        let ciphertext = self.ciphertext.len();
+        let (deflated, plaintext) = match self.header.content_length {
+            Some(length) if self.is_compressed() => {
+                (ciphertext, usize::try_from(length).unwrap_or(usize::MAX))
+            }
+            _ => (0, ciphertext),
+        };
+        let base64 = plaintext.div_ceil(3).saturating_mul(4);
+        ciphertext
+            .saturating_add(deflated)
+            .saturating_add(plaintext)
+            .saturating_add(base64)
+    }
+
+    /// Whether there is any ciphertext to decrypt (in any segment)
+    pub fn has_content(&self) -> bool {
+        if self.is_segmented() {
+            let tag_length = TAG_LENGTH as u64;
+            return self.header.segments.iter().any(|segment| segment.length > tag_length);
+        }
+        !self.ciphertext.is_empty()
+    }
+
     /// Check if device binding is required
     pub fn requires_device_binding(&self) -> bool {
         self.flags & FLAG_DEVICE_BINDING != 0
     }
 
     /// Check if offline viewing is allowed
+    ///
+    /// Always false for documents that forbid key caching, regardless of
+    /// `FLAG_OFFLINE_ALLOWED`.
     pub fn allows_offline(&self) -> bool {
-        self.flags & FLAG_OFFLINE_ALLOWED != 0
+        self.flags & FLAG_OFFLINE_ALLOWED != 0 && !self.requires_fresh_key()
+    }
+
+    /// Check if the key must be fetched from the server on every open
+    pub fn requires_fresh_key(&self) -> bool {
+        self.flags & FLAG_NO_OFFLINE_CACHE != 0
+    }
+
+    /// Check if the content must be inflated after decryption
+    pub fn is_compressed(&self) -> bool {
+        self.flags & FLAG_COMPRESSED != 0
+    }
+
+    /// Check if a fetched key may be stored in the local key cache
+    pub fn allows_key_cache(&self) -> bool {
+        !self.requires_fresh_key()
     }
 
     /// Check if printing is allowed
@@ -261,6 +1163,58 @@ impl SpdfFile {
         self.flags & FLAG_WATERMARK_ENABLED != 0
     }
 
+    /// Check that the print, copy, offline and watermark flags agree with
+    /// the header's `permissions` and `watermark`
+    ///
+    /// Both are signed, but an editor can set them independently; the
+    /// error lists every bit that disagrees.
+    pub fn validate_permission_consistency(&self) -> Result<(), SpdfError> {
+        let mismatches = self.permission_mismatches();
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(SpdfError::FormatError(format!(
+                "permission flags disagree with header: {}",
+                mismatches.join("; ")
+            )))
+        }
+    }
+
+    /// One line per flag that disagrees with the header, as
+    /// `validate_permission_consistency` reports them
+    pub fn permission_mismatches(&self) -> Vec<String> {
+        let permissions = &self.header.permissions;
+        let checks = [
+            ("print", FLAG_PRINT_ALLOWED, "allow_print", permissions.allow_print),
+            ("copy", FLAG_COPY_ALLOWED, "allow_copy", permissions.allow_copy),
+            ("offline", FLAG_OFFLINE_ALLOWED, "offline_days > 0", permissions.offline_days > 0),
+            (
+                "watermark",
+                FLAG_WATERMARK_ENABLED,
+                "watermark.enabled",
+                self.header.watermark.enabled,
+            ),
+        ];
+        checks
+            .iter()
+            .filter(|(_, flag, _, allowed)| (self.flags & flag != 0) != *allowed)
+            .map(|(name, flag, field, allowed)| {
+                format!(
+                    "{} flag is {} but {} is {}",
+                    name,
+                    if self.flags & flag != 0 { "set" } else { "clear" },
+                    field,
+                    allowed
+                )
+            })
+            .collect()
+    }
+
+    /// Get the custom watermark image (base64 PNG), if any
+    pub fn watermark_image(&self) -> Option<&str> {
+        self.header.watermark.image_base64.as_deref()
+    }
+
     /// Get document ID
     pub fn doc_id(&self) -> &str {
         &self.header.doc_id
@@ -282,11 +1236,318 @@ impl SpdfFile {
     }
 }
 
+/// Strict header parsing: reject a top-level key that appears twice
+///
+/// serde_json keeps the last value of a repeated key, so a second
+/// `permissions` object could silently replace a restrictive first one for
+/// any reader of the header. CBOR maps are checked the same way. Malformed
+/// headers are left for the typed parse to report.
+pub fn reject_duplicate_keys(header: &[u8], encoding: HeaderEncoding) -> Result<(), SpdfError> {
+    /// First repeated key of a map
+    struct FirstDuplicate(Option<String>);
+
+    impl<'de> Deserialize<'de> for FirstDuplicate {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_map(FirstDuplicateVisitor).map(FirstDuplicate)
+        }
+    }
+
+    struct FirstDuplicateVisitor;
+
+    impl<'de> Visitor<'de> for FirstDuplicateVisitor {
+        type Value = Option<String>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a map")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut seen = HashSet::new();
+            let mut duplicate = None;
+            while let Some(key) = map.next_key::<String>()? {
+                map.next_value::<IgnoredAny>()?;
+                if duplicate.is_none() && seen.contains(&key) {
+                    duplicate = Some(key);
+                } else {
+                    seen.insert(key);
+                }
+            }
+            Ok(duplicate)
+        }
+    }
+
+    match decode_header(header, encoding) {
+        Ok(FirstDuplicate(Some(key))) => {
+            Err(SpdfError::FormatError(format!("duplicate header key '{}'", key)))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Check that a segment table tiles the segment area exactly, in order,
+/// with every segment large enough to hold its auth tag
+fn validate_segment_table(segments: &[SpdfSegment], area_len: usize) -> Result<(), SpdfError> {
+    if segments.is_empty() {
+        return Err(SpdfError::FormatError(
+            "Version 2 file has an empty segment table".to_string(),
+        ));
+    }
+
+    let mut expected_offset = 0u64;
+    for (i, segment) in segments.iter().enumerate() {
+        if segment.offset != expected_offset {
+            return Err(SpdfError::FormatError(format!(
+                "Segment {} starts at offset {}, expected {}",
+                i, segment.offset, expected_offset
+            )));
+        }
+        if segment.length < TAG_LENGTH as u64 {
+            return Err(SpdfError::FormatError(format!(
+                "Segment {} is too short for an auth tag: {} bytes",
+                i, segment.length
+            )));
+        }
+        match hex::decode(&segment.nonce) {
+            Ok(nonce) if nonce.len() == NONCE_LENGTH => {}
+            _ => {
+                return Err(SpdfError::FormatError(format!(
+                    "Segment {} has an invalid nonce",
+                    i
+                )))
+            }
+        }
+        expected_offset = segment.offset.checked_add(segment.length).ok_or_else(|| {
+            SpdfError::FormatError(format!("Segment {} length overflows", i))
+        })?;
+    }
+
+    if expected_offset != area_len as u64 {
+        return Err(SpdfError::FormatError(format!(
+            "Segment table covers {} bytes but segment area is {} bytes",
+            expected_offset, area_len
+        )));
+    }
+
+    Ok(())
+}
+
 /// Validate SPDF magic bytes without full parsing
 pub fn validate_magic(data: &[u8]) -> bool {
     data.len() >= 4 && &data[0..4] == MAGIC
 }
 
+/// Result of peeking at the fixed-size prefix of a file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FilePeek {
+    pub is_spdf: bool,
+    pub version: Option<u8>,
+    pub flags: Option<u16>,
+    pub header_len: Option<u32>,
+}
+
+/// Identify an SPDF file by reading only its first `PEEK_LENGTH` bytes
+///
+/// Meant for triaging large folders: the rest of the file is never read.
+pub fn peek_file_type(path: &str) -> Result<FilePeek, SpdfError> {
+    peek_reader(fs::File::open(path)?)
+}
+
+/// Peek at the prefix of any reader, consuming at most `PEEK_LENGTH` bytes
+pub fn peek_reader<R: Read>(reader: R) -> Result<FilePeek, SpdfError> {
+    let mut prefix = Vec::with_capacity(PEEK_LENGTH);
+    reader.take(PEEK_LENGTH as u64).read_to_end(&mut prefix)?;
+
+    if !validate_magic(&prefix) {
+        return Ok(FilePeek {
+            is_spdf: false,
+            version: None,
+            flags: None,
+            header_len: None,
+        });
+    }
+
+    let header_len = if prefix.len() == PEEK_LENGTH {
+        Some(u32::from_be_bytes([prefix[7], prefix[8], prefix[9], prefix[10]]))
+    } else {
+        None
+    };
+
+    Ok(FilePeek {
+        is_spdf: true,
+        version: prefix.get(4).copied(),
+        flags: prefix.get(5..7).map(|b| u16::from_be_bytes([b[0], b[1]])),
+        header_len,
+    })
+}
+
+/// Read one file's bytes from `reader` in framing order
+///
+/// The prefix comes first; if it is short or not SPDF, reading stops there
+/// and `parse` reports why. A body over `max_body_len` bytes is an error.
+/// `size_hint` pre-sizes the buffer when the total length is known.
+fn read_framed<R: Read>(
+    mut reader: R,
+    size_hint: usize,
+    max_body_len: u64,
+) -> Result<Vec<u8>, SpdfError> {
+    // Saturates where the body limit does not fit in usize (32-bit targets)
+    let max_len = usize::try_from(max_body_len)
+        .unwrap_or(usize::MAX)
+        .saturating_add(PEEK_LENGTH + MAX_HEADER_LEN);
+    let mut data = Vec::with_capacity(size_hint.clamp(PEEK_LENGTH, max_len));
+    reader.by_ref().take(PEEK_LENGTH as u64).read_to_end(&mut data)?;
+    if data.len() < PEEK_LENGTH || !validate_magic(&data) {
+        return Ok(data);
+    }
+
+    let header_len_bytes = [data[7], data[8], data[9], data[10]];
+    let header_len = u32::from_be_bytes(header_len_bytes);
+    if check_header_len(header_len as usize).is_err() {
+        // Enough of a byte-swapped header for parsing to report the byte order
+        if let Some(little_endian) = little_endian_header_len(header_len_bytes) {
+            reader.take(little_endian as u64).read_to_end(&mut data)?;
+        }
+        return Ok(data);
+    }
+    reader.by_ref().take(header_len as u64).read_to_end(&mut data)?;
+    let body_start = data.len();
+    reader.take(max_body_len.saturating_add(1)).read_to_end(&mut data)?;
+    if (data.len() - body_start) as u64 > max_body_len {
+        return Err(SpdfError::FormatError(format!(
+            "File body exceeds the {}-byte limit",
+            max_body_len
+        )));
+    }
+    Ok(data)
+}
+
+/// Reject a `HEADER_LEN` over `MAX_HEADER_LEN`
+fn check_header_len(header_len: usize) -> Result<(), SpdfError> {
+    if header_len > MAX_HEADER_LEN {
+        return Err(SpdfError::FormatError(format!(
+            "Invalid header length: {} exceeds the {}-byte limit",
+            header_len, MAX_HEADER_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// `HEADER_LEN` read little-endian, if that reading is a length a file
+/// could have
+fn little_endian_header_len(header_len_bytes: [u8; 4]) -> Option<usize> {
+    let little_endian = u32::from_le_bytes(header_len_bytes) as usize;
+    (little_endian > 0 && little_endian <= MAX_HEADER_LEN).then_some(little_endian)
+}
+
+/// Smallest valid version 1 file with a `header_len`-byte header
+///
+/// Assumes the shortest wrapped key and a single ciphertext byte. Saturates
+/// rather than wrapping for a `header_len` past `MAX_HEADER_LEN`.
+pub fn minimum_file_size(header_len: usize) -> usize {
+    header_len.saturating_add(
+        PEEK_LENGTH + WRAPPED_KEY_LENGTH + NONCE_LENGTH + 1 + TAG_LENGTH + SIGNATURE_LENGTH,
+    )
+}
+
+/// Whether `data` is the start of an SPDF file that was cut off
+///
+/// True when the bytes match the format as far as they go but end before
+/// the header, before the minimum version 1 body, or (version 2) before the
+/// end of the segment table plus signature. A version 1 file cut off inside
+/// its ciphertext is indistinguishable from a shorter one and fails
+/// signature verification instead.
+pub fn appears_incomplete(data: &[u8]) -> bool {
+    if !MAGIC.starts_with(&data[..data.len().min(MAGIC.len())]) {
+        return false;
+    }
+    if data.len() < PEEK_LENGTH {
+        return true;
+    }
+
+    let version = data[4];
+    let flags = u16::from_be_bytes([data[5], data[6]]);
+    let header_len = u32::from_be_bytes([data[7], data[8], data[9], data[10]]) as usize;
+    if header_len > MAX_HEADER_LEN {
+        // Malformed, not unfinished
+        return false;
+    }
+    let header_end = PEEK_LENGTH + header_len;
+    if data.len() < header_end {
+        return true;
+    }
+    if version == VERSION {
+        return data.len() < minimum_file_size(header_len);
+    }
+    if version != VERSION_SEGMENTED {
+        return false;
+    }
+
+    let encoding = HeaderEncoding::from_flags(flags);
+    let Ok(header) = decode_header::<SpdfHeader>(&data[PEEK_LENGTH..header_end], encoding) else {
+        return false;
+    };
+    let wrapped_key_length = wrapped_key_length(header.wrap_scheme()).unwrap_or(WRAPPED_KEY_LENGTH);
+    let segments_end = header
+        .segments
+        .iter()
+        .map(|segment| segment.offset.saturating_add(segment.length))
+        .max()
+        .unwrap_or(0);
+    let expected = (header_end + wrapped_key_length + SIGNATURE_LENGTH) as u64 + segments_end;
+    (data.len() as u64) < expected
+}
+
+/// Wrapped key length for a wrap scheme
+pub fn wrapped_key_length(wrap_scheme: &str) -> Result<usize, SpdfError> {
+    WRAP_SCHEMES
+        .iter()
+        .find(|(scheme, _)| *scheme == wrap_scheme)
+        .map(|(_, length)| *length)
+        .ok_or_else(|| SpdfError::FormatError(format!("unsupported wrap_scheme '{}'", wrap_scheme)))
+}
+
+/// Check that a wrapped key length is what its wrap scheme produces
+pub fn validate_wrapped_key_length(wrap_scheme: &str, length: usize) -> Result<(), SpdfError> {
+    let expected = wrapped_key_length(wrap_scheme)?;
+    if length != expected {
+        return Err(SpdfError::FormatError(format!(
+            "wrapped_key is {} bytes, wrap_scheme '{}' requires {}",
+            length, wrap_scheme, expected
+        )));
+    }
+    Ok(())
+}
+
+/// Read the format version byte and header JSON of a file
+///
+/// Only the prefix and header are read; the body is never loaded. CBOR
+/// headers are converted to JSON.
+pub fn read_raw_header(path: &str) -> Result<(u8, Vec<u8>), SpdfError> {
+    let (version, _, header_json) = read_raw_header_and_flags(path)?;
+    Ok((version, header_json))
+}
+
+/// Like `read_raw_header`, also returning the flags word
+pub fn read_raw_header_and_flags(path: &str) -> Result<(u8, u16, Vec<u8>), SpdfError> {
+    let mut file = fs::File::open(path)?;
+    let peek = peek_reader(&mut file)?;
+    let prefix = (peek.is_spdf, peek.version, peek.flags, peek.header_len);
+    let (version, flags, header_len) = match prefix {
+        (true, Some(version), Some(flags), Some(header_len)) => (version, flags, header_len),
+        (false, ..) => return Err(SpdfError::FormatError("Not an SPDF file".to_string())),
+        _ => return Err(SpdfError::FormatError("File too short for header".to_string())),
+    };
+    check_header_len(header_len as usize)?;
+
+    let mut header = Vec::new();
+    file.take(header_len as u64).read_to_end(&mut header)?;
+    if header.len() != header_len as usize {
+        return Err(SpdfError::FormatError("File too short for header".to_string()));
+    }
+    let header_json = header_to_json(&header, HeaderEncoding::from_flags(flags))?.into_owned();
+    Ok((version, flags, header_json))
+}
+
 /// Get basic info from SPDF without full parsing
 pub fn quick_info(data: &[u8]) -> Result<(String, String, String), SpdfError> {
     let spdf = SpdfFile::parse(data)?;
@@ -322,4 +1583,796 @@ mod tests {
         let result = SpdfFile::parse(data);
         assert!(matches!(result, Err(SpdfError::FormatError(_))));
     }
+
+    #[test]
+    fn test_parse_dispatches_on_version() {
+        use crate::test_support::{build_segmented_spdf, build_spdf};
+
+        let v1 = SpdfFile::parse(&build_spdf(b"%PDF")).unwrap();
+        assert_eq!((v1.version, v1.nonce.len()), (VERSION, NONCE_LENGTH));
+        let v2 = SpdfFile::parse(&build_segmented_spdf(&[b"%PDF"])).unwrap();
+        assert_eq!((v2.version, v2.segment_count()), (VERSION_SEGMENTED, 1));
+
+        let mut future = build_spdf(b"%PDF");
+        future[4] = 3;
+        match SpdfFile::parse(&future) {
+            Err(SpdfError::FormatError(message)) => {
+                assert!(message.starts_with("Unsupported version: 3"), "{}", message)
+            }
+            _ => panic!("expected version 3 to be unsupported"),
+        }
+    }
+
+    #[test]
+    fn test_parse_segmented_rejects_bad_table() {
+        let segments: [&[u8]; 2] = [b"one", b"two"];
+        let data = crate::test_support::build_segmented_spdf(&segments);
+        let spdf = SpdfFile::parse(&data).unwrap();
+        assert_eq!(spdf.segment_count(), 2);
+
+        let mut table = spdf.header.segments.clone();
+        table[1].offset += 1;
+        let area_len = spdf.ciphertext.len();
+        assert!(matches!(
+            validate_segment_table(&table, area_len),
+            Err(SpdfError::FormatError(_))
+        ));
+        assert!(validate_segment_table(&spdf.header.segments, area_len + 1).is_err());
+    }
+
+    #[test]
+    fn test_header_json_is_signed_bytes() {
+        let data = crate::test_support::build_spdf(b"%PDF-1.4");
+        let spdf = SpdfFile::parse(&data).unwrap();
+        let raw: serde_json::Value = serde_json::from_slice(spdf.header_bytes()).unwrap();
+        assert_eq!(raw, crate::test_support::sample_header());
+        assert_eq!(spdf.header_json().unwrap(), spdf.header_bytes());
+    }
+
+    #[test]
+    fn test_metadata_accessors() {
+        let mut header = crate::test_support::sample_header();
+        header["metadata"] = serde_json::json!({
+            "author": "Legal Team",
+            "page_count": "12",
+            "language": 7,
+            "department": "Legal"
+        });
+        let header: SpdfHeader = serde_json::from_value(header).unwrap();
+
+        assert_eq!(header.metadata_str("author"), Some("Legal Team"));
+        assert_eq!(header.metadata_str("language"), None);
+        assert_eq!(header.metadata_u64("page_count"), Some(12));
+        assert_eq!(header.metadata_u64("author"), None);
+        assert_eq!(
+            header.metadata_keys(),
+            ["author", "department", "language", "page_count"]
+        );
+        assert_eq!(
+            header.document_metadata(),
+            DocumentMetadata {
+                author: Some("Legal Team".to_string()),
+                page_count: Some(12),
+                language: None,
+            }
+        );
+
+        // Not an object at all
+        let lenient: DocumentMetadata = serde_json::from_str("[1, 2]").unwrap();
+        assert_eq!(lenient, DocumentMetadata::default());
+        let mut header = header;
+        header.metadata = serde_json::Value::Null;
+        assert!(header.metadata_keys().is_empty());
+    }
+
+    #[test]
+    fn test_cbor_header_round_trip() {
+        use crate::test_support::{build_spdf_with, sample_header, DEFAULT_FLAGS};
+
+        let mut header = sample_header();
+        header["metadata"] = serde_json::json!({ "department": "Legal", "pages": 12 });
+        let json_file = SpdfFile::parse(&build_spdf_with(&header, DEFAULT_FLAGS, b"%PDF")).unwrap();
+        let flags = DEFAULT_FLAGS | FLAG_CBOR_HEADER;
+        let cbor_file = SpdfFile::parse(&build_spdf_with(&header, flags, b"%PDF")).unwrap();
+
+        assert_eq!(json_file.header_encoding(), HeaderEncoding::Json);
+        assert_eq!(cbor_file.header_encoding(), HeaderEncoding::Cbor);
+        assert!(cbor_file.header_bytes().len() < json_file.header_bytes().len());
+        assert_eq!(
+            serde_json::to_value(&cbor_file.header).unwrap(),
+            serde_json::to_value(&json_file.header).unwrap()
+        );
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&cbor_file.header_json().unwrap()).unwrap(),
+            header
+        );
+
+        // The signature covers the CBOR bytes as stored
+        assert!(crate::verify::verify_signature(&cbor_file).is_ok());
+        let encoded = encode_header(&cbor_file.header, HeaderEncoding::Cbor).unwrap();
+        let decoded: SpdfHeader = decode_header(&encoded, HeaderEncoding::Cbor).unwrap();
+        assert_eq!(decoded.doc_id, json_file.header.doc_id);
+    }
+
+    #[test]
+    fn test_cbor_header_flag_mismatch() {
+        use crate::test_support::{build_spdf, DEFAULT_FLAGS};
+
+        // JSON header bytes declared as CBOR are parsed as CBOR, and rejected
+        let mut data = build_spdf(b"%PDF");
+        data[5..7].copy_from_slice(&(DEFAULT_FLAGS | FLAG_CBOR_HEADER).to_be_bytes());
+        assert!(matches!(SpdfFile::parse(&data), Err(SpdfError::FormatError(_))));
+    }
+
+    #[test]
+    fn test_permission_flag_mismatch() {
+        use crate::test_support::{build_spdf_with, sample_header, DEFAULT_FLAGS};
+
+        let consistent = build_spdf_with(&sample_header(), DEFAULT_FLAGS, b"%PDF");
+        assert!(SpdfFile::parse_with_mode(&consistent, ParseMode::Strict).is_ok());
+
+        // JSON allows printing and copying; the flags do not
+        let mut header = sample_header();
+        header["permissions"]["allow_print"] = true.into();
+        header["permissions"]["allow_copy"] = true.into();
+        let data = build_spdf_with(&header, DEFAULT_FLAGS, b"%PDF");
+        assert!(SpdfFile::parse(&data).is_ok());
+        match SpdfFile::parse_with_mode(&data, ParseMode::Strict) {
+            Err(SpdfError::FormatError(message)) => {
+                assert!(message.contains("print flag is clear but allow_print is true"));
+                assert!(message.contains("copy flag is clear but allow_copy is true"));
+                assert!(!message.contains("watermark"));
+            }
+            _ => panic!("expected a permission mismatch"),
+        }
+
+        // And the other way round
+        let flags = DEFAULT_FLAGS | FLAG_OFFLINE_ALLOWED;
+        let spdf = SpdfFile::parse(&build_spdf_with(&sample_header(), flags, b"%PDF")).unwrap();
+        let error = spdf.validate_permission_consistency().unwrap_err();
+        assert!(error.to_string().contains("offline flag is set but offline_days > 0 is false"));
+    }
+
+    #[test]
+    fn test_validate_header_fields() {
+        use crate::test_support::{build_spdf_with, sample_header, DEFAULT_FLAGS};
+
+        let spdf = SpdfFile::parse(&crate::test_support::build_spdf(b"%PDF")).unwrap();
+        assert_eq!(spdf.header.validate_header(), Ok(()));
+
+        let mut header = sample_header();
+        header["doc_id"] = "".into();
+        header["org_id"] = "  ".into();
+        header["server_url"] = "ftp://keys.example.com".into();
+        let data = build_spdf_with(&header, DEFAULT_FLAGS, b"%PDF");
+        let spdf = SpdfFile::parse(&data).unwrap();
+        let errors = spdf.header.validate_header().unwrap_err();
+        assert_eq!(
+            errors[..2],
+            [
+                HeaderError::EmptyField {
+                    field: "doc_id".to_string()
+                },
+                HeaderError::EmptyField {
+                    field: "org_id".to_string()
+                },
+            ]
+        );
+        assert!(matches!(&errors[2], HeaderError::InvalidServerUrl { reason, .. }
+            if reason.contains("ftp")));
+        match SpdfFile::parse_with_mode(&data, ParseMode::Strict) {
+            Err(SpdfError::FormatError(message)) => {
+                assert!(message.contains("doc_id is empty"), "{}", message)
+            }
+            _ => panic!("expected strict parsing to reject the header"),
+        }
+
+        let mut header = spdf.header.clone();
+        for url in ["", "keys.example.com/api", "https://"] {
+            header.server_url = url.to_string();
+            assert!(header.validate_header().is_err(), "{}", url);
+        }
+        header.server_url = "http://localhost:8000/".to_string();
+        header.doc_id = "DOC-1".to_string();
+        header.org_id = "org".to_string();
+        assert_eq!(header.validate_header(), Ok(()));
+        header.spdf_version = "9.9".to_string();
+        assert_eq!(
+            header.validate_header(),
+            Err(vec![HeaderError::UnsupportedVersion {
+                spdf_version: "9.9".to_string()
+            }])
+        );
+    }
+
+    #[test]
+    fn test_no_offline_cache_flag() {
+        use crate::test_support::{build_spdf_with, sample_header, DEFAULT_FLAGS};
+
+        let data = build_spdf_with(&sample_header(), DEFAULT_FLAGS | FLAG_OFFLINE_ALLOWED, b"%PDF");
+        let spdf = SpdfFile::parse(&data).unwrap();
+        assert!(spdf.allows_offline());
+        assert!(spdf.allows_key_cache());
+
+        let flags = DEFAULT_FLAGS | FLAG_OFFLINE_ALLOWED | FLAG_NO_OFFLINE_CACHE;
+        let data = build_spdf_with(&sample_header(), flags, b"%PDF");
+        let spdf = SpdfFile::parse(&data).unwrap();
+        assert!(spdf.requires_fresh_key());
+        assert!(!spdf.allows_key_cache());
+        assert!(!spdf.allows_offline());
+    }
+
+    /// Replace the header JSON of a valid file with `header_json`
+    fn with_raw_header(header_json: &str) -> Vec<u8> {
+        let original = crate::test_support::build_spdf(b"%PDF");
+        let header_len = u32::from_be_bytes(original[7..11].try_into().unwrap()) as usize;
+
+        let mut data = original[..7].to_vec();
+        data.extend_from_slice(&(header_json.len() as u32).to_be_bytes());
+        data.extend_from_slice(header_json.as_bytes());
+        data.extend_from_slice(&original[11 + header_len..]);
+        data
+    }
+
+    #[test]
+    fn test_duplicate_header_key() {
+        let header = serde_json::to_string(&crate::test_support::sample_header()).unwrap();
+        let open = header.strip_suffix('}').unwrap();
+        let permissive = r#""permissions":{"allow_print":true,"allow_copy":true,"max_devices":99}"#;
+
+        let smuggled = format!("{},{}}}", open, permissive);
+        match SpdfFile::parse(&with_raw_header(&smuggled)) {
+            Err(SpdfError::FormatError(msg)) => {
+                assert_eq!(msg, "duplicate header key 'permissions'")
+            }
+            other => panic!("expected FormatError, got {:?}", other.map(|_| ())),
+        }
+
+        // Keys the typed header ignores are checked too
+        let smuggled = format!(r#"{},"x_note":"a","x_note":"b"}}"#, open);
+        assert!(SpdfFile::parse(&with_raw_header(&smuggled)).is_err());
+
+        // Repeats in nested objects are separate keys at the top level
+        assert!(SpdfFile::parse(&with_raw_header(&header)).is_ok());
+        let nested = br#"{"metadata":{"a":1},"title":"a"}"#;
+        assert!(reject_duplicate_keys(nested, HeaderEncoding::Json).is_ok());
+    }
+
+    #[test]
+    fn test_parse_unsupported_spdf_version() {
+        use crate::test_support::{build_spdf_with, sample_header, DEFAULT_FLAGS};
+
+        let mut header = sample_header();
+        header["spdf_version"] = serde_json::json!("2.1");
+        let data = build_spdf_with(&header, DEFAULT_FLAGS, b"%PDF");
+
+        match SpdfFile::parse(&data) {
+            Err(SpdfError::FormatError(msg)) => {
+                assert_eq!(msg, "unsupported spdf_version '2.1'")
+            }
+            other => panic!("expected format error, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_parse_unsupported_enc_alg() {
+        use crate::test_support::{build_spdf_with, sample_header, DEFAULT_FLAGS};
+
+        let mut header = sample_header();
+        header["enc_alg"] = serde_json::json!("AES-128-CBC");
+        let data = build_spdf_with(&header, DEFAULT_FLAGS, b"%PDF");
+
+        match SpdfFile::parse(&data) {
+            Err(SpdfError::FormatError(msg)) => {
+                assert_eq!(msg, "unsupported enc_alg 'AES-128-CBC'")
+            }
+            other => panic!("expected format error, got {:?}", other.err()),
+        }
+
+        header["enc_alg"] = serde_json::json!(DEFAULT_ENC_ALG);
+        let spdf = SpdfFile::parse(&build_spdf_with(&header, DEFAULT_FLAGS, b"%PDF")).unwrap();
+        assert_eq!(spdf.header.enc_alg(), DEFAULT_ENC_ALG);
+        assert_eq!(spdf.header.sig_alg(), DEFAULT_SIG_ALG);
+    }
+
+    #[test]
+    fn test_wrap_scheme_lengths() {
+        use crate::test_support::{build_spdf_with_wrapped_key, sample_header, DEFAULT_FLAGS, DOC_KEY};
+
+        for (scheme, length) in WRAP_SCHEMES {
+            let mut header = sample_header();
+            header["wrap_scheme"] = serde_json::json!(scheme);
+            let wrapped_key = vec![0x5A; *length];
+            let data = build_spdf_with_wrapped_key(&header, DEFAULT_FLAGS, &wrapped_key, b"%PDF");
+
+            let spdf = SpdfFile::parse(&data).unwrap();
+            assert_eq!(spdf.header.wrap_scheme(), *scheme);
+            assert_eq!(spdf.wrapped_key, wrapped_key);
+            assert_eq!(crate::decrypt::decrypt_content(&spdf, &DOC_KEY).unwrap(), b"%PDF");
+        }
+
+        // Absent field means AES-KW
+        let spdf = SpdfFile::parse(&crate::test_support::build_spdf(b"%PDF")).unwrap();
+        assert_eq!(spdf.header.wrap_scheme(), DEFAULT_WRAP_SCHEME);
+        assert_eq!(spdf.wrapped_key.len(), WRAPPED_KEY_LENGTH);
+    }
+
+    #[test]
+    fn test_wrapped_key_length_mismatch() {
+        use crate::test_support::{build_spdf_with_wrapped_key, sample_header, DEFAULT_FLAGS};
+
+        match validate_wrapped_key_length("AES-KW", 60) {
+            Err(SpdfError::FormatError(msg)) => {
+                assert_eq!(msg, "wrapped_key is 60 bytes, wrap_scheme 'AES-KW' requires 40")
+            }
+            other => panic!("expected format error, got {:?}", other),
+        }
+        assert!(validate_wrapped_key_length("AES-GCM-SIV", 40).is_err());
+        assert!(validate_wrapped_key_length("AES-GCM-SIV", 60).is_ok());
+
+        // An AES-KW sized key in a file declaring AES-GCM-SIV
+        let mut header = sample_header();
+        header["wrap_scheme"] = serde_json::json!("AES-GCM-SIV");
+        // With the shortest body, one ciphertext byte, 41 bytes are left for it
+        let data = build_spdf_with_wrapped_key(&header, DEFAULT_FLAGS, &[0x5A; 40], b"%");
+        match SpdfFile::parse(&data) {
+            Err(SpdfError::FormatError(msg)) => {
+                assert_eq!(msg, "wrapped_key is 41 bytes, wrap_scheme 'AES-GCM-SIV' requires 60")
+            }
+            other => panic!("expected format error, got {:?}", other.err()),
+        }
+
+        header["wrap_scheme"] = serde_json::json!("RSA-OAEP");
+        let data = build_spdf_with_wrapped_key(&header, DEFAULT_FLAGS, &[0x5A; 40], b"%PDF");
+        assert!(matches!(SpdfFile::parse(&data), Err(SpdfError::FormatError(_))));
+    }
+
+    #[test]
+    fn test_minimum_file_size() {
+        let data = crate::test_support::build_spdf(b"%");
+        let header_len = u32::from_be_bytes(data[7..11].try_into().unwrap()) as usize;
+        assert_eq!(data.len(), minimum_file_size(header_len));
+        assert!(SpdfFile::parse(&data).is_ok());
+
+        let too_short = |len: usize| match SpdfFile::parse(&data[..len]) {
+            Err(SpdfError::FormatError(msg)) => msg,
+            other => panic!("expected format error at {} bytes, got {:?}", len, other.err()),
+        };
+
+        // Before HEADER_LEN can be read
+        assert_eq!(too_short(8), "File too short: 8 bytes, minimum 11 bytes");
+        // Inside the header
+        assert!(too_short(PEEK_LENGTH + header_len - 1).starts_with("Invalid header length"));
+        // After the header: the wrapped key, nonce, tag or signature is cut off
+        let minimum = minimum_file_size(header_len);
+        for len in [PEEK_LENGTH + header_len, PEEK_LENGTH + header_len + 20, minimum - 1] {
+            assert_eq!(
+                too_short(len),
+                format!(
+                    "File too short: {} bytes, minimum {} bytes for a {}-byte header",
+                    len, minimum, header_len
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_read_incomplete_file() {
+        use crate::test_support::{build_segmented_spdf, build_spdf};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("doc.spdf");
+        let path_str = path.to_str().unwrap();
+        let read_message = |data: &[u8]| {
+            fs::write(&path, data).unwrap();
+            match SpdfFile::read(path_str) {
+                Err(SpdfError::FormatError(msg)) => msg,
+                other => panic!("expected format error, got {:?}", other.err()),
+            }
+        };
+
+        let v1 = build_spdf(b"%PDF-1.7 partial");
+        let v2 = build_segmented_spdf(&[b"%PDF", b"-1.7"]);
+        let header_end = PEEK_LENGTH + u32::from_be_bytes(v1[7..11].try_into().unwrap()) as usize;
+        for partial in [&v1[..0], &v1[..6], &v1[..header_end - 5], &v1[..header_end + 20]] {
+            assert!(appears_incomplete(partial));
+            assert_eq!(read_message(partial), INCOMPLETE_FILE_MESSAGE);
+        }
+        // Truncated before the signature
+        let partial = &v2[..v2.len() - SIGNATURE_LENGTH];
+        assert!(appears_incomplete(partial));
+        assert_eq!(read_message(partial), INCOMPLETE_FILE_MESSAGE);
+
+        // Complete files and garbage are not reported as incomplete
+        assert!(!appears_incomplete(&v1));
+        assert!(!appears_incomplete(&v2));
+        assert!(!appears_incomplete(b"%PDF-1.7 not an spdf file at all"));
+        assert!(read_message(b"%PDF-1.7").starts_with("File too short"));
+    }
+
+    #[test]
+    fn test_read_with_keyfile() {
+        use crate::test_support::{build_spdf_with_wrapped_key, sample_header, DEFAULT_FLAGS};
+
+        let dir = tempfile::tempdir().unwrap();
+        let spdf_path = dir.path().join("doc.spdf");
+        let keyfile_path = dir.path().join("doc.key");
+
+        // Shipped with a zeroed placeholder in place of the wrapped key,
+        // and the real one's hash in the signed header
+        let mut header = sample_header();
+        header["wrapped_key_sha256"] = serde_json::json!(hex::encode(Sha256::digest([0x5A; 40])));
+        let data = build_spdf_with_wrapped_key(&header, DEFAULT_FLAGS, &[0; 40], b"%PDF");
+        fs::write(&spdf_path, data).unwrap();
+        fs::write(&keyfile_path, [0x5A; 40]).unwrap();
+
+        let spdf_path = spdf_path.to_str().unwrap();
+        let spdf = SpdfFile::read_with_keyfile(spdf_path, keyfile_path.to_str().unwrap()).unwrap();
+        assert_eq!(spdf.wrapped_key, [0x5A; 40]);
+        assert!(crate::verify::verify_signature(&spdf).is_ok());
+
+        // A substituted key of the right length
+        fs::write(&keyfile_path, [0xA5; 40]).unwrap();
+        match SpdfFile::read_with_keyfile(spdf_path, keyfile_path.to_str().unwrap()) {
+            Err(SpdfError::FormatError(msg)) => assert!(msg.contains("does not match")),
+            other => panic!("expected format error, got {:?}", other.err()),
+        }
+
+        fs::write(&keyfile_path, [0x5A; 39]).unwrap();
+        match SpdfFile::read_with_keyfile(spdf_path, keyfile_path.to_str().unwrap()) {
+            Err(SpdfError::FormatError(msg)) => {
+                assert_eq!(msg, "wrapped_key is 39 bytes, wrap_scheme 'AES-KW' requires 40")
+            }
+            other => panic!("expected format error, got {:?}", other.err()),
+        }
+
+        let missing = dir.path().join("missing.key");
+        let result = SpdfFile::read_with_keyfile(spdf_path, missing.to_str().unwrap());
+        assert!(matches!(result, Err(SpdfError::IoError(_))));
+
+        // No pinned hash: no external key is trusted
+        let data = build_spdf_with_wrapped_key(&sample_header(), DEFAULT_FLAGS, &[0; 40], b"%PDF");
+        fs::write(spdf_path, data).unwrap();
+        fs::write(&keyfile_path, [0x5A; 40]).unwrap();
+        match SpdfFile::read_with_keyfile(spdf_path, keyfile_path.to_str().unwrap()) {
+            Err(SpdfError::FormatError(msg)) => {
+                assert_eq!(msg, "header does not pin an external wrapped key")
+            }
+            other => panic!("expected format error, got {:?}", other.err()),
+        }
+    }
+
+    /// Reader that records how many bytes were pulled from it
+    struct CountingReader<'a> {
+        inner: &'a [u8],
+        read: usize,
+    }
+
+    impl Read for CountingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.inner.read(buf)?;
+            self.read += n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_peek_reads_only_prefix() {
+        let data = crate::test_support::build_spdf(&vec![0x25; 4096]);
+        let mut reader = CountingReader {
+            inner: &data,
+            read: 0,
+        };
+
+        let peek = peek_reader(&mut reader).unwrap();
+        assert!(peek.is_spdf);
+        assert_eq!(peek.version, Some(VERSION));
+        assert_eq!(
+            peek.header_len,
+            Some(SpdfFile::parse(&data).unwrap().header_json().unwrap().len() as u32)
+        );
+        assert_eq!(reader.read, PEEK_LENGTH);
+    }
+
+    #[test]
+    fn test_from_reader() {
+        use crate::test_support::{build_segmented_spdf, build_spdf};
+
+        for data in [build_spdf(b"%PDF-1.7 streamed"), build_segmented_spdf(&[b"%PDF", b"-1.7"])] {
+            let parsed = SpdfFile::parse(&data).unwrap();
+            let streamed = SpdfFile::from_reader(std::io::Cursor::new(&data)).unwrap();
+            assert_eq!(streamed.unsigned_data, parsed.unsigned_data);
+            assert_eq!(streamed.ciphertext, parsed.ciphertext);
+            assert_eq!(streamed.signature, parsed.signature);
+            assert!(crate::verify::verify_signature(&streamed).is_ok());
+        }
+
+        // Not SPDF: rejected after the prefix, the rest is never read
+        let garbage = vec![0x25; 4096];
+        let mut reader = CountingReader {
+            inner: &garbage,
+            read: 0,
+        };
+        match SpdfFile::from_reader(&mut reader) {
+            Err(SpdfError::FormatError(msg)) => assert!(msg.starts_with("Invalid magic bytes")),
+            other => panic!("expected format error, got {:?}", other.err()),
+        }
+        assert_eq!(reader.read, PEEK_LENGTH);
+
+        // Cut off inside the header: fails like a truncated file on disk
+        let data = build_spdf(b"%PDF-1.7 cut");
+        match SpdfFile::from_reader(&data[..PEEK_LENGTH + 20]) {
+            Err(SpdfError::FormatError(msg)) => assert_eq!(msg, INCOMPLETE_FILE_MESSAGE),
+            other => panic!("expected format error, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_read_framed_caps_body() {
+        let data = crate::test_support::build_spdf(b"%PDF-1.7 capped");
+        let header_len = u32::from_be_bytes(data[7..11].try_into().unwrap()) as usize;
+        let body_len = (data.len() - PEEK_LENGTH - header_len) as u64;
+
+        assert_eq!(read_framed(&data[..], 0, body_len).unwrap(), data);
+        match read_framed(&data[..], 0, body_len - 1) {
+            Err(SpdfError::FormatError(msg)) => {
+                assert_eq!(msg, format!("File body exceeds the {}-byte limit", body_len - 1))
+            }
+            other => panic!("expected format error, got {:?}", other.err()),
+        }
+
+        // A limit past usize::MAX saturates instead of overflowing
+        assert_eq!(read_framed(&data[..], data.len(), u64::MAX).unwrap(), data);
+    }
+
+    #[test]
+    fn test_peek_non_spdf_and_truncated() {
+        let peek = peek_reader(&b"%PDF-1.7 not an spdf"[..]).unwrap();
+        assert!(!peek.is_spdf);
+        assert_eq!(peek.version, None);
+
+        let peek = peek_reader(&b"SPDF\x01"[..]).unwrap();
+        assert!(peek.is_spdf);
+        assert_eq!(peek.header_len, None);
+    }
+
+    fn assert_contiguous(map: &[(SectionKind, Range<usize>)], file_len: usize) {
+        let mut expected_start = 0;
+        for (kind, range) in map {
+            assert_eq!(range.start, expected_start, "gap before {:?}", kind);
+            expected_start = range.end;
+        }
+        assert_eq!(expected_start, file_len);
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        use crate::decrypt::decrypt_content;
+        use crate::test_support::{
+            public_key_pem, sample_header, signing_key, DEFAULT_FLAGS, DOC_KEY,
+        };
+        use crate::verify::verify_signature_with_key;
+
+        let header: SpdfHeader = serde_json::from_value(sample_header()).unwrap();
+        let plaintext = b"%PDF-1.7 written locally";
+        let options = WriteOptions {
+            header: &header,
+            flags: DEFAULT_FLAGS,
+            plaintext,
+            doc_key: &DOC_KEY,
+            wrapped_key: &[0xAA; WRAPPED_KEY_LENGTH],
+            signing_key: &signing_key(),
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("doc.spdf");
+        let path = path.to_str().unwrap();
+        let written = SpdfFile::write(path, &options).unwrap();
+        let spdf = SpdfFile::read(path).unwrap();
+
+        assert_eq!(spdf.encode().unwrap(), written.encode().unwrap());
+        assert!(verify_signature_with_key(&spdf, &public_key_pem()).is_ok());
+        assert_eq!(decrypt_content(&spdf, &DOC_KEY).unwrap(), plaintext);
+        assert_eq!(spdf.header.doc_id, header.doc_id);
+
+        // Fresh nonce per file
+        let again = SpdfFile::create(&options).unwrap();
+        assert_ne!(again.nonce, spdf.nonce);
+
+        // The wrapped key must fit the wrap scheme
+        let short = WriteOptions {
+            wrapped_key: &[0xAA; 8],
+            ..options
+        };
+        assert!(matches!(
+            SpdfFile::create(&short),
+            Err(SpdfError::FormatError(_))
+        ));
+    }
+
+    #[test]
+    fn test_encode_matches_parsed_bytes() {
+        use crate::test_support::{build_segmented_spdf, build_spdf};
+
+        for data in [
+            build_spdf(b"%PDF-1.7 v1"),
+            build_segmented_spdf(&[b"one", b"two"]),
+        ] {
+            let spdf = SpdfFile::parse(&data).unwrap();
+            assert_eq!(spdf.build_unsigned_data(), spdf.unsigned_data);
+            assert_eq!(spdf.encode().unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_section_map_covers_file() {
+        let data = crate::test_support::build_spdf(b"%PDF-1.4 mapped");
+        let spdf = SpdfFile::parse(&data).unwrap();
+        let map = spdf.section_map();
+
+        let kinds: Vec<SectionKind> = map.iter().map(|(k, _)| *k).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                SectionKind::Magic,
+                SectionKind::Version,
+                SectionKind::Flags,
+                SectionKind::HeaderLen,
+                SectionKind::Header,
+                SectionKind::WrappedKey,
+                SectionKind::Nonce,
+                SectionKind::Ciphertext,
+                SectionKind::AuthTag,
+                SectionKind::Signature,
+            ]
+        );
+        assert_contiguous(&map, data.len());
+
+        let ciphertext = spdf.section_range(SectionKind::Ciphertext).unwrap();
+        assert_eq!(&data[ciphertext], &spdf.ciphertext[..]);
+        let signature = spdf.section_range(SectionKind::Signature).unwrap();
+        assert_eq!(&data[signature], &spdf.signature[..]);
+    }
+
+    #[test]
+    fn test_section_map_segmented() {
+        let segments: [&[u8]; 3] = [b"a", b"bb", b"ccc"];
+        let data = crate::test_support::build_segmented_spdf(&segments);
+        let spdf = SpdfFile::parse(&data).unwrap();
+        let map = spdf.section_map();
+
+        assert_contiguous(&map, data.len());
+        let segment = spdf.section_range(SectionKind::Segment(2)).unwrap();
+        assert_eq!(&data[segment], spdf.segment_bytes(2).unwrap());
+    }
+
+    #[test]
+    fn test_segment_bytes_bounds() {
+        use crate::test_support::{build_spdf_with, sample_header, DEFAULT_FLAGS};
+
+        // A version 1 header listing a segment far past the content
+        let mut header = sample_header();
+        let segment = serde_json::json!({
+            "offset": 1u64 << 40,
+            "length": 16,
+            "nonce": "00".repeat(NONCE_LENGTH),
+        });
+        header["segments"] = serde_json::json!([segment]);
+        let spdf = SpdfFile::parse(&build_spdf_with(&header, DEFAULT_FLAGS, b"%PDF")).unwrap();
+        assert!(matches!(spdf.segment_bytes(0), Err(SpdfError::FormatError(_))));
+
+        let data = crate::test_support::build_segmented_spdf(&[b"a", b"bb"]);
+        let mut spdf = SpdfFile::parse(&data).unwrap();
+        assert!(spdf.segment_bytes(2).is_err());
+        spdf.header.segments[1].length = u64::MAX;
+        match spdf.segment_bytes(1) {
+            Err(SpdfError::FormatError(msg)) => {
+                assert_eq!(msg, "Segment 1 lies outside the content")
+            }
+            other => panic!("expected format error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_watermark_image_parsed() {
+        use crate::watermark::tests::TINY_PNG_BASE64;
+
+        let mut header = crate::test_support::sample_header();
+        header["watermark"]["image_base64"] = serde_json::json!(TINY_PNG_BASE64);
+        header["watermark"]["image_layout"] = serde_json::json!("tiled");
+        header["watermark"]["opacity"] = serde_json::json!(0.3);
+        let data = crate::test_support::build_spdf_with(
+            &header,
+            crate::test_support::DEFAULT_FLAGS,
+            b"%PDF",
+        );
+
+        let spdf = SpdfFile::parse(&data).unwrap();
+        assert_eq!(spdf.watermark_image(), Some(TINY_PNG_BASE64));
+        assert_eq!(spdf.header.watermark.image_layout, WatermarkImageLayout::Tiled);
+        assert_eq!(spdf.header.watermark.opacity, Some(0.3));
+
+        let plain = SpdfFile::parse(&crate::test_support::build_spdf(b"%PDF")).unwrap();
+        assert_eq!(plain.watermark_image(), None);
+        assert_eq!(plain.header.watermark.image_layout, WatermarkImageLayout::Centered);
+    }
+
+    #[test]
+    fn test_invalid_watermark_image_rejected() {
+        let mut header = crate::test_support::sample_header();
+        header["watermark"]["image_base64"] = serde_json::json!("R0lGODlhAQABAAAAACw=");
+        let data = crate::test_support::build_spdf_with(
+            &header,
+            crate::test_support::DEFAULT_FLAGS,
+            b"%PDF",
+        );
+        assert!(matches!(SpdfFile::parse(&data), Err(SpdfError::FormatError(_))));
+    }
+
+    #[test]
+    fn test_little_endian_header_length() {
+        let mut data = crate::test_support::build_spdf(b"%PDF");
+        data[7..11].reverse();
+        for result in [SpdfFile::parse(&data), SpdfFile::from_reader(&data[..])] {
+            match result {
+                Err(SpdfError::FormatError(msg)) => {
+                    assert_eq!(msg, "header length looks little-endian; expected big-endian")
+                }
+                other => panic!("expected a byte order error, got {:?}", other.map(|_| ())),
+            }
+        }
+
+        // Lengths that fit neither way are reported as before
+        data[7..11].copy_from_slice(&[0x00, 0x00, 0xff, 0xff]);
+        assert!(matches!(
+            SpdfFile::parse(&data),
+            Err(SpdfError::FormatError(msg)) if msg.contains("exceeds file size")
+        ));
+    }
+
+    #[test]
+    fn test_header_len_limit() {
+        let mut data = crate::test_support::build_spdf(b"%PDF");
+        for header_len in [MAX_HEADER_LEN as u32 + 1, u32::MAX] {
+            data[7..11].copy_from_slice(&header_len.to_be_bytes());
+            let expected = format!(
+                "Invalid header length: {} exceeds the {}-byte limit",
+                header_len, MAX_HEADER_LEN
+            );
+            for result in [SpdfFile::parse(&data), SpdfFile::from_reader(&data[..])] {
+                match result {
+                    Err(SpdfError::FormatError(msg)) => assert_eq!(msg, expected),
+                    other => panic!("expected format error, got {:?}", other.err()),
+                }
+            }
+            assert!(!appears_incomplete(&data));
+        }
+        assert_eq!(minimum_file_size(usize::MAX), usize::MAX);
+    }
+
+    #[test]
+    fn test_estimated_open_memory_scales_with_ciphertext() {
+        use crate::test_support::build_spdf;
+
+        let small = SpdfFile::parse(&build_spdf(&[0x25; 3000])).unwrap();
+        let large = SpdfFile::parse(&build_spdf(&[0x25; 30_000])).unwrap();
+        // Ciphertext, plaintext and base64 of the plaintext
+        let expected = |spdf: &SpdfFile| {
+            let n = spdf.ciphertext.len();
+            2 * n + n.div_ceil(3) * 4
+        };
+        assert_eq!(small.estimated_open_memory(), expected(&small));
+        assert_eq!(large.estimated_open_memory(), expected(&large));
+        assert!(large.estimated_open_memory() > 9 * small.estimated_open_memory());
+        assert!(small.estimated_open_memory() > 3 * small.ciphertext.len());
+
+        // A compressed file counts what it inflates to
+        let mut compressed = SpdfFile::parse(&build_spdf(&[0x25; 3000])).unwrap();
+        compressed.flags |= FLAG_COMPRESSED;
+        compressed.header.content_length = Some(1 << 20);
+        let n = compressed.ciphertext.len();
+        let inflated: usize = 1 << 20;
+        let expected = 2 * n + inflated + inflated.div_ceil(3) * 4;
+        assert_eq!(compressed.estimated_open_memory(), expected);
+    }
 }
@@ -0,0 +1,88 @@
+// Telemetry Module - Anonymous install identifier for usage analytics
+//
+// The telemetry id is derived with HKDF from a random secret generated once
+// per install and kept in the app data directory. It is deliberately NOT
+// tied to hardware: it shares nothing with the device binding hash, so
+// analytics cannot be joined against license bindings. Clearing the app
+// data directory (or reinstalling) produces a new id.
+
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::fs;
+use std::path::Path;
+
+/// File in the app data directory holding the install secret
+const INSTALL_SECRET_FILE: &str = "install_secret";
+
+/// HKDF info string; bump the suffix to rotate every id at once
+const TELEMETRY_INFO: &[u8] = b"spdf_telemetry_id_v1";
+
+/// Length of the install secret in bytes
+const INSTALL_SECRET_LENGTH: usize = 32;
+
+/// Get the stable anonymous telemetry id for this install
+///
+/// Creates the install secret on first use.
+pub fn telemetry_id(app_data_dir: &Path) -> Result<String, String> {
+    let secret = load_or_create_install_secret(app_data_dir)?;
+
+    let hkdf = Hkdf::<Sha256>::new(None, &secret);
+    let mut id = [0u8; 16];
+    hkdf.expand(TELEMETRY_INFO, &mut id)
+        .map_err(|e| format!("Failed to derive telemetry id: {}", e))?;
+
+    Ok(hex::encode(id))
+}
+
+fn load_or_create_install_secret(app_data_dir: &Path) -> Result<Vec<u8>, String> {
+    let secret_path = app_data_dir.join(INSTALL_SECRET_FILE);
+
+    if secret_path.exists() {
+        let secret =
+            fs::read(&secret_path).map_err(|e| format!("Failed to read install secret: {}", e))?;
+        if secret.len() == INSTALL_SECRET_LENGTH {
+            return Ok(secret);
+        }
+    }
+
+    if !app_data_dir.exists() {
+        fs::create_dir_all(app_data_dir).map_err(|e| format!("Failed to create app dir: {}", e))?;
+    }
+
+    let mut secret = vec![0u8; INSTALL_SECRET_LENGTH];
+    OsRng.fill_bytes(&mut secret);
+    fs::write(&secret_path, &secret).map_err(|e| format!("Failed to write install secret: {}", e))?;
+
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_id::generate_device_hash;
+
+    #[test]
+    fn test_telemetry_id_is_stable() {
+        let dir = tempfile::tempdir().unwrap();
+        // Not created yet: telemetry_id creates it
+        let app_dir = dir.path().join("app");
+        let first = telemetry_id(&app_dir).unwrap();
+        let second = telemetry_id(&app_dir).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(first.len(), 32);
+    }
+
+    #[test]
+    fn test_telemetry_id_is_independent_of_device() {
+        let dir_a = tempfile::tempdir().unwrap();
+        let dir_b = tempfile::tempdir().unwrap();
+        let id_a = telemetry_id(dir_a.path()).unwrap();
+        let id_b = telemetry_id(dir_b.path()).unwrap();
+
+        // Same hardware, different installs
+        assert_ne!(id_a, id_b);
+        let device_hash = generate_device_hash().unwrap();
+        assert!(!device_hash.contains(&id_a));
+    }
+}
@@ -0,0 +1,144 @@
+// Test Support - Fixtures for unit tests
+//
+// Builds signed SPDF files in memory so tests can exercise the parser,
+// verifier and decryptor without fixture files on disk.
+
+use ed25519_dalek::{Signer, SigningKey};
+
+use crate::encrypt::encrypt_content_with_nonce;
+use crate::spdf_parser::{
+    encode_header, HeaderEncoding, DEFAULT_HASH_ALG, FLAG_DEVICE_BINDING, FLAG_WATERMARK_ENABLED,
+    MAGIC, NONCE_LENGTH, TAG_LENGTH, VERSION, VERSION_SEGMENTED, WRAPPED_KEY_LENGTH,
+};
+use crate::verify::{ed25519_public_key_pem, signature_digest};
+
+/// Document key used by every fixture
+pub const DOC_KEY: [u8; 32] = *b"spdf-test-document-key-32-bytes!";
+
+/// Seed of the fixture Ed25519 signing key
+pub const SIGNING_SEED: [u8; 32] = [0x07; 32];
+
+/// Flags set on fixtures unless a test overrides them
+pub const DEFAULT_FLAGS: u16 = FLAG_DEVICE_BINDING | FLAG_WATERMARK_ENABLED;
+
+pub fn signing_key() -> SigningKey {
+    SigningKey::from_bytes(&SIGNING_SEED)
+}
+
+/// PEM-encode the public half of an Ed25519 signing key
+pub fn public_key_pem_for(key: &SigningKey) -> String {
+    ed25519_public_key_pem(key.verifying_key().as_bytes())
+}
+
+pub fn public_key_pem() -> String {
+    public_key_pem_for(&signing_key())
+}
+
+/// Header JSON matching what the server emits for a version 1 file
+pub fn sample_header() -> serde_json::Value {
+    serde_json::json!({
+        "spdf_version": "1.0",
+        "doc_id": "DOC-TEST-001",
+        "org_id": "test_org",
+        "title": "Test Document",
+        "server_url": "https://spdf.example.com",
+        "created_at": "2025-01-01T00:00:00+00:00",
+        "public_key": public_key_pem(),
+        "permissions": {
+            "allow_print": false,
+            "allow_copy": false,
+            "max_devices": 2,
+            "offline_days": 0
+        },
+        "watermark": {
+            "enabled": true,
+            "text": "{{user_email}} | {{device_id}}"
+        },
+        "metadata": {}
+    })
+}
+
+/// Deterministic nonce for a fixture segment
+fn fixture_nonce(index: usize) -> [u8; NONCE_LENGTH] {
+    let mut nonce = [0x09; NONCE_LENGTH];
+    nonce[NONCE_LENGTH - 1] = index as u8;
+    nonce
+}
+
+fn encrypt(plaintext: &[u8], nonce: &[u8; NONCE_LENGTH]) -> Vec<u8> {
+    let encrypted =
+        encrypt_content_with_nonce(plaintext, &DOC_KEY, nonce).expect("fixture encryption failed");
+    let mut ciphertext_with_tag = encrypted.ciphertext;
+    ciphertext_with_tag.extend_from_slice(&encrypted.auth_tag);
+    ciphertext_with_tag
+}
+
+/// Frame and sign a file from its parts
+pub fn assemble(version: u8, flags: u16, header: &serde_json::Value, body: &[u8]) -> Vec<u8> {
+    let header_json = encode_header(header, HeaderEncoding::from_flags(flags)).unwrap();
+
+    let mut data = Vec::new();
+    data.extend_from_slice(MAGIC);
+    data.push(version);
+    data.extend_from_slice(&flags.to_be_bytes());
+    data.extend_from_slice(&(header_json.len() as u32).to_be_bytes());
+    data.extend_from_slice(&header_json);
+    data.extend_from_slice(body);
+
+    let hash_alg = header["hash_alg"].as_str().unwrap_or(DEFAULT_HASH_ALG);
+    let hash = signature_digest(hash_alg, &data).expect("fixture hash_alg not supported");
+    let signature = signing_key().sign(&hash);
+    data.extend_from_slice(&signature.to_bytes());
+    data
+}
+
+/// Build a signed version 1 file with a custom header and flags
+pub fn build_spdf_with(header: &serde_json::Value, flags: u16, plaintext: &[u8]) -> Vec<u8> {
+    build_spdf_with_wrapped_key(header, flags, &[0xAA; WRAPPED_KEY_LENGTH], plaintext)
+}
+
+/// Build a signed version 1 file with an explicit wrapped key
+pub fn build_spdf_with_wrapped_key(
+    header: &serde_json::Value,
+    flags: u16,
+    wrapped_key: &[u8],
+    plaintext: &[u8],
+) -> Vec<u8> {
+    let nonce = fixture_nonce(0);
+    let ciphertext_with_tag = encrypt(plaintext, &nonce);
+    assert_eq!(ciphertext_with_tag.len(), plaintext.len() + TAG_LENGTH);
+
+    let mut body = wrapped_key.to_vec();
+    body.extend_from_slice(&nonce);
+    body.extend_from_slice(&ciphertext_with_tag);
+    assemble(VERSION, flags, header, &body)
+}
+
+/// Build a signed version 1 file around `plaintext`
+pub fn build_spdf(plaintext: &[u8]) -> Vec<u8> {
+    build_spdf_with(&sample_header(), DEFAULT_FLAGS, plaintext)
+}
+
+/// Build a signed version 2 file with one encrypted segment per input
+pub fn build_segmented_spdf(segments: &[&[u8]]) -> Vec<u8> {
+    let mut header = sample_header();
+    header["spdf_version"] = serde_json::json!("2.0");
+
+    let mut table = Vec::new();
+    let mut area = Vec::new();
+    for (i, plaintext) in segments.iter().enumerate() {
+        let nonce = fixture_nonce(i);
+        let encrypted = encrypt(plaintext, &nonce);
+        table.push(serde_json::json!({
+            "offset": area.len(),
+            "length": encrypted.len(),
+            "nonce": hex::encode(nonce),
+        }));
+        area.extend_from_slice(&encrypted);
+    }
+    header["segments"] = serde_json::Value::Array(table);
+
+    let mut body = vec![0xAA; WRAPPED_KEY_LENGTH];
+    body.extend_from_slice(&area);
+    assemble(VERSION_SEGMENTED, DEFAULT_FLAGS, &header, &body)
+}
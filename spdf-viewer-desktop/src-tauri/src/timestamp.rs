@@ -0,0 +1,178 @@
+// Timestamp Module - Time-stamp token verification
+//
+// An org can have a time-stamping authority (TSA) attest that a document's
+// content existed at a given time, for audit trails that need more than the
+// self-reported `created_at`. The token is modelled on RFC 3161: the TSA
+// signs (with Ed25519) a TSTInfo-like record of the time and a SHA-256
+// message imprint.
+//
+// The token lives in the signed header, so it cannot cover the document
+// signature that in turn covers it. It time-stamps everything the signature
+// covers after the header instead (wrapped key, nonces, ciphertext and
+// tags), and the org signature binds the token to the file.
+
+use base64::{engine::general_purpose, Engine as _};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::spdf_parser::{SectionKind, SpdfError, SpdfFile, SIGNATURE_LENGTH};
+use crate::verify::parse_ed25519_public_key_pem;
+
+/// Domain separator at the start of every signed TSTInfo record
+const TST_INFO_PREFIX: &[u8] = b"spdf-tst-v1\n";
+
+/// The bytes a time-stamp token's imprint covers
+pub fn timestamped_bytes(spdf: &SpdfFile) -> &[u8] {
+    let body_start = spdf
+        .section_range(SectionKind::Header)
+        .map_or(0, |range| range.end);
+    &spdf.unsigned_data[body_start..]
+}
+
+/// Hex SHA-256 message imprint of a file's time-stamped bytes
+pub fn message_imprint(spdf: &SpdfFile) -> String {
+    hex::encode(Sha256::digest(timestamped_bytes(spdf)))
+}
+
+/// The record a TSA signs for `gen_time` and `message_imprint`
+pub fn tst_info(gen_time: &str, message_imprint: &str) -> Vec<u8> {
+    let mut record = TST_INFO_PREFIX.to_vec();
+    record.extend_from_slice(gen_time.as_bytes());
+    record.push(b'\n');
+    record.extend_from_slice(message_imprint.as_bytes());
+    record
+}
+
+/// Verify the header's time-stamp token against a TSA public key (PEM)
+///
+/// Returns the attested time, or `None` if the file has no token (nothing
+/// is checked then). Fails if the imprint does not match the content or
+/// the TSA signature does not verify.
+pub fn verify_timestamp(
+    spdf: &SpdfFile,
+    tsa_public_key_pem: &str,
+) -> Result<Option<OffsetDateTime>, SpdfError> {
+    let Some(token) = &spdf.header.timestamp_token else {
+        return Ok(None);
+    };
+
+    if !token
+        .message_imprint
+        .eq_ignore_ascii_case(&message_imprint(spdf))
+    {
+        return Err(SpdfError::SignatureError(
+            "timestamp token does not match the document content".to_string(),
+        ));
+    }
+
+    let key_bytes = parse_ed25519_public_key_pem(tsa_public_key_pem)?;
+    let tsa_key = VerifyingKey::from_bytes(&key_bytes)
+        .map_err(|e| SpdfError::SignatureError(format!("Invalid TSA public key: {}", e)))?;
+    let signature_bytes = general_purpose::STANDARD
+        .decode(&token.signature)
+        .map_err(|e| SpdfError::SignatureError(format!("Invalid timestamp signature: {}", e)))?;
+    let signature_bytes: [u8; SIGNATURE_LENGTH] =
+        signature_bytes.as_slice().try_into().map_err(|_| {
+            SpdfError::SignatureError(format!(
+                "Invalid timestamp signature length: expected {}, got {}",
+                SIGNATURE_LENGTH,
+                signature_bytes.len()
+            ))
+        })?;
+
+    tsa_key
+        .verify(
+            &tst_info(&token.gen_time, &token.message_imprint),
+            &Signature::from_bytes(&signature_bytes),
+        )
+        .map_err(|_| {
+            SpdfError::SignatureError("timestamp signature does not verify".to_string())
+        })?;
+
+    OffsetDateTime::parse(&token.gen_time, &Rfc3339)
+        .map(Some)
+        .map_err(|e| SpdfError::FormatError(format!("Invalid timestamp gen_time: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{
+        build_spdf, build_spdf_with, public_key_pem_for, sample_header, DEFAULT_FLAGS,
+    };
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const PLAINTEXT: &[u8] = b"%PDF-1.7 time-stamped";
+    const GEN_TIME: &str = "2025-03-01T12:00:00Z";
+
+    fn tsa_key() -> SigningKey {
+        SigningKey::from_bytes(&[0x11; 32])
+    }
+
+    /// Build a file whose header carries `token`
+    fn with_token(token: serde_json::Value) -> SpdfFile {
+        let mut header = sample_header();
+        header["timestamp_token"] = token;
+        SpdfFile::parse(&build_spdf_with(&header, DEFAULT_FLAGS, PLAINTEXT)).unwrap()
+    }
+
+    /// A token the TSA issued for `PLAINTEXT` at `gen_time`
+    fn issue_token(gen_time: &str) -> serde_json::Value {
+        // The fixture body does not depend on the header, so the imprint
+        // of an untimestamped build is the imprint of the final file
+        let imprint = message_imprint(&SpdfFile::parse(&build_spdf(PLAINTEXT)).unwrap());
+        let signature = tsa_key().sign(&tst_info(gen_time, &imprint));
+        serde_json::json!({
+            "gen_time": gen_time,
+            "message_imprint": imprint,
+            "signature": general_purpose::STANDARD.encode(signature.to_bytes()),
+        })
+    }
+
+    #[test]
+    fn test_valid_token_returns_attested_time() {
+        let spdf = with_token(issue_token(GEN_TIME));
+        let attested = verify_timestamp(&spdf, &public_key_pem_for(&tsa_key())).unwrap();
+        assert_eq!(
+            attested,
+            Some(OffsetDateTime::parse(GEN_TIME, &Rfc3339).unwrap())
+        );
+
+        // Another TSA's key does not verify it
+        let other = public_key_pem_for(&SigningKey::from_bytes(&[0x12; 32]));
+        assert!(verify_timestamp(&spdf, &other).is_err());
+    }
+
+    #[test]
+    fn test_tampered_token_rejected() {
+        // Time moved after the TSA signed
+        let mut token = issue_token(GEN_TIME);
+        token["gen_time"] = serde_json::json!("2024-01-01T00:00:00Z");
+        match verify_timestamp(&with_token(token), &public_key_pem_for(&tsa_key())) {
+            Err(SpdfError::SignatureError(msg)) => {
+                assert_eq!(msg, "timestamp signature does not verify")
+            }
+            other => panic!("expected signature error, got {:?}", other),
+        }
+
+        // Token copied onto different content
+        let mut header = sample_header();
+        header["timestamp_token"] = issue_token(GEN_TIME);
+        let other_content =
+            SpdfFile::parse(&build_spdf_with(&header, DEFAULT_FLAGS, b"%PDF-1.7 other")).unwrap();
+        match verify_timestamp(&other_content, &public_key_pem_for(&tsa_key())) {
+            Err(SpdfError::SignatureError(msg)) => {
+                assert_eq!(msg, "timestamp token does not match the document content")
+            }
+            other => panic!("expected signature error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_absent_token_skipped() {
+        let spdf = SpdfFile::parse(&build_spdf(PLAINTEXT)).unwrap();
+        assert_eq!(verify_timestamp(&spdf, "not a key").unwrap(), None);
+    }
+}
@@ -0,0 +1,316 @@
+// Trust Module - Trust-on-first-use pinning of organization signing keys
+//
+// The first time a document from an organization is opened, the
+// fingerprint of its header public key is pinned together with the
+// organization's server URL (~/.spdf/pins.json). Later documents must
+// carry the same key.
+//
+// A mismatch is not immediately fatal: organizations rotate keys. The
+// viewer asks the PINNED server (never the header's `server_url`, which an
+// attacker controls) for the organization's current key set via
+// `GET {server_url}/keys/org/{org_id}`. If the new key is in that set the
+// pin is updated; otherwise the document is rejected.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::spdf_parser::{SpdfError, SpdfHeader};
+use crate::verify::parse_ed25519_public_key_pem;
+
+/// A pinned organization key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyPin {
+    /// Hex SHA-256 of the raw 32-byte Ed25519 public key
+    pub fingerprint: String,
+    /// Server the pin was first seen with; rotations are checked against it
+    pub server_url: String,
+}
+
+/// Outcome of checking a header key against the pins
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum PinCheck {
+    /// No pin existed; the key has now been pinned
+    FirstUse,
+    Match,
+    /// The header key differs from the pinned one
+    Mismatch { pinned: KeyPin },
+    /// The pinned server vouched for the new key and the pin was updated
+    Rotated { previous: KeyPin },
+}
+
+/// Response of `GET /keys/org/{org_id}`
+#[derive(Debug, Deserialize)]
+struct OrgKeysResponse {
+    org_id: String,
+    keys: Vec<String>,
+}
+
+/// Persistent org_id -> key pin map
+#[derive(Debug)]
+pub struct TrustStore {
+    path: PathBuf,
+    pins: BTreeMap<String, KeyPin>,
+}
+
+/// Fingerprint of a PEM-encoded Ed25519 public key
+pub fn key_fingerprint(public_key_pem: &str) -> Result<String, SpdfError> {
+    let key = parse_ed25519_public_key_pem(public_key_pem)?;
+    Ok(hex::encode(Sha256::digest(key)))
+}
+
+/// Parse a fingerprint as organizations publish it: hex in either case,
+/// optionally grouped with colons or whitespace
+pub fn normalize_fingerprint(fingerprint: &str) -> Result<[u8; 32], SpdfError> {
+    let digits: String = fingerprint
+        .chars()
+        .filter(|c| *c != ':' && !c.is_whitespace())
+        .collect();
+    hex::decode(&digits)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| SpdfError::FormatError(format!("Invalid key fingerprint '{}'", fingerprint)))
+}
+
+impl TrustStore {
+    /// Default pin file, `~/.spdf/pins.json`
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::home_dir().map(|home| home.join(".spdf").join("pins.json"))
+    }
+
+    /// Load pins from `path`; a missing file means no pins yet
+    pub fn load(path: &Path) -> Result<Self, SpdfError> {
+        let pins = if path.exists() {
+            serde_json::from_slice(&fs::read(path)?)?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(TrustStore {
+            path: path.to_path_buf(),
+            pins,
+        })
+    }
+
+    pub fn save(&self) -> Result<(), SpdfError> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_vec_pretty(&self.pins)
+            .map_err(|e| SpdfError::FormatError(format!("Failed to serialize pins: {}", e)))?;
+        fs::write(&self.path, json)?;
+        Ok(())
+    }
+
+    pub fn pin(&self, org_id: &str) -> Option<&KeyPin> {
+        self.pins.get(org_id)
+    }
+
+    /// Check a header's key against its organization's pin
+    ///
+    /// Pins (and saves) the key if the organization has never been seen.
+    pub fn check(&mut self, header: &SpdfHeader) -> Result<PinCheck, SpdfError> {
+        let fingerprint = key_fingerprint(&header.public_key)?;
+
+        match self.pins.get(&header.org_id) {
+            Some(pin) if pin.fingerprint == fingerprint => Ok(PinCheck::Match),
+            Some(pin) => Ok(PinCheck::Mismatch { pinned: pin.clone() }),
+            None => {
+                self.pins.insert(
+                    header.org_id.clone(),
+                    KeyPin {
+                        fingerprint,
+                        server_url: header.server_url.clone(),
+                    },
+                );
+                self.save()?;
+                Ok(PinCheck::FirstUse)
+            }
+        }
+    }
+
+    /// Accept a rotated header key if the organization's trusted key set
+    /// (fetched from the pinned server) contains it
+    ///
+    /// Updates and saves the pin on success; leaves it untouched otherwise.
+    pub fn accept_rotation(
+        &mut self,
+        header: &SpdfHeader,
+        trusted_keys: &[String],
+    ) -> Result<(), SpdfError> {
+        let fingerprint = key_fingerprint(&header.public_key)?;
+        let trusted = trusted_keys
+            .iter()
+            .filter_map(|pem| key_fingerprint(pem).ok())
+            .any(|trusted| trusted == fingerprint);
+
+        if !trusted {
+            return Err(SpdfError::SignatureError(format!(
+                "Key {} is not trusted by organization {}",
+                fingerprint, header.org_id
+            )));
+        }
+
+        let pin = self.pins.get_mut(&header.org_id).ok_or_else(|| {
+            SpdfError::SignatureError(format!("No pinned key for organization {}", header.org_id))
+        })?;
+        println!(
+            "Signing key for {} rotated: {} -> {}",
+            header.org_id, pin.fingerprint, fingerprint
+        );
+        pin.fingerprint = fingerprint;
+        self.save()
+    }
+}
+
+/// Fetch an organization's current trusted keys from its server, over
+/// `client` (which should be the one pinned for that server, if any)
+pub async fn fetch_trusted_keys(
+    client: &reqwest::Client,
+    server_url: &str,
+    org_id: &str,
+    auth_token: &str,
+) -> Result<Vec<String>, SpdfError> {
+    let url = format!("{}/keys/org/{}", server_url.trim_end_matches('/'), org_id);
+
+    let res = client
+        .get(&url)
+        .bearer_auth(auth_token)
+        .send()
+        .await
+        .map_err(|e| SpdfError::NetworkError(format!("Failed to fetch org keys: {}", e)))?;
+
+    if !res.status().is_success() {
+        return Err(SpdfError::NetworkError(format!(
+            "Failed to fetch org keys: {}",
+            res.status()
+        )));
+    }
+
+    let body: OrgKeysResponse = res
+        .json()
+        .await
+        .map_err(|e| SpdfError::NetworkError(format!("Invalid org keys response: {}", e)))?;
+    if body.org_id != org_id {
+        return Err(SpdfError::NetworkError(format!(
+            "Org keys response is for {}, expected {}",
+            body.org_id, org_id
+        )));
+    }
+
+    Ok(body.keys)
+}
+
+/// Full pinning flow: pin on first use, and on a mismatch accept the new
+/// key only if the pinned server vouches for it
+///
+/// Never returns `Mismatch`: an unaccepted key is an error.
+pub async fn enforce_pin(
+    client: &reqwest::Client,
+    store: &mut TrustStore,
+    header: &SpdfHeader,
+    auth_token: &str,
+) -> Result<PinCheck, SpdfError> {
+    match store.check(header)? {
+        PinCheck::Mismatch { pinned } => {
+            let trusted_keys =
+                fetch_trusted_keys(client, &pinned.server_url, &header.org_id, auth_token).await?;
+            store.accept_rotation(header, &trusted_keys)?;
+            Ok(PinCheck::Rotated { previous: pinned })
+        }
+        check => Ok(check),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{public_key_pem, public_key_pem_for, sample_header};
+    use ed25519_dalek::SigningKey;
+
+    fn temp_store() -> (tempfile::TempDir, TrustStore) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = TrustStore::load(&dir.path().join("pins.json")).unwrap();
+        (dir, store)
+    }
+
+    fn header_with_key(public_key: &str) -> SpdfHeader {
+        let mut header = sample_header();
+        header["public_key"] = serde_json::json!(public_key);
+        serde_json::from_value(header).unwrap()
+    }
+
+    fn rotated_key_pem(seed: u8) -> String {
+        public_key_pem_for(&SigningKey::from_bytes(&[seed; 32]))
+    }
+
+    #[test]
+    fn test_normalize_fingerprint() {
+        let fingerprint = key_fingerprint(&public_key_pem()).unwrap();
+        let grouped = fingerprint
+            .to_uppercase()
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| std::str::from_utf8(pair).unwrap())
+            .collect::<Vec<_>>()
+            .join(":");
+
+        let expected = normalize_fingerprint(&fingerprint).unwrap();
+        assert_eq!(normalize_fingerprint(&grouped).unwrap(), expected);
+        assert!(normalize_fingerprint("abcd").is_err());
+        assert!(normalize_fingerprint(&fingerprint.replace('a', "g")).is_err());
+    }
+
+    #[test]
+    fn test_pin_on_first_use() {
+        let (dir, mut store) = temp_store();
+        let header = header_with_key(&public_key_pem());
+
+        assert_eq!(store.check(&header).unwrap(), PinCheck::FirstUse);
+        assert_eq!(store.check(&header).unwrap(), PinCheck::Match);
+
+        let reloaded = TrustStore::load(&dir.path().join("pins.json")).unwrap();
+        let pin = reloaded.pin("test_org").unwrap();
+        assert_eq!(pin.fingerprint, key_fingerprint(&public_key_pem()).unwrap());
+        assert_eq!(pin.server_url, "https://spdf.example.com");
+    }
+
+    #[test]
+    fn test_legitimate_rotation_accepted() {
+        let (dir, mut store) = temp_store();
+        store.check(&header_with_key(&public_key_pem())).unwrap();
+
+        let rotated = header_with_key(&rotated_key_pem(0x11));
+        assert!(matches!(store.check(&rotated).unwrap(), PinCheck::Mismatch { .. }));
+
+        let server_keys = vec![public_key_pem(), rotated_key_pem(0x11)];
+        store.accept_rotation(&rotated, &server_keys).unwrap();
+        assert_eq!(store.check(&rotated).unwrap(), PinCheck::Match);
+
+        let reloaded = TrustStore::load(&dir.path().join("pins.json")).unwrap();
+        assert_eq!(
+            reloaded.pin("test_org").unwrap().fingerprint,
+            key_fingerprint(&rotated_key_pem(0x11)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_unknown_key_rejected() {
+        let (_dir, mut store) = temp_store();
+        let original = header_with_key(&public_key_pem());
+        store.check(&original).unwrap();
+
+        let attacker = header_with_key(&rotated_key_pem(0x66));
+        let server_keys = vec![public_key_pem(), rotated_key_pem(0x11)];
+        assert!(matches!(
+            store.accept_rotation(&attacker, &server_keys),
+            Err(SpdfError::SignatureError(_))
+        ));
+
+        // Pin is unchanged
+        assert_eq!(store.check(&original).unwrap(), PinCheck::Match);
+        assert!(matches!(store.check(&attacker).unwrap(), PinCheck::Mismatch { .. }));
+    }
+}
@@ -6,8 +6,93 @@
 use ed25519_dalek::{Signature, Verifier, VerifyingKey};
 use sha2::{Sha256, Digest};
 use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
 
-use crate::spdf_parser::{SpdfFile, SpdfError, SIGNATURE_LENGTH};
+use crate::decrypt::integrity_precheck;
+use crate::key_source::{verify_signature_against_trust_store, KeySource};
+use crate::spdf_parser::{
+    SpdfFile, SpdfError, DEFAULT_SIG_ALG, SIGNATURE_LENGTH, SUPPORTED_HASH_ALGS, VERSION,
+    VERSION_SEGMENTED,
+};
+
+/// The step at which signature verification failed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "step", content = "detail", rename_all = "snake_case")]
+pub enum VerifyFailure {
+    /// Public key missing, not base64, not an Ed25519 SubjectPublicKeyInfo,
+    /// or not a valid Ed25519 point
+    KeyParse(String),
+    /// Decoded public key shorter than 32 bytes
+    KeyLength { actual: usize },
+    SignatureLength { expected: usize, actual: usize },
+    /// `hash_alg` not supported by this build
+    HashAlg(String),
+    /// `sig_alg` not supported by this build
+    SigAlg(String),
+    /// Version byte and `header.spdf_version` describe different layouts
+    VersionMismatch { version: u8, spdf_version: String },
+    /// Well-formed key and signature, but the signature does not match
+    VerifyFailed(String),
+    /// Header `public_key` is not the key trusted for the document's org
+    KeyMismatch { org_id: String },
+}
+
+impl VerifyFailure {
+    fn message(&self) -> String {
+        match self {
+            VerifyFailure::KeyParse(msg) => msg.clone(),
+            VerifyFailure::KeyLength { actual } => format!(
+                "PEM decoded data too short: {} bytes, expected at least 32",
+                actual
+            ),
+            VerifyFailure::SignatureLength { expected, actual } => format!(
+                "Invalid signature length: expected {}, got {}",
+                expected, actual
+            ),
+            VerifyFailure::HashAlg(alg) => format!("Unsupported hash_alg '{}'", alg),
+            VerifyFailure::SigAlg(alg) => format!("Unsupported sig_alg '{}'", alg),
+            VerifyFailure::VersionMismatch { version, spdf_version } => format!(
+                "version/layout mismatch: version byte {} but spdf_version '{}'",
+                version, spdf_version
+            ),
+            VerifyFailure::VerifyFailed(msg) => format!("Signature verification failed: {}", msg),
+            VerifyFailure::KeyMismatch { org_id } => format!(
+                "Header public key does not match the trusted key for {}",
+                org_id
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for VerifyFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Signature error: {}", self.message())
+    }
+}
+
+impl std::error::Error for VerifyFailure {}
+
+impl From<VerifyFailure> for SpdfError {
+    fn from(failure: VerifyFailure) -> Self {
+        SpdfError::SignatureError(failure.message())
+    }
+}
+
+/// Digest of the signed bytes under `hash_alg`; the Ed25519 signature is
+/// over this digest, not the raw bytes
+///
+/// `None` if this build does not support `hash_alg` (BLAKE3 needs the
+/// `blake3` feature).
+pub fn signature_digest(hash_alg: &str, data: &[u8]) -> Option<Vec<u8>> {
+    if !SUPPORTED_HASH_ALGS.contains(&hash_alg) {
+        return None;
+    }
+    match hash_alg {
+        #[cfg(feature = "blake3")]
+        "blake3" => Some(blake3::hash(data).as_bytes().to_vec()),
+        _ => Some(Sha256::digest(data).to_vec()),
+    }
+}
 
 /// Verify the Ed25519 signature of an SPDF file
 ///
@@ -15,47 +100,75 @@ use crate::spdf_parser::{SpdfFile, SpdfError, SIGNATURE_LENGTH};
 /// * `spdf` - Parsed SPDF file
 ///
 /// # Returns
-/// Ok(()) if signature is valid, Err otherwise
-pub fn verify_signature(spdf: &SpdfFile) -> Result<(), SpdfError> {
+/// Ok(()) if signature is valid, otherwise the step that failed
+pub fn verify_signature(spdf: &SpdfFile) -> Result<(), VerifyFailure> {
     // Get public key from header
     let public_key_pem = &spdf.header.public_key;
     if public_key_pem.is_empty() {
-        return Err(SpdfError::SignatureError("No public key in header".to_string()));
+        return Err(VerifyFailure::KeyParse("No public key in header".to_string()));
     }
 
-    // Parse public key
-    let public_key_bytes = parse_ed25519_public_key_pem(public_key_pem)?;
-    
-    // Create verifying key
-    let verifying_key = VerifyingKey::from_bytes(&public_key_bytes)
-        .map_err(|e| SpdfError::SignatureError(format!("Invalid public key: {}", e)))?;
+    verify_signature_with_key(spdf, public_key_pem)
+}
 
-    // Validate signature length
-    if spdf.signature.len() != SIGNATURE_LENGTH {
-        return Err(SpdfError::SignatureError(format!(
-            "Invalid signature length: expected {}, got {}",
-            SIGNATURE_LENGTH,
-            spdf.signature.len()
-        )));
-    }
+/// DER prefix of an Ed25519 SubjectPublicKeyInfo
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
 
-    // Hash the unsigned data
-    let mut hasher = Sha256::new();
-    hasher.update(&spdf.unsigned_data);
-    let hash = hasher.finalize();
-
-    // Parse signature
-    let sig_bytes: [u8; 64] = spdf.signature[..]
-        .try_into()
-        .map_err(|_| SpdfError::SignatureError("Invalid signature format".to_string()))?;
-    let signature = Signature::from_bytes(&sig_bytes);
+/// PEM-encode a raw Ed25519 public key as SubjectPublicKeyInfo
+pub fn ed25519_public_key_pem(key: &[u8; 32]) -> String {
+    let mut der = ED25519_SPKI_PREFIX.to_vec();
+    der.extend_from_slice(key);
+    format!(
+        "-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----\n",
+        general_purpose::STANDARD.encode(der)
+    )
+}
 
-    // Verify
-    verifying_key
-        .verify(&hash, &signature)
-        .map_err(|e| SpdfError::SignatureError(format!("Signature verification failed: {}", e)))?;
+/// DER contents of the id-Ed25519 object identifier, 1.3.101.112
+const ED25519_OID: [u8; 3] = [0x2b, 0x65, 0x70];
 
-    Ok(())
+const DER_SEQUENCE: u8 = 0x30;
+const DER_OID: u8 = 0x06;
+const DER_BIT_STRING: u8 = 0x03;
+
+fn key_parse(message: impl Into<String>) -> VerifyFailure {
+    VerifyFailure::KeyParse(message.into())
+}
+
+/// Split the DER element `what`, which must have tag `tag`, off the front
+/// of `der`; returns its contents and what follows it
+fn der_element<'a>(
+    der: &'a [u8],
+    tag: u8,
+    what: &str,
+) -> Result<(&'a [u8], &'a [u8]), VerifyFailure> {
+    let truncated = || key_parse(format!("Public key DER truncated in {}", what));
+    let (&actual, rest) = der.split_first().ok_or_else(truncated)?;
+    if actual != tag {
+        return Err(key_parse(format!(
+            "Public key DER: expected {} (tag 0x{:02x}), found tag 0x{:02x}",
+            what, tag, actual
+        )));
+    }
+    let (&first, mut rest) = rest.split_first().ok_or_else(truncated)?;
+    let length = if first < 0x80 {
+        first as usize
+    } else {
+        // Long form; no SubjectPublicKeyInfo needs more than two length bytes
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 2 || rest.len() < count {
+            return Err(key_parse(format!("Public key DER: invalid length in {}", what)));
+        }
+        let (bytes, after) = rest.split_at(count);
+        rest = after;
+        bytes.iter().fold(0, |length, &b| length << 8 | b as usize)
+    };
+    if rest.len() < length {
+        return Err(truncated());
+    }
+    Ok(rest.split_at(length))
 }
 
 /// Parse Ed25519 public key from PEM format
@@ -64,7 +177,11 @@ pub fn verify_signature(spdf: &SpdfFile) -> Result<(), SpdfError> {
 /// -----BEGIN PUBLIC KEY-----
 /// <base64-encoded DER>
 /// -----END PUBLIC KEY-----
-fn parse_ed25519_public_key_pem(pem: &str) -> Result<[u8; 32], SpdfError> {
+///
+/// The DER must be a SubjectPublicKeyInfo for id-Ed25519 (RFC 8410): an
+/// AlgorithmIdentifier with that OID and no parameters, and a BIT STRING
+/// holding exactly the 32 key bytes. RSA and other keys are rejected.
+pub fn parse_ed25519_public_key_pem(pem: &str) -> Result<[u8; 32], VerifyFailure> {
     // Remove PEM headers and whitespace
     let pem = pem
         .replace("-----BEGIN PUBLIC KEY-----", "")
@@ -76,49 +193,188 @@ fn parse_ed25519_public_key_pem(pem: &str) -> Result<[u8; 32], SpdfError> {
     // Decode base64
     let decoded = general_purpose::STANDARD
         .decode(&pem)
-        .map_err(|e| SpdfError::SignatureError(format!("Invalid PEM base64: {}", e)))?;
+        .map_err(|e| VerifyFailure::KeyParse(format!("Invalid PEM base64: {}", e)))?;
 
-    // Ed25519 public key in SubjectPublicKeyInfo format is 44 bytes,
-    // the last 32 bytes are the actual key
+    // Ed25519 public key in SubjectPublicKeyInfo format is 44 bytes
     if decoded.len() < 32 {
-        return Err(SpdfError::SignatureError(format!(
-            "PEM decoded data too short: {} bytes, expected at least 32",
-            decoded.len()
-        )));
+        return Err(VerifyFailure::KeyLength {
+            actual: decoded.len(),
+        });
     }
 
-    let key_bytes: [u8; 32] = decoded[decoded.len() - 32..]
-        .try_into()
-        .map_err(|_| SpdfError::SignatureError("Invalid key length".to_string()))?;
+    let (spki, trailing) = der_element(&decoded, DER_SEQUENCE, "SubjectPublicKeyInfo")?;
+    if !trailing.is_empty() {
+        return Err(key_parse("Public key DER: trailing data after SubjectPublicKeyInfo"));
+    }
+    let (algorithm, rest) = der_element(spki, DER_SEQUENCE, "AlgorithmIdentifier")?;
+    let (oid, parameters) = der_element(algorithm, DER_OID, "algorithm OID")?;
+    if oid != ED25519_OID {
+        return Err(key_parse(format!(
+            "Public key is not Ed25519: algorithm OID {} (expected 1.3.101.112)",
+            hex::encode(oid)
+        )));
+    }
+    if !parameters.is_empty() {
+        return Err(key_parse("Public key DER: Ed25519 algorithm must not have parameters"));
+    }
+    let (bits, rest) = der_element(rest, DER_BIT_STRING, "subjectPublicKey BIT STRING")?;
+    if !rest.is_empty() {
+        return Err(key_parse("Public key DER: trailing data after subjectPublicKey"));
+    }
 
-    Ok(key_bytes)
+    // Leading byte is the count of unused bits, always 0 for a key
+    match bits.split_first() {
+        Some((0, key)) => key.try_into().map_err(|_| {
+            key_parse(format!("Ed25519 public key is {} bytes, expected 32", key.len()))
+        }),
+        _ => Err(key_parse("Public key DER: malformed subjectPublicKey BIT STRING")),
+    }
 }
 
 /// Verify signature using a specific public key (not from header)
-pub fn verify_signature_with_key(spdf: &SpdfFile, public_key_pem: &str) -> Result<(), SpdfError> {
-    let public_key_bytes = parse_ed25519_public_key_pem(public_key_pem)?;
-    
-    let verifying_key = VerifyingKey::from_bytes(&public_key_bytes)
-        .map_err(|e| SpdfError::SignatureError(format!("Invalid public key: {}", e)))?;
+pub fn verify_signature_with_key(
+    spdf: &SpdfFile,
+    public_key_pem: &str,
+) -> Result<(), VerifyFailure> {
+    verify_signature_with_key_bytes(spdf, &parse_ed25519_public_key_pem(public_key_pem)?)
+}
 
-    if spdf.signature.len() != SIGNATURE_LENGTH {
-        return Err(SpdfError::SignatureError("Invalid signature length".to_string()));
+/// Verify signature using the key pinned for the document's org
+///
+/// The header's own `public_key`, when present, must be that same key:
+/// otherwise a file whose content and embedded key were both replaced
+/// would still verify against itself.
+pub fn verify_signature_with_pinned_key(
+    spdf: &SpdfFile,
+    pinned_pem: &str,
+) -> Result<(), VerifyFailure> {
+    let pinned = parse_ed25519_public_key_pem(pinned_pem)?;
+    let header_key = &spdf.header.public_key;
+    if !header_key.is_empty() && parse_ed25519_public_key_pem(header_key)? != pinned {
+        return Err(VerifyFailure::KeyMismatch {
+            org_id: spdf.header.org_id.clone(),
+        });
     }
+    verify_signature_with_key_bytes(spdf, &pinned)
+}
 
-    let mut hasher = Sha256::new();
-    hasher.update(&spdf.unsigned_data);
-    let hash = hasher.finalize();
+/// Verify signature using a raw 32-byte Ed25519 public key
+fn verify_signature_with_key_bytes(
+    spdf: &SpdfFile,
+    public_key_bytes: &[u8; 32],
+) -> Result<(), VerifyFailure> {
+    // Ed25519 is the only algorithm implemented; anything else is named
+    // rather than reported as a bad key or signature
+    match spdf.header.sig_alg() {
+        DEFAULT_SIG_ALG => {}
+        other => return Err(VerifyFailure::SigAlg(other.to_string())),
+    }
+
+    let verifying_key = VerifyingKey::from_bytes(public_key_bytes)
+        .map_err(|e| VerifyFailure::KeyParse(format!("Invalid public key: {}", e)))?;
 
-    let sig_bytes: [u8; 64] = spdf.signature[..]
-        .try_into()
-        .map_err(|_| SpdfError::SignatureError("Invalid signature format".to_string()))?;
+    let sig_bytes: [u8; 64] = spdf.signature[..].try_into().map_err(|_| {
+        VerifyFailure::SignatureLength {
+            expected: SIGNATURE_LENGTH,
+            actual: spdf.signature.len(),
+        }
+    })?;
     let signature = Signature::from_bytes(&sig_bytes);
 
+    // `unsigned_data` was framed by the parser for the layout of the version
+    // byte. If the header claims the other layout the signer framed different
+    // bytes, so report that instead of a bare signature mismatch.
+    if !spdf.version_matches_header() {
+        return Err(VerifyFailure::VersionMismatch {
+            version: spdf.version,
+            spdf_version: spdf.header.spdf_version.clone(),
+        });
+    }
+
+    // Hash the unsigned data
+    let hash_alg = spdf.header.hash_alg();
+    let hash = signature_digest(hash_alg, &spdf.unsigned_data)
+        .ok_or_else(|| VerifyFailure::HashAlg(hash_alg.to_string()))?;
+
     verifying_key
         .verify(&hash, &signature)
-        .map_err(|e| SpdfError::SignatureError(format!("Signature verification failed: {}", e)))?;
+        .map_err(|e| VerifyFailure::VerifyFailed(e.to_string()))
+}
+
+/// A JWKS document (RFC 7517); only the fields needed for Ed25519 keys
+#[derive(Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
 
-    Ok(())
+#[derive(Deserialize)]
+struct Jwk {
+    kty: String,
+    #[serde(default)]
+    crv: Option<String>,
+    #[serde(default)]
+    kid: Option<String>,
+    /// Base64url public key (RFC 8037)
+    #[serde(default)]
+    x: Option<String>,
+}
+
+/// `kid` and raw public key of an Ed25519 JWK
+type JwksKey = (Option<String>, [u8; 32]);
+
+/// Ed25519 keys of a JWKS; other key types are skipped
+fn jwks_ed25519_keys(jwks_json: &str) -> Result<Vec<JwksKey>, SpdfError> {
+    let jwks: Jwks = serde_json::from_str(jwks_json)
+        .map_err(|e| SpdfError::FormatError(format!("Invalid JWKS: {}", e)))?;
+
+    jwks.keys
+        .into_iter()
+        .filter(|jwk| jwk.kty == "OKP" && jwk.crv.as_deref() == Some("Ed25519"))
+        .map(|jwk| {
+            let kid = jwk.kid.as_deref().unwrap_or("<none>");
+            let key = jwk
+                .x
+                .as_deref()
+                .and_then(|x| general_purpose::URL_SAFE_NO_PAD.decode(x).ok())
+                .and_then(|x| <[u8; 32]>::try_from(x).ok())
+                .ok_or_else(|| {
+                    SpdfError::FormatError(format!("Invalid Ed25519 JWK '{}': bad 'x'", kid))
+                })?;
+            Ok((jwk.kid, key))
+        })
+        .collect()
+}
+
+/// Verify the signature against an org's published JWKS
+///
+/// Uses the key whose `kid` matches the header's `key_id`, or, without a
+/// `key_id`, accepts any Ed25519 key in the set that verifies.
+pub fn verify_against_jwks(spdf: &SpdfFile, jwks_json: &str) -> Result<(), SpdfError> {
+    let keys = jwks_ed25519_keys(jwks_json)?;
+
+    if let Some(key_id) = &spdf.header.key_id {
+        let (_, key) = keys
+            .iter()
+            .find(|(kid, _)| kid.as_deref() == Some(key_id.as_str()))
+            .ok_or_else(|| {
+                SpdfError::SignatureError(format!("No Ed25519 key with kid '{}' in JWKS", key_id))
+            })?;
+        return Ok(verify_signature_with_key_bytes(spdf, key)?);
+    }
+
+    if keys.is_empty() {
+        return Err(SpdfError::SignatureError("JWKS has no Ed25519 keys".to_string()));
+    }
+    for (_, key) in &keys {
+        match verify_signature_with_key_bytes(spdf, key) {
+            Ok(()) => return Ok(()),
+            // Wrong key: try the next one
+            Err(VerifyFailure::VerifyFailed(_)) | Err(VerifyFailure::KeyParse(_)) => {}
+            // Anything else fails the same way for every key
+            Err(failure) => return Err(failure.into()),
+        }
+    }
+    Err(SpdfError::SignatureError("No key in JWKS verifies the signature".to_string()))
 }
 
 /// Check if an SPDF file is tampered (quick check without full verification)
@@ -135,8 +391,8 @@ pub fn is_potentially_tampered(spdf: &SpdfFile) -> bool {
         return true;
     }
 
-    // Check for empty required fields
-    if spdf.header.doc_id.is_empty() || spdf.header.server_url.is_empty() {
+    // Check for empty or malformed required fields
+    if spdf.header.validate_header().is_err() {
         return true;
     }
 
@@ -148,20 +404,138 @@ pub fn is_potentially_tampered(spdf: &SpdfFile) -> bool {
     false
 }
 
+/// Outcome of one `analyze_integrity` check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IntegrityCheck {
+    /// "version", "signature_length", "header_fields", "signature",
+    /// "permissions" or "content"
+    pub name: String,
+    pub passed: bool,
+    /// Why the check failed; empty when it passed
+    pub messages: Vec<String>,
+}
+
+/// Every integrity finding for a file, for a "why was this rejected" view
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IntegrityReport {
+    /// Every check passed
+    pub intact: bool,
+    pub checks: Vec<IntegrityCheck>,
+}
+
+/// Run every integrity check on `spdf` and report them all
+///
+/// Unlike `verify_signature` and `is_potentially_tampered`, a failed check
+/// does not stop the others, so one report shows everything wrong with the
+/// file. The signature is verified against the org key in `trust_store`;
+/// an org with no trusted key fails the signature check.
+pub fn analyze_integrity(spdf: &SpdfFile, trust_store: &KeySource) -> IntegrityReport {
+    let mut version = Vec::new();
+    if spdf.version != VERSION && spdf.version != VERSION_SEGMENTED {
+        version.push(format!("unsupported format version {}", spdf.version));
+    } else if !spdf.version_matches_header() {
+        version.push(format!(
+            "version byte {} but spdf_version '{}'",
+            spdf.version, spdf.header.spdf_version
+        ));
+    }
+
+    let mut signature_length = Vec::new();
+    if spdf.signature.len() != SIGNATURE_LENGTH {
+        signature_length.push(format!(
+            "expected {} bytes, got {}",
+            SIGNATURE_LENGTH,
+            spdf.signature.len()
+        ));
+    }
+
+    let mut header_fields: Vec<String> = match spdf.header.validate_header() {
+        Ok(()) => Vec::new(),
+        Err(errors) => errors.iter().map(|e| e.to_string()).collect(),
+    };
+    if spdf.header.public_key.trim().is_empty() {
+        header_fields.push("public_key is empty".to_string());
+    }
+
+    let signature = match verify_signature_against_trust_store(spdf, trust_store) {
+        Ok(()) => Vec::new(),
+        Err(failure) => vec![failure.message()],
+    };
+
+    let checks: Vec<IntegrityCheck> = [
+        ("version", version),
+        ("signature_length", signature_length),
+        ("header_fields", header_fields),
+        ("signature", signature),
+        ("permissions", spdf.permission_mismatches()),
+        ("content", integrity_precheck(spdf)),
+    ]
+    .into_iter()
+    .map(|(name, messages)| IntegrityCheck {
+        name: name.to_string(),
+        passed: messages.is_empty(),
+        messages,
+    })
+    .collect();
+    IntegrityReport {
+        intact: checks.iter().all(|check| check.passed),
+        checks,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_parse_pem_format() {
-        // Valid Ed25519 public key PEM (example, not a real key)
+        // RFC 8410, section 10.1
         let valid_pem = "-----BEGIN PUBLIC KEY-----
-MCowBQYDK2VwAyEAthisisafakepublickeyfortesting123456789
+MCowBQYDK2VwAyEAGb9ECWmEzf6FQbrBZ9w7lshQhqowtrbLDFw4rXAxZuE=
 -----END PUBLIC KEY-----";
 
-        // This should parse without error (though the key is fake)
-        let result = parse_ed25519_public_key_pem(valid_pem);
-        // Result depends on the actual base64 content
+        let key = parse_ed25519_public_key_pem(valid_pem).unwrap();
+        assert_eq!(
+            hex::encode(key),
+            "19bf44096984cdfe8541bac167dc3b96c85086aa30b6b6cb0c5c38ad703166e1"
+        );
+        assert_eq!(parse_ed25519_public_key_pem(&ed25519_public_key_pem(&key)), Ok(key));
+    }
+
+    #[test]
+    fn test_rejects_non_ed25519_keys() {
+        let rsa_pem = "-----BEGIN PUBLIC KEY-----
+MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQDqvwGdmSkDDCD9c8IDhCeWTCrF
+bzBxWaGamQKl+KW3BzwYQzwoZ5onNZOMh1GV6nZBrcpCFlnpwnwDfMwRBEZloc9F
+FwYHY3d1BYrhwYp98KuSiH6EAI0b7q/qnltTVL0jYytsRUbktEK+UfIFuY9lma/x
+VJNLIIJ4hyyHIf9G5wIDAQAB
+-----END PUBLIC KEY-----";
+        assert_eq!(
+            parse_ed25519_public_key_pem(rsa_pem),
+            Err(VerifyFailure::KeyParse(
+                "Public key is not Ed25519: algorithm OID 2a864886f70d010101 (expected 1.3.101.112)"
+                    .to_string()
+            ))
+        );
+
+        let pem = |der: &[u8]| {
+            let body = general_purpose::STANDARD.encode(der);
+            format!("-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----", body)
+        };
+        let mut der = ED25519_SPKI_PREFIX.to_vec();
+        der.extend_from_slice(&[0x11; 32]);
+        assert!(parse_ed25519_public_key_pem(&pem(&der)).is_ok());
+
+        // Truncated, padded, or bare key bytes without the SPKI wrapper
+        let is_key_parse = |der: &[u8]| {
+            matches!(parse_ed25519_public_key_pem(&pem(der)), Err(VerifyFailure::KeyParse(_)))
+        };
+        assert!(is_key_parse(&der[..40]));
+        assert!(is_key_parse(&[der.as_slice(), &[0]].concat()));
+        assert!(is_key_parse(&[0x11; 40]));
+        // Unused-bits count other than zero
+        der[11] = 1;
+        assert!(is_key_parse(&der));
     }
 
     #[test]
@@ -170,4 +544,228 @@ MCowBQYDK2VwAyEAthisisafakepublickeyfortesting123456789
         let result = parse_ed25519_public_key_pem(invalid_pem);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_verify_signed_file() {
+        let data = crate::test_support::build_spdf(b"%PDF-1.4 test");
+        let mut spdf = SpdfFile::parse(&data).unwrap();
+        assert!(verify_signature(&spdf).is_ok());
+
+        spdf.unsigned_data[20] ^= 0x01;
+        assert!(matches!(verify_signature(&spdf), Err(VerifyFailure::VerifyFailed(_))));
+    }
+
+    fn signed_file() -> SpdfFile {
+        SpdfFile::parse(&crate::test_support::build_spdf(b"%PDF-1.4 test")).unwrap()
+    }
+
+    #[test]
+    fn test_analyze_integrity_reports_every_failure() {
+        let keys_dir = tempfile::tempdir().unwrap();
+        let trust_store = KeySource::Files { keys_dir: keys_dir.path().to_path_buf() };
+
+        // Signed with the header's key, but nothing trusts it yet
+        let mut spdf = signed_file();
+        let report = analyze_integrity(&spdf, &trust_store);
+        assert!(!report.intact);
+        let signature = report.checks.iter().find(|check| check.name == "signature").unwrap();
+        assert_eq!(signature.messages, ["No trusted key for test_org"]);
+
+        let pem = crate::test_support::public_key_pem();
+        std::fs::write(keys_dir.path().join("test_org_public.pem"), pem).unwrap();
+        let report = analyze_integrity(&spdf, &trust_store);
+        assert!(report.intact, "{:?}", report);
+        assert_eq!(report.checks.len(), 6);
+
+        spdf.version = 0x07;
+        spdf.signature.pop();
+        spdf.header.doc_id.clear();
+        spdf.flags ^= crate::spdf_parser::FLAG_PRINT_ALLOWED;
+        spdf.auth_tag.pop();
+        let report = analyze_integrity(&spdf, &trust_store);
+        assert!(!report.intact);
+        assert!(report.checks.iter().all(|check| !check.passed), "{:?}", report);
+
+        let messages = |name: &str| {
+            let check = report.checks.iter().find(|check| check.name == name).unwrap();
+            check.messages.clone()
+        };
+        assert_eq!(messages("version"), ["unsupported format version 7"]);
+        assert_eq!(messages("signature_length"), ["expected 64 bytes, got 63"]);
+        assert_eq!(messages("header_fields").len(), 1);
+        assert_eq!(messages("permissions").len(), 1);
+        assert_eq!(messages("content"), ["Invalid auth tag length: expected 16, got 15"]);
+
+        // Crosses the Tauri boundary as JSON
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["checks"][0]["name"], "version");
+    }
+
+    #[test]
+    fn test_failure_key_parse() {
+        let mut spdf = signed_file();
+        spdf.header.public_key.clear();
+        assert_eq!(
+            verify_signature(&spdf),
+            Err(VerifyFailure::KeyParse("No public key in header".to_string()))
+        );
+
+        let result = verify_signature_with_key(&spdf, "-----BEGIN PUBLIC KEY-----\n!!\n");
+        assert!(matches!(result, Err(VerifyFailure::KeyParse(_))));
+    }
+
+    #[test]
+    fn test_failure_key_length() {
+        let short = general_purpose::STANDARD.encode([0u8; 16]);
+        let pem = format!("-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----", short);
+        let failure = verify_signature_with_key(&signed_file(), &pem).unwrap_err();
+        assert_eq!(failure, VerifyFailure::KeyLength { actual: 16 });
+        assert_eq!(
+            failure.to_string(),
+            "Signature error: PEM decoded data too short: 16 bytes, expected at least 32"
+        );
+    }
+
+    #[test]
+    fn test_failure_signature_length() {
+        let mut spdf = signed_file();
+        spdf.signature.truncate(63);
+        assert_eq!(
+            verify_signature(&spdf),
+            Err(VerifyFailure::SignatureLength { expected: 64, actual: 63 })
+        );
+    }
+
+    fn jwk(kid: &str, key: &ed25519_dalek::SigningKey) -> serde_json::Value {
+        serde_json::json!({
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "kid": kid,
+            "x": general_purpose::URL_SAFE_NO_PAD.encode(key.verifying_key().as_bytes()),
+        })
+    }
+
+    #[test]
+    fn test_verify_against_jwks() {
+        use crate::test_support::{build_spdf_with, sample_header, signing_key, DEFAULT_FLAGS};
+        use ed25519_dalek::SigningKey;
+
+        let other_key = SigningKey::from_bytes(&[0x99; 32]);
+        let right = jwk("org-2025", &signing_key());
+        let wrong = jwk("org-2024", &other_key);
+        let rsa = serde_json::json!({ "kty": "RSA", "kid": "legacy", "n": "AQAB", "e": "AQAB" });
+        let jwks = |keys: &[&serde_json::Value]| serde_json::json!({ "keys": keys }).to_string();
+
+        // No key_id: any key that verifies
+        let spdf = signed_file();
+        assert!(verify_against_jwks(&spdf, &jwks(&[&rsa, &wrong, &right])).is_ok());
+        let result = verify_against_jwks(&spdf, &jwks(&[&wrong]));
+        assert!(matches!(result, Err(SpdfError::SignatureError(_))));
+        let result = verify_against_jwks(&spdf, &jwks(&[&rsa]));
+        let no_keys = "JWKS has no Ed25519 keys".to_string();
+        assert!(matches!(result, Err(SpdfError::SignatureError(msg)) if msg == no_keys));
+
+        // key_id picks the key by kid
+        let mut header = sample_header();
+        header["key_id"] = serde_json::json!("org-2025");
+        let spdf = SpdfFile::parse(&build_spdf_with(&header, DEFAULT_FLAGS, b"%PDF")).unwrap();
+        assert!(verify_against_jwks(&spdf, &jwks(&[&wrong, &right])).is_ok());
+        let result = verify_against_jwks(&spdf, &jwks(&[&wrong]));
+        assert!(matches!(result, Err(SpdfError::SignatureError(msg)) if msg.contains("org-2025")));
+
+        // The right kid on the wrong key
+        let impostor = jwk("org-2025", &other_key);
+        let result = verify_against_jwks(&spdf, &jwks(&[&impostor]));
+        assert!(matches!(result, Err(SpdfError::SignatureError(_))));
+
+        assert!(matches!(verify_against_jwks(&spdf, "[]"), Err(SpdfError::FormatError(_))));
+    }
+
+    fn signed_with_hash_alg(hash_alg: &str) -> Vec<u8> {
+        use crate::test_support::{build_spdf_with, sample_header, DEFAULT_FLAGS};
+
+        let mut header = sample_header();
+        header["hash_alg"] = serde_json::json!(hash_alg);
+        build_spdf_with(&header, DEFAULT_FLAGS, b"%PDF-1.4 test")
+    }
+
+    #[test]
+    fn test_sha256_round_trip() {
+        let mut spdf = SpdfFile::parse(&signed_with_hash_alg("sha256")).unwrap();
+        assert_eq!(spdf.header.hash_alg(), "sha256");
+        assert!(verify_signature(&spdf).is_ok());
+
+        // The digest algorithm is part of the signed header
+        spdf.header.hash_alg = Some("blake3".to_string());
+        assert!(verify_signature(&spdf).is_err());
+    }
+
+    #[cfg(feature = "blake3")]
+    #[test]
+    fn test_blake3_round_trip() {
+        let mut spdf = SpdfFile::parse(&signed_with_hash_alg("blake3")).unwrap();
+        assert!(verify_signature(&spdf).is_ok());
+
+        spdf.unsigned_data[20] ^= 0x01;
+        assert!(matches!(verify_signature(&spdf), Err(VerifyFailure::VerifyFailed(_))));
+    }
+
+    #[test]
+    fn test_unsupported_hash_alg() {
+        let mut spdf = signed_file();
+        spdf.header.hash_alg = Some("md5".to_string());
+        assert_eq!(verify_signature(&spdf), Err(VerifyFailure::HashAlg("md5".to_string())));
+    }
+
+    #[test]
+    fn test_unsupported_sig_alg() {
+        let mut spdf = signed_file();
+        spdf.header.sig_alg = Some("ML-DSA-65".to_string());
+        assert_eq!(
+            verify_signature(&spdf),
+            Err(VerifyFailure::SigAlg("ML-DSA-65".to_string()))
+        );
+        spdf.header.sig_alg = Some(DEFAULT_SIG_ALG.to_string());
+        assert!(verify_signature(&spdf).is_ok());
+    }
+
+    #[test]
+    fn test_version_layout_mismatch() {
+        use crate::test_support::{build_segmented_spdf, build_spdf_with, sample_header};
+
+        // Version 1 framing, labeled 2.0 in the (signed) header
+        let mut header = sample_header();
+        header["spdf_version"] = serde_json::json!("2.0");
+        let data = build_spdf_with(&header, crate::test_support::DEFAULT_FLAGS, b"%PDF-1.4");
+        let spdf = SpdfFile::parse(&data).unwrap();
+        let failure = verify_signature(&spdf).unwrap_err();
+        assert_eq!(
+            failure,
+            VerifyFailure::VersionMismatch {
+                version: 1,
+                spdf_version: "2.0".to_string()
+            }
+        );
+        match SpdfError::from(failure) {
+            SpdfError::SignatureError(msg) => assert!(msg.starts_with("version/layout mismatch")),
+            other => panic!("expected SignatureError, got {:?}", other),
+        }
+
+        let mut segmented = SpdfFile::parse(&build_segmented_spdf(&[b"%PDF"])).unwrap();
+        assert!(verify_signature(&segmented).is_ok());
+        segmented.header.spdf_version = "1.0".to_string();
+        assert!(matches!(
+            verify_signature(&segmented),
+            Err(VerifyFailure::VersionMismatch { version: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_failure_into_spdf_error() {
+        let error = SpdfError::from(VerifyFailure::SignatureLength { expected: 64, actual: 0 });
+        assert_eq!(
+            error.to_string(),
+            "Signature error: Invalid signature length: expected 64, got 0"
+        );
+    }
 }
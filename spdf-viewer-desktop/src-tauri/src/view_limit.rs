@@ -0,0 +1,134 @@
+// View Limit Module - Local enforcement of `permissions.max_opens`
+//
+// Opens are counted per doc_id in ~/.spdf/opens.json. The count is
+// advisory: a user who deletes or edits the file resets it, so the key
+// server stays the authority whenever it reports a remaining count, and
+// the local count only fills in when it does not.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Refusal message once a document's opens are used up
+pub const VIEW_LIMIT_REACHED: &str = "view limit reached";
+
+/// Opens recorded for one document
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpenRecord {
+    pub opens: u32,
+    /// Limit from the header the last time the document was opened
+    pub max_opens: Option<u32>,
+}
+
+/// Persistent doc_id -> open count map
+#[derive(Debug)]
+pub struct ViewCounter {
+    path: PathBuf,
+    records: BTreeMap<String, OpenRecord>,
+}
+
+impl ViewCounter {
+    /// Default counter file, `~/.spdf/opens.json`
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::home_dir().map(|home| home.join(".spdf").join("opens.json"))
+    }
+
+    /// Load counts from `path`; a missing file means nothing opened yet
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let records = if path.exists() {
+            let data = fs::read(path).map_err(|e| format!("Failed to read open counts: {}", e))?;
+            serde_json::from_slice(&data).map_err(|e| format!("Invalid open counts: {}", e))?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(ViewCounter {
+            path: path.to_path_buf(),
+            records,
+        })
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create store dir: {}", e))?;
+        }
+        let json = serde_json::to_vec_pretty(&self.records)
+            .map_err(|e| format!("Failed to serialize open counts: {}", e))?;
+        fs::write(&self.path, json).map_err(|e| format!("Failed to write open counts: {}", e))
+    }
+
+    /// Opens left for a document; `None` means unlimited or never opened
+    pub fn remaining_opens(&self, doc_id: &str) -> Option<u32> {
+        let record = self.records.get(doc_id)?;
+        record.max_opens.map(|max| max.saturating_sub(record.opens))
+    }
+
+    /// Adopt the server's remaining count as the local one
+    pub fn sync_remaining(&mut self, doc_id: &str, max_opens: u32, remaining: u32) {
+        let record = self.records.entry(doc_id.to_string()).or_default();
+        record.max_opens = Some(max_opens);
+        record.opens = max_opens.saturating_sub(remaining);
+    }
+
+    /// Count one open, refusing with `VIEW_LIMIT_REACHED` if none are left
+    ///
+    /// Saves on success and returns the opens remaining after this one.
+    pub fn record_open(
+        &mut self,
+        doc_id: &str,
+        max_opens: Option<u32>,
+    ) -> Result<Option<u32>, String> {
+        let record = self.records.entry(doc_id.to_string()).or_default();
+        record.max_opens = max_opens;
+        if let Some(max) = max_opens {
+            if record.opens >= max {
+                return Err(VIEW_LIMIT_REACHED.to_string());
+            }
+        }
+        record.opens += 1;
+
+        let remaining = self.remaining_opens(doc_id);
+        self.save()?;
+        Ok(remaining)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_counter() -> (tempfile::TempDir, ViewCounter) {
+        let dir = tempfile::tempdir().unwrap();
+        let counter = ViewCounter::load(&dir.path().join("opens.json")).unwrap();
+        (dir, counter)
+    }
+
+    #[test]
+    fn test_limit_reached() {
+        let (dir, mut counter) = temp_counter();
+
+        assert_eq!(counter.record_open("DOC-1", Some(2)).unwrap(), Some(1));
+        assert_eq!(counter.record_open("DOC-1", Some(2)).unwrap(), Some(0));
+        assert_eq!(counter.record_open("DOC-1", Some(2)).unwrap_err(), VIEW_LIMIT_REACHED);
+        assert_eq!(counter.remaining_opens("DOC-1"), Some(0));
+
+        // Persisted across restarts
+        let mut reloaded = ViewCounter::load(&dir.path().join("opens.json")).unwrap();
+        assert!(reloaded.record_open("DOC-1", Some(2)).is_err());
+
+        // The server can grant more
+        reloaded.sync_remaining("DOC-1", 2, 1);
+        assert_eq!(reloaded.record_open("DOC-1", Some(2)).unwrap(), Some(0));
+    }
+
+    #[test]
+    fn test_none_is_unlimited() {
+        let (_dir, mut counter) = temp_counter();
+
+        for _ in 0..10 {
+            assert_eq!(counter.record_open("DOC-1", None).unwrap(), None);
+        }
+        assert_eq!(counter.remaining_opens("DOC-1"), None);
+        assert_eq!(counter.remaining_opens("DOC-UNSEEN"), None);
+    }
+}
@@ -0,0 +1,138 @@
+// Watermark Module - Custom watermark image checks
+//
+// Headers may carry a PNG watermark as base64 alongside (or instead of) the
+// text watermark. The image travels inside the signed header, so it is
+// bounded here before it reaches the renderer:
+// - at most MAX_WATERMARK_IMAGE_BYTES once decoded (32 KiB, which keeps
+//   the base64 form well inside the 65535-byte header limit of the spec)
+// - at most MAX_WATERMARK_IMAGE_DIMENSION pixels wide and high (2048)
+// - opacity, when set, between 0.0 and 1.0 (the viewer falls back to the
+//   .watermark-overlay opacity in styles.css)
+//
+// Only the PNG signature and IHDR chunk are inspected; the pixel data is
+// decoded by the webview when the overlay is drawn.
+
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
+
+/// Largest accepted watermark image, in decoded bytes
+pub const MAX_WATERMARK_IMAGE_BYTES: usize = 32 * 1024;
+
+/// Largest accepted watermark image width or height, in pixels
+pub const MAX_WATERMARK_IMAGE_DIMENSION: u32 = 2048;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// Signature (8) + IHDR length (4) + type (4) + width (4) + height (4)
+const PNG_IHDR_PREFIX_LENGTH: usize = 24;
+
+/// How a watermark image is placed over the page
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatermarkImageLayout {
+    #[default]
+    Centered,
+    Tiled,
+}
+
+/// Check a base64 PNG watermark and return its (width, height)
+pub fn validate_watermark_image(image_base64: &str) -> Result<(u32, u32), String> {
+    // Reject oversized payloads before decoding them
+    if image_base64.len() > MAX_WATERMARK_IMAGE_BYTES.div_ceil(3) * 4 {
+        return Err(format!(
+            "Watermark image exceeds {} bytes",
+            MAX_WATERMARK_IMAGE_BYTES
+        ));
+    }
+
+    let png = general_purpose::STANDARD
+        .decode(image_base64)
+        .map_err(|e| format!("Invalid watermark image base64: {}", e))?;
+
+    if png.len() > MAX_WATERMARK_IMAGE_BYTES {
+        return Err(format!(
+            "Watermark image exceeds {} bytes",
+            MAX_WATERMARK_IMAGE_BYTES
+        ));
+    }
+    if png.len() < PNG_IHDR_PREFIX_LENGTH || png[..8] != PNG_SIGNATURE || &png[12..16] != b"IHDR" {
+        return Err("Watermark image is not a PNG".to_string());
+    }
+
+    let width = u32::from_be_bytes([png[16], png[17], png[18], png[19]]);
+    let height = u32::from_be_bytes([png[20], png[21], png[22], png[23]]);
+
+    if width == 0
+        || height == 0
+        || width > MAX_WATERMARK_IMAGE_DIMENSION
+        || height > MAX_WATERMARK_IMAGE_DIMENSION
+    {
+        return Err(format!(
+            "Watermark image is {}x{}, limit is {}x{}",
+            width, height, MAX_WATERMARK_IMAGE_DIMENSION, MAX_WATERMARK_IMAGE_DIMENSION
+        ));
+    }
+
+    Ok((width, height))
+}
+
+/// Check that a watermark opacity is within 0.0..=1.0
+pub fn validate_watermark_opacity(opacity: f32) -> Result<(), String> {
+    if (0.0..=1.0).contains(&opacity) {
+        Ok(())
+    } else {
+        Err(format!("Watermark opacity {} is outside 0.0..=1.0", opacity))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A 1x1 transparent PNG
+    pub(crate) const TINY_PNG_BASE64: &str =
+        "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNkYPhfDwAChwGA60e6kgAAAABJRU5ErkJggg==";
+
+    fn png_with_size(width: u32, height: u32) -> String {
+        let mut png = general_purpose::STANDARD.decode(TINY_PNG_BASE64).unwrap();
+        png[16..20].copy_from_slice(&width.to_be_bytes());
+        png[20..24].copy_from_slice(&height.to_be_bytes());
+        general_purpose::STANDARD.encode(png)
+    }
+
+    #[test]
+    fn test_tiny_png_accepted() {
+        assert_eq!(validate_watermark_image(TINY_PNG_BASE64).unwrap(), (1, 1));
+    }
+
+    #[test]
+    fn test_dimension_limit() {
+        let max = MAX_WATERMARK_IMAGE_DIMENSION;
+        assert!(validate_watermark_image(&png_with_size(max, max)).is_ok());
+        assert!(validate_watermark_image(&png_with_size(max + 1, 1)).is_err());
+        assert!(validate_watermark_image(&png_with_size(0, 10)).is_err());
+    }
+
+    #[test]
+    fn test_byte_limit() {
+        let mut png = general_purpose::STANDARD.decode(TINY_PNG_BASE64).unwrap();
+        png.resize(MAX_WATERMARK_IMAGE_BYTES + 1, 0);
+        let encoded = general_purpose::STANDARD.encode(png);
+        assert!(validate_watermark_image(&encoded).unwrap_err().contains("exceeds"));
+    }
+
+    #[test]
+    fn test_not_png() {
+        let jpeg = general_purpose::STANDARD.encode([0xFF, 0xD8, 0xFF, 0xE0, 0, 0, 0, 0]);
+        assert!(validate_watermark_image(&jpeg).is_err());
+        assert!(validate_watermark_image("not base64!").is_err());
+    }
+
+    #[test]
+    fn test_opacity_range() {
+        assert!(validate_watermark_opacity(0.0).is_ok());
+        assert!(validate_watermark_opacity(1.0).is_ok());
+        assert!(validate_watermark_opacity(1.5).is_err());
+        assert!(validate_watermark_opacity(f32::NAN).is_err());
+    }
+}
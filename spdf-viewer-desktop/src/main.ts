@@ -14,6 +14,8 @@ let permissions = {
   allow_copy: false,
 };
 let watermarkText = '';
+let watermarkImage: { src: string; layout: 'centered' | 'tiled' } | null = null;
+let watermarkOpacity: number | null = null;
 
 // Elements
 const canvas = document.getElementById('pdf-canvas') as HTMLCanvasElement;
@@ -89,15 +91,36 @@ async function renderPage(pageNum: number) {
 
 // Update watermark overlay
 function updateWatermark() {
-  if (!watermarkText) {
+  if (!watermarkText && !watermarkImage) {
     watermarkOverlay.style.display = 'none';
     return;
   }
 
   watermarkOverlay.style.display = 'flex';
+  // Header opacity overrides the stylesheet default
+  watermarkOverlay.style.opacity = watermarkOpacity === null ? '' : String(watermarkOpacity);
+  watermarkOverlay.innerHTML = '';
+
+  // Image watermark: tiled as the overlay background, or centered on the page
+  watermarkOverlay.style.backgroundImage = '';
+  if (watermarkImage?.layout === 'tiled') {
+    watermarkOverlay.style.backgroundImage = `url(${watermarkImage.src})`;
+    watermarkOverlay.style.backgroundRepeat = 'repeat';
+  } else if (watermarkImage) {
+    const img = document.createElement('img');
+    img.src = watermarkImage.src;
+    img.style.position = 'absolute';
+    img.style.top = '50%';
+    img.style.left = '50%';
+    img.style.transform = 'translate(-50%, -50%)';
+    img.style.maxWidth = '80%';
+    img.style.maxHeight = '80%';
+    watermarkOverlay.appendChild(img);
+  }
+
+  if (!watermarkText) return;
 
   // Create multiple watermark instances
-  watermarkOverlay.innerHTML = '';
   for (let i = 0; i < 5; i++) {
     const watermarkDiv = document.createElement('div');
     watermarkDiv.textContent = watermarkText;
@@ -172,6 +195,9 @@ async function openSpdfFile(filePath?: string) {
         watermark: {
           enabled: boolean;
           text: string;
+          image_base64?: string;
+          image_layout?: 'centered' | 'tiled';
+          opacity?: number;
         };
       };
       pdf_base64?: string;
@@ -180,6 +206,7 @@ async function openSpdfFile(filePath?: string) {
         user_id: string;
         device_id: string;
       };
+      watermark_text?: string;
     }
 
     const result = await invoke<OpenFileResult>('open_spdf_file', {
@@ -208,10 +235,13 @@ async function openSpdfFile(filePath?: string) {
 
       // Set watermark
       if (result.header.watermark.enabled) {
-        // For Phase 2, use placeholder values
-        watermarkText = result.header.watermark.text
-          .replace('{{user_id}}', 'test@example.com') // This should come from auth user eventually
-          .replace('{{device_id}}', 'DEVICE-TEST');
+        watermarkText = result.watermark_text ?? '';
+
+        const { image_base64, image_layout, opacity } = result.header.watermark;
+        watermarkImage = image_base64
+          ? { src: `data:image/png;base64,${image_base64}`, layout: image_layout ?? 'centered' }
+          : null;
+        watermarkOpacity = opacity ?? null;
       }
     }
 